@@ -0,0 +1,46 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use rust_heightmap_generator::heightmap_gen::heightmap::{generate_perlin_noise_buffer, blend_buffers, colorize_buffer, thermal_erosion};
+use rust_heightmap_generator::heightmap_gen::parallel::build_thread_pool;
+use rust_heightmap_generator::heightmap_gen::cancel::CancelToken;
+
+const SIZES: [u32; 3] = [256, 1024, 4096];
+
+// Each stage is measured on a single thread (the serial baseline) and with one thread per core
+fn thread_counts() -> Vec<(&'static str, usize)> {
+    vec![("serial", 1), ("parallel", 0)]
+}
+
+fn bench_stages(c: &mut Criterion) {
+    for (label, threads) in thread_counts() {
+        let pool = build_thread_pool(threads).expect("Failed to build thread pool");
+        let mut group = c.benchmark_group(format!("pipeline_{}", label));
+        group.sample_size(10);
+
+        for size in SIZES {
+            let base = generate_perlin_noise_buffer(size, size, 0.0, 0.0, 0.05, 1.0, 1);
+            let layer = generate_perlin_noise_buffer(size, size, 10.0, 10.0, 0.02, 0.5, 2);
+            let colormap = colorize_buffer(&base, 2).unwrap();
+
+            group.bench_with_input(BenchmarkId::new("perlin", size), &size, |b, &size| {
+                b.iter(|| pool.install(|| generate_perlin_noise_buffer(size, size, 0.0, 0.0, 0.05, 1.0, 1)))
+            });
+            group.bench_with_input(BenchmarkId::new("blend", size), &size, |b, _| {
+                b.iter(|| pool.install(|| blend_buffers(&base, &layer, 0).unwrap()))
+            });
+            group.bench_with_input(BenchmarkId::new("colorize", size), &size, |b, _| {
+                b.iter(|| pool.install(|| colorize_buffer(&base, 2).unwrap()))
+            });
+            group.bench_with_input(BenchmarkId::new("thermal_erosion", size), &size, |b, _| {
+                b.iter(|| {
+                    let mut heightmap = base.clone();
+                    let mut colors = colormap.clone();
+                    pool.install(|| thermal_erosion(&mut heightmap, &mut colors, 1, 0.01, 2, &CancelToken::new()).unwrap());
+                })
+            });
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(benches, bench_stages);
+criterion_main!(benches);
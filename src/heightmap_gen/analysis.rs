@@ -0,0 +1,284 @@
+use image::{ImageBuffer, Rgba};
+use serde_derive::{Serialize, Deserialize};
+
+// Neighbourhood radius of the TPI and roughness in preview pixels, scaled with the detail
+const WINDOW_RADIUS: f64 = 6.0;
+// Box blur radius before the curvatures in preview pixels, which are otherwise the 8-bit steps of the heightmap
+const CURVATURE_SMOOTHING: f64 = 2.0;
+// Slopes below this many degrees have no aspect and are drawn gray
+const FLAT_DEGREES: f64 = 1.0;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+pub enum AnalysisMap {
+    #[default]
+    None,
+    Slope,
+    Aspect,
+    PlanCurvature,
+    ProfileCurvature,
+    Tpi,
+    Roughness,
+}
+
+impl AnalysisMap {
+    pub const ALL: [AnalysisMap; 6] = [
+        AnalysisMap::Slope,
+        AnalysisMap::Aspect,
+        AnalysisMap::PlanCurvature,
+        AnalysisMap::ProfileCurvature,
+        AnalysisMap::Tpi,
+        AnalysisMap::Roughness,
+    ];
+
+    pub fn from_index(index: i32) -> AnalysisMap {
+        match index {
+            1 => AnalysisMap::Slope,
+            2 => AnalysisMap::Aspect,
+            3 => AnalysisMap::PlanCurvature,
+            4 => AnalysisMap::ProfileCurvature,
+            5 => AnalysisMap::Tpi,
+            6 => AnalysisMap::Roughness,
+            _ => AnalysisMap::None,
+        }
+    }
+
+    pub fn index(&self) -> i32 {
+        match self {
+            AnalysisMap::None => 0,
+            AnalysisMap::Slope => 1,
+            AnalysisMap::Aspect => 2,
+            AnalysisMap::PlanCurvature => 3,
+            AnalysisMap::ProfileCurvature => 4,
+            AnalysisMap::Tpi => 5,
+            AnalysisMap::Roughness => 6,
+        }
+    }
+
+    // Graph output name, which is also the suffix of the exported image
+    pub fn name(&self) -> &'static str {
+        match self {
+            AnalysisMap::None => "none",
+            AnalysisMap::Slope => "slope",
+            AnalysisMap::Aspect => "aspect",
+            AnalysisMap::PlanCurvature => "plan_curvature",
+            AnalysisMap::ProfileCurvature => "profile_curvature",
+            AnalysisMap::Tpi => "tpi",
+            AnalysisMap::Roughness => "roughness",
+        }
+    }
+}
+
+// Heights in metres with the neighbours of border pixels repeating the edge
+struct Surface {
+    width: u32,
+    height: u32,
+    pixel_size: f64,
+    heights: Vec<f64>,
+}
+
+impl Surface {
+    fn new(heightmap: &ImageBuffer<Rgba<u8>, Vec<u8>>, size_m: f64, height_m: f64) -> Surface {
+        let (width, height) = heightmap.dimensions();
+        Surface {
+            width,
+            height,
+            pixel_size: size_m / width.max(height).max(1) as f64,
+            heights: heightmap.pixels().map(|pixel| pixel[0] as f64 / 255.0 * height_m).collect(),
+        }
+    }
+
+    fn get(&self, x: i64, y: i64) -> f64 {
+        let x = x.clamp(0, self.width as i64 - 1) as u32;
+        let y = y.clamp(0, self.height as i64 - 1) as u32;
+        self.heights[(y * self.width + x) as usize]
+    }
+
+    // Mean and mean square of the heights within the radius around every pixel, the edges repeated outwards
+    fn window_moments(&self, radius: usize) -> (Vec<f64>, Vec<f64>) {
+        let (w, h) = (self.width as usize, self.height as usize);
+        let (padded_w, padded_h) = (w + 2 * radius, h + 2 * radius);
+        let mut padded = Vec::with_capacity(padded_w * padded_h);
+        for y in 0..padded_h {
+            for x in 0..padded_w {
+                padded.push(self.get(x as i64 - radius as i64, y as i64 - radius as i64));
+            }
+        }
+        let squares: Vec<f64> = padded.iter().map(|z| z * z).collect();
+        let sums = SummedArea::new(&padded, padded_w, padded_h);
+        let square_sums = SummedArea::new(&squares, padded_w, padded_h);
+        let count = ((2 * radius + 1) * (2 * radius + 1)) as f64;
+        (0..w * h).map(|index| {
+            let (x, y) = (index % w, index / w);
+            let (x1, y1) = (x + 2 * radius + 1, y + 2 * radius + 1);
+            (sums.sum(x, y, x1, y1) / count, square_sums.sum(x, y, x1, y1) / count)
+        }).unzip()
+    }
+
+    // The 3x3 window, row by row from the top left
+    fn window(&self, x: u32, y: u32) -> [f64; 9] {
+        let (x, y) = (x as i64, y as i64);
+        [
+            self.get(x - 1, y - 1), self.get(x, y - 1), self.get(x + 1, y - 1),
+            self.get(x - 1, y), self.get(x, y), self.get(x + 1, y),
+            self.get(x - 1, y + 1), self.get(x, y + 1), self.get(x + 1, y + 1),
+        ]
+    }
+}
+
+// Summed-area table with one extra row and column of zeros, for box sums in constant time
+struct SummedArea {
+    width: usize,
+    sums: Vec<f64>,
+}
+
+impl SummedArea {
+    fn new(values: &[f64], w: usize, h: usize) -> SummedArea {
+        let mut sums = vec![0.0; (w + 1) * (h + 1)];
+        for y in 0..h {
+            for x in 0..w {
+                sums[(y + 1) * (w + 1) + x + 1] = values[y * w + x] + sums[y * (w + 1) + x + 1] + sums[(y + 1) * (w + 1) + x] - sums[y * (w + 1) + x];
+            }
+        }
+        SummedArea { width: w, sums }
+    }
+
+    // Sum over x0..x1, y0..y1, end exclusive
+    fn sum(&self, x0: usize, y0: usize, x1: usize, y1: usize) -> f64 {
+        let row = self.width + 1;
+        self.sums[y1 * row + x1] - self.sums[y0 * row + x1] - self.sums[y1 * row + x0] + self.sums[y0 * row + x0]
+    }
+}
+
+// The raw values of a map for every pixel: slope and aspect in degrees, aspect clockwise from north
+// (up the image) and negative on flat ground, curvatures in 1/m, TPI and roughness in metres.
+// Curvatures follow Zevenbergen and Thorne: positive plan curvature is a ridge across the slope,
+// positive profile curvature a slope that gets steeper downhill.
+pub fn analysis_field(heightmap: &ImageBuffer<Rgba<u8>, Vec<u8>>, map: AnalysisMap, size_m: f64, height_m: f64, detail: f64) -> Vec<f64> {
+    let mut surface = Surface::new(heightmap, size_m, height_m);
+    let (width, height) = (surface.width, surface.height);
+    let length = surface.pixel_size;
+
+    match map {
+        AnalysisMap::None => vec![0.0; (width * height) as usize],
+        AnalysisMap::Tpi => {
+            let (means, _) = surface.window_moments((WINDOW_RADIUS * detail).round().max(1.0) as usize);
+            surface.heights.iter().zip(means).map(|(z, mean)| z - mean).collect()
+        }
+        AnalysisMap::Roughness => {
+            let (means, squares) = surface.window_moments((WINDOW_RADIUS * detail).round().max(1.0) as usize);
+            means.iter().zip(squares).map(|(mean, square)| (square - mean * mean).max(0.0).sqrt()).collect()
+        }
+        _ => {
+            if matches!(map, AnalysisMap::PlanCurvature | AnalysisMap::ProfileCurvature) {
+                // Twice, which is close to a tent filter
+                for _ in 0..2 {
+                    surface.heights = surface.window_moments((CURVATURE_SMOOTHING * detail).round().max(1.0) as usize).0;
+                }
+            }
+            let mut values = Vec::with_capacity((width * height) as usize);
+            for y in 0..height {
+                for x in 0..width {
+                    let [z1, z2, z3, z4, z5, z6, z7, z8, z9] = surface.window(x, y);
+                    // East and north gradients; the image's y points south
+                    let g = (z6 - z4) / (2.0 * length);
+                    let h = (z2 - z8) / (2.0 * length);
+                    let gradient = g * g + h * h;
+                    let value = match map {
+                        AnalysisMap::Slope => gradient.sqrt().atan().to_degrees(),
+                        AnalysisMap::Aspect => {
+                            if gradient.sqrt().atan().to_degrees() < FLAT_DEGREES {
+                                -1.0
+                            } else {
+                                // The downhill direction
+                                (-g).atan2(-h).to_degrees().rem_euclid(360.0)
+                            }
+                        }
+                        _ => {
+                            let d = ((z4 + z6) / 2.0 - z5) / (length * length);
+                            let e = ((z2 + z8) / 2.0 - z5) / (length * length);
+                            let f = (-z1 + z3 + z7 - z9) / (4.0 * length * length);
+                            if gradient < 1e-12 {
+                                0.0
+                            } else if map == AnalysisMap::PlanCurvature {
+                                -2.0 * (d * h * h + e * g * g - f * g * h) / gradient
+                            } else {
+                                -2.0 * (d * g * g + e * h * h + f * g * h) / gradient
+                            }
+                        }
+                    };
+                    values.push(value);
+                }
+            }
+            values
+        }
+    }
+}
+
+fn mix(a: [u8; 3], b: [u8; 3], t: f64) -> [u8; 3] {
+    let t = t.clamp(0.0, 1.0);
+    [0, 1, 2].map(|i| (a[i] as f64 + (b[i] as f64 - a[i] as f64) * t).round() as u8)
+}
+
+// Linear interpolation between colour stops at increasing positions
+fn ramp(stops: &[(f64, [u8; 3])], value: f64) -> [u8; 3] {
+    let last = stops[stops.len() - 1];
+    if value >= last.0 {
+        return last.1;
+    }
+    for pair in stops.windows(2) {
+        let ((start, a), (end, b)) = (pair[0], pair[1]);
+        if value < end {
+            return mix(a, b, (value - start) / (end - start));
+        }
+    }
+    last.1
+}
+
+// Fully saturated colour at an angle on the colour wheel, red at 0
+pub(crate) fn hue(degrees: f64) -> [u8; 3] {
+    let sector = (degrees.rem_euclid(360.0) / 60.0) as usize;
+    let t = degrees.rem_euclid(60.0) / 60.0;
+    let rising = (t * 255.0).round() as u8;
+    let falling = 255 - rising;
+    match sector {
+        0 => [255, rising, 0],
+        1 => [falling, 255, 0],
+        2 => [0, 255, rising],
+        3 => [0, falling, 255],
+        4 => [rising, 0, 255],
+        _ => [255, 0, falling],
+    }
+}
+
+// A high percentile of the absolute values, so a few extreme pixels don't wash out the map
+fn robust_range(values: &[f64]) -> f64 {
+    let mut magnitudes: Vec<f64> = values.iter().map(|value| value.abs()).collect();
+    magnitudes.sort_by(|a, b| a.total_cmp(b));
+    let index = ((magnitudes.len() as f64 * 0.99) as usize).min(magnitudes.len().saturating_sub(1));
+    magnitudes.get(index).copied().unwrap_or(0.0).max(1e-9)
+}
+
+// Colours a map for viewing: slope from green over yellow to red at 45 degrees, aspect as a colour
+// wheel with red facing north, curvature and TPI from blue (concave, valleys) over white to red
+// (convex, ridges), and roughness from dark to bright.
+pub fn render_analysis(heightmap: &ImageBuffer<Rgba<u8>, Vec<u8>>, map: AnalysisMap, size_m: f64, height_m: f64, detail: f64) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    let (width, height) = heightmap.dimensions();
+    let values = analysis_field(heightmap, map, size_m, height_m, detail);
+    let diverging = [(-1.0, [40, 70, 200]), (0.0, [245, 245, 245]), (1.0, [200, 40, 40])];
+    let sequential = [(0.0, [30, 20, 60]), (0.5, [40, 140, 140]), (1.0, [250, 230, 80])];
+    let range = match map {
+        AnalysisMap::PlanCurvature | AnalysisMap::ProfileCurvature | AnalysisMap::Tpi | AnalysisMap::Roughness => robust_range(&values),
+        _ => 1.0,
+    };
+    ImageBuffer::from_fn(width, height, |x, y| {
+        let value = values[(y * width + x) as usize];
+        let [r, g, b] = match map {
+            AnalysisMap::None => [0, 0, 0],
+            AnalysisMap::Slope => ramp(&[(0.0, [30, 150, 40]), (15.0, [230, 220, 50]), (30.0, [240, 130, 30]), (45.0, [200, 30, 30]), (90.0, [80, 0, 0])], value),
+            AnalysisMap::Aspect => if value < 0.0 { [128, 128, 128] } else { hue(value) },
+            AnalysisMap::PlanCurvature | AnalysisMap::ProfileCurvature | AnalysisMap::Tpi => ramp(&diverging, value / range),
+            AnalysisMap::Roughness => ramp(&sequential, value / range),
+        };
+        Rgba([r, g, b, 255])
+    })
+}
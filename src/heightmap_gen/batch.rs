@@ -0,0 +1,176 @@
+use image::{imageops, ImageBuffer, Rgba};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::path::{Path, PathBuf};
+use super::cancel::CancelToken;
+use super::config::{config_to_json, parse_config, SerializedTool};
+use super::error::{HeightmapError, Result};
+use super::graph::EvalContext;
+use super::output::{arg_value, config_path, output_path, read_text, save_image, write_text};
+use super::pipeline::build_pipeline_graph;
+use super::size::{check_size, preview_size};
+
+pub const BATCH_ARG: &str = "--batch";
+pub const SEED_START_ARG: &str = "--seed-start";
+pub const CONFIG_ARG: &str = "--config";
+pub const JITTER_SCALE_ARG: &str = "--jitter-scale";
+pub const JITTER_OFFSET_ARG: &str = "--jitter-offset";
+pub const JITTER_OPACITY_ARG: &str = "--jitter-opacity";
+
+pub const MAX_VARIANTS: u32 = 256;
+const SHEET_GAP: u32 = 4;
+const SHEET_BACKGROUND: Rgba<u8> = Rgba([22, 22, 22, 255]);
+
+// How far each variant may stray from the base settings. All zero means only the seeds change.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct JitterBounds {
+    // Relative change of the noise scale of the base and every layer, 0.25 is up to ±25%
+    pub scale: f64,
+    // Added to the noise offsets, in noise units
+    pub offset: f64,
+    // Added to the layer opacities
+    pub opacity: f64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BatchOptions {
+    pub count: u32,
+    pub seed_start: u32,
+    pub jitter: JitterBounds,
+}
+
+// Reads `--batch <count>` and the other batch arguments, along with the settings the variants start
+// from: the config given with `--config <path>`, or the defaults. None when there is no `--batch`,
+// so the UI starts as usual.
+pub fn batch_from_args(args: &[String]) -> Option<Result<(SerializedTool, BatchOptions)>> {
+    let count = arg_value(args, BATCH_ARG)?;
+    let base = match arg_value(args, CONFIG_ARG) {
+        Some(path) => read_text(Path::new(&path)).and_then(|json| parse_config(&json)),
+        None => Ok(SerializedTool::default()),
+    };
+    Some(base.and_then(|base| {
+        let options = parse_batch_options(args, &count, base.seed)?;
+        Ok((base, options))
+    }))
+}
+
+fn parse_batch_options(args: &[String], count: &str, default_seed: u32) -> Result<BatchOptions> {
+    let count: u32 = parse_arg(BATCH_ARG, count)?;
+    let seed_start = match arg_value(args, SEED_START_ARG) {
+        Some(value) => parse_arg(SEED_START_ARG, &value)?,
+        None => default_seed,
+    };
+    let bound = |name: &str| -> Result<f64> {
+        match arg_value(args, name) {
+            Some(value) => parse_arg(name, &value),
+            None => Ok(0.0),
+        }
+    };
+    Ok(BatchOptions {
+        count,
+        seed_start,
+        jitter: JitterBounds { scale: bound(JITTER_SCALE_ARG)?, offset: bound(JITTER_OFFSET_ARG)?, opacity: bound(JITTER_OPACITY_ARG)? },
+    })
+}
+
+fn parse_arg<T: std::str::FromStr>(name: &str, value: &str) -> Result<T> {
+    value.trim().parse().map_err(|_| HeightmapError::Config(format!("Invalid value for {}: {}", name, value)))
+}
+
+// The settings of every variant. Variant i uses seed_start + i for the base noise and shifts the
+// layer, terrace and river seeds by the same amount, so layers keep their relation to each other.
+// The jitter is drawn from a generator seeded with the variant's seed, so a variant can be rebuilt.
+pub fn batch_variants(base: &SerializedTool, options: &BatchOptions) -> Result<Vec<SerializedTool>> {
+    if options.count == 0 {
+        return Err(HeightmapError::Config("A batch needs at least one variant".to_string()));
+    }
+    if options.count > MAX_VARIANTS {
+        return Err(HeightmapError::Config(format!("A batch can have at most {} variants, not {}", MAX_VARIANTS, options.count)));
+    }
+    Ok((0..options.count).map(|index| {
+        let seed = options.seed_start.wrapping_add(index);
+        let shift = seed.wrapping_sub(base.seed);
+        let mut rng = StdRng::seed_from_u64(seed as u64);
+        let jitter = options.jitter;
+
+        let mut variant = base.clone();
+        variant.filename = variant_name(&base.filename, index);
+        variant.seed = seed;
+        variant.scale = jitter_scale(&mut rng, base.scale, jitter.scale);
+        variant.offset_x += jitter_value(&mut rng, jitter.offset);
+        variant.offset_y += jitter_value(&mut rng, jitter.offset);
+        for layer in variant.layers.iter_mut() {
+            layer.seed = layer.seed.wrapping_add(shift);
+            layer.scale = jitter_scale(&mut rng, layer.scale, jitter.scale);
+            layer.offset_x += jitter_value(&mut rng, jitter.offset);
+            layer.offset_y += jitter_value(&mut rng, jitter.offset);
+            layer.opacity = (layer.opacity + jitter_value(&mut rng, jitter.opacity)).clamp(0.0, 1.0);
+        }
+        variant.terrace_seed = variant.terrace_seed.wrapping_add(shift);
+        variant.river_seed = variant.river_seed.wrapping_add(shift as u64);
+        variant
+    }).collect())
+}
+
+// Renders every variant at preview size, writes one config per variant and a contact sheet with the
+// variants' colormaps in reading order. Returns the path of the contact sheet.
+pub fn run_batch(base: &SerializedTool, options: &BatchOptions, dir: &Path, cancel: &CancelToken, progress: &dyn Fn(usize, usize)) -> Result<PathBuf> {
+    check_size(base.export_width, base.export_height)?;
+    let variants = batch_variants(base, options)?;
+    let (width, height) = preview_size(base.export_width, base.export_height);
+
+    let mut thumbnails = Vec::with_capacity(variants.len());
+    for (index, variant) in variants.iter().enumerate() {
+        let mut ctx = EvalContext::new(width, height);
+        ctx.cancel = cancel.clone();
+        let mut outputs = build_pipeline_graph(variant).evaluate(&ctx)?;
+        let thumbnail = outputs.remove("colormap")
+            .or_else(|| outputs.remove("heightmap"))
+            .unwrap_or_else(|| ImageBuffer::new(width, height));
+        write_text(&config_path(dir, &variant.filename), &config_to_json(variant)?)?;
+        thumbnails.push(thumbnail);
+        progress(index + 1, variants.len());
+    }
+
+    let sheet = contact_sheet(&thumbnails, width, height);
+    let sheet_path = output_path(dir, &base.filename, "sheet", "png");
+    save_image(&sheet, &sheet_path)?;
+    Ok(sheet_path)
+}
+
+// Name of a variant's files, e.g. "noise_007" so its config loads as "noise_007_config.json"
+pub fn variant_name(filename: &str, index: u32) -> String {
+    format!("{}_{:03}", filename, index + 1)
+}
+
+// Tiles the thumbnails into a roughly square grid, left to right and top to bottom
+pub fn contact_sheet(thumbnails: &[ImageBuffer<Rgba<u8>, Vec<u8>>], width: u32, height: u32) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    let count = thumbnails.len().max(1) as u32;
+    let columns = (count as f64).sqrt().ceil() as u32;
+    let rows = count.div_ceil(columns);
+    let mut sheet = ImageBuffer::from_pixel(
+        columns * (width + SHEET_GAP) + SHEET_GAP,
+        rows * (height + SHEET_GAP) + SHEET_GAP,
+        SHEET_BACKGROUND,
+    );
+    for (index, thumbnail) in thumbnails.iter().enumerate() {
+        let column = index as u32 % columns;
+        let row = index as u32 / columns;
+        let x = SHEET_GAP + column * (width + SHEET_GAP);
+        let y = SHEET_GAP + row * (height + SHEET_GAP);
+        imageops::overlay(&mut sheet, thumbnail, x as i64, y as i64);
+    }
+    sheet
+}
+
+fn jitter_value(rng: &mut StdRng, bound: f64) -> f64 {
+    if bound > 0.0 {
+        rng.gen_range(-bound..=bound)
+    } else {
+        0.0
+    }
+}
+
+fn jitter_scale(rng: &mut StdRng, scale: f64, bound: f64) -> f64 {
+    (scale * (1.0 + jitter_value(rng, bound.min(0.99)))).max(0.0001)
+}
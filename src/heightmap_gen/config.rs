@@ -0,0 +1,300 @@
+use serde_derive::{Serialize, Deserialize};
+use serde_json::Value;
+use super::error::{HeightmapError, Result};
+use super::sculpt::Stroke;
+use super::engine::EnginePreset;
+use super::scatter::ScatterRule;
+use super::roads::{Road, RoadParams};
+use super::contours::ContourParams;
+use super::analysis::AnalysisMap;
+use super::watershed::WatershedParams;
+use super::landmass::LandmassRules;
+use super::remap::NormalizeMode;
+use super::terrain::Terrain;
+use super::constants::IMAGE_SIZE;
+
+// Version 1: configs written before the version key existed, with layers stored as a JSON string
+// Version 2: layers stored as an array
+// Version 3: export size stored as width and height instead of an index into the square sizes
+// Version 4: terrain size, elevation range and sea level in metres, talus angle in degrees and
+// river depth and scatter height limits in metres instead of 0-255 steps, and a terrace jitter
+// scale of its own
+pub const CONFIG_VERSION: u32 = 4;
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(default)]
+pub struct Layers {
+    pub scale: f64,
+    pub offset_x: f64,
+    pub offset_y: f64,
+    pub seed: u32,
+    pub opacity: f64,
+    pub blend_mode: i32,
+    // Heightmap file used instead of noise when not empty
+    pub import_path: String,
+}
+
+impl Default for Layers {
+    fn default() -> Self {
+        Layers {
+            scale: 0.05,
+            offset_x: 0.0,
+            offset_y: 0.0,
+            seed: 1,
+            opacity: 1.0,
+            blend_mode: 0,
+            import_path: String::new(),
+        }
+    }
+}
+
+// New fields need a default here so older config files keep loading
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(default)]
+pub struct SerializedTool {
+    pub version: u32,
+    pub scale: f64,
+    pub offset_x: f64,
+    pub offset_y: f64,
+    pub seed: u32,
+    pub base_import: String,
+    pub layers: Vec<Layers>,
+    // Real-world size and elevations, which the erosion, maps and exports are measured in
+    pub terrain: Terrain,
+    pub erosion_mode: i32,
+    pub erosion_iterations: usize,
+    // Steepest slope in degrees that thermal erosion leaves standing
+    pub talus_angle: f32,
+    // Flattens everything below the terrain's sea level
+    pub flatten_enabled: bool,
+    pub calculate_rivers: bool,
+    pub river_iterations: usize,
+    pub river_depth_m: f64,
+    pub river_amount: usize,
+    pub river_seed: u64,
+    pub filename: String,
+    pub export_width: u32,
+    pub export_height: u32,
+    pub export_filter: u32,
+    pub export_resample: bool,
+    pub dem_export_asc: bool,
+    pub dem_export_xyz: bool,
+    pub dem_water_nodata: bool,
+    pub engine_preset: EnginePreset,
+    pub tile_export: bool,
+    pub tile_size: u32,
+    pub as_water: bool,
+    // Spreads the blended layers over the full height range, before the remap filters
+    pub normalize_mode: NormalizeMode,
+    pub normalize_clip_percent: f32,
+    pub curve_enabled: bool,
+    pub curve_points: Vec<f32>,
+    pub levels_enabled: bool,
+    pub levels_in_black: u8,
+    pub levels_in_white: u8,
+    pub levels_gamma: f32,
+    pub levels_out_black: u8,
+    pub levels_out_white: u8,
+    pub terrace_enabled: bool,
+    pub terrace_steps: u32,
+    pub terrace_sharpness: f32,
+    pub terrace_jitter: f32,
+    // Scale of the jitter noise, independent of the base noise so the terrace edges stay put
+    pub terrace_noise_scale: f64,
+    pub terrace_seed: u32,
+    // Brush strokes, replayed after the remap filters and before erosion
+    pub strokes: Vec<Stroke>,
+    // Object placement rules, evaluated on the finished terrain
+    pub scatter_rules: Vec<ScatterRule>,
+    pub scatter_overlay: bool,
+    pub scatter_export: bool,
+    // Roads carved after erosion and rivers
+    pub roads: Vec<Road>,
+    pub road_params: RoadParams,
+    pub road_export: bool,
+    // Topographic map written as <filename>_topo.png, optionally shown instead of the colormap
+    pub contour_enabled: bool,
+    pub contour_params: ContourParams,
+    pub contour_preview: bool,
+    pub contour_export_vector: bool,
+    // Analysis map shown instead of the colormap; all of them are written as <filename>_<map>.png on export
+    pub analysis_view: AnalysisMap,
+    pub analysis_export: bool,
+    // Drainage basins of the finished terrain, written as <filename>_basins.png, _basin_ids.png and _basins.json
+    pub watershed_enabled: bool,
+    pub watershed_params: WatershedParams,
+    pub watershed_preview: bool,
+    // Island and lake clean-up right after the flatten stage, which sets the sea level
+    pub landmass_rules: LandmassRules,
+    pub landmass_export: bool,
+}
+
+impl Default for SerializedTool {
+    fn default() -> Self {
+        SerializedTool {
+            version: CONFIG_VERSION,
+            scale: 0.05,
+            offset_x: 0.0,
+            offset_y: 0.0,
+            seed: 1,
+            base_import: String::new(),
+            layers: Vec::new(),
+            terrain: Terrain::default(),
+            erosion_mode: 0,
+            erosion_iterations: 5,
+            talus_angle: 35.0,
+            flatten_enabled: false,
+            calculate_rivers: false,
+            river_iterations: 1,
+            river_depth_m: 2.0,
+            river_amount: 1,
+            river_seed: 1,
+            filename: "noise".to_string(),
+            export_width: 256,
+            export_height: 256,
+            export_filter: 4,
+            export_resample: false,
+            dem_export_asc: false,
+            dem_export_xyz: false,
+            dem_water_nodata: false,
+            engine_preset: EnginePreset::None,
+            tile_export: false,
+            tile_size: 505,
+            as_water: false,
+            normalize_mode: NormalizeMode::Off,
+            normalize_clip_percent: 1.0,
+            curve_enabled: false,
+            curve_points: vec![0.0, 0.25, 0.5, 0.75, 1.0],
+            levels_enabled: false,
+            levels_in_black: 0,
+            levels_in_white: 255,
+            levels_gamma: 1.0,
+            levels_out_black: 0,
+            levels_out_white: 255,
+            terrace_enabled: false,
+            terrace_steps: 8,
+            terrace_sharpness: 0.5,
+            terrace_jitter: 0.0,
+            terrace_noise_scale: 0.05,
+            terrace_seed: 1,
+            strokes: Vec::new(),
+            scatter_rules: Vec::new(),
+            scatter_overlay: true,
+            scatter_export: false,
+            roads: Vec::new(),
+            road_params: RoadParams::default(),
+            road_export: false,
+            contour_enabled: false,
+            contour_params: ContourParams::default(),
+            contour_preview: true,
+            contour_export_vector: false,
+            analysis_view: AnalysisMap::None,
+            analysis_export: false,
+            watershed_enabled: false,
+            watershed_params: WatershedParams::default(),
+            watershed_preview: true,
+            landmass_rules: LandmassRules::default(),
+            landmass_export: false,
+        }
+    }
+}
+
+pub fn config_to_json(config: &SerializedTool) -> Result<String> {
+    Ok(serde_json::to_string_pretty(config)?)
+}
+
+// Parses a config of any known version, upgrading it to the current one
+pub fn parse_config(json: &str) -> Result<SerializedTool> {
+    let mut value: Value = serde_json::from_str(json)?;
+    if !value.is_object() {
+        return Err(HeightmapError::Config("Config is not a JSON object".to_string()));
+    }
+    let version = match value.get("version") {
+        Some(version) => version.as_u64().ok_or_else(|| HeightmapError::Config("Config version is not a number".to_string()))? as u32,
+        None => 1,
+    };
+    if version > CONFIG_VERSION {
+        return Err(HeightmapError::Config(format!("Config version {} is newer than the supported version {}", version, CONFIG_VERSION)));
+    }
+
+    if version < 2 {
+        migrate_v1_to_v2(&mut value)?;
+    }
+    if version < 3 {
+        migrate_v2_to_v3(&mut value);
+    }
+    if version < 4 {
+        migrate_v3_to_v4(&mut value);
+    }
+
+    value["version"] = Value::from(CONFIG_VERSION);
+    Ok(serde_json::from_value(value)?)
+}
+
+fn migrate_v1_to_v2(value: &mut Value) -> Result<()> {
+    if let Some(Value::String(layers)) = value.get("layers") {
+        let layers: Value = serde_json::from_str(layers)?;
+        value["layers"] = layers;
+    }
+    Ok(())
+}
+
+// The old export scale was an index into 256, 512, 1024, 2048 and 4096
+fn migrate_v2_to_v3(value: &mut Value) {
+    let index = value.get("export_scale").and_then(Value::as_u64).unwrap_or(0);
+    let size = if index <= 4 { 256u32 << index } else { 256 };
+    if let Some(object) = value.as_object_mut() {
+        object.remove("export_scale");
+        object.insert("export_width".to_string(), Value::from(size));
+        object.insert("export_height".to_string(), Value::from(size));
+    }
+}
+
+// Version 3 kept the terrain size and height for the engine export, a separate elevation range for
+// the DEM export and everything else in 0-255 steps. Configs that wrote DEMs but no engine files
+// keep the DEM range, all others the engine height. The talus angle and river depth are converted
+// so the erosion stays the same, and the scatter height limits become elevations.
+fn migrate_v3_to_v4(value: &mut Value) {
+    let number = |key: &str, default: f64| value.get(key).and_then(Value::as_f64).unwrap_or(default);
+    let flag = |key: &str| value.get(key).and_then(Value::as_bool).unwrap_or(false);
+    let size_m = number("terrain_size_m", 1000.0);
+    let dem_only = (flag("dem_export_asc") || flag("dem_export_xyz")) && value.get("engine_preset").and_then(Value::as_str).unwrap_or("None") == "None";
+    let (min_elevation_m, max_elevation_m) = if dem_only {
+        (number("dem_min_elevation", 0.0), number("dem_max_elevation", 255.0))
+    } else {
+        (0.0, number("terrain_height_m", 500.0))
+    };
+    let mut terrain = Terrain { world_size_km: size_m / 1000.0, min_elevation_m, max_elevation_m, sea_level_m: 0.0 };
+    terrain.sea_level_m = terrain.elevation_m(number("ground_level", 0.0));
+    // The old talus angle was measured on the preview
+    let talus_angle = terrain.slope_angle(number("talus_angle", 0.01), IMAGE_SIZE, IMAGE_SIZE);
+    let river_depth_m = number("erosion_factor", 1.0) / 255.0 * terrain.relief_m();
+    let terrace_noise_scale = number("scale", 0.05);
+    let scatter_rules: Option<Vec<Value>> = value.get("scatter_rules").and_then(Value::as_array).map(|rules| {
+        rules.iter().cloned().map(|mut rule| {
+            let limit = |key: &str, default: f64| terrain.elevation_m(rule.get(key).and_then(Value::as_f64).unwrap_or(default));
+            let (elevation_min_m, elevation_max_m) = (limit("height_min", 0.0), limit("height_max", 200.0));
+            if let Some(rule) = rule.as_object_mut() {
+                rule.remove("height_min");
+                rule.remove("height_max");
+                rule.insert("elevation_min_m".to_string(), Value::from(elevation_min_m));
+                rule.insert("elevation_max_m".to_string(), Value::from(elevation_max_m));
+            }
+            rule
+        }).collect()
+    });
+
+    if let Some(object) = value.as_object_mut() {
+        for key in ["terrain_size_m", "terrain_height_m", "ground_level", "erosion_factor", "dem_cell_size", "dem_min_elevation", "dem_max_elevation"] {
+            object.remove(key);
+        }
+        object.insert("terrain".to_string(), serde_json::to_value(terrain).unwrap_or_default());
+        object.insert("talus_angle".to_string(), Value::from(talus_angle));
+        object.insert("river_depth_m".to_string(), Value::from(river_depth_m));
+        // The jitter used to follow the base noise scale
+        object.insert("terrace_noise_scale".to_string(), Value::from(terrace_noise_scale));
+        if let Some(scatter_rules) = scatter_rules {
+            object.insert("scatter_rules".to_string(), Value::from(scatter_rules));
+        }
+    }
+}
@@ -0,0 +1,439 @@
+use image::{ImageBuffer, Rgba};
+use serde_derive::{Serialize, Deserialize};
+use serde_json::json;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use super::error::{check_same_size, Result};
+use super::output::{output_path, write_text};
+use super::terrain::Terrain;
+
+const CONTOUR_COLOR: Rgba<u8> = Rgba([110, 70, 35, 255]);
+const INDEX_COLOR: Rgba<u8> = Rgba([70, 40, 15, 255]);
+const LABEL_COLOR: Rgba<u8> = Rgba([20, 20, 20, 255]);
+const HALO_COLOR: Rgba<u8> = Rgba([255, 255, 255, 255]);
+// Highest peaks that get a spot height, so labels don't cover the map
+pub const MAX_SPOT_HEIGHTS: usize = 24;
+
+// Digits 0-9 as 3x5 bitmaps, one row per entry with the high bit on the left
+const DIGITS: [[u8; 5]; 10] = [
+    [0b111, 0b101, 0b101, 0b101, 0b111],
+    [0b010, 0b110, 0b010, 0b010, 0b111],
+    [0b111, 0b001, 0b111, 0b100, 0b111],
+    [0b111, 0b001, 0b111, 0b001, 0b111],
+    [0b101, 0b101, 0b111, 0b001, 0b001],
+    [0b111, 0b100, 0b111, 0b001, 0b111],
+    [0b111, 0b100, 0b111, 0b101, 0b111],
+    [0b111, 0b001, 0b010, 0b010, 0b010],
+    [0b111, 0b101, 0b111, 0b101, 0b111],
+    [0b111, 0b101, 0b111, 0b001, 0b111],
+];
+// For spot heights below zero
+const MINUS: [u8; 5] = [0b000, 0b000, 0b111, 0b000, 0b000];
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+pub enum ContourBase {
+    #[default]
+    Color,
+    Hillshade,
+    // The colormap darkened by the hillshade
+    ShadedColor,
+}
+
+impl ContourBase {
+    pub fn from_index(index: i32) -> ContourBase {
+        match index {
+            1 => ContourBase::Hillshade,
+            2 => ContourBase::ShadedColor,
+            _ => ContourBase::Color,
+        }
+    }
+
+    pub fn index(&self) -> i32 {
+        match self {
+            ContourBase::Color => 0,
+            ContourBase::Hillshade => 1,
+            ContourBase::ShadedColor => 2,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(default)]
+pub struct ContourParams {
+    pub interval_m: f64,
+    // Every nth line is an index line, drawn thicker and darker
+    pub index_every: u32,
+    pub base: ContourBase,
+    pub spot_heights: bool,
+}
+
+impl Default for ContourParams {
+    fn default() -> Self {
+        ContourParams {
+            interval_m: 10.0,
+            index_every: 5,
+            base: ContourBase::Color,
+            spot_heights: true,
+        }
+    }
+}
+
+// One traced line, in pixel coordinates with pixel centres at .5
+#[derive(Clone, PartialEq, Debug)]
+pub struct ContourLine {
+    pub elevation_m: f64,
+    pub index: bool,
+    pub closed: bool,
+    pub points: Vec<[f64; 2]>,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct SpotHeight {
+    pub x: u32,
+    pub y: u32,
+    pub elevation_m: f64,
+}
+
+// Edges of the grid between pixel centres: horizontal edges have even keys, vertical ones odd keys
+fn horizontal_edge(width: u32, x: u32, y: u32) -> u64 {
+    (y as u64 * width as u64 + x as u64) * 2
+}
+
+fn vertical_edge(width: u32, x: u32, y: u32) -> u64 {
+    (y as u64 * width as u64 + x as u64) * 2 + 1
+}
+
+// Traces the lines of every multiple of the interval above the lowest elevation with marching squares
+pub fn contour_lines(heightmap: &ImageBuffer<Rgba<u8>, Vec<u8>>, params: &ContourParams, terrain: &Terrain) -> Vec<ContourLine> {
+    let (width, height) = heightmap.dimensions();
+    let mut lines = Vec::new();
+    if width < 2 || height < 2 || params.interval_m <= 0.0 {
+        return lines;
+    }
+    let value = |x: u32, y: u32| terrain.elevation_m(heightmap.get_pixel(x, y)[0] as f64);
+    let first = (terrain.min_elevation_m / params.interval_m).floor() as i64 + 1;
+    let last = (terrain.elevation_m(255.0) / params.interval_m).floor() as i64;
+    let index_every = params.index_every.max(1) as i64;
+
+    for step in first..=last {
+        let level = step as f64 * params.interval_m;
+        // The point where the line crosses each edge, and the segments between them
+        let mut points: HashMap<u64, [f64; 2]> = HashMap::new();
+        let mut segments: Vec<(u64, u64)> = Vec::new();
+        let mut crossing = |edge: u64, a: ([f64; 2], f64), b: ([f64; 2], f64)| {
+            points.entry(edge).or_insert_with(|| {
+                let t = ((level - a.1) / (b.1 - a.1)).clamp(0.0, 1.0);
+                [a.0[0] + (b.0[0] - a.0[0]) * t, a.0[1] + (b.0[1] - a.0[1]) * t]
+            });
+            edge
+        };
+
+        for y in 0..height - 1 {
+            for x in 0..width - 1 {
+                let corners = [value(x, y), value(x + 1, y), value(x + 1, y + 1), value(x, y + 1)];
+                let case = corners.iter().fold(0, |case, &corner| case * 2 + (corner >= level) as u8);
+                if case == 0 || case == 15 {
+                    continue;
+                }
+                let (fx, fy) = (x as f64 + 0.5, y as f64 + 0.5);
+                let top_left = ([fx, fy], corners[0]);
+                let top_right = ([fx + 1.0, fy], corners[1]);
+                let bottom_right = ([fx + 1.0, fy + 1.0], corners[2]);
+                let bottom_left = ([fx, fy + 1.0], corners[3]);
+                let top = crossing(horizontal_edge(width, x, y), top_left, top_right);
+                let right = crossing(vertical_edge(width, x + 1, y), top_right, bottom_right);
+                let bottom = crossing(horizontal_edge(width, x, y + 1), bottom_left, bottom_right);
+                let left = crossing(vertical_edge(width, x, y), top_left, bottom_left);
+                let centre_inside = corners.iter().sum::<f64>() / 4.0 >= level;
+                let cell_segments: &[(u64, u64)] = match case {
+                    1 | 14 => &[(left, bottom)],
+                    2 | 13 => &[(bottom, right)],
+                    3 | 12 => &[(left, right)],
+                    4 | 11 => &[(top, right)],
+                    6 | 9 => &[(top, bottom)],
+                    7 | 8 => &[(left, top)],
+                    // Saddles: the centre decides which corners are connected
+                    5 if centre_inside => &[(left, top), (bottom, right)],
+                    5 => &[(top, right), (left, bottom)],
+                    10 if centre_inside => &[(top, right), (left, bottom)],
+                    _ => &[(left, top), (bottom, right)],
+                };
+                segments.extend_from_slice(cell_segments);
+            }
+        }
+
+        let index = step.rem_euclid(index_every) == 0;
+        lines.extend(chain_segments(&segments, &points).into_iter().map(|(points, closed)| ContourLine { elevation_m: level, index, closed, points }));
+    }
+    lines
+}
+
+// Joins segments that share an edge into polylines. Every edge point belongs to at most two segments.
+fn chain_segments(segments: &[(u64, u64)], points: &HashMap<u64, [f64; 2]>) -> Vec<(Vec<[f64; 2]>, bool)> {
+    let mut by_edge: HashMap<u64, Vec<usize>> = HashMap::new();
+    for (index, &(a, b)) in segments.iter().enumerate() {
+        by_edge.entry(a).or_default().push(index);
+        by_edge.entry(b).or_default().push(index);
+    }
+    let mut used = vec![false; segments.len()];
+    let mut chains = Vec::new();
+
+    // Open lines start at an edge with one segment, at the border; closed lines are left after that
+    let mut starts: Vec<usize> = (0..segments.len()).filter(|&index| {
+        let (a, b) = segments[index];
+        by_edge[&a].len() == 1 || by_edge[&b].len() == 1
+    }).collect();
+    starts.extend(0..segments.len());
+
+    for start in starts {
+        if used[start] {
+            continue;
+        }
+        used[start] = true;
+        let (a, b) = segments[start];
+        // Walk away from the loose end if there is one
+        let (first, mut current) = if by_edge[&b].len() == 1 && by_edge[&a].len() != 1 { (b, a) } else { (a, b) };
+        let mut chain = vec![first, current];
+        loop {
+            let next = by_edge[&current].iter().copied().find(|&index| !used[index]);
+            match next {
+                Some(index) => {
+                    used[index] = true;
+                    let (a, b) = segments[index];
+                    current = if a == current { b } else { a };
+                    chain.push(current);
+                }
+                None => break,
+            }
+        }
+        let closed = chain.len() > 2 && chain.first() == chain.last();
+        chains.push((chain.iter().map(|edge| points[edge]).collect(), closed));
+    }
+    chains
+}
+
+// Highest peaks, at least a sixteenth of the long side apart. Heights are whole steps, so a peak is
+// often a flat top of several pixels; it counts once, at the pixel closest to its middle.
+pub fn spot_heights(heightmap: &ImageBuffer<Rgba<u8>, Vec<u8>>, terrain: &Terrain) -> Vec<SpotHeight> {
+    let (width, height) = heightmap.dimensions();
+    let value = |x: u32, y: u32| heightmap.get_pixel(x, y)[0];
+    let neighbours = |x: u32, y: u32| {
+        let (x, y) = (x as i64, y as i64);
+        (-1..=1).flat_map(move |dy| (-1..=1).map(move |dx| (x + dx, y + dy)))
+            .filter(move |&(nx, ny)| (nx, ny) != (x, y) && nx >= 0 && ny >= 0 && nx < width as i64 && ny < height as i64)
+            .map(|(nx, ny)| (nx as u32, ny as u32))
+    };
+    let mut visited = vec![false; (width * height) as usize];
+    let mut candidates = Vec::new();
+    for y in 0..height {
+        for x in 0..width {
+            let top = value(x, y);
+            if visited[(y * width + x) as usize] || top == 0 || neighbours(x, y).any(|(nx, ny)| value(nx, ny) > top) {
+                continue;
+            }
+            // Collect the flat top and check that nothing around it is higher
+            let mut region = vec![(x, y)];
+            let mut is_peak = true;
+            visited[(y * width + x) as usize] = true;
+            let mut next = 0;
+            while next < region.len() {
+                let (rx, ry) = region[next];
+                next += 1;
+                for (nx, ny) in neighbours(rx, ry) {
+                    let neighbour = value(nx, ny);
+                    if neighbour > top {
+                        is_peak = false;
+                    } else if neighbour == top && !visited[(ny * width + nx) as usize] {
+                        visited[(ny * width + nx) as usize] = true;
+                        region.push((nx, ny));
+                    }
+                }
+            }
+            // A completely flat image has no peak
+            if !is_peak || region.len() as u64 == width as u64 * height as u64 {
+                continue;
+            }
+            let count = region.len() as f64;
+            let (mean_x, mean_y) = region.iter().fold((0.0, 0.0), |(sx, sy), &(rx, ry)| (sx + rx as f64 / count, sy + ry as f64 / count));
+            let &(px, py) = region.iter()
+                .min_by(|a, b| {
+                    let distance = |p: &(u32, u32)| (p.0 as f64 - mean_x).powi(2) + (p.1 as f64 - mean_y).powi(2);
+                    distance(a).total_cmp(&distance(b))
+                })
+                .unwrap_or(&(x, y));
+            candidates.push((top, px, py));
+        }
+    }
+    candidates.sort_by(|a, b| b.0.cmp(&a.0).then(a.2.cmp(&b.2)).then(a.1.cmp(&b.1)));
+
+    let spacing = (width.max(height) as f64 / 16.0).max(4.0);
+    let mut peaks: Vec<SpotHeight> = Vec::new();
+    for (peak, x, y) in candidates {
+        if peaks.len() >= MAX_SPOT_HEIGHTS {
+            break;
+        }
+        let far_enough = peaks.iter().all(|other| ((other.x as f64 - x as f64).powi(2) + (other.y as f64 - y as f64).powi(2)).sqrt() >= spacing);
+        if far_enough {
+            peaks.push(SpotHeight { x, y, elevation_m: terrain.elevation_m(peak as f64) });
+        }
+    }
+    peaks
+}
+
+// Gray relief lit from the north west at 45 degrees, like most printed maps
+pub fn hillshade(heightmap: &ImageBuffer<Rgba<u8>, Vec<u8>>, size_m: f64, height_m: f64) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    let (width, height) = heightmap.dimensions();
+    let pixel_size = size_m / width.max(height) as f64;
+    let value = |x: u32, y: u32| heightmap.get_pixel(x, y)[0] as f64 / 255.0 * height_m;
+    let (azimuth, altitude) = (315f64.to_radians(), 45f64.to_radians());
+    // Direction towards the light, with y pointing down the image
+    let light = [azimuth.sin() * altitude.cos(), -azimuth.cos() * altitude.cos(), altitude.sin()];
+    ImageBuffer::from_fn(width, height, |x, y| {
+        let (left, right) = (x.saturating_sub(1), (x + 1).min(width - 1));
+        let (up, down) = (y.saturating_sub(1), (y + 1).min(height - 1));
+        let dx = (value(right, y) - value(left, y)) / ((right - left).max(1) as f64 * pixel_size);
+        let dy = (value(x, down) - value(x, up)) / ((down - up).max(1) as f64 * pixel_size);
+        let length = (dx * dx + dy * dy + 1.0).sqrt();
+        let shade = ((-dx * light[0] - dy * light[1] + light[2]) / length).clamp(0.0, 1.0);
+        let gray = (shade * 255.0).round() as u8;
+        Rgba([gray, gray, gray, 255])
+    })
+}
+
+fn stamp(image: &mut ImageBuffer<Rgba<u8>, Vec<u8>>, cx: f64, cy: f64, radius: f64, color: Rgba<u8>) {
+    let (width, height) = image.dimensions();
+    let min_x = (cx - radius).floor().max(0.0) as u32;
+    let min_y = (cy - radius).floor().max(0.0) as u32;
+    let max_x = ((cx + radius).ceil().max(0.0) as u32).min(width);
+    let max_y = ((cy + radius).ceil().max(0.0) as u32).min(height);
+    for y in min_y..max_y {
+        for x in min_x..max_x {
+            if (x as f64 + 0.5 - cx).powi(2) + (y as f64 + 0.5 - cy).powi(2) <= radius * radius {
+                image.put_pixel(x, y, color);
+            }
+        }
+    }
+}
+
+fn draw_line(image: &mut ImageBuffer<Rgba<u8>, Vec<u8>>, points: &[[f64; 2]], thickness: f64, color: Rgba<u8>) {
+    let radius = (thickness / 2.0).max(0.5);
+    for pair in points.windows(2) {
+        let (a, b) = (pair[0], pair[1]);
+        let length = ((b[0] - a[0]).powi(2) + (b[1] - a[1]).powi(2)).sqrt();
+        let steps = (length * 2.0).ceil().max(1.0) as u32;
+        for step in 0..=steps {
+            let t = step as f64 / steps as f64;
+            stamp(image, a[0] + (b[0] - a[0]) * t, a[1] + (b[1] - a[1]) * t, radius, color);
+        }
+    }
+}
+
+// Writes a number with the built-in digits, centred on x, with a halo so it stays readable
+fn draw_label(image: &mut ImageBuffer<Rgba<u8>, Vec<u8>>, text: &str, x: i64, y: i64, scale: u32) {
+    let (width, height) = image.dimensions();
+    let digits: Vec<&[u8; 5]> = text.chars().filter_map(|c| if c == '-' { Some(&MINUS) } else { c.to_digit(10).map(|d| &DIGITS[d as usize]) }).collect();
+    let scale = scale.max(1) as i64;
+    let advance = 4 * scale;
+    let left = x - (digits.len() as i64 * advance - scale) / 2;
+    for (pass, color) in [(0, HALO_COLOR), (1, LABEL_COLOR)] {
+        for (i, digit) in digits.iter().enumerate() {
+            for (row, bits) in digit.iter().enumerate() {
+                for column in 0..3 {
+                    if bits & (0b100 >> column) == 0 {
+                        continue;
+                    }
+                    let (px, py) = (left + i as i64 * advance + column * scale, y + row as i64 * scale);
+                    let grow = if pass == 0 { 1 } else { 0 };
+                    for sy in py - grow..py + scale + grow {
+                        for sx in px - grow..px + scale + grow {
+                            if sx >= 0 && sy >= 0 && sx < width as i64 && sy < height as i64 {
+                                image.put_pixel(sx as u32, sy as u32, color);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+// The base map with the contour lines and spot heights drawn over it. Line widths and labels grow
+// with the detail, so exports look like the preview.
+pub fn render_topographic(heightmap: &ImageBuffer<Rgba<u8>, Vec<u8>>, colormap: &ImageBuffer<Rgba<u8>, Vec<u8>>, params: &ContourParams, terrain: &Terrain, detail: f64) -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>> {
+    check_same_size(heightmap.dimensions(), colormap.dimensions())?;
+    let (size_m, height_m) = (terrain.size_m(), terrain.relief_m());
+    let mut image = match params.base {
+        ContourBase::Color => colormap.clone(),
+        ContourBase::Hillshade => hillshade(heightmap, size_m, height_m),
+        ContourBase::ShadedColor => {
+            let mut shaded = hillshade(heightmap, size_m, height_m);
+            for (pixel, color) in shaded.pixels_mut().zip(colormap.pixels()) {
+                let light = 0.4 + 0.6 * pixel[0] as f64 / 255.0;
+                *pixel = Rgba([(color[0] as f64 * light) as u8, (color[1] as f64 * light) as u8, (color[2] as f64 * light) as u8, 255]);
+            }
+            shaded
+        }
+    };
+    let detail = detail.max(1.0);
+    for line in contour_lines(heightmap, params, terrain) {
+        let (thickness, color) = if line.index { (2.0 * detail, INDEX_COLOR) } else { (detail, CONTOUR_COLOR) };
+        draw_line(&mut image, &line.points, thickness, color);
+    }
+    if params.spot_heights {
+        let scale = detail.round() as u32;
+        for peak in spot_heights(heightmap, terrain) {
+            let (x, y) = (peak.x as f64 + 0.5, peak.y as f64 + 0.5);
+            stamp(&mut image, x, y, 1.5 * detail, LABEL_COLOR);
+            draw_label(&mut image, &format!("{}", peak.elevation_m.round()), x as i64, (y + 3.0 * detail) as i64, scale);
+        }
+    }
+    Ok(image)
+}
+
+fn svg_points(points: &[[f64; 2]]) -> String {
+    points.iter().map(|point| format!("{:.2},{:.2}", point[0], point[1])).collect::<Vec<_>>().join(" ")
+}
+
+// Writes the lines and spot heights as <filename>_contours.svg in pixel coordinates and as
+// <filename>_contours.geojson in metres with north up. Returns the written paths.
+pub fn export_contours(heightmap: &ImageBuffer<Rgba<u8>, Vec<u8>>, params: &ContourParams, dir: &Path, filename: &str, terrain: &Terrain) -> Result<Vec<PathBuf>> {
+    let (width, height) = heightmap.dimensions();
+    let lines = contour_lines(heightmap, params, terrain);
+    let peaks = if params.spot_heights { spot_heights(heightmap, terrain) } else { Vec::new() };
+
+    let mut svg = format!("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{0}\" height=\"{1}\" viewBox=\"0 0 {0} {1}\">\n", width, height);
+    for line in lines.iter() {
+        let (stroke, stroke_width) = if line.index { ("#46280f", 2) } else { ("#6e4623", 1) };
+        let element = if line.closed { "polygon" } else { "polyline" };
+        svg.push_str(&format!(
+            "  <{} data-elevation=\"{}\" points=\"{}\" fill=\"none\" stroke=\"{}\" stroke-width=\"{}\"/>\n",
+            element, line.elevation_m, svg_points(&line.points), stroke, stroke_width
+        ));
+    }
+    for peak in peaks.iter() {
+        let (x, y) = (peak.x as f64 + 0.5, peak.y as f64 + 0.5);
+        svg.push_str(&format!("  <circle cx=\"{:.1}\" cy=\"{:.1}\" r=\"1.5\" fill=\"#141414\"/>\n", x, y));
+        svg.push_str(&format!("  <text x=\"{:.1}\" y=\"{:.1}\" font-size=\"8\" text-anchor=\"middle\" fill=\"#141414\">{}</text>\n", x, y + 10.0, peak.elevation_m.round()));
+    }
+    svg.push_str("</svg>\n");
+    let svg_path = output_path(dir, filename, "contours", "svg");
+    write_text(&svg_path, &svg)?;
+
+    let pixel_size = terrain.pixel_size_m(width, height);
+    let extent_y = height as f64 * pixel_size;
+    let to_metres = |point: &[f64; 2]| [point[0] * pixel_size, extent_y - point[1] * pixel_size];
+    let mut features: Vec<serde_json::Value> = lines.iter().map(|line| json!({
+        "type": "Feature",
+        "properties": { "elevation": line.elevation_m, "index": line.index },
+        "geometry": { "type": "LineString", "coordinates": line.points.iter().map(to_metres).collect::<Vec<_>>() },
+    })).collect();
+    features.extend(peaks.iter().map(|peak| json!({
+        "type": "Feature",
+        "properties": { "elevation": peak.elevation_m, "spot_height": true },
+        "geometry": { "type": "Point", "coordinates": to_metres(&[peak.x as f64 + 0.5, peak.y as f64 + 0.5]) },
+    })));
+    let geojson = json!({ "type": "FeatureCollection", "features": features });
+    let geojson_path = output_path(dir, filename, "contours", "geojson");
+    write_text(&geojson_path, &serde_json::to_string_pretty(&geojson)?)?;
+    Ok(vec![svg_path, geojson_path])
+}
@@ -0,0 +1,248 @@
+use image::{ImageBuffer, Rgba};
+use std::path::Path;
+use super::error::{HeightmapError, Result};
+use super::output::{read_text, write_text};
+use super::size::check_size;
+
+pub const NODATA_VALUE: f32 = -9999.0;
+
+// How far an XYZ point may be from a cell centre, as a share of the cell size
+const GRID_TOLERANCE: f64 = 1e-3;
+
+// Elevation grid as GIS tools see it. Rows run from north to south like in the image,
+// (x_corner, y_corner) is the lower left corner and missing cells are NaN.
+pub struct DemGrid {
+    pub width: u32,
+    pub height: u32,
+    pub cell_size: f64,
+    pub x_corner: f64,
+    pub y_corner: f64,
+    pub values: Vec<f32>,
+}
+
+impl DemGrid {
+    pub fn get(&self, x: u32, y: u32) -> f32 {
+        self.values[(y * self.width + x) as usize]
+    }
+
+    // Smallest and largest elevation, ignoring nodata cells
+    pub fn range(&self) -> Option<(f32, f32)> {
+        self.values.iter().filter(|value| !value.is_nan()).fold(None, |range, &value| match range {
+            Some((min, max)) => Some((value.min(min), value.max(max))),
+            None => Some((value, value)),
+        })
+    }
+}
+
+// Maps the 0-255 heightmap to elevations between min_elevation and max_elevation.
+// Pixels at or below `nodata_below` are written as nodata, e.g. to mask out the sea.
+pub fn grid_from_buffer(buffer: &ImageBuffer<Rgba<u8>, Vec<u8>>, cell_size: f64, min_elevation: f64, max_elevation: f64, nodata_below: Option<u8>) -> DemGrid {
+    let (width, height) = buffer.dimensions();
+    let values = buffer.pixels().map(|pixel| {
+        match nodata_below {
+            Some(level) if pixel[0] <= level => f32::NAN,
+            _ => (min_elevation + pixel[0] as f64 / 255.0 * (max_elevation - min_elevation)) as f32,
+        }
+    }).collect();
+    DemGrid { width, height, cell_size, x_corner: 0.0, y_corner: 0.0, values }
+}
+
+// Normalises the elevations to 0-65535. Nodata cells get the lowest elevation so the terrain stays closed.
+pub fn grid_to_heightfield(grid: &DemGrid) -> ImageBuffer<image::Luma<u16>, Vec<u16>> {
+    let (min, max) = grid.range().unwrap_or((0.0, 0.0));
+    let span = if max > min { max - min } else { 1.0 };
+    ImageBuffer::from_fn(grid.width, grid.height, |x, y| {
+        let value = grid.get(x, y);
+        let normalized = if value.is_nan() { 0.0 } else { (value - min) / span };
+        image::Luma([(normalized * 65535.0).round() as u16])
+    })
+}
+
+pub fn write_ascii_grid(grid: &DemGrid, path: &Path) -> Result<()> {
+    let mut text = String::new();
+    text.push_str(&format!("ncols {}\n", grid.width));
+    text.push_str(&format!("nrows {}\n", grid.height));
+    text.push_str(&format!("xllcorner {}\n", grid.x_corner));
+    text.push_str(&format!("yllcorner {}\n", grid.y_corner));
+    text.push_str(&format!("cellsize {}\n", grid.cell_size));
+    text.push_str(&format!("NODATA_value {}\n", NODATA_VALUE));
+    for y in 0..grid.height {
+        let row: Vec<String> = (0..grid.width).map(|x| format_value(grid.get(x, y))).collect();
+        text.push_str(&row.join(" "));
+        text.push('\n');
+    }
+    write_text(path, &text)
+}
+
+// One "x y z" line per cell centre. Nodata cells are left out, as GIS tools expect.
+pub fn write_xyz(grid: &DemGrid, path: &Path) -> Result<()> {
+    let mut text = String::new();
+    for y in 0..grid.height {
+        for x in 0..grid.width {
+            let value = grid.get(x, y);
+            if value.is_nan() {
+                continue;
+            }
+            let (easting, northing) = cell_centre(grid, x, y);
+            text.push_str(&format!("{} {} {}\n", easting, northing, value));
+        }
+    }
+    write_text(path, &text)
+}
+
+pub fn read_ascii_grid(path: &Path) -> Result<DemGrid> {
+    let text = read_text(path)?;
+    let error = |message: String| HeightmapError::Import { path: path.to_path_buf(), message };
+    let mut tokens = text.split_whitespace().peekable();
+
+    let mut width = None;
+    let mut height = None;
+    let mut cell_size = None;
+    let mut cell_height = None;
+    let mut x_corner = 0.0;
+    let mut y_corner = 0.0;
+    let mut centred = false;
+    let mut nodata = None;
+
+    // The header is a list of "key value" pairs in any order, followed by the rows
+    while let Some(key) = tokens.peek().filter(|token| token.starts_with(|c: char| c.is_ascii_alphabetic())).map(|token| token.to_lowercase()) {
+        tokens.next();
+        let value = tokens.next().ok_or_else(|| error(format!("missing value for {}", key)))?;
+        let number: f64 = value.parse().map_err(|_| error(format!("{} is not a number: {}", key, value)))?;
+        match key.as_str() {
+            "ncols" => width = Some(number as u32),
+            "nrows" => height = Some(number as u32),
+            "cellsize" | "dx" => cell_size = Some(number),
+            "dy" => cell_height = Some(number),
+            "xllcorner" => x_corner = number,
+            "yllcorner" => y_corner = number,
+            "xllcenter" => { x_corner = number; centred = true; }
+            "yllcenter" => { y_corner = number; centred = true; }
+            "nodata_value" => nodata = Some(number as f32),
+            _ => return Err(error(format!("unknown header {}", key))),
+        }
+    }
+
+    let width = width.ok_or_else(|| error("ncols is missing".to_string()))?;
+    let height = height.ok_or_else(|| error("nrows is missing".to_string()))?;
+    let cell_size = cell_size.ok_or_else(|| error("cellsize is missing".to_string()))?;
+    if !(cell_size.is_finite() && cell_size > 0.0) {
+        return Err(error(format!("cellsize must be a positive number, not {}", cell_size)));
+    }
+    // The heightmap has square pixels, so cells of a different height would stretch the terrain
+    if let Some(cell_height) = cell_height {
+        if (cell_height - cell_size).abs() > cell_size * 1e-6 {
+            return Err(error(format!("cells must be square, but dx is {} and dy is {}", cell_size, cell_height)));
+        }
+    }
+    check_size(width, height)?;
+    if centred {
+        x_corner -= cell_size / 2.0;
+        y_corner -= cell_size / 2.0;
+    }
+
+    let mut values = Vec::with_capacity(width as usize * height as usize);
+    for token in tokens {
+        let value: f32 = token.parse().map_err(|_| error(format!("{} is not a number", token)))?;
+        values.push(if Some(value) == nodata { f32::NAN } else { value });
+    }
+    if values.len() != width as usize * height as usize {
+        return Err(error(format!("expected {} values but found {}", width as usize * height as usize, values.len())));
+    }
+
+    Ok(DemGrid { width, height, cell_size, x_corner, y_corner, values })
+}
+
+// XYZ files list cell centres in any order. The grid is rebuilt from the distinct coordinates
+// and cells without a point become nodata.
+pub fn read_xyz(path: &Path) -> Result<DemGrid> {
+    let text = read_text(path)?;
+    let error = |message: String| HeightmapError::Import { path: path.to_path_buf(), message };
+
+    let mut points: Vec<(f64, f64, f32)> = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let fields: Vec<&str> = line.split(|c: char| c.is_whitespace() || c == ',' || c == ';').filter(|field| !field.is_empty()).collect();
+        if fields.is_empty() {
+            continue;
+        }
+        let parsed: Option<Vec<f64>> = fields.iter().take(3).map(|field| field.parse().ok()).collect();
+        match parsed {
+            Some(values) if values.len() == 3 => {
+                let z = values[2] as f32;
+                points.push((values[0], values[1], if z == NODATA_VALUE { f32::NAN } else { z }));
+            }
+            // A header line like "x y z" is allowed at the top
+            _ if points.is_empty() && number == 0 => {}
+            _ => return Err(error(format!("line {} is not an \"x y z\" point", number + 1))),
+        }
+    }
+    if points.is_empty() {
+        return Err(error("the file contains no points".to_string()));
+    }
+
+    let xs = distinct(points.iter().map(|point| point.0));
+    let ys = distinct(points.iter().map(|point| point.1));
+    // A single row or column only gives the spacing in one direction
+    let cell_size = match (smallest_step(&xs), smallest_step(&ys)) {
+        (Some(x_step), Some(y_step)) if (x_step - y_step).abs() > x_step * 1e-6 => {
+            return Err(error(format!("cells must be square, but the points are {} apart in x and {} apart in y", x_step, y_step)));
+        }
+        (x_step, y_step) => x_step.or(y_step).unwrap_or(1.0),
+    };
+    let (min_x, max_x) = (xs[0], xs[xs.len() - 1]);
+    let (min_y, max_y) = (ys[0], ys[ys.len() - 1]);
+    // The float cast saturates, so a huge span ends up as u32::MAX and check_size rejects it
+    let width = (((max_x - min_x) / cell_size).round() as u32).saturating_add(1);
+    let height = (((max_y - min_y) / cell_size).round() as u32).saturating_add(1);
+    check_size(width, height)?;
+
+    let mut values = vec![f32::NAN; width as usize * height as usize];
+    let mut filled = vec![false; values.len()];
+    for (x, y, z) in points {
+        let column = (x - min_x) / cell_size;
+        let row = (max_y - y) / cell_size;
+        if (column - column.round()).abs() > GRID_TOLERANCE || (row - row.round()).abs() > GRID_TOLERANCE {
+            return Err(error(format!("the point {} {} is not on the {} m grid", x, y, cell_size)));
+        }
+        let index = row.round() as usize * width as usize + column.round() as usize;
+        if filled[index] {
+            return Err(error(format!("there is more than one point at {} {}", x, y)));
+        }
+        filled[index] = true;
+        values[index] = z;
+    }
+
+    Ok(DemGrid {
+        width,
+        height,
+        cell_size,
+        x_corner: min_x - cell_size / 2.0,
+        y_corner: min_y - cell_size / 2.0,
+        values,
+    })
+}
+
+fn cell_centre(grid: &DemGrid, x: u32, y: u32) -> (f64, f64) {
+    let easting = grid.x_corner + (x as f64 + 0.5) * grid.cell_size;
+    let northing = grid.y_corner + ((grid.height - y) as f64 - 0.5) * grid.cell_size;
+    (easting, northing)
+}
+
+fn format_value(value: f32) -> String {
+    if value.is_nan() {
+        NODATA_VALUE.to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+fn distinct(values: impl Iterator<Item = f64>) -> Vec<f64> {
+    let mut values: Vec<f64> = values.collect();
+    values.sort_by(|a, b| a.total_cmp(b));
+    values.dedup_by(|a, b| (*a - *b).abs() < 1e-9);
+    values
+}
+
+fn smallest_step(values: &[f64]) -> Option<f64> {
+    values.windows(2).map(|pair| pair[1] - pair[0]).filter(|step| *step > 1e-9).min_by(|a, b| a.total_cmp(b))
+}
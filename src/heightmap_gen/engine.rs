@@ -0,0 +1,169 @@
+use image::{ImageBuffer, Luma, Rgba};
+use serde_derive::{Serialize, Deserialize};
+use std::path::{Path, PathBuf};
+use super::error::Result;
+use super::output::{output_path, save_height16, write_bytes, write_text};
+use super::terrain::Terrain;
+
+// Heightmap resolutions Unity accepts for a terrain
+pub const UNITY_SIZES: [u32; 8] = [33, 65, 129, 257, 513, 1025, 2049, 4097];
+// Unreal's recommended landscape resolutions, which fill whole components without wasted quads
+pub const UNREAL_SIZES: [u32; 7] = [127, 253, 505, 1009, 2017, 4033, 8129];
+
+// At a Z scale of 100 the 16-bit range of an Unreal heightmap spans 512 metres
+const UNREAL_RANGE_AT_SCALE_100: f64 = 512.0;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+pub enum EnginePreset {
+    #[default]
+    None,
+    Unity,
+    Unreal,
+}
+
+impl EnginePreset {
+    pub fn from_index(index: i32) -> EnginePreset {
+        match index {
+            1 => EnginePreset::Unity,
+            2 => EnginePreset::Unreal,
+            _ => EnginePreset::None,
+        }
+    }
+
+    pub fn index(&self) -> i32 {
+        match self {
+            EnginePreset::None => 0,
+            EnginePreset::Unity => 1,
+            EnginePreset::Unreal => 2,
+        }
+    }
+
+    pub fn sizes(&self) -> &'static [u32] {
+        match self {
+            EnginePreset::None => &[],
+            EnginePreset::Unity => &UNITY_SIZES,
+            EnginePreset::Unreal => &UNREAL_SIZES,
+        }
+    }
+
+    pub fn is_valid_size(&self, width: u32, height: u32) -> bool {
+        *self == EnginePreset::None || (width == height && self.sizes().contains(&width))
+    }
+
+    // Both engines want square heightmaps, so the long side picks the closest allowed size
+    pub fn nearest_size(&self, width: u32, height: u32) -> (u32, u32) {
+        if self.is_valid_size(width, height) {
+            return (width, height);
+        }
+        let long_side = width.max(height);
+        let side = self.sizes().iter().copied().min_by_key(|size| size.abs_diff(long_side)).unwrap_or(long_side);
+        (side, side)
+    }
+}
+
+// What to enter in the engine's import dialog so the terrain has the intended size in metres
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct EngineSidecar {
+    pub engine: String,
+    pub heightmap: String,
+    pub format: String,
+    pub resolution: u32,
+    pub terrain_size_m: f64,
+    pub terrain_height_m: f64,
+    // Elevation of the lowest point
+    pub min_elevation_m: f64,
+    pub pixel_spacing_m: f64,
+    // Unity: Terrain Width, Length and Height
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unity_terrain_size: Option<[f64; 3]>,
+    // Unity: Y position of the terrain object that puts the lowest point at its elevation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unity_position_y: Option<f64>,
+    // Unreal: landscape scale in centimetres per pixel (X, Y) and the Z scale
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unreal_scale: Option<[f64; 3]>,
+    // Unreal: Z location that puts the lowest point at its elevation, in centimetres
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unreal_location_z_cm: Option<f64>,
+}
+
+// Heights 0-255 spread over the full 16-bit range
+pub fn to_height16(buffer: &ImageBuffer<Rgba<u8>, Vec<u8>>) -> ImageBuffer<Luma<u16>, Vec<u16>> {
+    let (width, height) = buffer.dimensions();
+    ImageBuffer::from_fn(width, height, |x, y| Luma([buffer.get_pixel(x, y)[0] as u16 * 257]))
+}
+
+// Unity reads the first row of a raw file as the south edge, so the rows are written bottom up
+// and the terrain matches the image with "Flip Vertically" off
+pub fn write_raw16(field: &ImageBuffer<Luma<u16>, Vec<u16>>, path: &Path) -> Result<()> {
+    let (width, height) = field.dimensions();
+    let mut bytes = Vec::with_capacity(width as usize * height as usize * 2);
+    for y in (0..height).rev() {
+        for x in 0..width {
+            bytes.extend_from_slice(&field.get_pixel(x, y)[0].to_le_bytes());
+        }
+    }
+    write_bytes(path, &bytes)
+}
+
+pub fn engine_sidecar(preset: EnginePreset, heightmap: &str, resolution: u32, terrain: &Terrain) -> EngineSidecar {
+    let (terrain_size_m, terrain_height_m) = (terrain.size_m(), terrain.relief_m());
+    let pixel_spacing_m = terrain_size_m / (resolution.max(2) - 1) as f64;
+    let mut sidecar = EngineSidecar {
+        engine: String::new(),
+        heightmap: heightmap.to_string(),
+        format: String::new(),
+        resolution,
+        terrain_size_m,
+        terrain_height_m,
+        min_elevation_m: terrain.min_elevation_m,
+        pixel_spacing_m,
+        unity_terrain_size: None,
+        unity_position_y: None,
+        unreal_scale: None,
+        unreal_location_z_cm: None,
+    };
+    match preset {
+        EnginePreset::Unity => {
+            sidecar.engine = "Unity".to_string();
+            sidecar.format = "RAW 16-bit, Windows byte order (little endian)".to_string();
+            sidecar.unity_terrain_size = Some([terrain_size_m, terrain_size_m, terrain_height_m]);
+            sidecar.unity_position_y = Some(terrain.min_elevation_m);
+        }
+        EnginePreset::Unreal => {
+            sidecar.engine = "Unreal".to_string();
+            sidecar.format = "PNG 16-bit grayscale".to_string();
+            let scale_z = terrain_height_m / UNREAL_RANGE_AT_SCALE_100 * 100.0;
+            sidecar.unreal_scale = Some([pixel_spacing_m * 100.0, pixel_spacing_m * 100.0, scale_z]);
+            // The middle of the 16-bit range sits at the actor's Z location
+            sidecar.unreal_location_z_cm = Some((terrain.min_elevation_m + terrain_height_m / 2.0) * 100.0);
+        }
+        EnginePreset::None => {}
+    }
+    sidecar
+}
+
+// Writes the 16-bit heightmap in the engine's format and the sidecar JSON next to it.
+// Returns the written paths.
+pub fn export_for_engine(preset: EnginePreset, heightmap: &ImageBuffer<Rgba<u8>, Vec<u8>>, dir: &Path, filename: &str, terrain: &Terrain) -> Result<Vec<PathBuf>> {
+    let field = to_height16(heightmap);
+    let height_path = match preset {
+        EnginePreset::None => return Ok(Vec::new()),
+        EnginePreset::Unity => {
+            let path = output_path(dir, filename, "unity", "raw");
+            write_raw16(&field, &path)?;
+            path
+        }
+        EnginePreset::Unreal => {
+            let path = output_path(dir, filename, "unreal", "png");
+            save_height16(&field, &path)?;
+            path
+        }
+    };
+
+    let name = height_path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    let sidecar = engine_sidecar(preset, &name, field.width(), terrain);
+    let sidecar_path = output_path(dir, filename, "engine", "json");
+    write_text(&sidecar_path, &serde_json::to_string_pretty(&sidecar)?)?;
+    Ok(vec![height_path, sidecar_path])
+}
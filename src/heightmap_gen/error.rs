@@ -0,0 +1,67 @@
+use std::fmt;
+use std::path::PathBuf;
+
+#[derive(Debug)]
+pub enum HeightmapError {
+    InvalidBlendMode(i32),
+    InvalidErosionMode(i32),
+    InvalidColorIndex(usize),
+    InvalidSize { width: u32, height: u32 },
+    ImageTooSmall { width: u32, height: u32, min: u32 },
+    SizeMismatch { expected: (u32, u32), found: (u32, u32) },
+    Graph(String),
+    Config(String),
+    ThreadPool(String),
+    Cancelled,
+    Io { path: PathBuf, source: std::io::Error },
+    Image { path: PathBuf, source: image::ImageError },
+    Import { path: PathBuf, message: String },
+    Json(serde_json::Error),
+}
+
+pub type Result<T> = std::result::Result<T, HeightmapError>;
+
+impl fmt::Display for HeightmapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HeightmapError::InvalidBlendMode(mode) => write!(f, "Invalid blend mode {}", mode),
+            HeightmapError::InvalidErosionMode(mode) => write!(f, "Invalid erosion mode {}", mode),
+            HeightmapError::InvalidColorIndex(index) => write!(f, "Color index {} is out of range", index),
+            HeightmapError::InvalidSize { width, height } => write!(f, "Invalid image size {}x{}", width, height),
+            HeightmapError::ImageTooSmall { width, height, min } => write!(f, "Image size {}x{} is too small, both sides need at least {} pixels", width, height, min),
+            HeightmapError::SizeMismatch { expected, found } => write!(f, "Expected an image of {}x{} but got {}x{}", expected.0, expected.1, found.0, found.1),
+            HeightmapError::Graph(message) => write!(f, "Graph error: {}", message),
+            HeightmapError::Config(message) => write!(f, "Config error: {}", message),
+            HeightmapError::ThreadPool(message) => write!(f, "Couldn't start worker threads: {}", message),
+            HeightmapError::Cancelled => write!(f, "Generation was cancelled"),
+            HeightmapError::Io { path, source } => write!(f, "Couldn't access {}: {}", path.display(), source),
+            HeightmapError::Image { path, source } => write!(f, "Couldn't process image {}: {}", path.display(), source),
+            HeightmapError::Import { path, message } => write!(f, "Couldn't import {}: {}", path.display(), message),
+            HeightmapError::Json(source) => write!(f, "JSON error: {}", source),
+        }
+    }
+}
+
+impl std::error::Error for HeightmapError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            HeightmapError::Io { source, .. } => Some(source),
+            HeightmapError::Image { source, .. } => Some(source),
+            HeightmapError::Json(source) => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<serde_json::Error> for HeightmapError {
+    fn from(error: serde_json::Error) -> Self {
+        HeightmapError::Json(error)
+    }
+}
+
+pub fn check_same_size(expected: (u32, u32), found: (u32, u32)) -> Result<()> {
+    if expected != found {
+        return Err(HeightmapError::SizeMismatch { expected, found });
+    }
+    Ok(())
+}
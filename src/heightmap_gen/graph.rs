@@ -0,0 +1,422 @@
+use image::{ImageBuffer, Rgba};
+use serde_derive::{Serialize, Deserialize};
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::Mutex;
+
+use super::heightmap::{generate_perlin_noise_buffer, blend_buffers, colorize_buffer, clamp_image_buffer, thermal_erosion, simulate_river_flow, generate_normal_map, mask_buffer};
+use super::remap::{apply_curve, apply_levels, apply_terrace, apply_normalize, NormalizeMode};
+use super::error::{HeightmapError, Result};
+use super::cancel::CancelToken;
+use super::import::import_heightmap;
+use super::sculpt::{apply_strokes, Stroke};
+use super::roads::{carve_roads, Road, RoadParams, RoadPath};
+use super::contours::{render_topographic, ContourParams};
+use super::analysis::{render_analysis, AnalysisMap};
+use super::landmass::{apply_landmass_rules, LandmassRules};
+use super::terrain::Terrain;
+use super::constants::IMAGE_SIZE;
+
+pub type Buffer = ImageBuffer<Rgba<u8>, Vec<u8>>;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum PortType {
+    Height,
+    Color,
+    Mask,
+    // Tangent-space normals packed into RGB, which only outputs take
+    Normal,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "type")]
+pub enum NodeKind {
+    // Generators
+    Perlin { scale: f64, offset_x: f64, offset_y: f64, opacity: f64, seed: u32 },
+    Import { path: String, opacity: f64 },
+    // Combiners
+    Blend { blend_mode: i32 },
+    // Filters
+    Normalize { mode: NormalizeMode, clip_percent: f32 },
+    Curve { points: Vec<f32> },
+    Levels { in_black: u8, in_white: u8, gamma: f32, out_black: u8, out_white: u8 },
+    Terrace { steps: u32, sharpness: f32, jitter: f32, noise_scale: f64, seed: u32 },
+    Sculpt { strokes: Vec<Stroke> },
+    Colorize { start_index: usize },
+    Flatten { as_water: bool, min: u8, max: u8 },
+    Landmass { sea_level: u8, rules: LandmassRules },
+    NormalMap { strength: f32 },
+    HeightMask { min: u8, max: u8 },
+    // Erosion. The talus angle is in degrees and the river depth in metres of the terrain.
+    ThermalErosion { iterations: usize, talus_angle: f32, erosion_mode: i32, #[serde(default)] terrain: Terrain },
+    Rivers { rain_iterations: usize, depth_m: f64, num_rivers: usize, seed: u64, #[serde(default)] terrain: Terrain },
+    // Terrain features. Roads avoid the sea and the white pixels of their mask input.
+    Roads { roads: Vec<Road>, params: RoadParams, #[serde(default)] terrain: Terrain },
+    // Maps
+    Topographic { params: ContourParams, #[serde(default)] terrain: Terrain },
+    Analysis { map: AnalysisMap, #[serde(default)] terrain: Terrain },
+    // Outputs
+    Output { name: String, port: PortType },
+}
+
+impl NodeKind {
+    pub fn inputs(&self) -> Vec<PortType> {
+        match self {
+            NodeKind::Perlin { .. } | NodeKind::Import { .. } => vec![],
+            NodeKind::Blend { .. } => vec![PortType::Height, PortType::Height],
+            NodeKind::Normalize { .. } | NodeKind::Curve { .. } | NodeKind::Levels { .. } | NodeKind::Terrace { .. } | NodeKind::Sculpt { .. } => vec![PortType::Height],
+            NodeKind::Colorize { .. } | NodeKind::NormalMap { .. } | NodeKind::HeightMask { .. } | NodeKind::Analysis { .. } => vec![PortType::Height],
+            NodeKind::Flatten { .. } | NodeKind::Landmass { .. } | NodeKind::ThermalErosion { .. } | NodeKind::Rivers { .. } => vec![PortType::Height, PortType::Color],
+            NodeKind::Topographic { .. } => vec![PortType::Height, PortType::Color],
+            NodeKind::Roads { .. } => vec![PortType::Height, PortType::Color, PortType::Mask],
+            NodeKind::Output { port, .. } => vec![*port],
+        }
+    }
+
+    pub fn outputs(&self) -> Vec<PortType> {
+        match self {
+            NodeKind::Perlin { .. } | NodeKind::Import { .. } | NodeKind::Blend { .. } => vec![PortType::Height],
+            NodeKind::Normalize { .. } | NodeKind::Curve { .. } | NodeKind::Levels { .. } | NodeKind::Terrace { .. } | NodeKind::Sculpt { .. } => vec![PortType::Height],
+            NodeKind::Colorize { .. } | NodeKind::Topographic { .. } | NodeKind::Analysis { .. } => vec![PortType::Color],
+            NodeKind::NormalMap { .. } => vec![PortType::Normal],
+            NodeKind::HeightMask { .. } => vec![PortType::Mask],
+            NodeKind::Flatten { .. } | NodeKind::Landmass { .. } | NodeKind::ThermalErosion { .. } | NodeKind::Roads { .. } => vec![PortType::Height, PortType::Color],
+            // The third output marks the river pixels
+            NodeKind::Rivers { .. } => vec![PortType::Height, PortType::Color, PortType::Mask],
+            NodeKind::Output { .. } => vec![],
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub struct Link {
+    pub node: usize,
+    pub output: usize,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Node {
+    pub id: usize,
+    pub kind: NodeKind,
+    pub inputs: Vec<Link>,
+}
+
+// Version 1: graphs written before the version key existed, with the talus angle as a slope per
+// preview pixel, river depth and map sizes in 0-255 steps and normal maps on colour ports
+// Version 2: terrain of each node in metres, talus angle in degrees and normal map ports
+// Version 3: a river mask input on roads, which used to find water by its colour
+pub const GRAPH_VERSION: u32 = 3;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Graph {
+    pub version: u32,
+    pub nodes: Vec<Node>,
+}
+
+impl Default for Graph {
+    fn default() -> Self {
+        Graph::new()
+    }
+}
+
+pub struct EvalContext {
+    pub width: u32,
+    pub height: u32,
+    // Output pixels per preview pixel. Node parameters are authored against the preview, so
+    // frequencies, slopes and iteration counts are scaled by this to keep the same look.
+    pub detail: f64,
+    pub cancel: CancelToken,
+    // Called with the finished fraction of nodes after each node
+    pub progress: Option<Box<dyn Fn(f32) + Send + Sync>>,
+    // Centre lines of the roads carved by Roads nodes, for exporting them
+    pub road_paths: Mutex<Vec<RoadPath>>,
+}
+
+impl EvalContext {
+    pub fn new(width: u32, height: u32) -> EvalContext {
+        EvalContext { width, height, detail: 1.0, cancel: CancelToken::new(), progress: None, road_paths: Mutex::new(Vec::new()) }
+    }
+}
+
+impl Graph {
+    pub fn new() -> Graph {
+        Graph { version: GRAPH_VERSION, nodes: Vec::new() }
+    }
+
+    // Adds a node and returns its id, which is used to link its outputs to other nodes
+    pub fn add_node(&mut self, kind: NodeKind, inputs: Vec<Link>) -> usize {
+        let id = self.nodes.iter().map(|node| node.id + 1).max().unwrap_or(0);
+        self.nodes.push(Node { id, kind, inputs });
+        id
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    // Older graphs are upgraded to the current version, like configs in parse_config
+    pub fn from_json(json: &str) -> Result<Graph> {
+        let mut value: Value = serde_json::from_str(json)?;
+        if !value.is_object() {
+            return Err(HeightmapError::Graph("Graph is not a JSON object".to_string()));
+        }
+        let version = match value.get("version") {
+            Some(version) => version.as_u64().ok_or_else(|| HeightmapError::Graph("Graph version is not a number".to_string()))? as u32,
+            None => 1,
+        };
+        if version > GRAPH_VERSION {
+            return Err(HeightmapError::Graph(format!("Graph version {} is newer than the supported version {}", version, GRAPH_VERSION)));
+        }
+
+        if version < 2 {
+            migrate_v1_to_v2(&mut value);
+        }
+        if version < 3 {
+            migrate_v2_to_v3(&mut value);
+        }
+
+        value["version"] = Value::from(GRAPH_VERSION);
+        let graph: Graph = serde_json::from_value(value)?;
+        graph.sorted_nodes()?;
+        Ok(graph)
+    }
+
+    fn node(&self, id: usize) -> Option<&Node> {
+        self.nodes.iter().find(|node| node.id == id)
+    }
+
+    // Checks links and port types and returns the nodes in evaluation order
+    fn sorted_nodes(&self) -> Result<Vec<&Node>> {
+        for node in &self.nodes {
+            if self.nodes.iter().filter(|other| other.id == node.id).count() > 1 {
+                return Err(HeightmapError::Graph(format!("Node id {} is used more than once", node.id)));
+            }
+            let expected = node.kind.inputs();
+            if node.inputs.len() != expected.len() {
+                return Err(HeightmapError::Graph(format!("Node {} expects {} inputs but has {}", node.id, expected.len(), node.inputs.len())));
+            }
+            for (link, port_type) in node.inputs.iter().zip(expected.iter()) {
+                let source = self.node(link.node).ok_or_else(|| HeightmapError::Graph(format!("Node {} is linked to missing node {}", node.id, link.node)))?;
+                match source.kind.outputs().get(link.output) {
+                    Some(output_type) if output_type == port_type => {}
+                    Some(output_type) => {
+                        return Err(HeightmapError::Graph(format!("Node {} expects {:?} but node {} output {} is {:?}", node.id, port_type, link.node, link.output, output_type)));
+                    }
+                    None => {
+                        return Err(HeightmapError::Graph(format!("Node {} has no output {}", link.node, link.output)));
+                    }
+                }
+            }
+        }
+
+        // Kahn's algorithm, keeping the declaration order for independent nodes
+        let mut in_degree: HashMap<usize, usize> = self.nodes.iter().map(|node| (node.id, node.inputs.len())).collect();
+        let mut sorted: Vec<&Node> = Vec::with_capacity(self.nodes.len());
+        while sorted.len() < self.nodes.len() {
+            let next = self.nodes.iter().find(|node| in_degree.get(&node.id) == Some(&0));
+            match next {
+                Some(node) => {
+                    in_degree.remove(&node.id);
+                    for other in &self.nodes {
+                        let links = other.inputs.iter().filter(|link| link.node == node.id).count();
+                        if let Some(degree) = in_degree.get_mut(&other.id) {
+                            *degree -= links;
+                        }
+                    }
+                    sorted.push(node);
+                }
+                None => {
+                    return Err(HeightmapError::Graph("Graph contains a cycle".to_string()));
+                }
+            }
+        }
+        Ok(sorted)
+    }
+
+    // Evaluates every node once and returns the buffers of all output nodes by name
+    pub fn evaluate(&self, ctx: &EvalContext) -> Result<HashMap<String, Buffer>> {
+        let mut results: HashMap<usize, Vec<Buffer>> = HashMap::new();
+        let mut outputs: HashMap<String, Buffer> = HashMap::new();
+
+        let sorted = self.sorted_nodes()?;
+        let total = sorted.len();
+        for (index, node) in sorted.into_iter().enumerate() {
+            ctx.cancel.check()?;
+            let inputs: Vec<Buffer> = node.inputs.iter().map(|link| results[&link.node][link.output].clone()).collect();
+            match &node.kind {
+                NodeKind::Output { name, .. } => {
+                    outputs.insert(name.clone(), inputs.into_iter().next().unwrap());
+                }
+                kind => {
+                    results.insert(node.id, evaluate_node(kind, inputs, ctx)?);
+                }
+            }
+            if let Some(progress) = &ctx.progress {
+                progress((index + 1) as f32 / total as f32);
+            }
+        }
+        Ok(outputs)
+    }
+}
+
+// Version 1 nodes had no terrain. Erosion was set up on the preview of the default terrain and the
+// maps and roads had their own size and height, which become their terrain.
+fn migrate_v1_to_v2(value: &mut Value) {
+    let Some(nodes) = value.get_mut("nodes").and_then(Value::as_array_mut) else {
+        return;
+    };
+    let normal_maps: HashSet<u64> = nodes.iter()
+        .filter(|node| node["kind"]["type"] == "NormalMap")
+        .filter_map(|node| node["id"].as_u64())
+        .collect();
+
+    for node in nodes.iter_mut() {
+        let feeds_normal_map = node["inputs"][0]["node"].as_u64().is_some_and(|id| normal_maps.contains(&id));
+        let Some(kind) = node.get_mut("kind").and_then(Value::as_object_mut) else {
+            continue;
+        };
+        let number = |key: &str, default: f64| kind.get(key).and_then(Value::as_f64).unwrap_or(default);
+        let terrain = Terrain::default();
+        match kind.get("type").and_then(Value::as_str).unwrap_or("") {
+            "ThermalErosion" => {
+                let talus_angle = terrain.slope_angle(number("talus_angle", 0.01), IMAGE_SIZE, IMAGE_SIZE);
+                kind.insert("talus_angle".to_string(), Value::from(talus_angle));
+                kind.insert("terrain".to_string(), serde_json::to_value(terrain).unwrap_or_default());
+            }
+            "Rivers" => {
+                let depth_m = number("erosion_factor", 1.0) / terrain.height_steps(1.0);
+                kind.remove("erosion_factor");
+                kind.insert("depth_m".to_string(), Value::from(depth_m));
+                kind.insert("terrain".to_string(), serde_json::to_value(terrain).unwrap_or_default());
+            }
+            "Roads" | "Topographic" | "Analysis" => {
+                let terrain = Terrain {
+                    world_size_km: number("size_m", terrain.size_m()) / 1000.0,
+                    min_elevation_m: 0.0,
+                    max_elevation_m: number("height_m", terrain.relief_m()),
+                    ..terrain
+                };
+                kind.remove("size_m");
+                kind.remove("height_m");
+                kind.insert("terrain".to_string(), serde_json::to_value(terrain).unwrap_or_default());
+            }
+            "Output" if feeds_normal_map => {
+                kind.insert("port".to_string(), Value::from("Normal"));
+            }
+            _ => {}
+        }
+    }
+}
+
+// Version 2 roads found water by its colour. They get a mask of everything at or below their sea
+// level, which the roads avoid anyway, as there is no river mask to link them to.
+fn migrate_v2_to_v3(value: &mut Value) {
+    let Some(nodes) = value.get_mut("nodes").and_then(Value::as_array_mut) else {
+        return;
+    };
+    let mut next_id = nodes.iter().filter_map(|node| node["id"].as_u64()).map(|id| id + 1).max().unwrap_or(0);
+    let mut masks = Vec::new();
+    for node in nodes.iter_mut() {
+        if node["kind"]["type"] != "Roads" || node["inputs"].as_array().is_none_or(|inputs| inputs.len() != 2) {
+            continue;
+        }
+        let terrain: Terrain = serde_json::from_value(node["kind"]["terrain"].clone()).unwrap_or_default();
+        masks.push(serde_json::json!({
+            "id": next_id,
+            "kind": { "type": "HeightMask", "min": 0, "max": terrain.sea_level() },
+            "inputs": [node["inputs"][0].clone()],
+        }));
+        if let Some(inputs) = node["inputs"].as_array_mut() {
+            inputs.push(serde_json::json!({ "node": next_id, "output": 0 }));
+        }
+        next_id += 1;
+    }
+    nodes.extend(masks);
+}
+
+fn evaluate_node(kind: &NodeKind, mut inputs: Vec<Buffer>, ctx: &EvalContext) -> Result<Vec<Buffer>> {
+    match kind {
+        NodeKind::Perlin { scale, offset_x, offset_y, opacity, seed } => {
+            Ok(vec![generate_perlin_noise_buffer(ctx.width, ctx.height, offset_x * ctx.detail, offset_y * ctx.detail, scale / ctx.detail, *opacity, *seed)])
+        }
+        NodeKind::Import { path, opacity } => {
+            Ok(vec![import_heightmap(Path::new(path), ctx.width, ctx.height, *opacity)?])
+        }
+        NodeKind::Blend { blend_mode } => {
+            Ok(vec![blend_buffers(&inputs[0], &inputs[1], *blend_mode)?])
+        }
+        NodeKind::Normalize { mode, clip_percent } => {
+            apply_normalize(&mut inputs[0], *mode, *clip_percent);
+            Ok(inputs)
+        }
+        NodeKind::Curve { points } => {
+            apply_curve(&mut inputs[0], points);
+            Ok(inputs)
+        }
+        NodeKind::Levels { in_black, in_white, gamma, out_black, out_white } => {
+            apply_levels(&mut inputs[0], *in_black, *in_white, *gamma, *out_black, *out_white);
+            Ok(inputs)
+        }
+        NodeKind::Terrace { steps, sharpness, jitter, noise_scale, seed } => {
+            apply_terrace(&mut inputs[0], *steps, *sharpness, *jitter, noise_scale / ctx.detail, *seed);
+            Ok(inputs)
+        }
+        NodeKind::Sculpt { strokes } => {
+            apply_strokes(&mut inputs[0], strokes, &ctx.cancel)?;
+            Ok(inputs)
+        }
+        NodeKind::Colorize { start_index } => {
+            Ok(vec![colorize_buffer(&inputs[0], *start_index)?])
+        }
+        NodeKind::Flatten { as_water, min, max } => {
+            let (height, color) = inputs.split_at_mut(1);
+            clamp_image_buffer(&mut height[0], &mut color[0], *as_water, *min, *max)?;
+            Ok(inputs)
+        }
+        NodeKind::Landmass { sea_level, rules } => {
+            let (height, color) = inputs.split_at_mut(1);
+            apply_landmass_rules(&mut height[0], &mut color[0], *sea_level, rules, ctx.detail)?;
+            Ok(inputs)
+        }
+        NodeKind::NormalMap { strength } => {
+            Ok(vec![generate_normal_map(&inputs[0], strength * ctx.detail as f32)])
+        }
+        NodeKind::HeightMask { min, max } => {
+            Ok(vec![mask_buffer(&inputs[0], *min, *max)])
+        }
+        NodeKind::ThermalErosion { iterations, talus_angle, erosion_mode, terrain } => {
+            let (height, color) = inputs.split_at_mut(1);
+            // Material moves one pixel per iteration, and the pixels get smaller at a higher detail
+            let iterations = (*iterations as f64 * ctx.detail).round() as usize;
+            let threshold = terrain.slope_threshold(*talus_angle as f64, ctx.width, ctx.height) as f32;
+            thermal_erosion(&mut height[0], &mut color[0], iterations, threshold, *erosion_mode, &ctx.cancel)?;
+            Ok(inputs)
+        }
+        NodeKind::Rivers { rain_iterations, depth_m, num_rivers, seed, terrain } => {
+            let (height, color) = inputs.split_at_mut(1);
+            let erosion_factor = terrain.height_steps(*depth_m).round().clamp(0.0, 255.0) as i16;
+            let river_mask = simulate_river_flow(&mut height[0], &mut color[0], *rain_iterations, erosion_factor, *num_rivers, *seed, ctx.detail, &ctx.cancel)?;
+            inputs.push(river_mask);
+            Ok(inputs)
+        }
+        NodeKind::Roads { roads, params, terrain } => {
+            let river_mask = inputs.pop();
+            let (height, color) = inputs.split_at_mut(1);
+            let paths = carve_roads(&mut height[0], &mut color[0], river_mask.as_ref(), roads, params, terrain, &ctx.cancel)?;
+            if let Ok(mut road_paths) = ctx.road_paths.lock() {
+                road_paths.extend(paths);
+            }
+            Ok(inputs)
+        }
+        NodeKind::Topographic { params, terrain } => {
+            Ok(vec![render_topographic(&inputs[0], &inputs[1], params, terrain, ctx.detail)?])
+        }
+        NodeKind::Analysis { map, terrain } => {
+            Ok(vec![render_analysis(&inputs[0], *map, terrain.size_m(), terrain.relief_m(), ctx.detail)])
+        }
+        NodeKind::Output { .. } => {
+            Ok(vec![])
+        }
+    }
+}
@@ -2,13 +2,20 @@ use image::{ImageBuffer, Rgba, Pixel, imageops::FilterType};
 use noise::{NoiseFn, Perlin};
 use rand::rngs::StdRng;
 use rand::{Rng, SeedableRng};
-use std::error::Error;
 use super::constants::COLORS;
+use super::error::{HeightmapError, Result, check_same_size};
+use super::parallel::{par_from_fn, par_for_each_row_pair};
+use super::cancel::CancelToken;
+
+// The 8 neighbours of a pixel in the order rivers look at them, so ties go to the first
+pub(crate) const NEIGHBOURS: [(i32, i32); 8] = [(-1, -1), (-1, 0), (-1, 1), (0, -1), (0, 1), (1, -1), (1, 0), (1, 1)];
+// River pixels in the river mask
+const RIVER: Rgba<u8> = Rgba([255, 255, 255, 255]);
 
 pub fn generate_perlin_noise_buffer(width: u32, height: u32, offset_x: f64, offset_y: f64, scale: f64, opacity: f64, seed: u32) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
     let perlin = Perlin::new(seed);
 
-    ImageBuffer::from_fn(width, height, |x, y| {
+    par_from_fn(width, height, |x, y| {
         let x = (x as f64 + offset_x) * scale;
         let y = (y as f64 + offset_y) * scale;
         let noise_val = perlin.get([x, y]) * 0.5 + 0.5;
@@ -17,10 +24,14 @@ pub fn generate_perlin_noise_buffer(width: u32, height: u32, offset_x: f64, offs
     })
 }
 
-pub fn blend_buffers(buffer_a: &ImageBuffer<Rgba<u8>, Vec<u8>>, buffer_b: &ImageBuffer<Rgba<u8>, Vec<u8>>, blend_mode: i32) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+pub fn blend_buffers(buffer_a: &ImageBuffer<Rgba<u8>, Vec<u8>>, buffer_b: &ImageBuffer<Rgba<u8>, Vec<u8>>, blend_mode: i32) -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>> {
     let (width, height) = buffer_a.dimensions();
+    check_same_size(buffer_a.dimensions(), buffer_b.dimensions())?;
+    if !(0..=2).contains(&blend_mode) {
+        return Err(HeightmapError::InvalidBlendMode(blend_mode));
+    }
     
-    ImageBuffer::from_fn(width, height, |x, y| {
+    Ok(par_from_fn(width, height, |x, y| {
         let pixel_a = buffer_a.get_pixel(x, y);
         let pixel_b = buffer_b.get_pixel(x, y);
         
@@ -50,7 +61,7 @@ pub fn blend_buffers(buffer_a: &ImageBuffer<Rgba<u8>, Vec<u8>>, buffer_b: &Image
                     blended_pixel[i] = ((channel_a * alpha_a + channel_b * alpha_b) * 255.0).min(255.0) as u8;
                 },
                 _ => {
-                    panic!("Invalid blend mode");
+                    unreachable!("blend mode is checked above");
                 }
             }
         }
@@ -58,30 +69,7 @@ pub fn blend_buffers(buffer_a: &ImageBuffer<Rgba<u8>, Vec<u8>>, buffer_b: &Image
         blended_pixel[3] = (new_alpha * 255.0).min(255.0) as u8;
         
         Rgba(blended_pixel)
-    })
-}
-
-pub fn save_image_to_desktop(buffer: &ImageBuffer<Rgba<u8>, Vec<u8>>, filename: &str, suffix: &str){
-    let desktop_path = dirs::desktop_dir();
-    match desktop_path {
-        Some(path) => {            
-            let full_path = path.join(format!("{}_{}.png", filename, suffix));
-            println!("Desktop path: {}", full_path.display());
-            match buffer.save(full_path) {
-                Ok(_) => {
-                    println!("Image saved");
-                },
-                Err(e) => {
-                    println!("Couldn't save image: {}", e);
-                }
-            }
-                
-        },
-        None => {
-            println!("Couldn't find desktop path");
-        }
-    }
-    
+    }))
 }
 
 // Function to decide if erosion should happen based on the pixels and the erosion mode
@@ -100,7 +88,7 @@ fn should_erode(center: Rgba<u8>, neighbor: Rgba<u8>, talus_angle: f32, erosion_
                     return true;
                 }
             },
-            _ => { panic!("Invalid erosion mode"); }
+            _ => { return false; }
         }
     }
     false
@@ -112,14 +100,27 @@ pub fn thermal_erosion(
     iterations: usize,
     talus_angle: f32,
     erosion_mode: i32,
-) {
+    cancel: &CancelToken,
+) -> Result<()> {
     let (width, height) = heightmap.dimensions();
+    check_same_size(heightmap.dimensions(), colormap.dimensions())?;
+    if !(0..=2).contains(&erosion_mode) {
+        return Err(HeightmapError::InvalidErosionMode(erosion_mode));
+    }
+    if width < 3 || height < 3 {
+        return Err(HeightmapError::ImageTooSmall { width, height, min: 3 });
+    }
 
     for _ in 0..iterations {
+        cancel.check()?;
         let temp_heightmap = heightmap.clone(); // Temporary heightmap to store updates
         let temp_colormap = colormap.clone(); // Temporary colormap to store updates
 
-        for y in 1..(height - 1) {
+        // Every pixel only reads the snapshot, so the rows can be updated in parallel
+        par_for_each_row_pair(heightmap, colormap, |y, height_row, color_row| {
+            if y == 0 || y == height - 1 {
+                return;
+            }
             for x in 1..(width - 1) {
                 let center_pixel = temp_heightmap.get_pixel(x, y);
                 let mut changed = false;
@@ -135,8 +136,9 @@ pub fn thermal_erosion(
 
                         if should_erode(*center_pixel, *neighbor_pixel, talus_angle, erosion_mode) {
                             // Update both heightmap and colormap
-                            heightmap.put_pixel(x, y, *neighbor_pixel);
-                            colormap.put_pixel(x, y, *neighbor_color);
+                            let i = x as usize * 4;
+                            height_row[i..i + 4].copy_from_slice(&neighbor_pixel.0);
+                            color_row[i..i + 4].copy_from_slice(&neighbor_color.0);
                             changed = true;
                             break;
                         }
@@ -146,15 +148,17 @@ pub fn thermal_erosion(
                     }
                 }
             }
-        }
+        });
     }
+    Ok(())
 }
 
 
 
-pub fn clamp_image_buffer(height_buffer: &mut ImageBuffer<Rgba<u8>, Vec<u8>>,color_buffer: &mut ImageBuffer<Rgba<u8>, Vec<u8>>,as_water: bool, min: u8, max: u8) {
+pub fn clamp_image_buffer(height_buffer: &mut ImageBuffer<Rgba<u8>, Vec<u8>>,color_buffer: &mut ImageBuffer<Rgba<u8>, Vec<u8>>,as_water: bool, min: u8, max: u8) -> Result<()> {
     let (width, height) = height_buffer.dimensions();
-    let min_range = min - 30;
+    check_same_size(height_buffer.dimensions(), color_buffer.dimensions())?;
+    let min_range = min.saturating_sub(30);
 
     for y in 0..height {
         for x in 0..width {
@@ -171,70 +175,90 @@ pub fn clamp_image_buffer(height_buffer: &mut ImageBuffer<Rgba<u8>, Vec<u8>>,col
             }
         }
     }
+    Ok(())
 }
 
 fn lerp(a: u8, b: u8, t: f32) -> u8 {
-    ((1.0 - t) * a as f32 + t * b as f32) as u8
+    ((1.0 - t) * a as f32 + t * b as f32).round() as u8
 }
 
 fn remap(value: f32, old_min: f32, old_max: f32, new_min: f32, new_max: f32) -> f32 {
     if value < old_min {
         return new_min;
     }
+    if old_max <= old_min {
+        return new_max;
+    }
     
     (value - old_min) / (old_max - old_min) * (new_max - new_min) + new_min
 }
 
-pub fn colorize_buffer(img: &ImageBuffer<Rgba<u8>, Vec<u8>>, start_index: usize) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+pub fn colorize_buffer(img: &ImageBuffer<Rgba<u8>, Vec<u8>>, start_index: usize) -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>> {
+    if start_index >= COLORS.len() {
+        return Err(HeightmapError::InvalidColorIndex(start_index));
+    }
     let (width, height) = img.dimensions();
-    let mut colorized_img = img.clone();
     
-    for y in 0..height {
-        for x in 0..width {
-            let pixel = img.get_pixel(x, y);
-            let luminance = pixel.to_luma()[0] as f32 / 255.0;
-            
-            // Calculate indices and interpolation factor
-            let t = luminance * (COLORS.len() as f32 - 1.0);
-            let mut index1 = t.floor() as usize;
-            let mut index2 = (index1 + 1).min(COLORS.len() - 1);
-            let factor = t - index1 as f32;
-            if index1 < start_index {
-                index1 = start_index;
-            }
-            if index2 < start_index {
-                index2 = start_index;
-            }
-            
-            let color1 = COLORS[index1];
-            let color2 = COLORS[index2];
-            
-            // Interpolate between the two colors
-            let mut new_color = [0u8; 4];
-            for i in 0..4 {
-                new_color[i] = lerp(color1[i], color2[i], factor);
-            }
-            
-            colorized_img.put_pixel(x, y, Rgba(new_color));
+    Ok(par_from_fn(width, height, |x, y| {
+        let pixel = img.get_pixel(x, y);
+        let luminance = pixel.to_luma()[0] as f32 / 255.0;
+        
+        // Calculate indices and interpolation factor
+        let t = luminance * (COLORS.len() as f32 - 1.0);
+        let mut index1 = t.floor() as usize;
+        let mut index2 = (index1 + 1).min(COLORS.len() - 1);
+        let factor = t - index1 as f32;
+        if index1 < start_index {
+            index1 = start_index;
         }
-    }
-    
-    colorized_img
+        if index2 < start_index {
+            index2 = start_index;
+        }
+        
+        let color1 = COLORS[index1];
+        let color2 = COLORS[index2];
+        
+        // Interpolate between the two colors
+        let mut new_color = [0u8; 4];
+        for i in 0..4 {
+            new_color[i] = lerp(color1[i], color2[i], factor);
+        }
+        
+        Rgba(new_color)
+    }))
 }
 
+// Carves the rivers and paints them with the water colour. Returns a mask of the river pixels,
+// white on black like mask_buffer, since the colour alone can't tell them apart from painted water.
+#[allow(clippy::too_many_arguments)]
 pub fn simulate_river_flow(
     heightmap: &mut ImageBuffer<Rgba<u8>, Vec<u8>>,
     colormap: &mut ImageBuffer<Rgba<u8>, Vec<u8>>,
     rain_iterations: usize,
     erosion_factor: i16,
     num_rivers: usize,
-    fixed_seed: u64
-) -> Result<(), String> {
+    fixed_seed: u64,
+    detail: f64,
+    cancel: &CancelToken,
+) -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>> {
     let (width, height) = heightmap.dimensions();
+    check_same_size(heightmap.dimensions(), colormap.dimensions())?;
+    if width < 3 || height < 3 {
+        return Err(HeightmapError::ImageTooSmall { width, height, min: 3 });
+    }
+    let mut river_mask = ImageBuffer::from_pixel(width, height, Rgba([0, 0, 0, 255]));
     
     // Use a seeded RNG for consistent river origins
     let mut rng = StdRng::seed_from_u64(fixed_seed);
 
+    // With a detail above 1 the origins are picked on the preview grid and the rivers are longer
+    // and wider by the same factor, so a seed gives the same rivers at every resolution
+    let detail = detail.max(1.0);
+    let grid_width = ((width as f64 / detail).round() as u32).max(3);
+    let grid_height = ((height as f64 / detail).round() as u32).max(3);
+    let rain_iterations = (rain_iterations as f64 * detail).round() as usize;
+    let radius = ((detail - 1.0) / 2.0).round() as i64;
+
     let mut heightmap_i16: Vec<Vec<i16>> = vec![vec![0; height as usize]; width as usize];
     for x in 0..width {
         for y in 0..height {
@@ -243,8 +267,9 @@ pub fn simulate_river_flow(
     }
 
     for _ in 0..num_rivers {
-        let mut x = rng.gen_range(1..width - 1);
-        let mut y = rng.gen_range(1..height - 1);
+        cancel.check()?;
+        let mut x = ((rng.gen_range(1..grid_width - 1) as f64 * detail) as u32).min(width - 2);
+        let mut y = ((rng.gen_range(1..grid_height - 1) as f64 * detail) as u32).min(height - 2);
 
         for _ in 0..rain_iterations {
             if x > 0 && x < width - 1 && y > 0 && y < height - 1 {
@@ -253,24 +278,19 @@ pub fn simulate_river_flow(
                 let mut min_x = x;
                 let mut min_y = y;
 
-                for dx in -1..=1 {
-                    for dy in -1..=1 {
-                        if dx == 0 && dy == 0 {
-                            continue;
-                        }
-                        let neighbor_x = (x as i32 + dx) as usize;
-                        let neighbor_y = (y as i32 + dy) as usize;
+                for (dx, dy) in NEIGHBOURS {
+                    let neighbor_x = (x as i32 + dx) as usize;
+                    let neighbor_y = (y as i32 + dy) as usize;
 
-                        if neighbor_x >= width as usize || neighbor_y >= height as usize {
-                            continue;
-                        }
+                    if neighbor_x >= width as usize || neighbor_y >= height as usize {
+                        continue;
+                    }
 
-                        let neighbor_height = heightmap_i16[neighbor_x][neighbor_y];
-                        if neighbor_height < min_height {
-                            min_height = neighbor_height;
-                            min_x = neighbor_x as u32;
-                            min_y = neighbor_y as u32;
-                        }
+                    let neighbor_height = heightmap_i16[neighbor_x][neighbor_y];
+                    if neighbor_height < min_height {
+                        min_height = neighbor_height;
+                        min_x = neighbor_x as u32;
+                        min_y = neighbor_y as u32;
                     }
                 }
 
@@ -279,6 +299,8 @@ pub fn simulate_river_flow(
                     let new_height_u8 = std::cmp::max(0, std::cmp::min(new_height, 255)) as u8;
                     heightmap.put_pixel(min_x, min_y, Rgba([new_height_u8, new_height_u8, new_height_u8, 255]));
                     colormap.put_pixel(min_x, min_y, COLORS[0]);
+                    river_mask.put_pixel(min_x, min_y, RIVER);
+                    carve_river_bank(heightmap, colormap, &mut river_mask, min_x, min_y, radius, new_height_u8);
                     x = min_x;
                     y = min_y;
                 }
@@ -286,19 +308,78 @@ pub fn simulate_river_flow(
         }
     }
 
-    Ok(())
+    Ok(river_mask)
 }
 
+// Lowers the pixels around a river pixel to the river height, without raising anything
+#[allow(clippy::too_many_arguments)]
+fn carve_river_bank(heightmap: &mut ImageBuffer<Rgba<u8>, Vec<u8>>, colormap: &mut ImageBuffer<Rgba<u8>, Vec<u8>>, river_mask: &mut ImageBuffer<Rgba<u8>, Vec<u8>>, x: u32, y: u32, radius: i64, river_height: u8) {
+    let (width, height) = heightmap.dimensions();
+    for dy in -radius..=radius {
+        for dx in -radius..=radius {
+            if (dx == 0 && dy == 0) || dx * dx + dy * dy > radius * radius {
+                continue;
+            }
+            let bank_x = x as i64 + dx;
+            let bank_y = y as i64 + dy;
+            if bank_x < 0 || bank_y < 0 || bank_x >= width as i64 || bank_y >= height as i64 {
+                continue;
+            }
+            let (bank_x, bank_y) = (bank_x as u32, bank_y as u32);
+            if heightmap.get_pixel(bank_x, bank_y)[0] > river_height {
+                heightmap.put_pixel(bank_x, bank_y, Rgba([river_height, river_height, river_height, 255]));
+                colormap.put_pixel(bank_x, bank_y, COLORS[0]);
+                river_mask.put_pixel(bank_x, bank_y, RIVER);
+            }
+        }
+    }
+}
 
-pub fn scale_image(buffer: &mut ImageBuffer<Rgba<u8>, Vec<u8>>, target_size: (u32, u32), scale_method: FilterType) -> Result<(), Box<dyn Error>> {
+pub fn scale_image(buffer: &mut ImageBuffer<Rgba<u8>, Vec<u8>>, target_size: (u32, u32), scale_method: FilterType) -> Result<()> {
     let (target_width, target_height) = target_size;
 
     if target_width == 0 || target_height == 0 {
-        return Err("Target size should be greater than zero".into());
+        return Err(HeightmapError::InvalidSize { width: target_width, height: target_height });
     }
 
     let scaled_image = image::imageops::resize(buffer, target_width, target_height, scale_method);
     *buffer = scaled_image;
 
     Ok(())
-}
\ No newline at end of file
+}
+
+pub fn generate_normal_map(heightmap: &ImageBuffer<Rgba<u8>, Vec<u8>>, strength: f32) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    let (width, height) = heightmap.dimensions();
+
+    par_from_fn(width, height, |x, y| {
+        let sample = |sx: i64, sy: i64| -> f32 {
+            let sx = sx.clamp(0, width as i64 - 1) as u32;
+            let sy = sy.clamp(0, height as i64 - 1) as u32;
+            heightmap.get_pixel(sx, sy)[0] as f32 / 255.0
+        };
+        let (x, y) = (x as i64, y as i64);
+
+        // Sobel operator
+        let dx = (sample(x + 1, y - 1) + 2.0 * sample(x + 1, y) + sample(x + 1, y + 1))
+            - (sample(x - 1, y - 1) + 2.0 * sample(x - 1, y) + sample(x - 1, y + 1));
+        let dy = (sample(x - 1, y + 1) + 2.0 * sample(x, y + 1) + sample(x + 1, y + 1))
+            - (sample(x - 1, y - 1) + 2.0 * sample(x, y - 1) + sample(x + 1, y - 1));
+
+        let normal_x = -dx * strength;
+        let normal_y = -dy * strength;
+        let length = (normal_x * normal_x + normal_y * normal_y + 1.0).sqrt();
+
+        let encode = |value: f32| ((value / length * 0.5 + 0.5) * 255.0) as u8;
+        Rgba([encode(normal_x), encode(normal_y), encode(1.0), 255])
+    })
+}
+
+pub fn mask_buffer(heightmap: &ImageBuffer<Rgba<u8>, Vec<u8>>, min: u8, max: u8) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    let (width, height) = heightmap.dimensions();
+
+    par_from_fn(width, height, |x, y| {
+        let value = heightmap.get_pixel(x, y)[0];
+        let mask = if value >= min && value <= max { 255 } else { 0 };
+        Rgba([mask, mask, mask, 255])
+    })
+}
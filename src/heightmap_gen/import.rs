@@ -0,0 +1,123 @@
+use image::{imageops, imageops::FilterType, ImageBuffer, Luma, Rgba};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::SystemTime;
+use super::error::{HeightmapError, Result};
+use super::dem::{grid_to_heightfield, read_ascii_grid, read_xyz};
+
+pub type Heightfield = ImageBuffer<Luma<u16>, Vec<u16>>;
+
+const CACHE_LIMIT: usize = 8;
+
+// Decoded files by path, so regenerating the preview doesn't read the file again until it changes
+type Cache = HashMap<PathBuf, (Option<SystemTime>, Arc<Heightfield>)>;
+static CACHE: OnceLock<Mutex<Cache>> = OnceLock::new();
+
+// Loads a heightmap file and resamples it to width x height. The result is a grayscale
+// buffer like the one generate_perlin_noise_buffer makes, with the opacity in the alpha channel.
+pub fn import_heightmap(path: &Path, width: u32, height: u32, opacity: f64) -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>> {
+    if width == 0 || height == 0 {
+        return Err(HeightmapError::InvalidSize { width, height });
+    }
+    let field = load_heightfield(path)?;
+    let field = if field.dimensions() == (width, height) {
+        field.as_ref().clone()
+    } else {
+        imageops::resize(field.as_ref(), width, height, FilterType::CatmullRom)
+    };
+
+    let alpha = (opacity * 255.0) as u8;
+    Ok(ImageBuffer::from_fn(width, height, |x, y| {
+        let value = to_u8(field.get_pixel(x, y)[0]);
+        Rgba([value, value, value, alpha])
+    }))
+}
+
+// 8 and 16-bit PNG or TIFF files are converted to 16-bit grayscale and ESRI ASCII grids or XYZ
+// files are normalised to their elevation range. Anything else is read as a headerless raw file.
+pub fn load_heightfield(path: &Path) -> Result<Arc<Heightfield>> {
+    let modified = fs::metadata(path)
+        .map_err(|source| HeightmapError::Io { path: path.to_path_buf(), source })?
+        .modified()
+        .ok();
+
+    let cache = CACHE.get_or_init(|| Mutex::new(HashMap::new()));
+    if let Ok(cache) = cache.lock() {
+        if let Some((cached_modified, field)) = cache.get(path) {
+            if *cached_modified == modified {
+                return Ok(Arc::clone(field));
+            }
+        }
+    }
+
+    let field = Arc::new(decode_heightfield(path)?);
+    if let Ok(mut cache) = cache.lock() {
+        if cache.len() >= CACHE_LIMIT {
+            cache.clear();
+        }
+        cache.insert(path.to_path_buf(), (modified, Arc::clone(&field)));
+    }
+    Ok(field)
+}
+
+fn decode_heightfield(path: &Path) -> Result<Heightfield> {
+    let extension = path.extension().and_then(|extension| extension.to_str()).unwrap_or("").to_lowercase();
+    match extension.as_str() {
+        "png" | "tif" | "tiff" => {
+            let image = image::open(path).map_err(|source| HeightmapError::Image { path: path.to_path_buf(), source })?;
+            Ok(image.into_luma16())
+        }
+        "asc" => Ok(grid_to_heightfield(&read_ascii_grid(path)?)),
+        "xyz" => Ok(grid_to_heightfield(&read_xyz(path)?)),
+        _ => {
+            let bytes = fs::read(path).map_err(|source| HeightmapError::Io { path: path.to_path_buf(), source })?;
+            decode_raw(path, &bytes, extension == "r8")
+        }
+    }
+}
+
+// Raw files have no header, so they have to be square. The bit depth follows from the file
+// length: n*n*2 bytes is 16-bit little endian (as Unity and World Machine write it), n*n is 8-bit.
+// The first row is the south edge, like Unity and the Unity export write it.
+fn decode_raw(path: &Path, bytes: &[u8], prefer_8_bit: bool) -> Result<Heightfield> {
+    let side_16 = square_side(bytes.len() / 2).filter(|&side| side as usize * side as usize * 2 == bytes.len());
+    let side_8 = square_side(bytes.len());
+
+    match (side_16, side_8) {
+        (_, Some(side)) if prefer_8_bit || side_16.is_none() => {
+            let values = bytes.iter().map(|&value| value as u16 * 257).collect();
+            raw_heightfield(path, side, values)
+        }
+        (Some(side), _) => {
+            let values = bytes.chunks_exact(2).map(|pair| u16::from_le_bytes([pair[0], pair[1]])).collect();
+            raw_heightfield(path, side, values)
+        }
+        _ => Err(HeightmapError::Import {
+            path: path.to_path_buf(),
+            message: format!("a raw file of {} bytes is not a square 8 or 16-bit heightmap", bytes.len()),
+        }),
+    }
+}
+
+fn raw_heightfield(path: &Path, side: u32, values: Vec<u16>) -> Result<Heightfield> {
+    let field = Heightfield::from_raw(side, side, values).ok_or_else(|| HeightmapError::Import {
+        path: path.to_path_buf(),
+        message: "raw data doesn't match its size".to_string(),
+    })?;
+    Ok(imageops::flip_vertical(&field))
+}
+
+fn square_side(length: usize) -> Option<u32> {
+    let side = (length as f64).sqrt().round() as usize;
+    if side > 0 && side * side == length {
+        Some(side as u32)
+    } else {
+        None
+    }
+}
+
+fn to_u8(value: u16) -> u8 {
+    ((value as u32 * 255 + 32767) / 65535) as u8
+}
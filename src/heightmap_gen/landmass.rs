@@ -0,0 +1,254 @@
+use image::{ImageBuffer, Rgba};
+use serde_derive::{Serialize, Deserialize};
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use super::error::{check_same_size, Result};
+use super::output::{output_path, write_text};
+
+const SIDES: [(i64, i64); 4] = [(-1, 0), (1, 0), (0, -1), (0, 1)];
+const AROUND: [(i64, i64); 8] = [(-1, -1), (0, -1), (1, -1), (-1, 0), (1, 0), (-1, 1), (0, 1), (1, 1)];
+
+// Clean-up after the sea level is set. Areas are pixels of the 256 preview and grow with the
+// square of the detail on exports, so the same islands and lakes go at every resolution. 0 turns
+// a limit off.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug, Default)]
+#[serde(default)]
+pub struct LandmassRules {
+    // Islands below this area sink to sea level, the largest landmass always stays
+    pub min_island_px: u32,
+    // Lakes below this area are filled up to their lowest shore; water touching the map edge is sea
+    pub min_lake_px: u32,
+    // Sinks everything but the largest landmass
+    pub single_landmass: bool,
+}
+
+impl LandmassRules {
+    pub fn is_active(&self) -> bool {
+        self.min_island_px > 0 || self.min_lake_px > 0 || self.single_landmass
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct Island {
+    pub area_px: usize,
+    pub area_km2: f64,
+    // Length of the coast, the pixel edges between land and water
+    pub perimeter_km: f64,
+    // Centre of the island in metres from the top left corner
+    pub centroid_m: [f64; 2],
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct Lake {
+    pub area_px: usize,
+    pub area_km2: f64,
+    pub centroid_m: [f64; 2],
+}
+
+// Landmasses and lakes of a heightmap, largest first, so the continent is the first island
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct LandmassStats {
+    pub sea_level: u8,
+    pub land_percent: f64,
+    pub islands: Vec<Island>,
+    pub lakes: Vec<Lake>,
+}
+
+struct Component {
+    pixels: Vec<usize>,
+    touches_edge: bool,
+}
+
+// Connected regions of the mask, largest first. Land connects across sides only and water also
+// across corners, so a diagonal strait splits two islands and still joins two seas.
+fn components(mask: &[bool], w: usize, h: usize, corners: bool) -> Vec<Component> {
+    let steps: &[(i64, i64)] = if corners { &AROUND } else { &SIDES };
+    let mut seen = vec![false; w * h];
+    let mut found = Vec::new();
+    let mut stack = Vec::new();
+    for start in 0..w * h {
+        if !mask[start] || seen[start] {
+            continue;
+        }
+        let mut component = Component { pixels: Vec::new(), touches_edge: false };
+        seen[start] = true;
+        stack.push(start);
+        while let Some(index) = stack.pop() {
+            let (x, y) = ((index % w) as i64, (index / w) as i64);
+            component.pixels.push(index);
+            component.touches_edge |= x == 0 || y == 0 || x == w as i64 - 1 || y == h as i64 - 1;
+            for &(dx, dy) in steps {
+                let (nx, ny) = (x + dx, y + dy);
+                if nx < 0 || ny < 0 || nx >= w as i64 || ny >= h as i64 {
+                    continue;
+                }
+                let neighbour = ny as usize * w + nx as usize;
+                if mask[neighbour] && !seen[neighbour] {
+                    seen[neighbour] = true;
+                    stack.push(neighbour);
+                }
+            }
+        }
+        found.push(component);
+    }
+    found.sort_by_key(|component: &Component| std::cmp::Reverse(component.pixels.len()));
+    found
+}
+
+fn land_mask(heightmap: &ImageBuffer<Rgba<u8>, Vec<u8>>, sea_level: u8) -> Vec<bool> {
+    heightmap.pixels().map(|pixel| pixel[0] > sea_level).collect()
+}
+
+fn centroid(pixels: &[usize], w: usize, pixel_size: f64) -> [f64; 2] {
+    let count = pixels.len().max(1) as f64;
+    let (sum_x, sum_y) = pixels.iter().fold((0.0, 0.0), |(sx, sy), &index| (sx + (index % w) as f64 + 0.5, sy + (index / w) as f64 + 0.5));
+    [sum_x / count * pixel_size, sum_y / count * pixel_size]
+}
+
+// Everything above the sea level is land
+pub fn landmass_stats(heightmap: &ImageBuffer<Rgba<u8>, Vec<u8>>, sea_level: u8, size_m: f64) -> LandmassStats {
+    let (width, height) = heightmap.dimensions();
+    let (w, h) = (width as usize, height as usize);
+    let pixel_size = size_m / width.max(height).max(1) as f64;
+    let km2 = |area: usize| area as f64 * pixel_size * pixel_size / 1_000_000.0;
+    let land = land_mask(heightmap, sea_level);
+    let water: Vec<bool> = land.iter().map(|&land| !land).collect();
+
+    let landmasses = components(&land, w, h, false);
+    let islands = landmasses.iter().map(|landmass| {
+        let coast = landmass.pixels.iter().map(|&index| {
+            let (x, y) = ((index % w) as i64, (index / w) as i64);
+            SIDES.iter().filter(|&&(dx, dy)| {
+                let (nx, ny) = (x + dx, y + dy);
+                nx >= 0 && ny >= 0 && nx < w as i64 && ny < h as i64 && water[ny as usize * w + nx as usize]
+            }).count()
+        }).sum::<usize>();
+        Island {
+            area_px: landmass.pixels.len(),
+            area_km2: km2(landmass.pixels.len()),
+            perimeter_km: coast as f64 * pixel_size / 1000.0,
+            centroid_m: centroid(&landmass.pixels, w, pixel_size),
+        }
+    }).collect();
+
+    let waters = components(&water, w, h, true);
+    let lakes = waters.iter().filter(|water| !water.touches_edge).map(|lake| Lake {
+        area_px: lake.pixels.len(),
+        area_km2: km2(lake.pixels.len()),
+        centroid_m: centroid(&lake.pixels, w, pixel_size),
+    }).collect();
+
+    LandmassStats {
+        sea_level,
+        land_percent: land.iter().filter(|&&land| land).count() as f64 / (w * h).max(1) as f64 * 100.0,
+        islands,
+        lakes,
+    }
+}
+
+// Gives every pixel of the region the colour of the closest pixel outside it, spreading inwards
+// from its border
+fn paint_from_border(colormap: &mut ImageBuffer<Rgba<u8>, Vec<u8>>, region: &[bool]) {
+    let (width, height) = colormap.dimensions();
+    let (w, h) = (width as usize, height as usize);
+    let mut painted: Vec<bool> = region.iter().map(|&inside| !inside).collect();
+    let next_to_region = |index: usize| {
+        let (x, y) = ((index % w) as i64, (index / w) as i64);
+        SIDES.iter().any(|&(dx, dy)| {
+            let (nx, ny) = (x + dx, y + dy);
+            nx >= 0 && ny >= 0 && nx < w as i64 && ny < h as i64 && region[ny as usize * w + nx as usize]
+        })
+    };
+    let mut queue: VecDeque<usize> = (0..w * h).filter(|&index| !region[index] && next_to_region(index)).collect();
+    while let Some(index) = queue.pop_front() {
+        let (x, y) = ((index % w) as i64, (index / w) as i64);
+        let color = *colormap.get_pixel(x as u32, y as u32);
+        for (dx, dy) in SIDES {
+            let (nx, ny) = (x + dx, y + dy);
+            if nx < 0 || ny < 0 || nx >= w as i64 || ny >= h as i64 {
+                continue;
+            }
+            let neighbour = ny as usize * w + nx as usize;
+            if !painted[neighbour] {
+                painted[neighbour] = true;
+                colormap.put_pixel(nx as u32, ny as u32, color);
+                queue.push_back(neighbour);
+            }
+        }
+    }
+}
+
+// Sinks the islands and fills the lakes the rules ask for. Sunk land takes the height of the sea and
+// the colour of the closest water, filled lakes the height of their lowest shore and the colour of
+// the closest land.
+pub fn apply_landmass_rules(heightmap: &mut ImageBuffer<Rgba<u8>, Vec<u8>>, colormap: &mut ImageBuffer<Rgba<u8>, Vec<u8>>, sea_level: u8, rules: &LandmassRules, detail: f64) -> Result<()> {
+    check_same_size(heightmap.dimensions(), colormap.dimensions())?;
+    let (width, height) = heightmap.dimensions();
+    let (w, h) = (width as usize, height as usize);
+    let scale = |area: u32| (area as f64 * detail * detail).round() as usize;
+    let set_height = |heightmap: &mut ImageBuffer<Rgba<u8>, Vec<u8>>, index: usize, value: u8| {
+        heightmap.put_pixel((index % w) as u32, (index / w) as u32, Rgba([value, value, value, 255]));
+    };
+
+    let land = land_mask(heightmap, sea_level);
+    let landmasses = components(&land, w, h, false);
+    let mut sunk = vec![false; w * h];
+    // The first landmass is the largest
+    for landmass in landmasses.iter().skip(1) {
+        if rules.single_landmass || landmass.pixels.len() < scale(rules.min_island_px) {
+            for &index in landmass.pixels.iter() {
+                sunk[index] = true;
+                set_height(heightmap, index, sea_level);
+            }
+        }
+    }
+    if sunk.iter().any(|&sunk| sunk) {
+        paint_from_border(colormap, &sunk);
+    }
+
+    if rules.min_lake_px > 0 {
+        let water: Vec<bool> = land_mask(heightmap, sea_level).iter().map(|&land| !land).collect();
+        let waters = components(&water, w, h, true);
+        let mut filled = vec![false; w * h];
+        for lake in waters.iter().filter(|water| !water.touches_edge && water.pixels.len() < scale(rules.min_lake_px)) {
+            let shore = lake.pixels.iter().flat_map(|&index| {
+                let (x, y) = ((index % w) as i64, (index / w) as i64);
+                AROUND.iter().filter_map(move |&(dx, dy)| {
+                    let (nx, ny) = (x + dx, y + dy);
+                    (nx >= 0 && ny >= 0 && nx < w as i64 && ny < h as i64).then(|| ny as usize * w + nx as usize)
+                })
+            }).filter(|&neighbour| !water[neighbour]).map(|neighbour| heightmap.as_raw()[neighbour * 4]).min();
+            let level = shore.unwrap_or(sea_level.saturating_add(1));
+            for &index in lake.pixels.iter() {
+                filled[index] = true;
+                set_height(heightmap, index, level);
+            }
+        }
+        if filled.iter().any(|&filled| filled) {
+            paint_from_border(colormap, &filled);
+        }
+    }
+    Ok(())
+}
+
+// One line for the panel, e.g. "3 islands, the largest 12.5 km² with 20.1 km of coast, 2 lakes, 41% land"
+pub fn stats_summary(stats: &LandmassStats) -> String {
+    let islands = match stats.islands.first() {
+        None => "No land".to_string(),
+        Some(largest) => format!(
+            "{} island{}, the largest {:.2} km² with {:.1} km of coast",
+            stats.islands.len(),
+            if stats.islands.len() == 1 { "" } else { "s" },
+            largest.area_km2,
+            largest.perimeter_km,
+        ),
+    };
+    format!("{}, {} lake{}, {:.0}% land", islands, stats.lakes.len(), if stats.lakes.len() == 1 { "" } else { "s" }, stats.land_percent)
+}
+
+// Writes <filename>_landmass.json
+pub fn export_landmass_stats(stats: &LandmassStats, dir: &Path, filename: &str) -> Result<PathBuf> {
+    let path = output_path(dir, filename, "landmass", "json");
+    write_text(&path, &serde_json::to_string_pretty(stats)?)?;
+    Ok(path)
+}
@@ -1,2 +1,25 @@
 pub mod heightmap;
-pub mod constants;
\ No newline at end of file
+pub mod constants;
+pub mod remap;
+pub mod graph;
+pub mod config;
+pub mod output;
+pub mod error;
+pub mod parallel;
+pub mod cancel;
+pub mod size;
+pub mod import;
+pub mod dem;
+pub mod sculpt;
+pub mod pipeline;
+pub mod batch;
+pub mod engine;
+pub mod tiles;
+pub mod scatter;
+pub mod roads;
+pub mod contours;
+pub mod analysis;
+pub mod watershed;
+pub mod landmass;
+pub mod stats;
+pub mod terrain;
\ No newline at end of file
@@ -0,0 +1,89 @@
+use image::{ImageBuffer, Luma, Rgba};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use super::error::{HeightmapError, Result};
+
+pub const OUTPUT_DIR_ENV: &str = "HEIGHTMAP_OUTPUT_DIR";
+pub const OUTPUT_DIR_ARG: &str = "--output-dir";
+
+// Picks the output directory from the command line, then the environment, then the usual user folders
+pub fn default_output_dir(args: &[String]) -> PathBuf {
+    if let Some(dir) = output_dir_from_args(args) {
+        return dir;
+    }
+    if let Ok(dir) = env::var(OUTPUT_DIR_ENV) {
+        if !dir.is_empty() {
+            return PathBuf::from(dir);
+        }
+    }
+    dirs::desktop_dir()
+        .or_else(dirs::home_dir)
+        .or_else(|| env::current_dir().ok())
+        .unwrap_or_else(|| PathBuf::from("."))
+}
+
+fn output_dir_from_args(args: &[String]) -> Option<PathBuf> {
+    arg_value(args, OUTPUT_DIR_ARG).map(PathBuf::from)
+}
+
+// Finds "<name> <value>" or "<name>=<value>" in the command line arguments
+pub(crate) fn arg_value(args: &[String], name: &str) -> Option<String> {
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == name {
+            return args.next().cloned();
+        }
+        if let Some(value) = arg.strip_prefix(&format!("{}=", name)) {
+            return Some(value.to_string());
+        }
+    }
+    None
+}
+
+// Builds "<dir>/<filename>_<suffix>.<extension>". The filename may also be a relative or absolute path.
+pub fn output_path(dir: &Path, filename: &str, suffix: &str, extension: &str) -> PathBuf {
+    dir.join(format!("{}_{}.{}", filename, suffix, extension))
+}
+
+pub fn config_path(dir: &Path, filename: &str) -> PathBuf {
+    output_path(dir, filename, "config", "json")
+}
+
+fn create_parent_dir(path: &Path) -> Result<()> {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => {
+            fs::create_dir_all(parent).map_err(|source| HeightmapError::Io { path: parent.to_path_buf(), source })
+        }
+        _ => Ok(()),
+    }
+}
+
+pub fn save_image(buffer: &ImageBuffer<Rgba<u8>, Vec<u8>>, path: &Path) -> Result<()> {
+    create_parent_dir(path)?;
+    buffer.save(path).map_err(|source| HeightmapError::Image { path: path.to_path_buf(), source })
+}
+
+pub fn save_height16(buffer: &ImageBuffer<Luma<u16>, Vec<u16>>, path: &Path) -> Result<()> {
+    create_parent_dir(path)?;
+    buffer.save(path).map_err(|source| HeightmapError::Image { path: path.to_path_buf(), source })
+}
+
+pub fn save_gray(buffer: &ImageBuffer<Luma<u8>, Vec<u8>>, path: &Path) -> Result<()> {
+    create_parent_dir(path)?;
+    buffer.save(path).map_err(|source| HeightmapError::Image { path: path.to_path_buf(), source })
+}
+
+pub fn write_bytes(path: &Path, contents: &[u8]) -> Result<()> {
+    create_parent_dir(path)?;
+    fs::write(path, contents).map_err(|source| HeightmapError::Io { path: path.to_path_buf(), source })
+}
+
+pub fn write_text(path: &Path, contents: &str) -> Result<()> {
+    create_parent_dir(path)?;
+    fs::write(path, contents).map_err(|source| HeightmapError::Io { path: path.to_path_buf(), source })
+}
+
+pub fn read_text(path: &Path) -> Result<String> {
+    fs::read_to_string(path).map_err(|source| HeightmapError::Io { path: path.to_path_buf(), source })
+}
@@ -0,0 +1,81 @@
+use image::{ImageBuffer, Rgba};
+use rayon::prelude::*;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use std::env;
+use std::sync::Arc;
+use super::error::{HeightmapError, Result};
+use super::output::arg_value;
+
+pub const THREADS_ENV: &str = "HEIGHTMAP_THREADS";
+pub const THREADS_ARG: &str = "--threads";
+
+// Thread count from the command line, then the environment. 0 lets rayon use one thread per core.
+pub fn default_thread_count(args: &[String]) -> usize {
+    arg_value(args, THREADS_ARG)
+        .or_else(|| env::var(THREADS_ENV).ok())
+        .and_then(|threads| threads.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+pub fn build_thread_pool(threads: usize) -> Result<ThreadPool> {
+    ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()
+        .map_err(|e| HeightmapError::ThreadPool(e.to_string()))
+}
+
+// Keeps the pool alive between runs and only rebuilds it when the thread count changes
+#[derive(Default)]
+pub struct PoolCache {
+    threads: usize,
+    pool: Option<Arc<ThreadPool>>,
+}
+
+impl PoolCache {
+    pub fn get(&mut self, threads: usize) -> Result<Arc<ThreadPool>> {
+        match &self.pool {
+            Some(pool) if self.threads == threads => Ok(Arc::clone(pool)),
+            _ => {
+                let pool = Arc::new(build_thread_pool(threads)?);
+                self.threads = threads;
+                self.pool = Some(Arc::clone(&pool));
+                Ok(pool)
+            }
+        }
+    }
+}
+
+// Parallel version of ImageBuffer::from_fn. Rows are filled independently, so the result
+// is the same for any number of threads as long as `f` only depends on its arguments.
+pub fn par_from_fn<F>(width: u32, height: u32, f: F) -> ImageBuffer<Rgba<u8>, Vec<u8>>
+where
+    F: Fn(u32, u32) -> Rgba<u8> + Sync,
+{
+    let mut buffer = ImageBuffer::new(width, height);
+    if width == 0 || height == 0 {
+        return buffer;
+    }
+    let row_len = width as usize * 4;
+    buffer.par_chunks_exact_mut(row_len).enumerate().for_each(|(y, row)| {
+        for (x, pixel) in row.chunks_exact_mut(4).enumerate() {
+            pixel.copy_from_slice(&f(x as u32, y as u32).0);
+        }
+    });
+    buffer
+}
+
+// Runs `f` for every row of two equally sized buffers, passing the raw RGBA bytes of both rows
+pub fn par_for_each_row_pair<F>(buffer_a: &mut ImageBuffer<Rgba<u8>, Vec<u8>>, buffer_b: &mut ImageBuffer<Rgba<u8>, Vec<u8>>, f: F)
+where
+    F: Fn(u32, &mut [u8], &mut [u8]) + Sync,
+{
+    let (width, height) = buffer_a.dimensions();
+    if width == 0 || height == 0 {
+        return;
+    }
+    let row_len = width as usize * 4;
+    buffer_a.par_chunks_exact_mut(row_len)
+        .zip(buffer_b.par_chunks_exact_mut(row_len))
+        .enumerate()
+        .for_each(|(y, (row_a, row_b))| f(y as u32, row_a, row_b));
+}
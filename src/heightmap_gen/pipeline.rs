@@ -0,0 +1,144 @@
+use super::config::SerializedTool;
+use super::graph::{Graph, Link, NodeKind, PortType};
+use super::analysis::AnalysisMap;
+use super::remap::NormalizeMode;
+
+// Builds the default stage order: noise -> layers -> normalize -> remap -> sculpt -> colorize -> flatten -> landmass rules -> thermal erosion -> rivers -> roads
+pub fn build_pipeline_graph(settings: &SerializedTool) -> Graph {
+    let mut graph = Graph::new();
+
+    // An imported heightmap replaces the noise of the base or a layer
+    let mut height = if settings.base_import.is_empty() {
+        graph.add_node(NodeKind::Perlin {
+            scale: settings.scale,
+            offset_x: settings.offset_x,
+            offset_y: settings.offset_y,
+            opacity: 1.0,
+            seed: settings.seed,
+        }, vec![])
+    } else {
+        graph.add_node(NodeKind::Import { path: settings.base_import.clone(), opacity: 1.0 }, vec![])
+    };
+    for layer in settings.layers.iter() {
+        let layer_node = if layer.import_path.trim().is_empty() {
+            graph.add_node(NodeKind::Perlin {
+                scale: layer.scale,
+                offset_x: layer.offset_x,
+                offset_y: layer.offset_y,
+                opacity: layer.opacity,
+                seed: layer.seed,
+            }, vec![])
+        } else {
+            graph.add_node(NodeKind::Import { path: layer.import_path.trim().to_string(), opacity: layer.opacity }, vec![])
+        };
+        height = graph.add_node(NodeKind::Blend { blend_mode: layer.blend_mode }, vec![Link { node: height, output: 0 }, Link { node: layer_node, output: 0 }]);
+    }
+
+    if settings.normalize_mode != NormalizeMode::Off {
+        height = graph.add_node(NodeKind::Normalize { mode: settings.normalize_mode, clip_percent: settings.normalize_clip_percent }, vec![Link { node: height, output: 0 }]);
+    }
+    if settings.curve_enabled {
+        height = graph.add_node(NodeKind::Curve { points: settings.curve_points.clone() }, vec![Link { node: height, output: 0 }]);
+    }
+    if settings.levels_enabled {
+        height = graph.add_node(NodeKind::Levels {
+            in_black: settings.levels_in_black,
+            in_white: settings.levels_in_white,
+            gamma: settings.levels_gamma,
+            out_black: settings.levels_out_black,
+            out_white: settings.levels_out_white,
+        }, vec![Link { node: height, output: 0 }]);
+    }
+    if settings.terrace_enabled {
+        height = graph.add_node(NodeKind::Terrace {
+            steps: settings.terrace_steps,
+            sharpness: settings.terrace_sharpness,
+            jitter: settings.terrace_jitter,
+            noise_scale: settings.terrace_noise_scale,
+            seed: settings.terrace_seed,
+        }, vec![Link { node: height, output: 0 }]);
+    }
+
+    if !settings.strokes.is_empty() {
+        height = graph.add_node(NodeKind::Sculpt { strokes: settings.strokes.clone() }, vec![Link { node: height, output: 0 }]);
+    }
+
+    let color = graph.add_node(NodeKind::Colorize { start_index: 2 }, vec![Link { node: height, output: 0 }]);
+    let mut height = Link { node: height, output: 0 };
+    let mut color = Link { node: color, output: 0 };
+
+    if settings.flatten_enabled {
+        let flatten = graph.add_node(NodeKind::Flatten { as_water: settings.as_water, min: settings.terrain.sea_level(), max: 255 }, vec![height, color]);
+        height = Link { node: flatten, output: 0 };
+        color = Link { node: flatten, output: 1 };
+        if settings.landmass_rules.is_active() {
+            let landmass = graph.add_node(NodeKind::Landmass { sea_level: settings.terrain.sea_level(), rules: settings.landmass_rules.clone() }, vec![height, color]);
+            height = Link { node: landmass, output: 0 };
+            color = Link { node: landmass, output: 1 };
+        }
+    }
+    if settings.erosion_mode != 0 {
+        let erosion = graph.add_node(NodeKind::ThermalErosion {
+            iterations: settings.erosion_iterations,
+            talus_angle: settings.talus_angle,
+            erosion_mode: settings.erosion_mode,
+            terrain: settings.terrain,
+        }, vec![height, color]);
+        height = Link { node: erosion, output: 0 };
+        color = Link { node: erosion, output: 1 };
+    }
+    let mut river_mask = None;
+    if settings.calculate_rivers {
+        let rivers = graph.add_node(NodeKind::Rivers {
+            rain_iterations: settings.river_iterations,
+            depth_m: settings.river_depth_m,
+            num_rivers: settings.river_amount,
+            seed: settings.river_seed,
+            terrain: settings.terrain,
+        }, vec![height, color]);
+        height = Link { node: rivers, output: 0 };
+        color = Link { node: rivers, output: 1 };
+        river_mask = Some(Link { node: rivers, output: 2 });
+        graph.add_node(NodeKind::Output { name: "rivers".to_string(), port: PortType::Mask }, vec![Link { node: rivers, output: 2 }]);
+    }
+    if settings.roads.iter().any(|road| road.waypoints.len() >= 2) {
+        // Without rivers the roads get a mask of the sea, which they avoid anyway
+        let river_mask = river_mask.unwrap_or_else(|| {
+            let sea = graph.add_node(NodeKind::HeightMask { min: 0, max: settings.terrain.sea_level() }, vec![height]);
+            Link { node: sea, output: 0 }
+        });
+        let roads = graph.add_node(NodeKind::Roads {
+            roads: settings.roads.clone(),
+            params: settings.road_params.clone(),
+            terrain: settings.terrain,
+        }, vec![height, color, river_mask]);
+        height = Link { node: roads, output: 0 };
+        color = Link { node: roads, output: 1 };
+    }
+
+    let normal = graph.add_node(NodeKind::NormalMap { strength: 4.0 }, vec![height]);
+    graph.add_node(NodeKind::Output { name: "heightmap".to_string(), port: PortType::Height }, vec![height]);
+    graph.add_node(NodeKind::Output { name: "colormap".to_string(), port: PortType::Color }, vec![color]);
+    graph.add_node(NodeKind::Output { name: "normal".to_string(), port: PortType::Normal }, vec![Link { node: normal, output: 0 }]);
+    if settings.contour_enabled {
+        let topo = graph.add_node(NodeKind::Topographic {
+            params: settings.contour_params.clone(),
+            terrain: settings.terrain,
+        }, vec![height, color]);
+        graph.add_node(NodeKind::Output { name: "topo".to_string(), port: PortType::Color }, vec![Link { node: topo, output: 0 }]);
+    }
+    for map in AnalysisMap::ALL {
+        if settings.analysis_export || settings.analysis_view == map {
+            let analysis = graph.add_node(NodeKind::Analysis {
+                map,
+                terrain: settings.terrain,
+            }, vec![height]);
+            graph.add_node(NodeKind::Output { name: map.name().to_string(), port: PortType::Color }, vec![Link { node: analysis, output: 0 }]);
+        }
+    }
+    if settings.flatten_enabled {
+        let mask = graph.add_node(NodeKind::HeightMask { min: 0, max: settings.terrain.sea_level() }, vec![height]);
+        graph.add_node(NodeKind::Output { name: "mask".to_string(), port: PortType::Mask }, vec![Link { node: mask, output: 0 }]);
+    }
+    graph
+}
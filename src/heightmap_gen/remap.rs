@@ -0,0 +1,181 @@
+use image::{ImageBuffer, Rgba};
+use noise::{NoiseFn, Perlin};
+use serde_derive::{Serialize, Deserialize};
+use super::stats::histogram;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+pub enum NormalizeMode {
+    #[default]
+    Off,
+    Stretch,
+    Equalize,
+    PercentileClip,
+}
+
+impl NormalizeMode {
+    pub fn from_index(index: i32) -> NormalizeMode {
+        match index {
+            1 => NormalizeMode::Stretch,
+            2 => NormalizeMode::Equalize,
+            3 => NormalizeMode::PercentileClip,
+            _ => NormalizeMode::Off,
+        }
+    }
+
+    pub fn index(&self) -> i32 {
+        match self {
+            NormalizeMode::Off => 0,
+            NormalizeMode::Stretch => 1,
+            NormalizeMode::Equalize => 2,
+            NormalizeMode::PercentileClip => 3,
+        }
+    }
+}
+
+// Builds a lookup table from evenly spaced curve points using a Catmull-Rom spline
+fn curve_lut(points: &[f32]) -> [u8; 256] {
+    let mut lut = [0u8; 256];
+    if points.len() < 2 {
+        for (i, value) in lut.iter_mut().enumerate() {
+            *value = i as u8;
+        }
+        return lut;
+    }
+
+    let segments = points.len() - 1;
+    for (i, value) in lut.iter_mut().enumerate() {
+        let t = i as f32 / 255.0 * segments as f32;
+        let segment = (t.floor() as usize).min(segments - 1);
+        let local_t = t - segment as f32;
+
+        let p1 = points[segment];
+        let p2 = points[segment + 1];
+        // Past either end the curve is extended in a straight line, so straight points stay straight
+        let p0 = if segment == 0 { 2.0 * p1 - p2 } else { points[segment - 1] };
+        let p3 = if segment + 1 == segments { 2.0 * p2 - p1 } else { points[segment + 2] };
+
+        let t2 = local_t * local_t;
+        let t3 = t2 * local_t;
+        let y = 0.5 * ((2.0 * p1)
+            + (-p0 + p2) * local_t
+            + (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * t2
+            + (-p0 + 3.0 * p1 - 3.0 * p2 + p3) * t3);
+
+        *value = (y.clamp(0.0, 1.0) * 255.0).round() as u8;
+    }
+    lut
+}
+
+fn apply_lut(buffer: &mut ImageBuffer<Rgba<u8>, Vec<u8>>, lut: &[u8; 256]) {
+    for pixel in buffer.pixels_mut() {
+        for channel in 0..3 {
+            pixel[channel] = lut[pixel[channel] as usize];
+        }
+    }
+}
+
+// Remaps heights through a curve given as evenly spaced control points in the range 0-1
+pub fn apply_curve(buffer: &mut ImageBuffer<Rgba<u8>, Vec<u8>>, points: &[f32]) {
+    let lut = curve_lut(points);
+    apply_lut(buffer, &lut);
+}
+
+pub fn apply_levels(buffer: &mut ImageBuffer<Rgba<u8>, Vec<u8>>, in_black: u8, in_white: u8, gamma: f32, out_black: u8, out_white: u8) {
+    let in_black = in_black as f32;
+    let in_white = (in_white as f32).max(in_black + 1.0);
+    let gamma = gamma.max(0.01);
+
+    let mut lut = [0u8; 256];
+    for (i, value) in lut.iter_mut().enumerate() {
+        let t = ((i as f32 - in_black) / (in_white - in_black)).clamp(0.0, 1.0);
+        let t = t.powf(1.0 / gamma);
+        *value = (out_black as f32 + t * (out_white as f32 - out_black as f32)).round().clamp(0.0, 255.0) as u8;
+    }
+    apply_lut(buffer, &lut);
+}
+
+// Quantizes heights into steps. Sharpness 0 keeps the slope, 1 gives flat steps with vertical cliffs.
+// The step edges are jittered with perlin noise so the terraces don't follow the contour lines exactly.
+pub fn apply_terrace(buffer: &mut ImageBuffer<Rgba<u8>, Vec<u8>>, steps: u32, sharpness: f32, jitter: f32, noise_scale: f64, seed: u32) {
+    if steps == 0 {
+        return;
+    }
+
+    let perlin = Perlin::new(seed);
+    let steps = steps as f32;
+    let exponent = 1.0 / (1.0 - sharpness.clamp(0.0, 1.0));
+    let (width, height) = buffer.dimensions();
+
+    for y in 0..height {
+        for x in 0..width {
+            let pixel = buffer.get_pixel_mut(x, y);
+            let noise_val = perlin.get([x as f64 * noise_scale, y as f64 * noise_scale]) as f32;
+            let value = (pixel[0] as f32 / 255.0 + noise_val * jitter / steps).clamp(0.0, 1.0);
+
+            let scaled = value * steps;
+            let step = scaled.floor().min(steps - 1.0);
+            let t = (scaled - step).min(1.0);
+            // Full sharpness gives an infinite exponent; the steps are flat then, the top one included
+            let rise = if exponent.is_finite() { t.powf(exponent) } else { 0.0 };
+            let terraced = (step + rise) / steps;
+
+            let color = (terraced.clamp(0.0, 1.0) * 255.0).round() as u8;
+            for channel in 0..3 {
+                pixel[channel] = color;
+            }
+        }
+    }
+}
+
+// The lowest and highest height once the given share of the pixels is cut off at either end
+fn clipped_range(counts: &[u64; 256], total: u64, percent: f32) -> (usize, usize) {
+    let cut = (total as f64 * percent as f64 / 100.0).floor() as u64;
+    let mut below = 0;
+    let low = counts.iter().position(|&count| {
+        below += count;
+        below > cut
+    }).unwrap_or(0);
+    let mut above = 0;
+    let high = 255 - counts.iter().rev().position(|&count| {
+        above += count;
+        above > cut
+    }).unwrap_or(0);
+    (low, high.max(low))
+}
+
+// Spreads the heights over the full 0-255 range. Stretch maps the lowest and highest height to 0
+// and 255, percentile clip does the same with the heights at clip_percent from either end and cuts
+// off the rest, and equalize flattens the histogram so every height is about equally common.
+pub fn apply_normalize(buffer: &mut ImageBuffer<Rgba<u8>, Vec<u8>>, mode: NormalizeMode, clip_percent: f32) {
+    let counts = histogram(buffer);
+    let total: u64 = counts.iter().sum();
+    if total == 0 {
+        return;
+    }
+    let stretch = |(low, high): (usize, usize)| {
+        let mut lut = [0u8; 256];
+        let range = high.saturating_sub(low).max(1) as f32;
+        for (i, value) in lut.iter_mut().enumerate() {
+            *value = ((i as f32 - low as f32) / range * 255.0).round().clamp(0.0, 255.0) as u8;
+        }
+        lut
+    };
+    let lut = match mode {
+        NormalizeMode::Off => return,
+        NormalizeMode::Stretch => stretch(clipped_range(&counts, total, 0.0)),
+        NormalizeMode::PercentileClip => stretch(clipped_range(&counts, total, clip_percent.clamp(0.0, 49.0))),
+        NormalizeMode::Equalize => {
+            let lowest = counts.iter().position(|&count| count > 0).unwrap_or(0);
+            let first = counts[lowest];
+            let mut lut = [0u8; 256];
+            let mut cumulative = 0;
+            for (i, value) in lut.iter_mut().enumerate() {
+                cumulative += counts[i];
+                let share = cumulative.saturating_sub(first) as f64 / (total - first).max(1) as f64;
+                *value = (share * 255.0).round() as u8;
+            }
+            lut
+        }
+    };
+    apply_lut(buffer, &lut);
+}
@@ -0,0 +1,409 @@
+use image::{ImageBuffer, Rgba};
+use serde_derive::{Serialize, Deserialize};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::f64::consts::SQRT_2;
+use std::path::{Path, PathBuf};
+use super::cancel::CancelToken;
+use super::error::{check_same_size, Result};
+use super::output::{output_path, write_text};
+use super::terrain::Terrain;
+
+// Colour painted on the road surface
+pub const ROAD_COLOR: Rgba<u8> = Rgba([70, 62, 55, 255]);
+// The route is searched on a grid with at most this many cells along the long side, so exports
+// find the same route as the preview and large exports don't search millions of pixels
+const SEARCH_SIZE: u32 = 256;
+// Turning radius in metres below which a curve gets the full banking
+const BANKING_RADIUS_M: f64 = 50.0;
+
+// Neighbour offsets in the order of the eight compass directions, so turns are index differences
+const DIRECTIONS: [(i32, i32); 8] = [(1, 0), (1, 1), (0, 1), (-1, 1), (-1, 0), (-1, -1), (0, -1), (1, -1)];
+// Direction index of the first cell, which has no previous move to turn from
+const NO_DIRECTION: usize = 8;
+
+// A road through its waypoints. Positions are 0-1 across the image, like brush strokes.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug, Default)]
+#[serde(default)]
+pub struct Road {
+    pub waypoints: Vec<[f32; 2]>,
+}
+
+// Shared by every road. Costs are relative to the length of a flat, straight route.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(default)]
+pub struct RoadParams {
+    pub width_m: f64,
+    // Steepest grade along the road in percent
+    pub max_grade: f64,
+    // Cross slope in tight curves in percent, lower on the inside
+    pub banking: f64,
+    // Extra cost of a move at the maximum grade, growing with the square of the grade
+    pub slope_cost: f64,
+    // Extra cost per metre over water
+    pub water_cost: f64,
+    // Extra cost of a 45 degree turn, in metres
+    pub curve_cost: f64,
+}
+
+impl Default for RoadParams {
+    fn default() -> Self {
+        RoadParams {
+            width_m: 8.0,
+            max_grade: 10.0,
+            banking: 3.0,
+            slope_cost: 10.0,
+            water_cost: 50.0,
+            curve_cost: 2.0,
+        }
+    }
+}
+
+// The centre line of a carved road, in metres from the top left corner with z the elevation, like
+// the scatter points
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct RoadPath {
+    pub road: usize,
+    pub width_m: f64,
+    pub points: Vec<[f64; 3]>,
+}
+
+// The terrain sampled down to the search resolution
+struct CostGrid {
+    width: usize,
+    height: usize,
+    cell_m: f64,
+    // Heights in metres
+    heights: Vec<f64>,
+    water: Vec<bool>,
+}
+
+impl CostGrid {
+    // Water is everything at or below the sea level and the white pixels of the river mask
+    fn new(heightmap: &ImageBuffer<Rgba<u8>, Vec<u8>>, river_mask: Option<&ImageBuffer<Rgba<u8>, Vec<u8>>>, sea_level: u8, size_m: f64, height_m: f64) -> CostGrid {
+        let (width, height) = heightmap.dimensions();
+        let step = width.max(height).div_ceil(SEARCH_SIZE).max(1);
+        let (grid_width, grid_height) = (width.div_ceil(step), height.div_ceil(step));
+        let mut heights = Vec::with_capacity((grid_width * grid_height) as usize);
+        let mut water = Vec::with_capacity((grid_width * grid_height) as usize);
+        for cell_y in 0..grid_height {
+            for cell_x in 0..grid_width {
+                let (x0, y0) = (cell_x * step, cell_y * step);
+                let (x1, y1) = ((x0 + step).min(width), (y0 + step).min(height));
+                let mut sum = 0.0;
+                for y in y0..y1 {
+                    for x in x0..x1 {
+                        sum += heightmap.get_pixel(x, y)[0] as f64;
+                    }
+                }
+                heights.push(sum / ((x1 - x0) * (y1 - y0)) as f64 / 255.0 * height_m);
+                let (centre_x, centre_y) = ((x0 + x1) / 2, (y0 + y1) / 2);
+                let river = river_mask.is_some_and(|mask| mask.get_pixel(centre_x, centre_y)[0] > 127);
+                water.push(river || heightmap.get_pixel(centre_x, centre_y)[0] <= sea_level);
+            }
+        }
+        CostGrid {
+            width: grid_width as usize,
+            height: grid_height as usize,
+            cell_m: size_m / width.max(height) as f64 * step as f64,
+            heights,
+            water,
+        }
+    }
+
+    // Cell containing a 0-1 position
+    fn cell(&self, point: [f32; 2]) -> usize {
+        let x = ((point[0].clamp(0.0, 1.0) * self.width as f32) as usize).min(self.width - 1);
+        let y = ((point[1].clamp(0.0, 1.0) * self.height as f32) as usize).min(self.height - 1);
+        y * self.width + x
+    }
+
+    fn move_cost(&self, from: usize, to: usize, previous: usize, direction: usize, params: &RoadParams) -> f64 {
+        let length = if direction % 2 == 1 { self.cell_m * SQRT_2 } else { self.cell_m };
+        let grade = (self.heights[to] - self.heights[from]).abs() / length;
+        let limit = (params.max_grade / 100.0).max(0.001);
+        let mut cost = length * (1.0 + params.slope_cost * (grade / limit).powi(2));
+        if self.water[to] {
+            cost += length * params.water_cost;
+        }
+        if previous != NO_DIRECTION {
+            let turn = (direction + 8 - previous) % 8;
+            let steps = turn.min(8 - turn) as f64;
+            cost += params.curve_cost * steps * steps;
+        }
+        cost
+    }
+
+    // A* from one cell to another over states of cell and incoming direction, so turns can be
+    // charged. Returns the cells of the cheapest route, both ends included.
+    fn route(&self, start: usize, goal: usize, params: &RoadParams, cancel: &CancelToken) -> Result<Vec<usize>> {
+        let states = self.width * self.height * 9;
+        let mut cost = vec![f64::INFINITY; states];
+        let mut came_from = vec![usize::MAX; states];
+        let mut open = BinaryHeap::new();
+        let (goal_x, goal_y) = ((goal % self.width) as f64, (goal / self.width) as f64);
+        // Every move costs at least its length, so the straight distance never overestimates
+        let estimate = |cell: usize| {
+            let (x, y) = ((cell % self.width) as f64, (cell / self.width) as f64);
+            ((x - goal_x).powi(2) + (y - goal_y).powi(2)).sqrt() * self.cell_m
+        };
+
+        let start_state = start * 9 + NO_DIRECTION;
+        cost[start_state] = 0.0;
+        // Costs are never negative, so their bits sort like the numbers
+        open.push(Reverse((estimate(start).to_bits(), start_state)));
+        let mut popped = 0usize;
+        let mut end_state = None;
+        while let Some(Reverse((_, state))) = open.pop() {
+            popped += 1;
+            if popped.is_multiple_of(65536) {
+                cancel.check()?;
+            }
+            let (cell, previous) = (state / 9, state % 9);
+            if cell == goal {
+                end_state = Some(state);
+                break;
+            }
+            let (x, y) = ((cell % self.width) as i32, (cell / self.width) as i32);
+            for (direction, (dx, dy)) in DIRECTIONS.iter().enumerate() {
+                let (next_x, next_y) = (x + dx, y + dy);
+                if next_x < 0 || next_y < 0 || next_x >= self.width as i32 || next_y >= self.height as i32 {
+                    continue;
+                }
+                let next = next_y as usize * self.width + next_x as usize;
+                let next_cost = cost[state] + self.move_cost(cell, next, previous, direction, params);
+                let next_state = next * 9 + direction;
+                if next_cost < cost[next_state] {
+                    cost[next_state] = next_cost;
+                    came_from[next_state] = state;
+                    open.push(Reverse(((next_cost + estimate(next)).to_bits(), next_state)));
+                }
+            }
+        }
+
+        let mut cells = Vec::new();
+        let mut state = end_state.unwrap_or(start_state);
+        loop {
+            cells.push(state / 9);
+            if came_from[state] == usize::MAX {
+                break;
+            }
+            state = came_from[state];
+        }
+        cells.reverse();
+        Ok(cells)
+    }
+
+    fn cell_centre(&self, cell: usize) -> [f64; 2] {
+        [((cell % self.width) as f64 + 0.5) * self.cell_m, ((cell / self.width) as f64 + 0.5) * self.cell_m]
+    }
+}
+
+// Cuts the corners of the grid route off, leaving the ends where they are
+fn chaikin(points: &[[f64; 2]], iterations: usize) -> Vec<[f64; 2]> {
+    let mut points = points.to_vec();
+    for _ in 0..iterations {
+        if points.len() < 3 {
+            break;
+        }
+        let mut smoothed = vec![points[0]];
+        for pair in points.windows(2) {
+            let (a, b) = (pair[0], pair[1]);
+            smoothed.push([a[0] * 0.75 + b[0] * 0.25, a[1] * 0.75 + b[1] * 0.25]);
+            smoothed.push([a[0] * 0.25 + b[0] * 0.75, a[1] * 0.25 + b[1] * 0.75]);
+        }
+        smoothed.push(points[points.len() - 1]);
+        points = smoothed;
+    }
+    points
+}
+
+// Blurs a value along the road, keeping the ends
+fn smooth_along(values: &mut [f64], passes: usize) {
+    for _ in 0..passes {
+        let previous = values.to_vec();
+        for i in 1..values.len().saturating_sub(1) {
+            values[i] = (previous[i - 1] + 2.0 * previous[i] + previous[i + 1]) / 4.0;
+        }
+    }
+}
+
+// Limits the change between neighbouring points to the grade. Clamping from the start and from the
+// end each gives a valid profile biased to one end, and their average is valid as well.
+fn limit_grade(heights: &mut [f64], distances: &[f64], grade: f64) {
+    let clamp_pass = |order: &mut dyn Iterator<Item = (usize, usize)>| {
+        let mut limited = heights.to_vec();
+        for (from, to) in order {
+            let reach = grade * (distances[to] - distances[from]).abs();
+            limited[to] = limited[to].clamp(limited[from] - reach, limited[from] + reach);
+        }
+        limited
+    };
+    let count = heights.len();
+    let forward = clamp_pass(&mut (1..count).map(|i| (i - 1, i)));
+    let backward = clamp_pass(&mut (0..count.saturating_sub(1)).rev().map(|i| (i + 1, i)));
+    for (i, height) in heights.iter_mut().enumerate() {
+        *height = (forward[i] + backward[i]) / 2.0;
+    }
+}
+
+fn sample_height(heightmap: &ImageBuffer<Rgba<u8>, Vec<u8>>, pixel_size: f64, height_m: f64, point: [f64; 2]) -> f64 {
+    let (width, height) = heightmap.dimensions();
+    let fx = (point[0] / pixel_size - 0.5).clamp(0.0, (width - 1) as f64);
+    let fy = (point[1] / pixel_size - 0.5).clamp(0.0, (height - 1) as f64);
+    let (x0, y0) = (fx.floor() as u32, fy.floor() as u32);
+    let (x1, y1) = ((x0 + 1).min(width - 1), (y0 + 1).min(height - 1));
+    let (tx, ty) = (fx - x0 as f64, fy - y0 as f64);
+    let value = |x: u32, y: u32| heightmap.get_pixel(x, y)[0] as f64;
+    let top = value(x0, y0) * (1.0 - tx) + value(x1, y0) * tx;
+    let bottom = value(x0, y1) * (1.0 - tx) + value(x1, y1) * tx;
+    (top * (1.0 - ty) + bottom * ty) / 255.0 * height_m
+}
+
+// Signed curvature at every point in radians per metre, positive for turns to the right on the image
+fn curvature(points: &[[f64; 2]]) -> Vec<f64> {
+    let mut curvature = vec![0.0; points.len()];
+    for i in 1..points.len().saturating_sub(1) {
+        let (a, b, c) = (points[i - 1], points[i], points[i + 1]);
+        let (ux, uy) = (b[0] - a[0], b[1] - a[1]);
+        let (vx, vy) = (c[0] - b[0], c[1] - b[1]);
+        let angle = (ux * vy - uy * vx).atan2(ux * vx + uy * vy);
+        let length = ((ux * ux + uy * uy).sqrt() + (vx * vx + vy * vy).sqrt()) / 2.0;
+        if length > 0.0 {
+            curvature[i] = angle / length;
+        }
+    }
+    curvature
+}
+
+// Finds the route of every road with at least two waypoints, carves it into the heightmap and
+// paints it into the colormap. Returns the carved centre lines.
+pub fn carve_roads(
+    heightmap: &mut ImageBuffer<Rgba<u8>, Vec<u8>>,
+    colormap: &mut ImageBuffer<Rgba<u8>, Vec<u8>>,
+    river_mask: Option<&ImageBuffer<Rgba<u8>, Vec<u8>>>,
+    roads: &[Road],
+    params: &RoadParams,
+    terrain: &Terrain,
+    cancel: &CancelToken,
+) -> Result<Vec<RoadPath>> {
+    check_same_size(heightmap.dimensions(), colormap.dimensions())?;
+    if let Some(mask) = river_mask {
+        check_same_size(heightmap.dimensions(), mask.dimensions())?;
+    }
+    let (size_m, height_m) = (terrain.size_m(), terrain.relief_m());
+    let (width, height) = heightmap.dimensions();
+    let pixel_size = size_m / width.max(height) as f64;
+    let grid = CostGrid::new(heightmap, river_mask, terrain.sea_level(), size_m, height_m);
+    let mut paths = Vec::new();
+
+    for (index, road) in roads.iter().enumerate() {
+        if road.waypoints.len() < 2 {
+            continue;
+        }
+        let mut cells: Vec<usize> = Vec::new();
+        for pair in road.waypoints.windows(2) {
+            let leg = grid.route(grid.cell(pair[0]), grid.cell(pair[1]), params, cancel)?;
+            let skip = if cells.last() == leg.first() { 1 } else { 0 };
+            cells.extend(leg.into_iter().skip(skip));
+        }
+        if cells.len() < 2 {
+            continue;
+        }
+        let centre_line = chaikin(&cells.iter().map(|&cell| grid.cell_centre(cell)).collect::<Vec<_>>(), 3);
+
+        let mut distances = vec![0.0; centre_line.len()];
+        for i in 1..centre_line.len() {
+            let (a, b) = (centre_line[i - 1], centre_line[i]);
+            distances[i] = distances[i - 1] + ((b[0] - a[0]).powi(2) + (b[1] - a[1]).powi(2)).sqrt();
+        }
+        let mut profile: Vec<f64> = centre_line.iter().map(|&point| sample_height(heightmap, pixel_size, height_m, point)).collect();
+        smooth_along(&mut profile, 8);
+        limit_grade(&mut profile, &distances, params.max_grade / 100.0);
+        let mut banking: Vec<f64> = curvature(&centre_line).iter()
+            .map(|curvature| params.banking / 100.0 * (curvature * BANKING_RADIUS_M).clamp(-1.0, 1.0))
+            .collect();
+        smooth_along(&mut banking, 8);
+
+        carve_path(heightmap, colormap, &centre_line, &profile, &banking, params.width_m, pixel_size, height_m);
+        paths.push(RoadPath {
+            road: index,
+            width_m: params.width_m,
+            // The profile is in metres above the lowest height
+            points: centre_line.iter().zip(profile.iter()).map(|(point, z)| [point[0], point[1], terrain.elevation_m(terrain.height_steps(*z))]).collect(),
+        });
+    }
+    Ok(paths)
+}
+
+// Sets the road surface along the centre line and blends it into the terrain over a shoulder as wide
+// as the road. Every pixel takes the surface of the closest segment.
+#[allow(clippy::too_many_arguments)]
+fn carve_path(
+    heightmap: &mut ImageBuffer<Rgba<u8>, Vec<u8>>,
+    colormap: &mut ImageBuffer<Rgba<u8>, Vec<u8>>,
+    centre_line: &[[f64; 2]],
+    profile: &[f64],
+    banking: &[f64],
+    width_m: f64,
+    pixel_size: f64,
+    height_m: f64,
+) {
+    let (width, height) = heightmap.dimensions();
+    let half_width = width_m / 2.0;
+    let reach = half_width + width_m;
+    let mut closest = vec![f64::INFINITY; (width * height) as usize];
+    let mut surface = vec![0.0; (width * height) as usize];
+
+    for i in 0..centre_line.len() - 1 {
+        let (a, b) = (centre_line[i], centre_line[i + 1]);
+        let (dx, dy) = (b[0] - a[0], b[1] - a[1]);
+        let length_squared = dx * dx + dy * dy;
+        let min_x = ((a[0].min(b[0]) - reach) / pixel_size).floor().max(0.0) as u32;
+        let max_x = (((a[0].max(b[0]) + reach) / pixel_size).ceil().max(0.0) as u32).min(width);
+        let min_y = ((a[1].min(b[1]) - reach) / pixel_size).floor().max(0.0) as u32;
+        let max_y = (((a[1].max(b[1]) + reach) / pixel_size).ceil().max(0.0) as u32).min(height);
+        for y in min_y..max_y {
+            for x in min_x..max_x {
+                let (px, py) = ((x as f64 + 0.5) * pixel_size, (y as f64 + 0.5) * pixel_size);
+                let t = if length_squared > 0.0 { (((px - a[0]) * dx + (py - a[1]) * dy) / length_squared).clamp(0.0, 1.0) } else { 0.0 };
+                let (cx, cy) = (a[0] + dx * t, a[1] + dy * t);
+                let distance = ((px - cx).powi(2) + (py - cy).powi(2)).sqrt();
+                let index = (y * width + x) as usize;
+                if distance < closest[index] {
+                    // Positive on the left of the direction of travel, which is the outside of a right turn
+                    let side = if length_squared > 0.0 { ((px - a[0]) * dy - (py - a[1]) * dx) / length_squared.sqrt() } else { 0.0 };
+                    let bank = banking[i] + (banking[i + 1] - banking[i]) * t;
+                    closest[index] = distance;
+                    surface[index] = profile[i] + (profile[i + 1] - profile[i]) * t + bank * side.clamp(-half_width, half_width);
+                }
+            }
+        }
+    }
+
+    for (index, (&distance, &road_height)) in closest.iter().zip(surface.iter()).enumerate() {
+        if distance >= reach {
+            continue;
+        }
+        let (x, y) = (index as u32 % width, index as u32 / width);
+        let pixel = heightmap.get_pixel_mut(x, y);
+        let terrain = pixel[0] as f64 / 255.0 * height_m;
+        let blend = ((distance - half_width) / width_m).clamp(0.0, 1.0);
+        let blend = blend * blend * (3.0 - 2.0 * blend);
+        let value = ((road_height * (1.0 - blend) + terrain * blend) / height_m * 255.0).round().clamp(0.0, 255.0) as u8;
+        for channel in 0..3 {
+            pixel[channel] = value;
+        }
+        if distance <= half_width {
+            let alpha = colormap.get_pixel(x, y)[3];
+            colormap.put_pixel(x, y, Rgba([ROAD_COLOR[0], ROAD_COLOR[1], ROAD_COLOR[2], alpha]));
+        }
+    }
+}
+
+// Writes the centre lines as <filename>_roads.json
+pub fn export_road_paths(paths: &[RoadPath], dir: &Path, filename: &str) -> Result<PathBuf> {
+    let path = output_path(dir, filename, "roads", "json");
+    write_text(&path, &serde_json::to_string_pretty(paths)?)?;
+    Ok(path)
+}
@@ -0,0 +1,346 @@
+use image::{ImageBuffer, Rgba};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde_derive::{Serialize, Deserialize};
+use std::path::{Path, PathBuf};
+use super::cancel::CancelToken;
+use super::error::Result;
+use super::output::{output_path, write_text};
+
+// Upper bound per rule, so a tiny minimum distance on a large terrain can't run away
+pub const MAX_POINTS: usize = 200_000;
+// Candidates tried around each point before it stops spawning new ones (Bridson's k)
+const CANDIDATES: usize = 30;
+// Overlay colours handed out to new rules in turn
+pub const RULE_COLORS: [[u8; 3]; 6] = [[255, 40, 40], [255, 220, 0], [255, 0, 255], [0, 230, 255], [255, 140, 0], [255, 255, 255]];
+
+// Where one kind of object goes. Distances and elevations are in metres of the terrain, and a
+// limit of 0 means no limit where noted.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(default)]
+pub struct ScatterRule {
+    pub name: String,
+    pub min_distance_m: f64,
+    // Share of the candidates that are kept where every condition holds
+    pub density: f64,
+    pub elevation_min_m: f64,
+    pub elevation_max_m: f64,
+    pub slope_max_deg: f64,
+    pub water_distance_min_m: f64,
+    // 0 means no limit
+    pub water_distance_max_m: f64,
+    // Points keep at least this far from rivers, 0 allows points on rivers
+    pub river_distance_min_m: f64,
+    pub scale_min: f64,
+    pub scale_max: f64,
+    pub seed: u32,
+    pub color: [u8; 3],
+}
+
+impl Default for ScatterRule {
+    fn default() -> Self {
+        ScatterRule {
+            name: "trees".to_string(),
+            min_distance_m: 20.0,
+            density: 0.8,
+            elevation_min_m: 0.0,
+            elevation_max_m: 400.0,
+            slope_max_deg: 40.0,
+            water_distance_min_m: 5.0,
+            water_distance_max_m: 0.0,
+            river_distance_min_m: 5.0,
+            scale_min: 0.8,
+            scale_max: 1.2,
+            seed: 1,
+            color: [255, 40, 40],
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub struct ScatterPoint {
+    // Metres from the top left corner, x to the right and y down the image
+    pub x: f64,
+    pub y: f64,
+    // Elevation in metres
+    pub z: f64,
+    pub normal: [f64; 3],
+    pub scale: f64,
+    // Degrees around the up axis
+    pub rotation: f64,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct ScatterSet {
+    pub rule: String,
+    pub points: Vec<ScatterPoint>,
+}
+
+// The finished terrain the rules are evaluated on, with its size in metres. Heights 0-255 span
+// height_m metres from min_elevation_m up. The river mask is white where there is a river and
+// heights at or below sea_level are water, whether or not the ground was flattened.
+pub struct ScatterTerrain<'a> {
+    pub heightmap: &'a ImageBuffer<Rgba<u8>, Vec<u8>>,
+    pub river_mask: Option<&'a ImageBuffer<Rgba<u8>, Vec<u8>>>,
+    pub sea_level: u8,
+    pub size_m: f64,
+    pub height_m: f64,
+    pub min_elevation_m: f64,
+}
+
+impl ScatterTerrain<'_> {
+    // Width and height in metres, the long side is size_m
+    fn extent(&self) -> (f64, f64) {
+        let (width, height) = self.heightmap.dimensions();
+        let long_side = width.max(height) as f64;
+        (self.size_m * width as f64 / long_side, self.size_m * height as f64 / long_side)
+    }
+
+    fn pixel_size(&self) -> f64 {
+        let (width, height) = self.heightmap.dimensions();
+        self.size_m / width.max(height) as f64
+    }
+}
+
+// Distance in metres from every pixel to the closest marked pixel, or infinity without any
+struct DistanceField {
+    width: u32,
+    distances: Vec<f64>,
+}
+
+impl DistanceField {
+    // Two pass chamfer transform with diagonal steps of √2, close enough to Euclidean for rules
+    fn new(width: u32, height: u32, pixel_size: f64, marked: impl Fn(u32, u32) -> bool) -> DistanceField {
+        let (w, h) = (width as usize, height as usize);
+        let mut distances: Vec<f64> = (0..w * h).map(|i| if marked((i % w) as u32, (i / w) as u32) { 0.0 } else { f64::INFINITY }).collect();
+        let diagonal = std::f64::consts::SQRT_2;
+
+        for y in 0..h {
+            for x in 0..w {
+                let mut best = distances[y * w + x];
+                if x > 0 { best = best.min(distances[y * w + x - 1] + 1.0); }
+                if y > 0 {
+                    best = best.min(distances[(y - 1) * w + x] + 1.0);
+                    if x > 0 { best = best.min(distances[(y - 1) * w + x - 1] + diagonal); }
+                    if x + 1 < w { best = best.min(distances[(y - 1) * w + x + 1] + diagonal); }
+                }
+                distances[y * w + x] = best;
+            }
+        }
+        for y in (0..h).rev() {
+            for x in (0..w).rev() {
+                let mut best = distances[y * w + x];
+                if x + 1 < w { best = best.min(distances[y * w + x + 1] + 1.0); }
+                if y + 1 < h {
+                    best = best.min(distances[(y + 1) * w + x] + 1.0);
+                    if x + 1 < w { best = best.min(distances[(y + 1) * w + x + 1] + diagonal); }
+                    if x > 0 { best = best.min(distances[(y + 1) * w + x - 1] + diagonal); }
+                }
+                distances[y * w + x] = best;
+            }
+        }
+        for distance in distances.iter_mut() {
+            *distance *= pixel_size;
+        }
+        DistanceField { width, distances }
+    }
+
+    fn get(&self, x: u32, y: u32) -> f64 {
+        self.distances[(y * self.width + x) as usize]
+    }
+}
+
+// Places the points of every rule. The candidate positions only depend on the rule's seed and the
+// terrain size in metres, so the preview and an export at any resolution get the same points,
+// apart from where the rules decide differently on the finer terrain.
+pub fn scatter(terrain: &ScatterTerrain, rules: &[ScatterRule], cancel: &CancelToken) -> Result<Vec<ScatterSet>> {
+    let (width, height) = terrain.heightmap.dimensions();
+    let pixel_size = terrain.pixel_size();
+    let water = DistanceField::new(width, height, pixel_size, |x, y| {
+        terrain.heightmap.get_pixel(x, y)[0] <= terrain.sea_level
+    });
+    let rivers = DistanceField::new(width, height, pixel_size, |x, y| {
+        terrain.river_mask.map(|mask| mask.get_pixel(x, y)[0] > 127).unwrap_or(false)
+    });
+
+    let mut sets = Vec::with_capacity(rules.len());
+    for rule in rules {
+        cancel.check()?;
+        let (extent_x, extent_y) = terrain.extent();
+        let candidates = poisson_disk(extent_x, extent_y, rule.min_distance_m, rule.seed, cancel)?;
+        // A second generator draws the same three values for every candidate, kept or not,
+        // so one candidate's fate doesn't shift the scale and rotation of the others
+        let mut rng = StdRng::seed_from_u64(rule.seed as u64 ^ 0x5ca7_7e12);
+        let mut points = Vec::new();
+        for (x, y) in candidates {
+            let roll: f64 = rng.gen();
+            let scale = rule.scale_min + (rule.scale_max - rule.scale_min) * rng.gen::<f64>();
+            let rotation = rng.gen::<f64>() * 360.0;
+            if roll >= rule.density {
+                continue;
+            }
+            let px = ((x / pixel_size) as u32).min(width - 1);
+            let py = ((y / pixel_size) as u32).min(height - 1);
+            let elevation = height_m(terrain, px, py);
+            if elevation < rule.elevation_min_m || elevation > rule.elevation_max_m {
+                continue;
+            }
+            let normal = surface_normal(terrain, px, py);
+            let slope = normal[2].clamp(-1.0, 1.0).acos().to_degrees();
+            if slope > rule.slope_max_deg {
+                continue;
+            }
+            let water_distance = water.get(px, py);
+            if water_distance < rule.water_distance_min_m || (rule.water_distance_max_m > 0.0 && water_distance > rule.water_distance_max_m) {
+                continue;
+            }
+            if rivers.get(px, py) < rule.river_distance_min_m {
+                continue;
+            }
+            points.push(ScatterPoint {
+                x,
+                y,
+                z: sample_height(terrain, x, y),
+                normal,
+                scale,
+                rotation,
+            });
+        }
+        sets.push(ScatterSet { rule: rule.name.clone(), points });
+    }
+    Ok(sets)
+}
+
+// Bridson's Poisson-disk sampling over a width x height area in metres
+fn poisson_disk(width: f64, height: f64, min_distance: f64, seed: u32, cancel: &CancelToken) -> Result<Vec<(f64, f64)>> {
+    let min_distance = min_distance.max(0.01);
+    let cell = min_distance / std::f64::consts::SQRT_2;
+    let columns = (width / cell).ceil().max(1.0) as usize;
+    let rows = (height / cell).ceil().max(1.0) as usize;
+    let mut grid: Vec<Option<usize>> = vec![None; columns * rows];
+    let mut points: Vec<(f64, f64)> = Vec::new();
+    let mut active: Vec<usize> = Vec::new();
+    let mut rng = StdRng::seed_from_u64(seed as u64);
+    let cell_of = |x: f64, y: f64| (((x / cell) as usize).min(columns - 1), ((y / cell) as usize).min(rows - 1));
+
+    let first = (rng.gen::<f64>() * width, rng.gen::<f64>() * height);
+    let (column, row) = cell_of(first.0, first.1);
+    grid[row * columns + column] = Some(0);
+    points.push(first);
+    active.push(0);
+
+    while !active.is_empty() && points.len() < MAX_POINTS {
+        if points.len().is_multiple_of(4096) {
+            cancel.check()?;
+        }
+        let slot = rng.gen_range(0..active.len());
+        let (origin_x, origin_y) = points[active[slot]];
+        let mut found = false;
+        for _ in 0..CANDIDATES {
+            let angle = rng.gen::<f64>() * std::f64::consts::TAU;
+            let distance = min_distance * (1.0 + rng.gen::<f64>());
+            let (x, y) = (origin_x + angle.cos() * distance, origin_y + angle.sin() * distance);
+            if x < 0.0 || y < 0.0 || x >= width || y >= height {
+                continue;
+            }
+            let (column, row) = cell_of(x, y);
+            let mut free = true;
+            'neighbours: for neighbour_row in row.saturating_sub(2)..(row + 3).min(rows) {
+                for neighbour_column in column.saturating_sub(2)..(column + 3).min(columns) {
+                    if let Some(index) = grid[neighbour_row * columns + neighbour_column] {
+                        let (other_x, other_y) = points[index];
+                        if (other_x - x).powi(2) + (other_y - y).powi(2) < min_distance * min_distance {
+                            free = false;
+                            break 'neighbours;
+                        }
+                    }
+                }
+            }
+            if free {
+                grid[row * columns + column] = Some(points.len());
+                active.push(points.len());
+                points.push((x, y));
+                found = true;
+                break;
+            }
+        }
+        if !found {
+            active.swap_remove(slot);
+        }
+    }
+    Ok(points)
+}
+
+fn height_m(terrain: &ScatterTerrain, x: u32, y: u32) -> f64 {
+    terrain.min_elevation_m + terrain.heightmap.get_pixel(x, y)[0] as f64 / 255.0 * terrain.height_m
+}
+
+// Bilinear height in metres at a position in metres
+fn sample_height(terrain: &ScatterTerrain, x: f64, y: f64) -> f64 {
+    let (width, height) = terrain.heightmap.dimensions();
+    let pixel_size = terrain.pixel_size();
+    let fx = (x / pixel_size - 0.5).clamp(0.0, (width - 1) as f64);
+    let fy = (y / pixel_size - 0.5).clamp(0.0, (height - 1) as f64);
+    let (x0, y0) = (fx.floor() as u32, fy.floor() as u32);
+    let (x1, y1) = ((x0 + 1).min(width - 1), (y0 + 1).min(height - 1));
+    let (tx, ty) = (fx - x0 as f64, fy - y0 as f64);
+    let top = height_m(terrain, x0, y0) * (1.0 - tx) + height_m(terrain, x1, y0) * tx;
+    let bottom = height_m(terrain, x0, y1) * (1.0 - tx) + height_m(terrain, x1, y1) * tx;
+    top * (1.0 - ty) + bottom * ty
+}
+
+// Unit normal from central differences, with z pointing up
+fn surface_normal(terrain: &ScatterTerrain, x: u32, y: u32) -> [f64; 3] {
+    let (width, height) = terrain.heightmap.dimensions();
+    let pixel_size = terrain.pixel_size();
+    let (left, right) = (x.saturating_sub(1), (x + 1).min(width - 1));
+    let (up, down) = (y.saturating_sub(1), (y + 1).min(height - 1));
+    let dx = (height_m(terrain, right, y) - height_m(terrain, left, y)) / (((right - left).max(1)) as f64 * pixel_size);
+    let dy = (height_m(terrain, x, down) - height_m(terrain, x, up)) / (((down - up).max(1)) as f64 * pixel_size);
+    let length = (dx * dx + dy * dy + 1.0).sqrt();
+    [-dx / length, -dy / length, 1.0 / length]
+}
+
+// Marks every point with a small dot in its rule's colour
+pub fn draw_scatter_overlay(colormap: &mut ImageBuffer<Rgba<u8>, Vec<u8>>, sets: &[ScatterSet], rules: &[ScatterRule], size_m: f64) {
+    let (width, height) = colormap.dimensions();
+    let pixel_size = size_m / width.max(height) as f64;
+    for (set, rule) in sets.iter().zip(rules) {
+        let color = Rgba([rule.color[0], rule.color[1], rule.color[2], 255]);
+        for point in set.points.iter() {
+            let (px, py) = ((point.x / pixel_size) as i64, (point.y / pixel_size) as i64);
+            for (dx, dy) in [(0, 0), (1, 0), (-1, 0), (0, 1), (0, -1)] {
+                let (x, y) = (px + dx, py + dy);
+                if x >= 0 && y >= 0 && x < width as i64 && y < height as i64 {
+                    colormap.put_pixel(x as u32, y as u32, color);
+                }
+            }
+        }
+    }
+}
+
+pub fn write_scatter_csv(sets: &[ScatterSet], path: &Path) -> Result<()> {
+    let mut text = String::from("rule,x,y,z,normal_x,normal_y,normal_z,scale,rotation\n");
+    for set in sets {
+        for point in set.points.iter() {
+            text.push_str(&format!(
+                "{},{:.3},{:.3},{:.3},{:.5},{:.5},{:.5},{:.4},{:.2}\n",
+                set.rule, point.x, point.y, point.z, point.normal[0], point.normal[1], point.normal[2], point.scale, point.rotation
+            ));
+        }
+    }
+    write_text(path, &text)
+}
+
+pub fn write_scatter_json(sets: &[ScatterSet], path: &Path) -> Result<()> {
+    write_text(path, &serde_json::to_string_pretty(sets)?)
+}
+
+// Writes every point as <filename>_points.csv and <filename>_points.json. Returns the written paths.
+pub fn export_scatter_points(sets: &[ScatterSet], dir: &Path, filename: &str) -> Result<Vec<PathBuf>> {
+    let csv_path = output_path(dir, filename, "points", "csv");
+    write_scatter_csv(sets, &csv_path)?;
+    let json_path = output_path(dir, filename, "points", "json");
+    write_scatter_json(sets, &json_path)?;
+    Ok(vec![csv_path, json_path])
+}
@@ -0,0 +1,226 @@
+use image::{ImageBuffer, Rgba};
+use noise::{NoiseFn, Perlin};
+use serde_derive::{Serialize, Deserialize};
+use super::cancel::CancelToken;
+use super::error::Result;
+
+// Height change of one dab at full strength for the raise, lower and noise brushes
+const DAB_STEP: f32 = 4.0;
+// Distance between dabs along a stroke, relative to the brush radius
+const DAB_SPACING: f32 = 0.25;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+pub enum BrushMode {
+    #[default]
+    Raise,
+    Lower,
+    Smooth,
+    Flatten,
+    Noise,
+}
+
+impl BrushMode {
+    pub fn from_index(index: i32) -> BrushMode {
+        match index {
+            1 => BrushMode::Lower,
+            2 => BrushMode::Smooth,
+            3 => BrushMode::Flatten,
+            4 => BrushMode::Noise,
+            _ => BrushMode::Raise,
+        }
+    }
+
+    pub fn index(&self) -> i32 {
+        match self {
+            BrushMode::Raise => 0,
+            BrushMode::Lower => 1,
+            BrushMode::Smooth => 2,
+            BrushMode::Flatten => 3,
+            BrushMode::Noise => 4,
+        }
+    }
+}
+
+// One brush stroke. Positions are 0-1 across the image and the radius is a fraction of its
+// long side, so strokes painted on the preview land on the same spot at every export size.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(default)]
+pub struct Stroke {
+    pub mode: BrushMode,
+    pub radius: f32,
+    pub strength: f32,
+    // 0 is a hard edge, 1 fades all the way from the centre
+    pub falloff: f32,
+    pub seed: u32,
+    pub points: Vec<[f32; 2]>,
+}
+
+impl Default for Stroke {
+    fn default() -> Self {
+        Stroke {
+            mode: BrushMode::Raise,
+            radius: 0.05,
+            strength: 0.5,
+            falloff: 0.5,
+            seed: 1,
+            points: Vec::new(),
+        }
+    }
+}
+
+// Replays the strokes in order on top of the heightmap. Heights are kept as floats until the
+// end so many weak dabs add up instead of rounding away.
+pub fn apply_strokes(buffer: &mut ImageBuffer<Rgba<u8>, Vec<u8>>, strokes: &[Stroke], cancel: &CancelToken) -> Result<()> {
+    if strokes.is_empty() {
+        return Ok(());
+    }
+    let (width, height) = buffer.dimensions();
+    let mut heights: Vec<f32> = buffer.pixels().map(|pixel| pixel[0] as f32).collect();
+
+    for stroke in strokes {
+        cancel.check()?;
+        apply_stroke(&mut heights, width, height, stroke);
+    }
+
+    for (pixel, value) in buffer.pixels_mut().zip(heights) {
+        let value = value.round().clamp(0.0, 255.0) as u8;
+        pixel[0] = value;
+        pixel[1] = value;
+        pixel[2] = value;
+    }
+    Ok(())
+}
+
+fn apply_stroke(heights: &mut [f32], width: u32, height: u32, stroke: &Stroke) {
+    let long_side = width.max(height) as f32;
+    let radius = (stroke.radius * long_side).max(1.0);
+    let strength = stroke.strength.clamp(0.0, 1.0);
+    let to_pixels = |point: &[f32; 2]| (point[0] * width as f32, point[1] * height as f32);
+
+    // Flatten pulls everything towards the height under the first dab
+    let target = stroke.points.first().map(|point| {
+        let (x, y) = to_pixels(point);
+        let x = (x as u32).min(width - 1);
+        let y = (y as u32).min(height - 1);
+        heights[(y * width + x) as usize]
+    });
+    let perlin = Perlin::new(stroke.seed);
+
+    for (x, y) in dabs(stroke.points.iter().map(to_pixels), radius * DAB_SPACING) {
+        let min_x = (x - radius).floor().max(0.0) as u32;
+        let max_x = ((x + radius).ceil().max(0.0) as u32).min(width - 1);
+        let min_y = (y - radius).floor().max(0.0) as u32;
+        let max_y = ((y + radius).ceil().max(0.0) as u32).min(height - 1);
+        if min_x > max_x || min_y > max_y {
+            continue;
+        }
+
+        let averages = if stroke.mode == BrushMode::Smooth {
+            Some(box_average(heights, width, height, (min_x, min_y, max_x, max_y), (radius / 4.0).ceil() as u32))
+        } else {
+            None
+        };
+
+        for py in min_y..=max_y {
+            for px in min_x..=max_x {
+                let distance = ((px as f32 + 0.5 - x).powi(2) + (py as f32 + 0.5 - y).powi(2)).sqrt() / radius;
+                let weight = falloff_weight(distance, stroke.falloff) * strength;
+                if weight <= 0.0 {
+                    continue;
+                }
+                let index = (py * width + px) as usize;
+                let value = heights[index];
+                heights[index] = match stroke.mode {
+                    BrushMode::Raise => value + weight * DAB_STEP,
+                    BrushMode::Lower => value - weight * DAB_STEP,
+                    BrushMode::Smooth => {
+                        let averages = averages.as_ref().unwrap();
+                        let average = averages[((py - min_y) * (max_x - min_x + 1) + (px - min_x)) as usize];
+                        value + (average - value) * weight
+                    }
+                    BrushMode::Flatten => value + (target.unwrap_or(value) - value) * weight,
+                    BrushMode::Noise => {
+                        // Sampled in image space so the pattern doesn't change with the export size
+                        let frequency = 4.0 / (stroke.radius.max(0.001) as f64);
+                        let sample = perlin.get([px as f64 / long_side as f64 * frequency, py as f64 / long_side as f64 * frequency]) as f32;
+                        value + sample * weight * DAB_STEP * 2.0
+                    }
+                }.clamp(0.0, 255.0);
+            }
+        }
+    }
+}
+
+// Evenly spaced dab centres along the stroke, so the result doesn't depend on how fast the mouse moved
+fn dabs(points: impl Iterator<Item = (f32, f32)>, spacing: f32) -> Vec<(f32, f32)> {
+    let spacing = spacing.max(0.5);
+    let mut result = Vec::new();
+    let mut previous: Option<(f32, f32)> = None;
+    let mut carried = 0.0;
+    for point in points {
+        match previous {
+            None => result.push(point),
+            Some(last) => {
+                let length = ((point.0 - last.0).powi(2) + (point.1 - last.1).powi(2)).sqrt();
+                let mut travelled = spacing - carried;
+                while travelled <= length {
+                    let t = travelled / length;
+                    result.push((last.0 + (point.0 - last.0) * t, last.1 + (point.1 - last.1) * t));
+                    travelled += spacing;
+                }
+                carried = length - (travelled - spacing);
+            }
+        }
+        previous = Some(point);
+    }
+    result
+}
+
+fn falloff_weight(distance: f32, falloff: f32) -> f32 {
+    if distance >= 1.0 {
+        return 0.0;
+    }
+    let falloff = falloff.clamp(0.0, 1.0);
+    let inner = 1.0 - falloff;
+    if distance <= inner {
+        return 1.0;
+    }
+    let t = (1.0 - distance) / falloff;
+    t * t * (3.0 - 2.0 * t)
+}
+
+// Mean height in a square window around every pixel of the region, read from a summed area table
+fn box_average(heights: &[f32], width: u32, height: u32, region: (u32, u32, u32, u32), window: u32) -> Vec<f32> {
+    let (min_x, min_y, max_x, max_y) = region;
+    let window = window.max(1);
+    let table_min_x = min_x.saturating_sub(window);
+    let table_min_y = min_y.saturating_sub(window);
+    let table_max_x = (max_x + window).min(width - 1);
+    let table_max_y = (max_y + window).min(height - 1);
+    let table_width = (table_max_x - table_min_x + 2) as usize;
+    let table_height = (table_max_y - table_min_y + 2) as usize;
+
+    let mut table = vec![0.0f64; table_width * table_height];
+    for ty in 1..table_height {
+        let mut row_sum = 0.0;
+        for tx in 1..table_width {
+            let x = table_min_x + tx as u32 - 1;
+            let y = table_min_y + ty as u32 - 1;
+            row_sum += heights[(y * width + x) as usize] as f64;
+            table[ty * table_width + tx] = table[(ty - 1) * table_width + tx] + row_sum;
+        }
+    }
+
+    let mut averages = Vec::with_capacity(((max_x - min_x + 1) * (max_y - min_y + 1)) as usize);
+    for y in min_y..=max_y {
+        for x in min_x..=max_x {
+            let x0 = (x.saturating_sub(window) - table_min_x) as usize;
+            let y0 = (y.saturating_sub(window) - table_min_y) as usize;
+            let x1 = ((x + window).min(table_max_x) - table_min_x + 1) as usize;
+            let y1 = ((y + window).min(table_max_y) - table_min_y + 1) as usize;
+            let sum = table[y1 * table_width + x1] - table[y0 * table_width + x1] - table[y1 * table_width + x0] + table[y0 * table_width + x0];
+            averages.push((sum / ((x1 - x0) * (y1 - y0)) as f64) as f32);
+        }
+    }
+    averages
+}
@@ -0,0 +1,64 @@
+use super::constants::IMAGE_SIZE;
+use super::error::{HeightmapError, Result};
+
+// Sizes offered in the UI. 2ⁿ+1 sizes are what Unity and most heightfield tools expect,
+// 1009, 2017 and 4033 are recommended Unreal landscape sizes and the 2:1 sizes suit world maps.
+pub const SIZE_PRESETS: [(u32, u32); 15] = [
+    (256, 256),
+    (512, 512),
+    (1024, 1024),
+    (2048, 2048),
+    (4096, 4096),
+    (257, 257),
+    (513, 513),
+    (1025, 1025),
+    (2049, 2049),
+    (4097, 4097),
+    (1009, 1009),
+    (2017, 2017),
+    (4033, 4033),
+    (2048, 1024),
+    (4096, 2048),
+];
+
+pub const MAX_SIZE: u32 = 16384;
+
+pub fn preset_label(width: u32, height: u32) -> String {
+    let mut label = format!("{} x {}", width, height);
+    if width == height && width > 1 && (width - 1).is_power_of_two() {
+        label.push_str(" (2^n+1)");
+    } else if width == height && matches!(width, 1009 | 2017 | 4033) {
+        label.push_str(" (Unreal)");
+    } else if width == 2 * height {
+        label.push_str(" (2:1)");
+    }
+    label
+}
+
+pub fn check_size(width: u32, height: u32) -> Result<()> {
+    if width == 0 || height == 0 || width > MAX_SIZE || height > MAX_SIZE {
+        return Err(HeightmapError::InvalidSize { width, height });
+    }
+    Ok(())
+}
+
+// The preview keeps the aspect ratio of the output with its long side at IMAGE_SIZE,
+// unless the output itself is smaller than that
+pub fn preview_size(width: u32, height: u32) -> (u32, u32) {
+    let long_side = width.max(height);
+    if long_side <= IMAGE_SIZE {
+        return (width, height);
+    }
+    let fit = |side: u32| ((side as f64 * IMAGE_SIZE as f64 / long_side as f64).round() as u32).max(1);
+    (fit(width), fit(height))
+}
+
+// Output pixels per preview pixel, see EvalContext::detail
+pub fn detail(width: u32, height: u32) -> f64 {
+    let (preview_width, preview_height) = preview_size(width, height);
+    if width >= height {
+        width as f64 / preview_width as f64
+    } else {
+        height as f64 / preview_height as f64
+    }
+}
@@ -0,0 +1,71 @@
+use image::{ImageBuffer, Rgba};
+use super::terrain::Terrain;
+
+const BAR_COLOR: Rgba<u8> = Rgba([200, 200, 200, 255]);
+const WATER_BAR_COLOR: Rgba<u8> = Rgba([60, 120, 220, 255]);
+const BACKGROUND: Rgba<u8> = Rgba([22, 22, 22, 255]);
+
+// Heights are 0-255 like the heightmap
+pub struct HeightStats {
+    pub min: u8,
+    pub max: u8,
+    pub mean: f64,
+    pub std_dev: f64,
+    pub histogram: [u64; 256],
+    // Water sits exactly at the sea level after flattening, so it counts as below. None without a sea.
+    pub below_sea_percent: Option<f64>,
+}
+
+// Number of pixels at each height
+pub fn histogram(buffer: &ImageBuffer<Rgba<u8>, Vec<u8>>) -> [u64; 256] {
+    let mut counts = [0u64; 256];
+    for pixel in buffer.pixels() {
+        counts[pixel[0] as usize] += 1;
+    }
+    counts
+}
+
+pub fn height_stats(buffer: &ImageBuffer<Rgba<u8>, Vec<u8>>, sea_level: Option<u8>) -> HeightStats {
+    let histogram = histogram(buffer);
+    let total = histogram.iter().sum::<u64>().max(1) as f64;
+    let mean = histogram.iter().enumerate().map(|(value, &count)| value as f64 * count as f64).sum::<f64>() / total;
+    let variance = histogram.iter().enumerate().map(|(value, &count)| (value as f64 - mean).powi(2) * count as f64).sum::<f64>() / total;
+    HeightStats {
+        min: histogram.iter().position(|&count| count > 0).unwrap_or(0) as u8,
+        max: histogram.iter().rposition(|&count| count > 0).unwrap_or(0) as u8,
+        mean,
+        std_dev: variance.sqrt(),
+        histogram,
+        below_sea_percent: sea_level.map(|level| histogram[..=level as usize].iter().sum::<u64>() as f64 / total * 100.0),
+    }
+}
+
+// One column per height, scaled to the most common one, blue up to the sea level
+pub fn render_histogram(stats: &HeightStats, sea_level: Option<u8>, height: u32) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    let highest = stats.histogram.iter().copied().max().unwrap_or(0).max(1) as f64;
+    ImageBuffer::from_fn(256, height, |x, y| {
+        let bar = (stats.histogram[x as usize] as f64 / highest * height as f64).ceil() as u32;
+        if y < height - bar {
+            BACKGROUND
+        } else if sea_level.is_some_and(|level| x <= level as u32) {
+            WATER_BAR_COLOR
+        } else {
+            BAR_COLOR
+        }
+    })
+}
+
+// The heights as elevations of the terrain
+pub fn height_summary(stats: &HeightStats, terrain: &Terrain) -> String {
+    let summary = format!(
+        "Min {:.0} m, max {:.0} m, mean {:.1} m, std dev {:.1} m",
+        terrain.elevation_m(stats.min as f64),
+        terrain.elevation_m(stats.max as f64),
+        terrain.elevation_m(stats.mean),
+        stats.std_dev / 255.0 * terrain.relief_m(),
+    );
+    match stats.below_sea_percent {
+        Some(percent) => format!("{}\n{:.1}% at or below sea level", summary, percent),
+        None => summary,
+    }
+}
@@ -0,0 +1,73 @@
+use serde_derive::{Serialize, Deserialize};
+
+// Smallest difference between the lowest and highest elevation, so a flat range can't divide by zero
+const MIN_RELIEF_M: f64 = 0.01;
+
+// The real-world extent of the map. Heights 0-255 span the minimum to the maximum elevation and
+// the long side of the map is the world size.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(default)]
+pub struct Terrain {
+    pub world_size_km: f64,
+    pub min_elevation_m: f64,
+    pub max_elevation_m: f64,
+    // Flatten Ground raises everything below it to this level
+    pub sea_level_m: f64,
+}
+
+impl Default for Terrain {
+    fn default() -> Self {
+        Terrain {
+            world_size_km: 1.0,
+            min_elevation_m: 0.0,
+            max_elevation_m: 500.0,
+            sea_level_m: 0.0,
+        }
+    }
+}
+
+impl Terrain {
+    pub fn size_m(&self) -> f64 {
+        self.world_size_km * 1000.0
+    }
+
+    // Metres between height 0 and height 255
+    pub fn relief_m(&self) -> f64 {
+        (self.max_elevation_m - self.min_elevation_m).max(MIN_RELIEF_M)
+    }
+
+    // Side of one pixel in metres for a map of the given size
+    pub fn pixel_size_m(&self, width: u32, height: u32) -> f64 {
+        self.size_m() / width.max(height).max(1) as f64
+    }
+
+    // Elevation of a height 0-255, fractions allowed
+    pub fn elevation_m(&self, height: f64) -> f64 {
+        self.min_elevation_m + height / 255.0 * self.relief_m()
+    }
+
+    // Height 0-255 of an elevation, not rounded or clamped
+    pub fn height(&self, elevation_m: f64) -> f64 {
+        (elevation_m - self.min_elevation_m) / self.relief_m() * 255.0
+    }
+
+    // Height steps of a vertical distance, e.g. a river depth
+    pub fn height_steps(&self, distance_m: f64) -> f64 {
+        distance_m / self.relief_m() * 255.0
+    }
+
+    pub fn sea_level(&self) -> u8 {
+        self.height(self.sea_level_m).round().clamp(0.0, 255.0) as u8
+    }
+
+    // Height difference between neighbouring pixels, as a fraction of the full range, at which a
+    // slope reaches the angle
+    pub fn slope_threshold(&self, angle_degrees: f64, width: u32, height: u32) -> f64 {
+        angle_degrees.clamp(0.0, 90.0).to_radians().tan() * self.pixel_size_m(width, height) / self.relief_m()
+    }
+
+    // Angle in degrees of a slope threshold, the reverse of slope_threshold
+    pub fn slope_angle(&self, threshold: f64, width: u32, height: u32) -> f64 {
+        (threshold * self.relief_m() / self.pixel_size_m(width, height)).atan().to_degrees()
+    }
+}
@@ -0,0 +1,143 @@
+use image::{ImageBuffer, Luma, Pixel, Rgba};
+use serde_derive::{Serialize, Deserialize};
+use std::path::{Path, PathBuf};
+use super::constants::COLORS;
+use super::engine::{engine_sidecar, to_height16, EnginePreset, EngineSidecar};
+use super::error::{HeightmapError, Result};
+use super::output::{output_path, save_gray, save_height16, write_text};
+use super::terrain::Terrain;
+
+// Tile resolutions offered in the UI, matching Unreal's landscape component sizes
+pub const TILE_SIZES: [u32; 4] = [253, 505, 1009, 2017];
+
+// Paint layers written as weight tiles, in the order of the colormap's height bands
+pub const WEIGHT_LAYERS: [&str; 5] = ["Water", "Grass", "Dirt", "Rock", "Snow"];
+
+// Describes a tiled export so it can be imported or checked without counting files
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct TileManifest {
+    pub tile_size: u32,
+    pub columns: u32,
+    pub rows: u32,
+    pub width: u32,
+    pub height: u32,
+    pub heightmap_pattern: String,
+    pub weight_patterns: Vec<String>,
+    pub unreal: EngineSidecar,
+}
+
+// Neighbouring tiles share their edge row and column, so tiles start every tile_size - 1 pixels
+pub fn tile_grid(width: u32, height: u32, tile_size: u32) -> (u32, u32) {
+    let step = tile_size.saturating_sub(1).max(1);
+    let count = |side: u32| (side.saturating_sub(1)).div_ceil(step).max(1);
+    (count(width), count(height))
+}
+
+// Cuts one tile out of the image. Tiles that reach past the edge repeat the last row and column,
+// because Unreal needs every tile to have the same size.
+pub fn extract_tile<P: Pixel>(image: &ImageBuffer<P, Vec<P::Subpixel>>, column: u32, row: u32, tile_size: u32) -> ImageBuffer<P, Vec<P::Subpixel>> {
+    let (width, height) = image.dimensions();
+    let step = tile_size.saturating_sub(1).max(1);
+    ImageBuffer::from_fn(tile_size, tile_size, |x, y| {
+        let source_x = (column * step + x).min(width - 1);
+        let source_y = (row * step + y).min(height - 1);
+        *image.get_pixel(source_x, source_y)
+    })
+}
+
+// One weight map per entry of WEIGHT_LAYERS. Heights are split into the same bands colorize_buffer
+// uses, blending between neighbouring bands, and the water mask, if there is one, takes precedence.
+// The weights of a pixel add up to 255.
+pub fn weight_maps(heightmap: &ImageBuffer<Rgba<u8>, Vec<u8>>, water_mask: Option<&ImageBuffer<Rgba<u8>, Vec<u8>>>) -> Vec<ImageBuffer<Luma<u8>, Vec<u8>>> {
+    let (width, height) = heightmap.dimensions();
+    let mut maps: Vec<ImageBuffer<Luma<u8>, Vec<u8>>> = WEIGHT_LAYERS.iter().map(|_| ImageBuffer::new(width, height)).collect();
+
+    for (x, y, pixel) in heightmap.enumerate_pixels() {
+        let mut weights = [0.0f32; WEIGHT_LAYERS.len()];
+        let t = pixel[0] as f32 / 255.0 * (COLORS.len() - 1) as f32;
+        let lower = t.floor() as usize;
+        let upper = (lower + 1).min(COLORS.len() - 1);
+        let factor = t - lower as f32;
+        weights[band_layer(lower)] += 1.0 - factor;
+        weights[band_layer(upper)] += factor;
+
+        if let Some(mask) = water_mask {
+            let water = mask.get_pixel(x, y)[0] as f32 / 255.0;
+            for weight in weights.iter_mut() {
+                *weight *= 1.0 - water;
+            }
+            weights[0] += water;
+        }
+
+        // Rounding can leave the sum one off, the largest weight takes the difference
+        let mut values = weights.map(|weight| (weight * 255.0).round() as i32);
+        let largest = (0..values.len()).max_by_key(|&index| values[index]).unwrap_or(0);
+        values[largest] += 255 - values.iter().sum::<i32>();
+        for (map, value) in maps.iter_mut().zip(values) {
+            map.put_pixel(x, y, Luma([value.clamp(0, 255) as u8]));
+        }
+    }
+    maps
+}
+
+// Colorize starts at the grassland colours, so the two water bands count as grass as well.
+// Water comes from the flatten mask instead.
+fn band_layer(index: usize) -> usize {
+    match index {
+        0..=4 => 1,
+        5 | 6 => 2,
+        7 | 8 => 3,
+        _ => 4,
+    }
+}
+
+// Writes the heightmap as 16-bit tiles named <filename>_x<column>_y<row>.png, the weight layers as
+// <filename>_<layer>_x<column>_y<row>.png and a manifest. Returns the manifest's path.
+pub fn export_world_partition_tiles(
+    heightmap: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    water_mask: Option<&ImageBuffer<Rgba<u8>, Vec<u8>>>,
+    dir: &Path,
+    filename: &str,
+    tile_size: u32,
+    terrain: &Terrain,
+) -> Result<PathBuf> {
+    if tile_size < 2 {
+        return Err(HeightmapError::InvalidSize { width: tile_size, height: tile_size });
+    }
+    let (width, height) = heightmap.dimensions();
+    let (columns, rows) = tile_grid(width, height, tile_size);
+    let field = to_height16(heightmap);
+    let weights = weight_maps(heightmap, water_mask);
+
+    for row in 0..rows {
+        for column in 0..columns {
+            let position = format!("x{}_y{}", column, row);
+            save_height16(&extract_tile(&field, column, row, tile_size), &output_path(dir, filename, &position, "png"))?;
+            for (layer, map) in WEIGHT_LAYERS.iter().zip(weights.iter()) {
+                let suffix = format!("{}_{}", layer, position);
+                save_gray(&extract_tile(map, column, row, tile_size), &output_path(dir, filename, &suffix, "png"))?;
+            }
+        }
+    }
+
+    // The landscape covers every tile, including any repeated edge, at the spacing of the heightmap
+    let step = tile_size - 1;
+    let resolution = columns.max(rows) * step + 1;
+    let spacing = terrain.size_m() / (width.max(height).max(2) - 1) as f64;
+    let landscape = Terrain { world_size_km: spacing * (resolution - 1) as f64 / 1000.0, ..*terrain };
+    // The filename may include folders, the patterns are relative to the tiles
+    let name = Path::new(filename).file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_else(|| filename.to_string());
+    let manifest = TileManifest {
+        tile_size,
+        columns,
+        rows,
+        width,
+        height,
+        heightmap_pattern: format!("{}_x{{x}}_y{{y}}.png", name),
+        weight_patterns: WEIGHT_LAYERS.iter().map(|layer| format!("{}_{}_x{{x}}_y{{y}}.png", name, layer)).collect(),
+        unreal: engine_sidecar(EnginePreset::Unreal, &format!("{}_x0_y0.png", name), resolution, &landscape),
+    };
+    let manifest_path = output_path(dir, filename, "tiles", "json");
+    write_text(&manifest_path, &serde_json::to_string_pretty(&manifest)?)?;
+    Ok(manifest_path)
+}
@@ -0,0 +1,341 @@
+use image::{ImageBuffer, Luma, Rgba};
+use serde_derive::{Serialize, Deserialize};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::path::{Path, PathBuf};
+use super::analysis::hue;
+use super::cancel::CancelToken;
+use super::contours::hillshade;
+use super::error::Result;
+use super::heightmap::NEIGHBOURS;
+use super::output::{output_path, save_height16, save_image, write_text};
+use super::terrain::Terrain;
+
+// The IDs are written as a 16-bit PNG, so smaller basins are merged beyond this
+pub const MAX_BASINS: usize = 65_535;
+const UNLABELLED: usize = usize::MAX;
+const WATER_COLOR: [u8; 3] = [40, 70, 130];
+const BORDER_COLOR: [u8; 3] = [40, 40, 40];
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(default)]
+pub struct WatershedParams {
+    // Basins with less than this share of the land are merged into the neighbour they border most
+    pub min_area_percent: f64,
+}
+
+impl Default for WatershedParams {
+    fn default() -> Self {
+        WatershedParams { min_area_percent: 0.5 }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Outlet {
+    // Drains off the side of the map
+    Edge,
+    // Drains into the sea
+    Water,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct Basin {
+    pub id: u32,
+    pub area_px: usize,
+    pub area_km2: f64,
+    // The pixel the basin drains through, and its centre in metres from the top left corner
+    pub outlet_px: [u32; 2],
+    pub outlet_m: [f64; 2],
+    pub outlet_elevation_m: f64,
+    pub drains_to: Outlet,
+}
+
+pub struct Watersheds {
+    pub width: u32,
+    pub height: u32,
+    // Basin ID of every pixel, 0 for water
+    pub labels: Vec<u32>,
+    // Largest first, so basin n is at index n - 1
+    pub basins: Vec<Basin>,
+}
+
+// Where the flooding started: a land pixel on the map edge or next to water
+struct Seed {
+    index: usize,
+    drains_to: Outlet,
+}
+
+// Heights with 8 more bits, blurred twice over 3x3 pixels, so the flat steps of the 8-bit
+// heightmap still slope and the water on them runs downhill instead of the way the flood came
+fn smoothed_levels(heights: &[u8], w: usize, h: usize) -> Vec<u32> {
+    let mut levels: Vec<u32> = heights.iter().map(|&height| (height as u32) << 8).collect();
+    for _ in 0..2 {
+        let blurred: Vec<u32> = (0..w * h).map(|index| {
+            let (x, y) = (index % w, index / w);
+            let (mut sum, mut count) = (0, 0);
+            for ny in y.saturating_sub(1)..(y + 2).min(h) {
+                for nx in x.saturating_sub(1)..(x + 2).min(w) {
+                    sum += levels[ny * w + nx];
+                    count += 1;
+                }
+            }
+            sum / count
+        }).collect();
+        levels = blurred;
+    }
+    levels
+}
+
+// Splits the land into drainage basins. Starting from every outlet pixel, the terrain is flooded
+// from the lowest pixel up to fill its pits. Like a river, every pixel then drains to its lowest
+// neighbour on the filled terrain, or across a filled pit towards where the flood came from, and
+// joins the basin of the outlet it ends up at. Basins below the minimum area are merged into the
+// neighbour they share the longest border with, keeping the outlet of the larger part, which is
+// the mouth of the main river. Heights at or below sea level are water, whether or not the ground
+// was flattened.
+pub fn watersheds(heightmap: &ImageBuffer<Rgba<u8>, Vec<u8>>, params: &WatershedParams, terrain: &Terrain, cancel: &CancelToken) -> Result<Watersheds> {
+    let (width, height) = heightmap.dimensions();
+    let (w, h) = (width as usize, height as usize);
+    let heights: Vec<u8> = heightmap.pixels().map(|pixel| pixel[0]).collect();
+    let levels = smoothed_levels(&heights, w, h);
+    let sea_level = terrain.sea_level();
+    let water: Vec<bool> = heights.iter().map(|&value| value <= sea_level).collect();
+    let neighbours = |index: usize| {
+        let (x, y) = ((index % w) as i64, (index / w) as i64);
+        NEIGHBOURS.iter().filter_map(move |&(dx, dy)| {
+            let (nx, ny) = (x + dx as i64, y + dy as i64);
+            (nx >= 0 && ny >= 0 && nx < w as i64 && ny < h as i64).then(|| ny as usize * w + nx as usize)
+        })
+    };
+
+    let mut flooded_from = vec![UNLABELLED; w * h];
+    let mut filled = levels.clone();
+    let mut starts: Vec<Seed> = Vec::new();
+    let mut queue = BinaryHeap::new();
+    let mut order = 0usize;
+    for index in 0..w * h {
+        if water[index] {
+            continue;
+        }
+        let (x, y) = (index % w, index / w);
+        let drains_to = if neighbours(index).any(|neighbour| water[neighbour]) {
+            Outlet::Water
+        } else if x == 0 || y == 0 || x == w - 1 || y == h - 1 {
+            Outlet::Edge
+        } else {
+            continue;
+        };
+        flooded_from[index] = index;
+        starts.push(Seed { index, drains_to });
+        queue.push(Reverse((levels[index], order, index)));
+        order += 1;
+    }
+
+    // Priority flood; ties keep the order they were reached in, so the result is deterministic
+    let mut popped = 0usize;
+    while let Some(Reverse((level, _, index))) = queue.pop() {
+        popped += 1;
+        if popped.is_multiple_of(65_536) {
+            cancel.check()?;
+        }
+        for neighbour in neighbours(index) {
+            if water[neighbour] || flooded_from[neighbour] != UNLABELLED {
+                continue;
+            }
+            flooded_from[neighbour] = index;
+            filled[neighbour] = levels[neighbour].max(level);
+            queue.push(Reverse((filled[neighbour], order, neighbour)));
+            order += 1;
+        }
+    }
+    cancel.check()?;
+
+    // Downhill is always lower, and across flats the flood came from a pixel reached earlier, so
+    // following it can't go in circles. The flood starts lead nowhere.
+    let downstream = |index: usize| {
+        let mut lowest = (filled[index], flooded_from[index]);
+        for neighbour in neighbours(index) {
+            if !water[neighbour] && filled[neighbour] < lowest.0 {
+                lowest = (filled[neighbour], neighbour);
+            }
+        }
+        lowest.1
+    };
+    // Water next to the coast is always lower, but off the map edge only counts where no
+    // neighbour is lower, otherwise the water runs along the edge
+    let mut labels = vec![UNLABELLED; w * h];
+    let mut seeds: Vec<Seed> = Vec::new();
+    for start in starts {
+        if start.drains_to == Outlet::Water || downstream(start.index) == start.index {
+            labels[start.index] = seeds.len();
+            seeds.push(start);
+        }
+    }
+    let mut path = Vec::new();
+    for start in 0..w * h {
+        if water[start] || labels[start] != UNLABELLED {
+            continue;
+        }
+        let mut index = start;
+        while labels[index] == UNLABELLED {
+            path.push(index);
+            index = downstream(index);
+        }
+        let label = labels[index];
+        for step in path.drain(..) {
+            labels[step] = label;
+        }
+    }
+
+    let mut areas = vec![0usize; seeds.len()];
+    for &label in labels.iter().filter(|&&label| label != UNLABELLED) {
+        areas[label] += 1;
+    }
+    let land = areas.iter().sum::<usize>();
+    let min_area = (params.min_area_percent.max(0.0) / 100.0 * land as f64).ceil() as usize;
+
+    // Shared border lengths between the basins
+    let mut borders: Vec<HashMap<usize, usize>> = vec![HashMap::new(); seeds.len()];
+    for y in 0..h {
+        for x in 0..w {
+            let label = labels[y * w + x];
+            if label == UNLABELLED {
+                continue;
+            }
+            for other in [(x + 1 < w).then(|| labels[y * w + x + 1]), (y + 1 < h).then(|| labels[(y + 1) * w + x])].into_iter().flatten() {
+                if other != UNLABELLED && other != label {
+                    *borders[label].entry(other).or_default() += 1;
+                    *borders[other].entry(label).or_default() += 1;
+                }
+            }
+        }
+    }
+
+    // Merge the smallest basin first; entries whose area is out of date are skipped
+    let mut parent: Vec<usize> = (0..seeds.len()).collect();
+    let mut outlets: Vec<usize> = (0..seeds.len()).collect();
+    let mut merged_areas = areas.clone();
+    let mut remaining = seeds.len();
+    let mut smallest: BinaryHeap<Reverse<(usize, usize)>> = (0..seeds.len()).map(|label| Reverse((areas[label], label))).collect();
+    while let Some(Reverse((area, label))) = smallest.pop() {
+        if parent[label] != label || merged_areas[label] != area {
+            continue;
+        }
+        if area >= min_area && remaining <= MAX_BASINS {
+            break;
+        }
+        let Some(target) = borders[label].iter().max_by_key(|&(&other, &length)| (length, Reverse(other))).map(|(&other, _)| other) else {
+            // An island with a single small basin has nothing to join
+            continue;
+        };
+        parent[label] = target;
+        merged_areas[target] += area;
+        if areas[outlets[label]] > areas[outlets[target]] {
+            outlets[target] = outlets[label];
+        }
+        for (other, length) in std::mem::take(&mut borders[label]) {
+            borders[other].remove(&label);
+            if other != target {
+                *borders[target].entry(other).or_default() += length;
+                *borders[other].entry(target).or_default() += length;
+            }
+        }
+        remaining -= 1;
+        smallest.push(Reverse((merged_areas[target], target)));
+    }
+
+    let root = |mut label: usize| {
+        while parent[label] != label {
+            label = parent[label];
+        }
+        label
+    };
+    let mut roots: Vec<usize> = (0..seeds.len()).filter(|&label| parent[label] == label).collect();
+    roots.sort_by_key(|&label| (Reverse(merged_areas[label]), seeds[outlets[label]].index));
+    let mut ids = vec![0u32; seeds.len()];
+    for (rank, &label) in roots.iter().enumerate() {
+        ids[label] = rank as u32 + 1;
+    }
+    let mut seed_ids = vec![0u32; seeds.len()];
+    for label in 0..seeds.len() {
+        seed_ids[label] = ids[root(label)];
+    }
+
+    let pixel_size = terrain.pixel_size_m(width, height);
+    let basins = roots.iter().map(|&label| {
+        let seed = &seeds[outlets[label]];
+        let (x, y) = ((seed.index % w) as u32, (seed.index / w) as u32);
+        Basin {
+            id: ids[label],
+            area_px: merged_areas[label],
+            area_km2: merged_areas[label] as f64 * pixel_size * pixel_size / 1_000_000.0,
+            outlet_px: [x, y],
+            outlet_m: [(x as f64 + 0.5) * pixel_size, (y as f64 + 0.5) * pixel_size],
+            outlet_elevation_m: terrain.elevation_m(heights[seed.index] as f64),
+            drains_to: seed.drains_to,
+        }
+    }).collect();
+
+    Ok(Watersheds {
+        width,
+        height,
+        labels: labels.iter().map(|&label| if label == UNLABELLED { 0 } else { seed_ids[label] }).collect(),
+        basins,
+    })
+}
+
+// A distinct colour per basin, shaded with the relief, with dark borders between basins and a
+// white dot on every outlet
+pub fn render_basins(watersheds: &Watersheds, heightmap: &ImageBuffer<Rgba<u8>, Vec<u8>>, terrain: &Terrain) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    let (width, height) = (watersheds.width, watersheds.height);
+    let shade = hillshade(heightmap, terrain.size_m(), terrain.relief_m());
+    let label = |x: u32, y: u32| watersheds.labels[(y * width + x) as usize];
+    let mut image = ImageBuffer::from_fn(width, height, |x, y| {
+        let id = label(x, y);
+        let border = (x + 1 < width && label(x + 1, y) != id && label(x + 1, y) != 0)
+            || (y + 1 < height && label(x, y + 1) != id && label(x, y + 1) != 0);
+        let [r, g, b] = if id == 0 {
+            WATER_COLOR
+        } else if border {
+            BORDER_COLOR
+        } else {
+            // Golden angle steps keep neighbouring IDs apart on the colour wheel
+            let light = 0.55 + 0.45 * shade.get_pixel(x, y)[0] as f64 / 255.0;
+            hue(id as f64 * 137.508).map(|channel| ((channel as f64 * 0.6 + 255.0 * 0.4) * light).round() as u8)
+        };
+        Rgba([r, g, b, 255])
+    });
+    let radius = (width.max(height) / 256).max(1) as i64;
+    for basin in watersheds.basins.iter() {
+        let [cx, cy] = basin.outlet_px.map(|value| value as i64);
+        for y in cy - radius - 1..=cy + radius + 1 {
+            for x in cx - radius - 1..=cx + radius + 1 {
+                if x < 0 || y < 0 || x >= width as i64 || y >= height as i64 {
+                    continue;
+                }
+                let inside = (x - cx).abs().max((y - cy).abs()) <= radius;
+                image.put_pixel(x as u32, y as u32, if inside { Rgba([255, 255, 255, 255]) } else { Rgba([0, 0, 0, 255]) });
+            }
+        }
+    }
+    image
+}
+
+// The basin IDs as 16-bit gray values, 0 for water
+pub fn basin_id_map(watersheds: &Watersheds) -> ImageBuffer<Luma<u16>, Vec<u16>> {
+    ImageBuffer::from_fn(watersheds.width, watersheds.height, |x, y| {
+        Luma([watersheds.labels[(y * watersheds.width + x) as usize].min(u16::MAX as u32) as u16])
+    })
+}
+
+// Writes <filename>_basins.png, <filename>_basin_ids.png and <filename>_basins.json
+pub fn export_watersheds(watersheds: &Watersheds, heightmap: &ImageBuffer<Rgba<u8>, Vec<u8>>, dir: &Path, filename: &str, terrain: &Terrain) -> Result<Vec<PathBuf>> {
+    let color_path = output_path(dir, filename, "basins", "png");
+    save_image(&render_basins(watersheds, heightmap, terrain), &color_path)?;
+    let id_path = output_path(dir, filename, "basin_ids", "png");
+    save_height16(&basin_id_map(watersheds), &id_path)?;
+    let json_path = output_path(dir, filename, "basins", "json");
+    write_text(&json_path, &serde_json::to_string_pretty(&watersheds.basins)?)?;
+    Ok(vec![color_path, id_path, json_path])
+}
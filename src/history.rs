@@ -0,0 +1,136 @@
+use std::time::{Duration, Instant};
+
+// Undo/redo stack of settings snapshots. Changes that arrive in quick succession,
+// like the stream of events while a slider is dragged, are merged into one step.
+pub struct History<T> {
+    undo_stack: Vec<T>,
+    redo_stack: Vec<T>,
+    current: Option<T>,
+    last_change: Option<Instant>,
+    coalesce_window: Duration,
+    limit: usize,
+}
+
+impl<T: Clone + PartialEq> History<T> {
+    pub fn new(coalesce_window: Duration, limit: usize) -> History<T> {
+        History {
+            undo_stack: Vec::new(),
+            redo_stack: Vec::new(),
+            current: None,
+            last_change: None,
+            coalesce_window,
+            limit,
+        }
+    }
+
+    pub fn record(&mut self, state: T) {
+        let now = Instant::now();
+        match self.current.take() {
+            Some(current) if current == state => {
+                self.current = Some(current);
+                return;
+            }
+            Some(current) => {
+                let coalesce = match self.last_change {
+                    Some(last_change) => now.duration_since(last_change) < self.coalesce_window,
+                    None => false,
+                };
+                if !coalesce {
+                    self.undo_stack.push(current);
+                    if self.undo_stack.len() > self.limit {
+                        self.undo_stack.remove(0);
+                    }
+                }
+                self.redo_stack.clear();
+            }
+            None => {
+                // The first snapshot is the starting point and never merges with a later change
+                self.current = Some(state);
+                return;
+            }
+        }
+        self.current = Some(state);
+        self.last_change = Some(now);
+    }
+
+    pub fn undo(&mut self) -> Option<T> {
+        let previous = self.undo_stack.pop()?;
+        if let Some(current) = self.current.replace(previous.clone()) {
+            self.redo_stack.push(current);
+        }
+        self.last_change = None;
+        Some(previous)
+    }
+
+    pub fn redo(&mut self) -> Option<T> {
+        let next = self.redo_stack.pop()?;
+        if let Some(current) = self.current.replace(next.clone()) {
+            self.undo_stack.push(current);
+        }
+        self.last_change = None;
+        Some(next)
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo_stack.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo_stack.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The window and limit the settings history is created with
+    fn settings_history() -> History<u32> {
+        History::new(Duration::from_millis(500), 100)
+    }
+
+    #[test]
+    fn changes_within_the_window_are_one_step() {
+        let mut history = settings_history();
+        history.record(0);
+        history.record(1);
+        history.record(2);
+        history.record(3);
+
+        assert_eq!(history.undo(), Some(0));
+        assert!(!history.can_undo());
+        assert_eq!(history.redo(), Some(3));
+    }
+
+    #[test]
+    fn a_new_change_clears_redo() {
+        let mut history = History::new(Duration::ZERO, 100);
+        history.record(0);
+        history.record(1);
+        history.record(2);
+        assert_eq!(history.undo(), Some(1));
+        assert!(history.can_redo());
+
+        history.record(5);
+        assert!(!history.can_redo());
+        assert_eq!(history.redo(), None);
+        assert_eq!(history.undo(), Some(1));
+        assert_eq!(history.undo(), Some(0));
+    }
+
+    #[test]
+    fn the_oldest_steps_are_dropped_at_the_limit() {
+        let mut history = History::new(Duration::ZERO, 100);
+        for state in 0..=102 {
+            history.record(state);
+        }
+
+        let mut undone = Vec::new();
+        while let Some(state) = history.undo() {
+            undone.push(state);
+        }
+        assert_eq!(undone.len(), 100);
+        assert_eq!(undone.first(), Some(&101));
+        assert_eq!(undone.last(), Some(&2));
+    }
+}
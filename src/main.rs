@@ -1,4 +1,5 @@
-mod heightmap_gen;
+mod history;
+mod worker;
 
 extern crate renderer;
 extern crate image;
@@ -9,21 +10,48 @@ extern crate serde_json;
 use image::{ImageBuffer, Rgba, imageops::FilterType};
 use std::sync::{Arc, Mutex};
 use std::thread;
-use std::fs;
-use std::io::Read;
+use std::path::PathBuf;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::cell::{Cell, RefCell};
+use std::time::Duration;
 use nalgebra::Vector4;
-use slint::{slint, Model, VecModel, SharedPixelBuffer, Rgba8Pixel};
-use serde_derive::{Serialize, Deserialize};
+use slint::{slint, Model, ModelRc, VecModel, SharedPixelBuffer, Rgba8Pixel};
 
-use heightmap_gen::heightmap::{generate_perlin_noise_buffer, blend_buffers, colorize_buffer, clamp_image_buffer, thermal_erosion, simulate_river_flow, scale_image, save_image_to_desktop};
+use rust_heightmap_generator::heightmap_gen;
+use heightmap_gen::heightmap::scale_image;
+use heightmap_gen::error::{HeightmapError, Result};
+use heightmap_gen::output::{default_output_dir, output_path, config_path, save_image, write_text, read_text};
+use heightmap_gen::graph::{Graph, EvalContext, Buffer};
 use heightmap_gen::constants::{IMAGE_SIZE, BIG_IMAGE_SIZE};
+use heightmap_gen::size::{SIZE_PRESETS, preset_label, check_size, preview_size, detail};
+use heightmap_gen::parallel::{PoolCache, build_thread_pool, default_thread_count};
+use heightmap_gen::cancel::CancelToken;
+use heightmap_gen::dem::{grid_from_buffer, write_ascii_grid, write_xyz};
+use heightmap_gen::sculpt::{BrushMode, Stroke};
+use heightmap_gen::pipeline::build_pipeline_graph;
+use heightmap_gen::engine::{EnginePreset, export_for_engine};
+use heightmap_gen::tiles::{TILE_SIZES, export_world_partition_tiles};
+use heightmap_gen::contours::{ContourBase, ContourParams, export_contours};
+use heightmap_gen::analysis::AnalysisMap;
+use heightmap_gen::watershed::{WatershedParams, watersheds, render_basins, export_watersheds};
+use heightmap_gen::landmass::{LandmassRules, landmass_stats, stats_summary, export_landmass_stats};
+use heightmap_gen::stats::{height_stats, height_summary, render_histogram};
+use heightmap_gen::remap::NormalizeMode;
+use heightmap_gen::terrain::Terrain;
+use heightmap_gen::roads::{Road, RoadParams, RoadPath, export_road_paths};
+use heightmap_gen::scatter::{RULE_COLORS, ScatterRule, ScatterTerrain, scatter, draw_scatter_overlay, export_scatter_points};
+use heightmap_gen::batch::{BatchOptions, JitterBounds, batch_from_args, run_batch};
+use heightmap_gen::config::{SerializedTool, Layers, CONFIG_VERSION, parse_config, config_to_json};
+use history::History;
+use worker::Worker;
 
 use renderer::{renderer as rend, modifiers};
 
 
 
 slint! {
-    import { Button , VerticalBox, Slider, HorizontalBox, CheckBox, TextEdit, ComboBox} from "std-widgets.slint";
+    import { Button , VerticalBox, Slider, HorizontalBox, CheckBox, TextEdit, ComboBox, TabWidget, SpinBox, ScrollView} from "std-widgets.slint";
 
     export struct LayerParams {
         scale: float,
@@ -32,7 +60,37 @@ slint! {
         seed: float,
         opacity: float,
         blend_mode: int,
-    }    
+        import_path: string,
+    }
+
+    export struct BrushStroke {
+        mode: int,
+        radius: float,
+        strength: float,
+        falloff: float,
+        seed: int,
+        points: [float],
+    }
+
+    export struct RoadWaypoints {
+        points: [float],
+    }
+
+    export struct ScatterRuleParams {
+        name: string,
+        min_distance: float,
+        density: float,
+        elevation_min: float,
+        elevation_max: float,
+        slope_max: float,
+        water_min: float,
+        water_max: float,
+        river_min: float,
+        scale_min: float,
+        scale_max: float,
+        seed: float,
+        color: color,
+    }
     
     export component App inherits Window {
         title: "Heightmap Generator";
@@ -41,527 +99,1655 @@ slint! {
         background: #161616;
         
         callback ui_changed;
+        callback undo;
+        callback redo;
         callback load_btn_clicked <=> load_btn.clicked;
         callback export_btn_clicked <=> btn.clicked;
         callback add_layer_btn_clicked <=> add_layer_btn.clicked;
         callback remove_layer_btn_clicked <=> remove_layer_btn.clicked;
+        callback save_graph_btn_clicked <=> save_graph_btn.clicked;
+        callback load_graph_btn_clicked <=> load_graph_btn.clicked;
+        callback reset_graph_btn_clicked <=> reset_graph_btn.clicked;
+        callback size_preset_selected(int);
+        callback sculpt_pressed(float, float, float, float);
+        callback sculpt_moved(float, float, float, float);
+        callback sculpt_released;
+        callback clear_strokes_btn_clicked <=> clear_strokes_btn.clicked;
+        callback batch_btn_clicked <=> batch_btn.clicked;
+        callback engine_preset_selected(int);
+        callback road_clicked(float, float, float, float);
+        callback new_road_btn_clicked <=> new_road_btn.clicked;
+        callback clear_roads_btn_clicked <=> clear_roads_btn.clicked;
+        callback add_scatter_rule_btn_clicked <=> add_scatter_rule_btn.clicked;
+        callback remove_scatter_rule_btn_clicked <=> remove_scatter_rule_btn.clicked;
         
         in-out property <float> scale <=> scl.value;
         in-out property <float> offset_x <=> ofx.value;
         in-out property <float> offset_y <=> ofy.value;
         in-out property <float> seed <=> sd.value;
+        in-out property <string> base_import <=> base_import.text;
         in-out property <image> image <=> img.source;
         in-out property <image> colormap <=> colormap.source;
         in-out property <image> image_perspective <=> persp_image.source;
         in-out property <[LayerParams]> layers: [];
+        in-out property <[BrushStroke]> strokes: [];
+        in-out property <[RoadWaypoints]> roads: [];
+        in-out property <bool> road_placing <=> road_placing.checked;
+        in-out property <float> road_width <=> road_width.value;
+        in-out property <float> road_max_grade <=> road_max_grade.value;
+        in-out property <float> road_banking <=> road_banking.value;
+        in-out property <float> road_slope_cost <=> road_slope_cost.value;
+        in-out property <float> road_water_cost <=> road_water_cost.value;
+        in-out property <float> road_curve_cost <=> road_curve_cost.value;
+        in-out property <bool> road_export <=> road_export.checked;
+        in-out property <bool> contour_enabled <=> contour_enabled.checked;
+        in-out property <float> contour_interval <=> contour_interval.value;
+        in-out property <float> contour_index_every <=> contour_index_every.value;
+        in-out property <int> contour_base <=> contour_base.current-index;
+        in-out property <bool> contour_spot_heights <=> contour_spot_heights.checked;
+        in-out property <bool> contour_preview <=> contour_preview.checked;
+        in-out property <bool> contour_export_vector <=> contour_export_vector.checked;
+        in-out property <int> analysis_view <=> analysis_view.current-index;
+        in-out property <bool> analysis_export <=> analysis_export.checked;
+        in-out property <bool> watershed_enabled <=> watershed_enabled.checked;
+        in-out property <float> watershed_min_area <=> watershed_min_area.value;
+        in-out property <bool> watershed_preview <=> watershed_preview.checked;
+        in-out property <float> landmass_min_island <=> landmass_min_island.value;
+        in-out property <float> landmass_min_lake <=> landmass_min_lake.value;
+        in-out property <bool> landmass_single <=> landmass_single.checked;
+        in-out property <bool> landmass_export <=> landmass_export.checked;
+        in-out property <string> landmass_stats;
+        in-out property <int> normalize_mode <=> normalize_mode.current-index;
+        in-out property <float> normalize_clip <=> normalize_clip.value;
+        in-out property <string> height_stats;
+        in-out property <image> histogram;
+        in-out property <[ScatterRuleParams]> scatter_rules: [];
+        in-out property <bool> scatter_overlay <=> scatter_overlay.checked;
+        in-out property <bool> scatter_export <=> scatter_export.checked;
+        in-out property <bool> sculpt_enabled <=> sculpt_enabled.checked;
+        in-out property <int> brush_mode <=> brush_mode.current-index;
+        in-out property <float> brush_radius <=> brush_radius.value;
+        in-out property <float> brush_strength <=> brush_strength.value;
+        in-out property <float> brush_falloff <=> brush_falloff.value;
+        in-out property <float> batch_count <=> batch_count.value;
+        in-out property <float> batch_jitter_scale <=> batch_jitter_scale.value;
+        in-out property <float> batch_jitter_offset <=> batch_jitter_offset.value;
+        in-out property <float> batch_jitter_opacity <=> batch_jitter_opacity.value;
+        in-out property <bool> custom_graph: false;
+        in-out property <bool> can_undo: false;
+        in-out property <bool> can_redo: false;
 
         in-out property <string> filename <=> filename.text;
-        in-out property <int> export_scale <=> export_scale.current-index;
+        in-out property <string> output_dir <=> output_dir.text;
+        in-out property <int> threads <=> threads.value;
+        in-out property <string> status: "Exported textures will be saved or loaded to/from the output folder";
+        in-out property <bool> status_error: false;
+        in-out property <bool> busy: false;
+        in-out property <float> progress: 0.0;
+        in-out property <[string]> size_presets;
+        in-out property <int> size_preset <=> size_preset.current-index;
+        in-out property <string> export_width <=> export_width.text;
+        in-out property <string> export_height <=> export_height.text;
         in-out property <int> scale_type <=> export_filter.current-index;
+        in-out property <bool> export_resample <=> export_resample.checked;
+        in-out property <bool> dem_asc <=> dem_asc.checked;
+        in-out property <bool> dem_xyz <=> dem_xyz.checked;
+        in-out property <bool> dem_water_nodata <=> dem_water_nodata.checked;
+        in-out property <int> engine_preset <=> engine_preset.current-index;
+        in-out property <bool> tile_export <=> tile_export.checked;
+        in-out property <int> tile_size <=> tile_size.current-index;
+
+        in-out property <string> world_size_km <=> world_size_km.text;
+        in-out property <string> min_elevation <=> min_elevation.text;
+        in-out property <string> max_elevation <=> max_elevation.text;
+        in-out property <float> sea_level <=> sea_level.value;
 
         in-out property <int> erosion_mode <=> erosion_mode.current-index;
         in-out property <float> erosion_iterations <=> erosion_iterations.value;
         in-out property <float> talus_angle <=> talus_angle.value;
 
         in-out property <bool> flatten_enabled <=> flatten_enabled.checked;
-        in-out property <float> ground_level <=> ground_level.value;
         in-out property <bool> as_water <=> as_water.checked;
 
         in-out property <bool> calculate_rivers <=> river_enabled.checked;
         in-out property <float> river_iterations <=> river_iterations.value;
-        in-out property <float> erosion_factor <=> erosion_factor.value;
+        in-out property <float> river_depth <=> river_depth.value;
         in-out property <float> river_amount <=> river_amount.value;
         in-out property <float> river_seed <=> river_seed.value;
 
+        in-out property <bool> curve_enabled <=> curve_enabled.checked;
+        in-out property <float> curve_0 <=> curve_0.value;
+        in-out property <float> curve_1 <=> curve_1.value;
+        in-out property <float> curve_2 <=> curve_2.value;
+        in-out property <float> curve_3 <=> curve_3.value;
+        in-out property <float> curve_4 <=> curve_4.value;
+
+        in-out property <bool> levels_enabled <=> levels_enabled.checked;
+        in-out property <float> levels_in_black <=> levels_in_black.value;
+        in-out property <float> levels_in_white <=> levels_in_white.value;
+        in-out property <float> levels_gamma <=> levels_gamma.value;
+        in-out property <float> levels_out_black <=> levels_out_black.value;
+        in-out property <float> levels_out_white <=> levels_out_white.value;
+
+        in-out property <bool> terrace_enabled <=> terrace_enabled.checked;
+        in-out property <float> terrace_steps <=> terrace_steps.value;
+        in-out property <float> terrace_sharpness <=> terrace_sharpness.value;
+        in-out property <float> terrace_jitter <=> terrace_jitter.value;
+        in-out property <float> terrace_noise_scale <=> terrace_noise_scale.value;
+        in-out property <float> terrace_seed <=> terrace_seed.value;
+
         out property <int> preview_scale <=> preview_scale.current-index;
         out property <float> camera_horizontal <=> camera_horizontal.value;
         out property <float> camera_vertical <=> camera_vertical.value;
         out property <float> height_3d <=> height_3d.value;
 
-        Rectangle {
-            background: #292929;
-            border-color: #161616;
-            border-width: 5px;
-            border-radius: 10px;
-            HorizontalBox {
-                VerticalBox {
-                    Rectangle {
-                        background: #161616;
-                        border-radius: 10px;
-                        border-width: 2px;
-                        max-height: 150px;
-                        VerticalBox {
-                            spacing: 0px;
-                            max-height: 150px;       
-                            HorizontalBox {
-                                Text {text: "Scale";}
-                                scl:=Slider {value: 0.05;minimum: 0.001;maximum: 0.1; changed => {
-                                    //scl_label.text = scl.value;
-                                    root.ui_changed();
-                                }}
-                                //scl_label:=Text{ text: scl.value;}
-                            }
-                            HorizontalBox {
-                                Text {text: "Offset X";}
-                                ofx:=Slider {value: 0;minimum: 0.0;maximum: 256; changed => {
-                                    //ofx_label.text = ofx.value;
-                                    root.ui_changed();
-                                }}
-                                //ofx_label:=Text{ text: ofx.value;}
-                            }
-                            HorizontalBox {
-                                Text {text: "Offset Y";}
-                                ofy:=Slider {value: 0;minimum: 0.0;maximum: 256; changed => {
-                                    //ofy_label.text = ofy.value;
-                                    root.ui_changed();
-                                }}
-                                //ofy_label:=Text{ text: ofy.value;}
-                            }
-                            HorizontalBox {
-                                Text {text: "Seed";}
-                                sd:=Slider {value: 1;minimum: 1;maximum: 5000; changed => {
-                                    //sd_label.text = sd.value;
-                                    root.ui_changed();
-                                }}
-                                //sd_label:=Text{ text: sd.value;}
-                            }                
-                        }            
-                    }
-                    HorizontalBox {
-                        add_layer_btn:=Button {height: 25px; text: "Add Layer";}
-                        remove_layer_btn:=Button {height: 25px; text: "Remove Layer";}
+        forward-focus: key_handler;
+
+        key_handler:=FocusScope {
+            key-pressed(event) => {
+                if (event.modifiers.control && (event.text == "z" || event.text == "Z")) {
+                    if (event.modifiers.shift) {
+                        root.redo();
+                    } else {
+                        root.undo();
                     }
-                    for layer[i] in root.layers: Rectangle {
-                        background: #161616;
-                        border-radius: 10px;
-                        border-width: 2px;
-                        max-height: 150px;
-                        VerticalBox {
-                            spacing: -15px;
-                            max-height: 150px;
-                            Text {text: "Layer " + i; height: 25px;}     
-                            HorizontalBox {
-                                Text {text: "Scale";}
-                                layer_scl:=Slider {value: 0.05;minimum: 0.001;maximum: 0.1; changed => {
-                                    layer.scale = self.value;
-                                    //layer_scl_label.text = layer.scale;
-                                    root.ui_changed();
-                                }}
-                                //layer_scl_label:=Text{ text: layer_scl.value;}
-                            }
-                            HorizontalBox {
-                                Text {text: "Offset X";}
-                                layer_ofx:=Slider {value: 0;minimum: 0.0;maximum: 256; changed => {
-                                    layer.offset-x = self.value;
-                                    //layer_ofx_label.text = layer_ofx.value;
-                                    root.ui_changed();
-                                }}
-                                //layer_ofx_label:=Text{ text: layer_ofx.value;}
-                            }
-                            HorizontalBox {
-                                Text {text: "Offset Y";}
-                                layer_ofy:=Slider {value: 0;minimum: 0.0;maximum: 256; changed => {
-                                    layer.offset-y = self.value;
-                                    //layer_ofy_label.text = layer_ofy.value;
-                                    root.ui_changed();
-                                }}
-                                //layer_ofy_label:=Text{ text: layer_ofy.value;}
-                            }
-                            HorizontalBox {
-                                Text {text: "Seed";}
-                                layer_sd:=Slider {value: 1;minimum: 1;maximum: 5000; changed => {
-                                    layer.seed = self.value;
-                                    //layer_sd_label.text = layer_sd.value;
-                                    root.ui_changed();
-                                }}
-                                //layer_sd_label:=Text{ text: layer_sd.value;}
-                            }
-                            HorizontalBox {
-                                Text {text: "Opacity";}
-                                layer_ops:=Slider {value: 1;minimum: 0.0;maximum: 1.0; changed => {
-                                    layer.opacity = self.value;
-                                    //layer_ops_label.text = layer_ops.value;
-                                    root.ui_changed();
-                                }}
-                                //layer_ops_label:=Text{ text: layer_ops.value;}
-                            }
-                            HorizontalBox {
-                                Text {text: "Blend Mode";}
-                                layer_mul:=ComboBox {
-                                    model: ["Blend", "Multiply", "Screen"];
-                                    current-index: 0;
-                                    selected => {
-                                        layer.blend_mode = self.current-index;
+                    return accept;
+                }
+                if (event.modifiers.control && (event.text == "y" || event.text == "Y")) {
+                    root.redo();
+                    return accept;
+                }
+                return reject;
+            }
+            Rectangle {
+                background: #292929;
+                border-color: #161616;
+                border-width: 5px;
+                border-radius: 10px;
+                HorizontalBox {
+                    VerticalBox {
+                        Rectangle {
+                            background: #161616;
+                            border-radius: 10px;
+                            border-width: 2px;
+                            max-height: 185px;
+                            VerticalBox {
+                                spacing: 0px;
+                                max-height: 185px;       
+                                HorizontalBox {
+                                    Text {text: "Scale";}
+                                    scl:=Slider {value: 0.05;minimum: 0.001;maximum: 0.1; changed => {
+                                        //scl_label.text = scl.value;
+                                        root.ui_changed();
+                                    }}
+                                    //scl_label:=Text{ text: scl.value;}
+                                }
+                                HorizontalBox {
+                                    Text {text: "Offset X";}
+                                    ofx:=Slider {value: 0;minimum: 0.0;maximum: 256; changed => {
+                                        //ofx_label.text = ofx.value;
+                                        root.ui_changed();
+                                    }}
+                                    //ofx_label:=Text{ text: ofx.value;}
+                                }
+                                HorizontalBox {
+                                    Text {text: "Offset Y";}
+                                    ofy:=Slider {value: 0;minimum: 0.0;maximum: 256; changed => {
+                                        //ofy_label.text = ofy.value;
+                                        root.ui_changed();
+                                    }}
+                                    //ofy_label:=Text{ text: ofy.value;}
+                                }
+                                HorizontalBox {
+                                    Text {text: "Seed";}
+                                    sd:=Slider {value: 1;minimum: 1;maximum: 5000; changed => {
+                                        //sd_label.text = sd.value;
+                                        root.ui_changed();
+                                    }}
+                                    //sd_label:=Text{ text: sd.value;}
+                                }                
+                                HorizontalBox {
+                                    Text {text: "File";}
+                                    Rectangle {
+                                        background: #262626;
+                                        border-radius: 5px;
+                                        base_import:=TextInput {
+                                            single-line: true;
+                                            text: "";
+                                            vertical-alignment: center;
+                                            accepted => {
+                                                root.ui_changed();
+                                            }
+                                        }
                                     }
                                 }
-                            }             
+                            }            
+                        }
+                        HorizontalBox {
+                            add_layer_btn:=Button {height: 25px; text: "Add Layer";}
+                            remove_layer_btn:=Button {height: 25px; text: "Remove Layer";}
+                        }
+                        HorizontalBox {
+                            Button {height: 25px; text: "Undo"; enabled: root.can_undo; clicked => { root.undo(); }}
+                            Button {height: 25px; text: "Redo"; enabled: root.can_redo; clicked => { root.redo(); }}
+                        }
+                        for layer[i] in root.layers: Rectangle {
+                            background: #161616;
+                            border-radius: 10px;
+                            border-width: 2px;
+                            max-height: 185px;
+                            VerticalBox {
+                                spacing: -15px;
+                                max-height: 185px;
+                                Text {text: "Layer " + i; height: 25px;}     
+                                HorizontalBox {
+                                    Text {text: "Scale";}
+                                    layer_scl:=Slider {value: layer.scale;minimum: 0.001;maximum: 0.1; changed => {
+                                        layer.scale = self.value;
+                                        //layer_scl_label.text = layer.scale;
+                                        root.ui_changed();
+                                    }}
+                                    //layer_scl_label:=Text{ text: layer_scl.value;}
+                                }
+                                HorizontalBox {
+                                    Text {text: "Offset X";}
+                                    layer_ofx:=Slider {value: layer.offset_x;minimum: 0.0;maximum: 256; changed => {
+                                        layer.offset-x = self.value;
+                                        //layer_ofx_label.text = layer_ofx.value;
+                                        root.ui_changed();
+                                    }}
+                                    //layer_ofx_label:=Text{ text: layer_ofx.value;}
+                                }
+                                HorizontalBox {
+                                    Text {text: "Offset Y";}
+                                    layer_ofy:=Slider {value: layer.offset_y;minimum: 0.0;maximum: 256; changed => {
+                                        layer.offset-y = self.value;
+                                        //layer_ofy_label.text = layer_ofy.value;
+                                        root.ui_changed();
+                                    }}
+                                    //layer_ofy_label:=Text{ text: layer_ofy.value;}
+                                }
+                                HorizontalBox {
+                                    Text {text: "Seed";}
+                                    layer_sd:=Slider {value: layer.seed;minimum: 1;maximum: 5000; changed => {
+                                        layer.seed = self.value;
+                                        //layer_sd_label.text = layer_sd.value;
+                                        root.ui_changed();
+                                    }}
+                                    //layer_sd_label:=Text{ text: layer_sd.value;}
+                                }
+                                HorizontalBox {
+                                    Text {text: "Opacity";}
+                                    layer_ops:=Slider {value: layer.opacity;minimum: 0.0;maximum: 1.0; changed => {
+                                        layer.opacity = self.value;
+                                        //layer_ops_label.text = layer_ops.value;
+                                        root.ui_changed();
+                                    }}
+                                    //layer_ops_label:=Text{ text: layer_ops.value;}
+                                }
+                                HorizontalBox {
+                                    Text {text: "Blend Mode";}
+                                    layer_mul:=ComboBox {
+                                        model: ["Blend", "Multiply", "Screen"];
+                                        current-index: layer.blend_mode;
+                                        selected => {
+                                            layer.blend_mode = self.current-index;
+                                            root.ui_changed();
+                                        }
+                                    }
+                                }             
+                                HorizontalBox {
+                                    Text {text: "File";}
+                                    Rectangle {
+                                        background: #262626;
+                                        border-radius: 5px;
+                                        TextInput {
+                                            single-line: true;
+                                            text: layer.import_path;
+                                            vertical-alignment: center;
+                                            accepted => {
+                                                layer.import_path = self.text;
+                                                root.ui_changed();
+                                            }
+                                        }
+                                    }
+                                }
+                            }
                         }
                     }
-                }
-                VerticalBox {
-                    Rectangle {
-                        background: #161616;
-                        border-radius: 10px;
-                        VerticalBox {
-                            Text {text: "Thermal Erosion"; height: 25px;}
-                            erosion_mode:=ComboBox {
-                                model: ["None", "Standard", "With Talus"];
-                                current-index: 0;
-                                height: 25px;
-                                selected => {
-                                    root.ui_changed();
+                    VerticalBox {
+                        TabWidget {
+                            Tab {
+                                title: "Terrain";
+                                VerticalBox {
+                                    alignment: start;
+                                    Rectangle {
+                                        background: #161616;
+                                        border-radius: 10px;
+                                        VerticalBox {
+                                            Text {text: "Real-World Units"; height: 25px;}
+                                            HorizontalBox {
+                                                Text {text: "World Size (km)"; vertical-alignment: center;}
+                                                Rectangle {
+                                                    background: #262626;
+                                                    border-radius: 5px;
+                                                    width: 80px;
+                                                    world_size_km:=TextInput {
+                                                        text: "1";
+                                                        single-line: true;
+                                                        width: 70px;
+                                                        vertical-alignment: center;
+                                                        accepted => {
+                                                            root.ui_changed();
+                                                        }
+                                                    }
+                                                }
+                                            }
+                                            HorizontalBox {
+                                                Text {text: "Min Elevation (m)"; vertical-alignment: center;}
+                                                Rectangle {
+                                                    background: #262626;
+                                                    border-radius: 5px;
+                                                    width: 80px;
+                                                    min_elevation:=TextInput {
+                                                        text: "0";
+                                                        single-line: true;
+                                                        width: 70px;
+                                                        vertical-alignment: center;
+                                                        accepted => {
+                                                            root.ui_changed();
+                                                        }
+                                                    }
+                                                }
+                                            }
+                                            HorizontalBox {
+                                                Text {text: "Max Elevation (m)"; vertical-alignment: center;}
+                                                Rectangle {
+                                                    background: #262626;
+                                                    border-radius: 5px;
+                                                    width: 80px;
+                                                    max_elevation:=TextInput {
+                                                        text: "500";
+                                                        single-line: true;
+                                                        width: 70px;
+                                                        vertical-alignment: center;
+                                                        accepted => {
+                                                            root.ui_changed();
+                                                        }
+                                                    }
+                                                }
+                                            }
+                                            HorizontalBox {
+                                                Text {text: "Sea Level (m)"; vertical-alignment: center;}
+                                                sea_level:=Slider {value: 0;minimum: min_elevation.text.to-float();maximum: max_elevation.text.to-float(); height: 25px; changed => {
+                                                    root.ui_changed();
+                                                }}
+                                                Text {text: round(sea_level.value); vertical-alignment: center; width: 40px;}
+                                            }
+                                            Text {
+                                                text: "The long side of the map is the world size and heights 0-255 span the elevation range. Talus angles, river depths, contours, analysis maps, the 3D preview and all exports are measured in these units.";
+                                                color: #8a8a8a;
+                                                font-size: 10px;
+                                                wrap: word-wrap;
+                                            }
+                                        }
+                                    }
+                                }
+                            }
+                            Tab {
+                                title: "Erosion";
+                                VerticalBox {
+                                    alignment: start;
+                                    Rectangle {
+                                        background: #161616;
+                                        border-radius: 10px;
+                                        VerticalBox {
+                                            Text {text: "Thermal Erosion"; height: 25px;}
+                                            erosion_mode:=ComboBox {
+                                                model: ["None", "Standard", "With Talus"];
+                                                current-index: 0;
+                                                height: 25px;
+                                                selected => {
+                                                    root.ui_changed();
+                                                }
+                                            }
+                                            HorizontalBox {
+                                                Text {text: "Iterations"; vertical-alignment: center;}
+                                                erosion_iterations:=Slider {value: 5;minimum: 5;maximum: 100; height: 25px; changed => {
+                                                    root.ui_changed();
+                                                }}
+                                            }
+                                            HorizontalBox {
+                                                Text {text: "Talus Angle (°)"; vertical-alignment: center;}
+                                                talus_angle:=Slider {value: 35;minimum: 0;maximum: 90; height: 25px; changed => {
+                                                    root.ui_changed();
+                                                }}
+                                                Text {text: round(talus_angle.value); vertical-alignment: center; width: 25px;}
+                                            }
+                                        }
+                                    }
+                                    Rectangle {
+                                        background: #161616;
+                                        border-radius: 10px;
+                                        VerticalBox {                            
+                                            Text {text: "River Flow"; height: 25px;}
+                                            HorizontalBox {
+                                                Text {text: "Calculate Rivers"; vertical-alignment: center;}
+                                                river_enabled:=CheckBox {enabled: erosion-mode.current-index != 0 ;checked: false; toggled => {
+                                                    root.ui_changed();
+                                                }}
+                                            }
+                                            HorizontalBox {
+                                                Text {text: "Iterations"; vertical-alignment: center;}
+                                                river_iterations:=Slider {enabled: erosion-mode.current-index != 0 ;value: 1;minimum: 1;maximum: 256; height: 25px; changed => {
+                                                    root.ui_changed();
+                                                }}
+                                            }
+                                            HorizontalBox {
+                                                Text {text: "Depth (m)"; vertical-alignment: center;}
+                                                river_depth:=Slider {enabled: erosion-mode.current-index != 0 ;value: 2;minimum: 0;maximum: 100; height: 25px; changed => {
+                                                    root.ui_changed();
+                                                }}
+                                                Text {text: round(river_depth.value * 10) / 10; vertical-alignment: center; width: 25px;}
+                                            }
+                                            HorizontalBox {
+                                                Text {text: "River Amount"; vertical-alignment: center;}
+                                                river_amount:=Slider {enabled: erosion-mode.current-index != 0 ;value: 1;minimum: 1;maximum: 100; height: 25px; changed => {
+                                                    root.ui_changed();
+                                                }}
+                                            }
+                                            HorizontalBox {
+                                                Text {text: "Seed"; vertical-alignment: center;}
+                                                river_seed:=Slider {enabled: erosion-mode.current-index != 0 ;value: 1;minimum: 1;maximum: 5000; height: 25px; changed => {
+                                                    root.ui_changed();
+                                                }}
+                                            }
+                                        }
+                                    }
+                                    Rectangle {
+                                        background: #161616;
+                                        border-radius: 10px;
+                                        VerticalBox {
+                                            Text {text: "Flatten Ground"; height: 25px;}
+                                            HorizontalBox {
+                                                Text {text: "Flatten Enabled"; vertical-alignment: center; height: 25px;}
+                                                flatten_enabled:=CheckBox {checked: false; height: 25px; toggled => {
+                                                    root.ui_changed();
+                                                }}
+                                            }
+                                            HorizontalBox {
+                                                Text {text: "As Water"; vertical-alignment: center; height: 25px;}
+                                                as_water:=CheckBox {checked: false; height: 25px; toggled => {
+                                                    root.ui_changed();
+                                                }}
+                                            }
+                                            Text {
+                                                text: "Raises everything below the sea level of the Terrain tab to it.";
+                                                color: #8a8a8a;
+                                                font-size: 10px;
+                                                wrap: word-wrap;
+                                            }
+                                        }
+                                    }                   
                                 }
                             }
-                            HorizontalBox {
-                                Text {text: "Iterations"; vertical-alignment: center;}
-                                erosion_iterations:=Slider {value: 5;minimum: 5;maximum: 100; height: 25px; changed => {
-                                    root.ui_changed();
-                                }}
+                            Tab {
+                                title: "Remap";
+                                VerticalBox {
+                                    alignment: start;
+                                    Rectangle {
+                                        background: #161616;
+                                        border-radius: 10px;
+                                        VerticalBox {
+                                            Text {text: "Curve"; height: 25px;}
+                                            HorizontalBox {
+                                                Text {text: "Curve Enabled"; vertical-alignment: center;}
+                                                curve_enabled:=CheckBox {checked: false; toggled => {
+                                                    root.ui_changed();
+                                                }}
+                                            }
+                                            HorizontalBox {
+                                                Text {text: "0%"; vertical-alignment: center;}
+                                                curve_0:=Slider {enabled: curve_enabled.checked; value: 0.0;minimum: 0.0;maximum: 1.0; height: 25px; changed => {
+                                                    root.ui_changed();
+                                                }}
+                                            }
+                                            HorizontalBox {
+                                                Text {text: "25%"; vertical-alignment: center;}
+                                                curve_1:=Slider {enabled: curve_enabled.checked; value: 0.25;minimum: 0.0;maximum: 1.0; height: 25px; changed => {
+                                                    root.ui_changed();
+                                                }}
+                                            }
+                                            HorizontalBox {
+                                                Text {text: "50%"; vertical-alignment: center;}
+                                                curve_2:=Slider {enabled: curve_enabled.checked; value: 0.5;minimum: 0.0;maximum: 1.0; height: 25px; changed => {
+                                                    root.ui_changed();
+                                                }}
+                                            }
+                                            HorizontalBox {
+                                                Text {text: "75%"; vertical-alignment: center;}
+                                                curve_3:=Slider {enabled: curve_enabled.checked; value: 0.75;minimum: 0.0;maximum: 1.0; height: 25px; changed => {
+                                                    root.ui_changed();
+                                                }}
+                                            }
+                                            HorizontalBox {
+                                                Text {text: "100%"; vertical-alignment: center;}
+                                                curve_4:=Slider {enabled: curve_enabled.checked; value: 1.0;minimum: 0.0;maximum: 1.0; height: 25px; changed => {
+                                                    root.ui_changed();
+                                                }}
+                                            }
+                                        }
+                                    }
+                                    Rectangle {
+                                        background: #161616;
+                                        border-radius: 10px;
+                                        VerticalBox {
+                                            Text {text: "Levels"; height: 25px;}
+                                            HorizontalBox {
+                                                Text {text: "Levels Enabled"; vertical-alignment: center;}
+                                                levels_enabled:=CheckBox {checked: false; toggled => {
+                                                    root.ui_changed();
+                                                }}
+                                            }
+                                            HorizontalBox {
+                                                Text {text: "Input Black"; vertical-alignment: center;}
+                                                levels_in_black:=Slider {enabled: levels_enabled.checked; value: 0;minimum: 0;maximum: 255; height: 25px; changed => {
+                                                    root.ui_changed();
+                                                }}
+                                            }
+                                            HorizontalBox {
+                                                Text {text: "Input White"; vertical-alignment: center;}
+                                                levels_in_white:=Slider {enabled: levels_enabled.checked; value: 255;minimum: 0;maximum: 255; height: 25px; changed => {
+                                                    root.ui_changed();
+                                                }}
+                                            }
+                                            HorizontalBox {
+                                                Text {text: "Gamma"; vertical-alignment: center;}
+                                                levels_gamma:=Slider {enabled: levels_enabled.checked; value: 1.0;minimum: 0.1;maximum: 3.0; height: 25px; changed => {
+                                                    root.ui_changed();
+                                                }}
+                                            }
+                                            HorizontalBox {
+                                                Text {text: "Output Black"; vertical-alignment: center;}
+                                                levels_out_black:=Slider {enabled: levels_enabled.checked; value: 0;minimum: 0;maximum: 255; height: 25px; changed => {
+                                                    root.ui_changed();
+                                                }}
+                                            }
+                                            HorizontalBox {
+                                                Text {text: "Output White"; vertical-alignment: center;}
+                                                levels_out_white:=Slider {enabled: levels_enabled.checked; value: 255;minimum: 0;maximum: 255; height: 25px; changed => {
+                                                    root.ui_changed();
+                                                }}
+                                            }
+                                        }
+                                    }
+                                    Rectangle {
+                                        background: #161616;
+                                        border-radius: 10px;
+                                        VerticalBox {
+                                            Text {text: "Terracing"; height: 25px;}
+                                            HorizontalBox {
+                                                Text {text: "Terrace Enabled"; vertical-alignment: center;}
+                                                terrace_enabled:=CheckBox {checked: false; toggled => {
+                                                    root.ui_changed();
+                                                }}
+                                            }
+                                            HorizontalBox {
+                                                Text {text: "Steps"; vertical-alignment: center;}
+                                                terrace_steps:=Slider {enabled: terrace_enabled.checked; value: 8;minimum: 1;maximum: 32; height: 25px; changed => {
+                                                    root.ui_changed();
+                                                }}
+                                            }
+                                            HorizontalBox {
+                                                Text {text: "Sharpness"; vertical-alignment: center;}
+                                                terrace_sharpness:=Slider {enabled: terrace_enabled.checked; value: 0.5;minimum: 0.0;maximum: 1.0; height: 25px; changed => {
+                                                    root.ui_changed();
+                                                }}
+                                            }
+                                            HorizontalBox {
+                                                Text {text: "Jitter"; vertical-alignment: center;}
+                                                terrace_jitter:=Slider {enabled: terrace_enabled.checked; value: 0.0;minimum: 0.0;maximum: 1.0; height: 25px; changed => {
+                                                    root.ui_changed();
+                                                }}
+                                            }
+                                            HorizontalBox {
+                                                Text {text: "Jitter Scale"; vertical-alignment: center;}
+                                                terrace_noise_scale:=Slider {enabled: terrace_enabled.checked && terrace_jitter.value > 0; value: 0.05;minimum: 0.001;maximum: 0.1; height: 25px; changed => {
+                                                    root.ui_changed();
+                                                }}
+                                            }
+                                            HorizontalBox {
+                                                Text {text: "Seed"; vertical-alignment: center;}
+                                                terrace_seed:=Slider {enabled: terrace_enabled.checked; value: 1;minimum: 1;maximum: 5000; height: 25px; changed => {
+                                                    root.ui_changed();
+                                                }}
+                                            }
+                                        }
+                                    }
+                                }
                             }
-                            HorizontalBox {
-                                Text {text: "Talus Angle"; vertical-alignment: center;}
-                                talus_angle:=Slider {value: 0.01;minimum: 0.0;maximum: 0.1; height: 25px; changed => {
-                                    root.ui_changed();
-                                }}
+                            Tab {
+                                title: "DEM";
+                                VerticalBox {
+                                    alignment: start;
+                                    Rectangle {
+                                        background: #161616;
+                                        border-radius: 10px;
+                                        VerticalBox {
+                                            Text {text: "GIS Export"; height: 25px;}
+                                            dem_asc:=CheckBox {text: "ESRI ASCII Grid (.asc)"; checked: false;}
+                                            dem_xyz:=CheckBox {text: "XYZ Points (.xyz)"; checked: false;}
+                                            dem_water_nodata:=CheckBox {text: "Water as NODATA"; checked: false;}
+                                            Text {
+                                                text: "Heights 0-255 are mapped to the elevation range of the Terrain tab and the cells are as wide as a pixel of the world size. With flatten enabled, cells at sea level can be written as NODATA.";
+                                                color: #8a8a8a;
+                                                font-size: 10px;
+                                                wrap: word-wrap;
+                                            }
+                                        }
+                                    }
+                                }
                             }
-                        }
-                    }
-                    Rectangle {
-                        background: #161616;
-                        border-radius: 10px;
-                        VerticalBox {                            
-                            Text {text: "River Flow"; height: 25px;}
-                            HorizontalBox {
-                                Text {text: "Calculate Rivers"; vertical-alignment: center;}
-                                river_enabled:=CheckBox {enabled: erosion-mode.current-index != 0 ;checked: false; toggled => {
-                                    root.ui_changed();
-                                }}
-                            }
-                            HorizontalBox {
-                                Text {text: "Iterations"; vertical-alignment: center;}
-                                river_iterations:=Slider {enabled: erosion-mode.current-index != 0 ;value: 1;minimum: 1;maximum: 256; height: 25px; changed => {
-                                    root.ui_changed();
-                                }}
-                            }
-                            HorizontalBox {
-                                Text {text: "Erosion Factor"; vertical-alignment: center;}
-                                erosion_factor:=Slider {enabled: erosion-mode.current-index != 0 ;value: 1;minimum: 1;maximum: 100; height: 25px; changed => {
-                                    root.ui_changed();
-                                }}
-                            }
-                            HorizontalBox {
-                                Text {text: "River Amount"; vertical-alignment: center;}
-                                river_amount:=Slider {enabled: erosion-mode.current-index != 0 ;value: 1;minimum: 1;maximum: 100; height: 25px; changed => {
-                                    root.ui_changed();
-                                }}
-                            }
-                            HorizontalBox {
-                                Text {text: "Seed"; vertical-alignment: center;}
-                                river_seed:=Slider {enabled: erosion-mode.current-index != 0 ;value: 1;minimum: 1;maximum: 5000; height: 25px; changed => {
-                                    root.ui_changed();
-                                }}
+                            Tab {
+                                title: "Engine";
+                                VerticalBox {
+                                    alignment: start;
+                                    Rectangle {
+                                        background: #161616;
+                                        border-radius: 10px;
+                                        VerticalBox {
+                                            HorizontalBox {
+                                                Text {text: "Engine"; vertical-alignment: center;}
+                                                engine_preset:=ComboBox {
+                                                    model: ["None","Unity (RAW16)","Unreal (PNG16)"];
+                                                    current-index: 0;
+                                                    selected(value) => {
+                                                        root.engine_preset_selected(self.current-index);
+                                                    }
+                                                }
+                                            }
+                                            HorizontalBox {
+                                                tile_export:=CheckBox {text: "World Partition Tiles"; checked: false;}
+                                                tile_size:=ComboBox {
+                                                    enabled: tile_export.checked;
+                                                    model: ["253","505","1009","2017"];
+                                                    current-index: 1;
+                                                }
+                                            }
+                                            Text {
+                                                text: "Snaps the export size to one the engine accepts and writes a 16-bit heightmap with a JSON of the terrain size, height, position and Z scale to enter on import, taken from the Terrain tab. Tiles are written for Unreal as <name>_x0_y0.png with one shared pixel, plus weight layer tiles.";
+                                                color: #8a8a8a;
+                                                font-size: 10px;
+                                                wrap: word-wrap;
+                                            }
+                                        }
+                                    }
+                                }
+                            }
+                            Tab {
+                                title: "Sculpt";
+                                VerticalBox {
+                                    alignment: start;
+                                    Rectangle {
+                                        background: #161616;
+                                        border-radius: 10px;
+                                        VerticalBox {
+                                            sculpt_enabled:=CheckBox {text: "Paint on Height Preview"; checked: false; toggled => {
+                                                if (self.checked) {
+                                                    road_placing.checked = false;
+                                                }
+                                            }}
+                                            HorizontalBox {
+                                                Text {text: "Brush"; vertical-alignment: center;}
+                                                brush_mode:=ComboBox {
+                                                    enabled: sculpt_enabled.checked;
+                                                    model: ["Raise","Lower","Smooth","Flatten","Noise Stamp"];
+                                                    current-index: 0;
+                                                }
+                                            }
+                                            HorizontalBox {
+                                                Text {text: "Radius"; vertical-alignment: center;}
+                                                brush_radius:=Slider {enabled: sculpt_enabled.checked; value: 5;minimum: 1;maximum: 50; height: 25px;}
+                                            }
+                                            HorizontalBox {
+                                                Text {text: "Strength"; vertical-alignment: center;}
+                                                brush_strength:=Slider {enabled: sculpt_enabled.checked; value: 0.5;minimum: 0.01;maximum: 1; height: 25px;}
+                                            }
+                                            HorizontalBox {
+                                                Text {text: "Falloff"; vertical-alignment: center;}
+                                                brush_falloff:=Slider {enabled: sculpt_enabled.checked; value: 0.5;minimum: 0;maximum: 1; height: 25px;}
+                                            }
+                                            HorizontalBox {
+                                                Text {text: root.strokes.length + " strokes"; vertical-alignment: center;}
+                                                clear_strokes_btn:=Button {text: "Clear Strokes"; enabled: root.strokes.length > 0;}
+                                            }
+                                            Text {
+                                                text: "Strokes are replayed after the remap filters and before erosion, so they stay when seeds change.";
+                                                color: #8a8a8a;
+                                                font-size: 10px;
+                                                wrap: word-wrap;
+                                            }
+                                        }
+                                    }
+                                }
+                            }
+                            Tab {
+                                title: "Batch";
+                                VerticalBox {
+                                    alignment: start;
+                                    Rectangle {
+                                        background: #161616;
+                                        border-radius: 10px;
+                                        VerticalBox {
+                                            HorizontalBox {
+                                                Text {text: "Variations"; vertical-alignment: center;}
+                                                batch_count:=Slider {value: 9;minimum: 2;maximum: 64; height: 25px;}
+                                                Text {text: round(batch_count.value); vertical-alignment: center; width: 25px;}
+                                            }
+                                            Text {text: "Jitter"; height: 25px;}
+                                            HorizontalBox {
+                                                Text {text: "Scale"; vertical-alignment: center;}
+                                                batch_jitter_scale:=Slider {value: 0;minimum: 0;maximum: 0.5; height: 25px;}
+                                            }
+                                            HorizontalBox {
+                                                Text {text: "Offset"; vertical-alignment: center;}
+                                                batch_jitter_offset:=Slider {value: 0;minimum: 0;maximum: 50; height: 25px;}
+                                            }
+                                            HorizontalBox {
+                                                Text {text: "Opacity"; vertical-alignment: center;}
+                                                batch_jitter_opacity:=Slider {value: 0;minimum: 0;maximum: 0.5; height: 25px;}
+                                            }
+                                            batch_btn:=Button {text: "Generate Variations";}
+                                            Text {
+                                                text: "Seeds count up from the current seed. Writes a contact sheet and one config per variation, load one by setting the filename to e.g. noise_003.";
+                                                color: #8a8a8a;
+                                                font-size: 10px;
+                                                wrap: word-wrap;
+                                            }
+                                        }
+                                    }
+                                }
+                            }
+                            Tab {
+                                title: "Roads";
+                                VerticalBox {
+                                    alignment: start;
+                                    Rectangle {
+                                        background: #161616;
+                                        border-radius: 10px;
+                                        VerticalBox {
+                                            road_placing:=CheckBox {text: "Place Waypoints on Height Preview"; checked: false; toggled => {
+                                                if (self.checked) {
+                                                    sculpt_enabled.checked = false;
+                                                }
+                                            }}
+                                            HorizontalBox {
+                                                Text {text: root.roads.length + " roads"; vertical-alignment: center;}
+                                                new_road_btn:=Button {text: "New Road";}
+                                                clear_roads_btn:=Button {text: "Clear Roads"; enabled: root.roads.length > 0;}
+                                            }
+                                            HorizontalBox {
+                                                Text {text: "Width (m)"; vertical-alignment: center;}
+                                                road_width:=Slider {value: 8;minimum: 2;maximum: 30; height: 25px; changed => {
+                                                    root.ui_changed();
+                                                }}
+                                            }
+                                            HorizontalBox {
+                                                Text {text: "Max Grade (%)"; vertical-alignment: center;}
+                                                road_max_grade:=Slider {value: 10;minimum: 1;maximum: 30; height: 25px; changed => {
+                                                    root.ui_changed();
+                                                }}
+                                            }
+                                            HorizontalBox {
+                                                Text {text: "Banking (%)"; vertical-alignment: center;}
+                                                road_banking:=Slider {value: 3;minimum: 0;maximum: 10; height: 25px; changed => {
+                                                    root.ui_changed();
+                                                }}
+                                            }
+                                            HorizontalBox {
+                                                Text {text: "Slope Cost"; vertical-alignment: center;}
+                                                road_slope_cost:=Slider {value: 10;minimum: 0;maximum: 50; height: 25px; changed => {
+                                                    root.ui_changed();
+                                                }}
+                                            }
+                                            HorizontalBox {
+                                                Text {text: "Water Cost"; vertical-alignment: center;}
+                                                road_water_cost:=Slider {value: 50;minimum: 0;maximum: 200; height: 25px; changed => {
+                                                    root.ui_changed();
+                                                }}
+                                            }
+                                            HorizontalBox {
+                                                Text {text: "Curve Cost"; vertical-alignment: center;}
+                                                road_curve_cost:=Slider {value: 2;minimum: 0;maximum: 20; height: 25px; changed => {
+                                                    root.ui_changed();
+                                                }}
+                                            }
+                                            road_export:=CheckBox {text: "Export Polylines"; checked: false;}
+                                            Text {
+                                                text: "Clicks add waypoints to the last road. The route between them avoids steep slopes, water and sharp turns, and is carved after rivers with its grade limited. Exported as <name>_roads.json.";
+                                                color: #8a8a8a;
+                                                font-size: 10px;
+                                                wrap: word-wrap;
+                                            }
+                                        }
+                                    }
+                                }
+                            }
+                            Tab {
+                                title: "Contours";
+                                VerticalBox {
+                                    alignment: start;
+                                    Rectangle {
+                                        background: #161616;
+                                        border-radius: 10px;
+                                        VerticalBox {
+                                            contour_enabled:=CheckBox {text: "Topographic Map"; checked: false; toggled => {
+                                                root.ui_changed();
+                                            }}
+                                            HorizontalBox {
+                                                Text {text: "Interval (m)"; vertical-alignment: center;}
+                                                contour_interval:=Slider {enabled: contour_enabled.checked; value: 10;minimum: 1;maximum: 100; height: 25px; changed => {
+                                                    root.ui_changed();
+                                                }}
+                                                Text {text: round(contour_interval.value); vertical-alignment: center; width: 25px;}
+                                            }
+                                            HorizontalBox {
+                                                Text {text: "Index Line Every"; vertical-alignment: center;}
+                                                contour_index_every:=Slider {enabled: contour_enabled.checked; value: 5;minimum: 2;maximum: 10; height: 25px; changed => {
+                                                    root.ui_changed();
+                                                }}
+                                                Text {text: round(contour_index_every.value); vertical-alignment: center; width: 25px;}
+                                            }
+                                            HorizontalBox {
+                                                Text {text: "Base"; vertical-alignment: center;}
+                                                contour_base:=ComboBox {
+                                                    enabled: contour_enabled.checked;
+                                                    model: ["Color Map","Hillshade","Shaded Color"];
+                                                    current-index: 0;
+                                                    selected(value) => {
+                                                        root.ui_changed();
+                                                    }
+                                                }
+                                            }
+                                            contour_spot_heights:=CheckBox {enabled: contour_enabled.checked; text: "Spot Heights"; checked: true; toggled => {
+                                                root.ui_changed();
+                                            }}
+                                            contour_preview:=CheckBox {enabled: contour_enabled.checked; text: "Show on Color Preview"; checked: true; toggled => {
+                                                root.ui_changed();
+                                            }}
+                                            contour_export_vector:=CheckBox {enabled: contour_enabled.checked; text: "Export SVG and GeoJSON"; checked: false;}
+                                            Text {
+                                                text: "Lines are traced with marching squares at every multiple of the interval, using the height range from the Engine tab. Exports write <name>_topo.png, and the lines as <name>_contours.svg and .geojson.";
+                                                color: #8a8a8a;
+                                                font-size: 10px;
+                                                wrap: word-wrap;
+                                            }
+                                        }
+                                    }
+                                }
+                            }
+                            Tab {
+                                title: "Analysis";
+                                VerticalBox {
+                                    alignment: start;
+                                    Rectangle {
+                                        background: #161616;
+                                        border-radius: 10px;
+                                        VerticalBox {
+                                            HorizontalBox {
+                                                Text {text: "Show on Color Preview"; vertical-alignment: center;}
+                                                analysis_view:=ComboBox {
+                                                    model: ["None","Slope","Aspect","Plan Curvature","Profile Curvature","TPI","Roughness"];
+                                                    current-index: 0;
+                                                    selected(value) => {
+                                                        root.ui_changed();
+                                                    }
+                                                }
+                                            }
+                                            analysis_export:=CheckBox {text: "Export All Maps"; checked: false;}
+                                            Text {
+                                                text: "Slope: green to red at 45 degrees. Aspect: downhill direction, red facing north, gray when flat. Curvature and TPI: blue for valleys, red for ridges. Roughness: height spread around each pixel, dark to bright. Uses the size and height range from the Engine tab. Exports write <name>_slope.png, <name>_aspect.png and so on; the shown map is always exported.";
+                                                color: #8a8a8a;
+                                                font-size: 10px;
+                                                wrap: word-wrap;
+                                            }
+                                        }
+                                    }
+                                }
+                            }
+                            Tab {
+                                title: "Basins";
+                                VerticalBox {
+                                    alignment: start;
+                                    Rectangle {
+                                        background: #161616;
+                                        border-radius: 10px;
+                                        VerticalBox {
+                                            watershed_enabled:=CheckBox {text: "Drainage Basins"; checked: false; toggled => {
+                                                root.ui_changed();
+                                            }}
+                                            HorizontalBox {
+                                                Text {text: "Min Area (%)"; vertical-alignment: center;}
+                                                watershed_min_area:=Slider {enabled: watershed_enabled.checked; value: 0.5;minimum: 0;maximum: 5; height: 25px; changed => {
+                                                    root.ui_changed();
+                                                }}
+                                                Text {text: round(watershed_min_area.value * 10) / 10; vertical-alignment: center; width: 25px;}
+                                            }
+                                            watershed_preview:=CheckBox {enabled: watershed_enabled.checked; text: "Show on Color Preview"; checked: true; toggled => {
+                                                root.ui_changed();
+                                            }}
+                                            Text {
+                                                text: "Every pixel drains to its lowest neighbour like the rivers, with pits filled, until it leaves the map or reaches the flattened water. Basins smaller than the minimum share of the land join their neighbour. Exports write <name>_basins.png, the IDs as 16-bit <name>_basin_ids.png, and the area and outlet of every basin as <name>_basins.json.";
+                                                color: #8a8a8a;
+                                                font-size: 10px;
+                                                wrap: word-wrap;
+                                            }
+                                        }
+                                    }
+                                }
+                            }
+                            Tab {
+                                title: "Land";
+                                VerticalBox {
+                                    alignment: start;
+                                    Rectangle {
+                                        background: #161616;
+                                        border-radius: 10px;
+                                        VerticalBox {
+                                            Text {text: root.landmass_stats; wrap: word-wrap;}
+                                            HorizontalBox {
+                                                Text {text: "Min Island (px)"; vertical-alignment: center;}
+                                                landmass_min_island:=Slider {enabled: flatten_enabled.checked; value: 0;minimum: 0;maximum: 2000; height: 25px; changed => {
+                                                    root.ui_changed();
+                                                }}
+                                                Text {text: round(landmass_min_island.value); vertical-alignment: center; width: 35px;}
+                                            }
+                                            HorizontalBox {
+                                                Text {text: "Min Lake (px)"; vertical-alignment: center;}
+                                                landmass_min_lake:=Slider {enabled: flatten_enabled.checked; value: 0;minimum: 0;maximum: 2000; height: 25px; changed => {
+                                                    root.ui_changed();
+                                                }}
+                                                Text {text: round(landmass_min_lake.value); vertical-alignment: center; width: 35px;}
+                                            }
+                                            landmass_single:=CheckBox {enabled: flatten_enabled.checked; text: "Single Landmass"; checked: false; toggled => {
+                                                root.ui_changed();
+                                            }}
+                                            landmass_export:=CheckBox {text: "Export Statistics"; checked: false;}
+                                            Text {
+                                                text: "Land is everything above the sea level of the Terrain tab. With Flatten Ground on, right after flattening, islands below the minimum area sink to sea level and lakes below it are filled to their lowest shore; 0 keeps them. Single Landmass sinks everything but the largest. Areas are preview pixels. Exports write every island and lake to <name>_landmass.json.";
+                                                color: #8a8a8a;
+                                                font-size: 10px;
+                                                wrap: word-wrap;
+                                            }
+                                        }
+                                    }
+                                }
+                            }
+                            Tab {
+                                title: "Stats";
+                                VerticalBox {
+                                    alignment: start;
+                                    Rectangle {
+                                        background: #161616;
+                                        border-radius: 10px;
+                                        VerticalBox {
+                                            Text {text: root.height_stats; wrap: word-wrap;}
+                                            Image {source: root.histogram; height: 80px; image-fit: fill;}
+                                            Text {
+                                                text: "Elevations of the finished heightmap. The histogram covers heights 0-255 and is blue up to the sea level while Flatten Ground is on.";
+                                                color: #8a8a8a;
+                                                font-size: 10px;
+                                                wrap: word-wrap;
+                                            }
+                                        }
+                                    }
+                                    Rectangle {
+                                        background: #161616;
+                                        border-radius: 10px;
+                                        VerticalBox {
+                                            HorizontalBox {
+                                                Text {text: "Normalize"; vertical-alignment: center;}
+                                                normalize_mode:=ComboBox {
+                                                    model: ["Off","Stretch","Equalize","Percentile Clip"];
+                                                    current-index: 0;
+                                                    selected(value) => {
+                                                        root.ui_changed();
+                                                    }
+                                                }
+                                            }
+                                            HorizontalBox {
+                                                Text {text: "Clip (%)"; vertical-alignment: center;}
+                                                normalize_clip:=Slider {enabled: normalize_mode.current-index == 3; value: 1;minimum: 0;maximum: 10; height: 25px; changed => {
+                                                    root.ui_changed();
+                                                }}
+                                                Text {text: round(normalize_clip.value * 10) / 10; vertical-alignment: center; width: 25px;}
+                                            }
+                                            Text {
+                                                text: "Runs after the layers are blended and before the remap filters. Stretch maps the lowest and highest height to 0 and 255, Percentile Clip does the same after cutting off the clip share at both ends, and Equalize makes every height about equally common.";
+                                                color: #8a8a8a;
+                                                font-size: 10px;
+                                                wrap: word-wrap;
+                                            }
+                                        }
+                                    }
+                                }
+                            }
+                            Tab {
+                                title: "Scatter";
+                                VerticalBox {
+                                    alignment: start;
+                                    HorizontalBox {
+                                        add_scatter_rule_btn:=Button {height: 25px; text: "Add Rule";}
+                                        remove_scatter_rule_btn:=Button {height: 25px; text: "Remove Rule"; enabled: root.scatter_rules.length > 0;}
+                                    }
+                                    HorizontalBox {
+                                        scatter_overlay:=CheckBox {text: "Show Points"; checked: true; toggled => {
+                                            root.ui_changed();
+                                        }}
+                                        scatter_export:=CheckBox {text: "Export Points"; checked: false;}
+                                    }
+                                    ScrollView {
+                                        min-height: 300px;
+                                        VerticalBox {
+                                            alignment: start;
+                                            for rule[i] in root.scatter_rules: Rectangle {
+                                                background: #161616;
+                                                border-radius: 10px;
+                                                VerticalBox {
+                                                    spacing: -10px;
+                                                    HorizontalBox {
+                                                        Rectangle {width: 12px; height: 12px; border-radius: 6px; background: rule.color;}
+                                                        Rectangle {
+                                                            background: #262626;
+                                                            border-radius: 5px;
+                                                            TextInput {
+                                                                single-line: true;
+                                                                text: rule.name;
+                                                                vertical-alignment: center;
+                                                                accepted => {
+                                                                    rule.name = self.text;
+                                                                    root.ui_changed();
+                                                                }
+                                                            }
+                                                        }
+                                                    }
+                                                    HorizontalBox {
+                                                        Text {text: "Min Distance (m)"; vertical-alignment: center;}
+                                                        Slider {value: rule.min_distance;minimum: 1;maximum: 200; height: 25px; changed => {
+                                                            rule.min_distance = self.value;
+                                                            root.ui_changed();
+                                                        }}
+                                                    }
+                                                    HorizontalBox {
+                                                        Text {text: "Density"; vertical-alignment: center;}
+                                                        Slider {value: rule.density;minimum: 0;maximum: 1; height: 25px; changed => {
+                                                            rule.density = self.value;
+                                                            root.ui_changed();
+                                                        }}
+                                                    }
+                                                    HorizontalBox {
+                                                        Text {text: "Elevation Min (m)"; vertical-alignment: center;}
+                                                        Slider {value: rule.elevation_min;minimum: min_elevation.text.to-float();maximum: max_elevation.text.to-float(); height: 25px; changed => {
+                                                            rule.elevation_min = self.value;
+                                                            root.ui_changed();
+                                                        }}
+                                                    }
+                                                    HorizontalBox {
+                                                        Text {text: "Elevation Max (m)"; vertical-alignment: center;}
+                                                        Slider {value: rule.elevation_max;minimum: min_elevation.text.to-float();maximum: max_elevation.text.to-float(); height: 25px; changed => {
+                                                            rule.elevation_max = self.value;
+                                                            root.ui_changed();
+                                                        }}
+                                                    }
+                                                    HorizontalBox {
+                                                        Text {text: "Max Slope"; vertical-alignment: center;}
+                                                        Slider {value: rule.slope_max;minimum: 0;maximum: 90; height: 25px; changed => {
+                                                            rule.slope_max = self.value;
+                                                            root.ui_changed();
+                                                        }}
+                                                    }
+                                                    HorizontalBox {
+                                                        Text {text: "Water Min (m)"; vertical-alignment: center;}
+                                                        Slider {value: rule.water_min;minimum: 0;maximum: 500; height: 25px; changed => {
+                                                            rule.water_min = self.value;
+                                                            root.ui_changed();
+                                                        }}
+                                                    }
+                                                    HorizontalBox {
+                                                        Text {text: "Water Max (m)"; vertical-alignment: center;}
+                                                        Slider {value: rule.water_max;minimum: 0;maximum: 2000; height: 25px; changed => {
+                                                            rule.water_max = self.value;
+                                                            root.ui_changed();
+                                                        }}
+                                                    }
+                                                    HorizontalBox {
+                                                        Text {text: "River Min (m)"; vertical-alignment: center;}
+                                                        Slider {value: rule.river_min;minimum: 0;maximum: 200; height: 25px; changed => {
+                                                            rule.river_min = self.value;
+                                                            root.ui_changed();
+                                                        }}
+                                                    }
+                                                    HorizontalBox {
+                                                        Text {text: "Scale Min"; vertical-alignment: center;}
+                                                        Slider {value: rule.scale_min;minimum: 0.1;maximum: 3; height: 25px; changed => {
+                                                            rule.scale_min = self.value;
+                                                            root.ui_changed();
+                                                        }}
+                                                    }
+                                                    HorizontalBox {
+                                                        Text {text: "Scale Max"; vertical-alignment: center;}
+                                                        Slider {value: rule.scale_max;minimum: 0.1;maximum: 3; height: 25px; changed => {
+                                                            rule.scale_max = self.value;
+                                                            root.ui_changed();
+                                                        }}
+                                                    }
+                                                    HorizontalBox {
+                                                        Text {text: "Seed"; vertical-alignment: center;}
+                                                        Slider {value: rule.seed;minimum: 1;maximum: 5000; height: 25px; changed => {
+                                                            rule.seed = self.value;
+                                                            root.ui_changed();
+                                                        }}
+                                                    }
+                                                }
+                                            }
+                                        }
+                                    }
+                                    Text {
+                                        text: "Points keep the minimum distance from each other and are placed where the height, slope and distances to water and rivers fit. Distances use the terrain size from the Engine tab. Exported as <name>_points.csv and .json.";
+                                        color: #8a8a8a;
+                                        font-size: 10px;
+                                        wrap: word-wrap;
+                                    }
+                                }
                             }
                         }
-                    }
-                    Rectangle {
-                        background: #161616;
-                        border-radius: 10px;
-                        VerticalBox {
-                            Text {text: "Flatten Ground"; height: 25px;}
-                            HorizontalBox {
-                                Text {text: "Flatten Enabled"; vertical-alignment: center; height: 25px;}
-                                flatten_enabled:=CheckBox {checked: false; height: 25px; toggled => {
-                                    root.ui_changed();
-                                }}
-                            }
-                            HorizontalBox {
-                                Text {text: "As Water"; vertical-alignment: center; height: 25px;}
-                                as_water:=CheckBox {checked: false; height: 25px; toggled => {
-                                    root.ui_changed();
-                                }}
-                            }                            
-                            HorizontalBox {
-                                Text {text: "Ground Level"; vertical-alignment: center; height: 25px;}
-                                ground_level:=Slider {height: 25px;value: 0.5;minimum: 0.0;maximum: 255.0; changed => {
-                                    root.ui_changed();
-                                }}
-                            }
-                        }
-                    }                   
                                         
-                }
-                VerticalBox {
-                    HorizontalBox {
-                        Text {
-                            text: "3D Preview Resolution";
-                            vertical-alignment: center;
-                            height: 25px;
-                        }
-                        preview_scale:=ComboBox{
-                            model: ["32x32","64x64","128x128","Full Size"];
-                            current-index: 2;
-                            height: 25px;
-                        }                        
-                    }
-                    HorizontalBox{
-                        Text {text: "Horizontal"; vertical-alignment: center;}
-                        camera_horizontal:= Slider {value: 1;minimum: 0;maximum: 4; height: 25px; changed => {
-                            root.ui_changed();
-                        }}
-                        Text {text: "Vertical"; vertical-alignment: center;}
-                        camera_vertical:= Slider {value: 25;minimum: 15;maximum: 50; height: 25px; changed => {
-                            root.ui_changed();
-                        }}
-                    }
-                    HorizontalBox{
-                        Text {text: "Height 3D"; vertical-alignment: center;}
-                        height_3d:= Slider {value: 30;minimum: 0;maximum: 100; height: 25px; changed => {
-                            root.ui_changed();
-                        }}
                     }
-                    persp_image:=Image {source: @image-url("images/reload_icon.png");min-width: 512px;min-height: 512px;}
-                }
-                VerticalBox {
-                    img:=Image {source: @image-url("images/reload_icon.png");min-width: 256px;min-height: 256px;}
-                    colormap:=Image {source: @image-url("images/reload_icon.png");min-width: 256px;min-height: 256px;}
-                    HorizontalBox {
-                        Text {
-                            text: "Filename";
-                            vertical-alignment: center;
-                            height: 25px;
+                    VerticalBox {
+                        HorizontalBox {
+                            Text {
+                                text: "3D Preview Resolution";
+                                vertical-alignment: center;
+                                height: 25px;
+                            }
+                            preview_scale:=ComboBox{
+                                model: ["32x32","64x64","128x128","Full Size"];
+                                current-index: 2;
+                                height: 25px;
+                            }                        
+                        }
+                        HorizontalBox{
+                            Text {text: "Horizontal"; vertical-alignment: center;}
+                            camera_horizontal:= Slider {value: 1;minimum: 0;maximum: 4; height: 25px; changed => {
+                                root.ui_changed();
+                            }}
+                            Text {text: "Vertical"; vertical-alignment: center;}
+                            camera_vertical:= Slider {value: 25;minimum: 15;maximum: 50; height: 25px; changed => {
+                                root.ui_changed();
+                            }}
                         }
+                        HorizontalBox{
+                            Text {text: "Exaggeration"; vertical-alignment: center;}
+                            height_3d:= Slider {value: 1;minimum: 0;maximum: 10; height: 25px; changed => {
+                                root.ui_changed();
+                            }}
+                            Text {text: round(height_3d.value * 10) / 10 + "x"; vertical-alignment: center; width: 30px;}
+                        }
+                        persp_image:=Image {source: @image-url("images/reload_icon.png");min-width: 512px;min-height: 512px;}
+                    }
+                    VerticalBox {
                         Rectangle {
-                            background: #161616;
-                            border-radius: 5px;
-                            width: 160px;
-                            filename:=TextInput {
-                                single-line: true;
-                                text: "noise";
-                                height: 30px;
-                                width: 150px;
-                                vertical-alignment: center;
-                                horizontal-alignment: left;
+                            min-width: 256px;
+                            min-height: 256px;
+                            img:=Image {source: @image-url("images/reload_icon.png"); width: parent.width; height: parent.height; image-fit: contain;}
+                            TouchArea {
+                                enabled: sculpt_enabled.checked || road_placing.checked;
+                                mouse-cursor: self.enabled ? MouseCursor.crosshair : MouseCursor.default;
+                                pointer-event(event) => {
+                                    if (road_placing.checked) {
+                                        if (event.button == PointerEventButton.left && event.kind == PointerEventKind.down) {
+                                            root.road_clicked(self.mouse-x / 1px, self.mouse-y / 1px, self.width / 1px, self.height / 1px);
+                                        }
+                                    } else if (event.button == PointerEventButton.left && event.kind == PointerEventKind.down) {
+                                        root.sculpt_pressed(self.mouse-x / 1px, self.mouse-y / 1px, self.width / 1px, self.height / 1px);
+                                    } else if (event.button == PointerEventButton.left && event.kind == PointerEventKind.up) {
+                                        root.sculpt_released();
+                                    }
+                                }
+                                moved => {
+                                    if (self.pressed) {
+                                        root.sculpt_moved(self.mouse-x / 1px, self.mouse-y / 1px, self.width / 1px, self.height / 1px);
+                                    }
+                                }
                             }
                         }
+                        colormap:=Image {source: @image-url("images/reload_icon.png");min-width: 256px;min-height: 256px;image-fit: contain;}
+                        HorizontalBox {
+                            Text {
+                                text: "Filename";
+                                vertical-alignment: center;
+                                height: 25px;
+                            }
+                            Rectangle {
+                                background: #161616;
+                                border-radius: 5px;
+                                width: 160px;
+                                filename:=TextInput {
+                                    single-line: true;
+                                    text: "noise";
+                                    height: 30px;
+                                    width: 150px;
+                                    vertical-alignment: center;
+                                    horizontal-alignment: left;
+                                }
+                            }
                         
-                    }
-                    HorizontalBox {
-                        VerticalBox {
-                            Text {text: "Scale"; vertical-alignment: center;}
-                            export_scale:=ComboBox{
-                                model: ["256","515","1024","2048","4096"];
-                                current-index: 0;
+                        }
+                        HorizontalBox {
+                            Text {
+                                text: "Output Folder";
+                                vertical-alignment: center;
+                                height: 25px;
+                            }
+                            Rectangle {
+                                background: #161616;
+                                border-radius: 5px;
+                                width: 160px;
+                                output_dir:=TextInput {
+                                    single-line: true;
+                                    text: "";
+                                    height: 30px;
+                                    width: 150px;
+                                    vertical-alignment: center;
+                                    horizontal-alignment: left;
+                                }
+                            }
+                        }
+                        HorizontalBox {
+                            Text {
+                                text: "Threads (0 = auto)";
+                                vertical-alignment: center;
                                 height: 25px;
                             }
+                            threads:=SpinBox {
+                                minimum: 0;
+                                maximum: 64;
+                                value: 0;
+                            }
                         }
-                        VerticalBox {
-                            Text {text: "Filter"; vertical-alignment: center;}
-                            export_filter:=ComboBox{
-                                model: ["Nearest","Triangle","CatmullRom","Gaussian","Lanczos3"];
-                                current-index: 4;
+                        HorizontalBox {
+                            VerticalBox {
+                                Text {text: "Size"; vertical-alignment: center;}
+                                size_preset:=ComboBox{
+                                    model: root.size_presets;
+                                    current-index: 0;
+                                    height: 25px;
+                                    selected(value) => {
+                                        root.size_preset_selected(self.current-index);
+                                    }
+                                }
+                            }
+                            VerticalBox {
+                                Text {text: "Filter"; vertical-alignment: center;}
+                                export_filter:=ComboBox{
+                                    model: ["Nearest","Triangle","CatmullRom","Gaussian","Lanczos3"];
+                                    current-index: 4;
+                                    enabled: export_resample.checked;
+                                    height: 25px;
+                                }
+                            }
+                        }
+                        HorizontalBox {
+                            Text {
+                                text: "Width";
+                                vertical-alignment: center;
                                 height: 25px;
                             }
+                            Rectangle {
+                                background: #161616;
+                                border-radius: 5px;
+                                width: 70px;
+                                export_width:=TextInput {
+                                    single-line: true;
+                                    text: "256";
+                                    height: 30px;
+                                    width: 60px;
+                                    vertical-alignment: center;
+                                    horizontal-alignment: left;
+                                    edited => {
+                                        root.ui_changed();
+                                    }
+                                }
+                            }
+                            Text {
+                                text: "Height";
+                                vertical-alignment: center;
+                                height: 25px;
+                            }
+                            Rectangle {
+                                background: #161616;
+                                border-radius: 5px;
+                                width: 70px;
+                                export_height:=TextInput {
+                                    single-line: true;
+                                    text: "256";
+                                    height: 30px;
+                                    width: 60px;
+                                    vertical-alignment: center;
+                                    horizontal-alignment: left;
+                                    edited => {
+                                        root.ui_changed();
+                                    }
+                                }
+                            }
+                        }
+                        export_resample:=CheckBox {
+                            text: "Upscale preview instead of generating";
+                            checked: false;
+                        }
+                        HorizontalBox {
+                            load_btn:=Button {height: 30px; text: "Try Load Texture";}
+                            btn:=Button {height: 30px; text: "Export Texture";}
+                        }
+                        HorizontalBox {
+                            save_graph_btn:=Button {height: 30px; text: "Save Graph";}
+                            load_graph_btn:=Button {height: 30px; text: "Load Graph";}
+                            reset_graph_btn:=Button {height: 30px; enabled: root.custom_graph; text: "Reset Graph";}
+                        }
+                        Text {
+                            text: root.custom_graph ? "Pipeline: custom graph from " + root.filename + "_graph.json" : "Pipeline: default stage order";
+                            color: #8a8a8a;
+                            font-size: 10px;
+                            font-italic: true;
                         }
-                    }
-                    HorizontalBox {
-                        load_btn:=Button {height: 30px; text: "Try Load Texture";}
-                        btn:=Button {height: 30px; text: "Export Texture";}
-                    }
                     
-                    Text {
-                        text: "Exported textures will be saved or loaded to/from your Desktop";
-                        color: #8a8a8a;
-                        font-size: 10px;
-                        font-italic: true;
+                        Rectangle {
+                            height: 4px;
+                            width: 300px;
+                            border-radius: 2px;
+                            background: #161616;
+                            Rectangle {
+                                x: 0px;
+                                width: parent.width * root.progress;
+                                border-radius: 2px;
+                                background: root.busy ? #4a90d9 : #3a3a3a;
+                            }
+                        }
+                        Text {
+                            text: root.status;
+                            color: root.status_error ? #e05050 : #8a8a8a;
+                            font-size: 10px;
+                            font-italic: true;
+                            wrap: word-wrap;
+                            max-width: 300px;
+                        }
                     }
                 }
             }
         }
-          
+
     }      
         
 }
 
-#[derive(Serialize, Deserialize)]
-struct Layers {
-    scale: f64,
-    offset_x: f64,
-    offset_y: f64,
-    seed: u32,
-    opacity: f64,
-    blend_mode: i32,
-}
-
 fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    // `--batch <count>` writes the variations and exits without opening the window
+    if let Some(batch) = batch_from_args(&args) {
+        if let Err(e) = batch.and_then(|(base, options)| run_headless_batch(&args, &base, &options)) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     let app: App = App::new().expect("Failed to create App");
     let app_weak: slint::Weak<App> = app.as_weak();
     let app_add_weak = app_weak.clone();
     let app_remove_weak = app_weak.clone();
     let app_export_weak = app_weak.clone();
     let app_load_weak = app_weak.clone();
+    let app_save_graph_weak = app_weak.clone();
+    let app_load_graph_weak = app_weak.clone();
+    let app_reset_graph_weak = app_weak.clone();
+    let app_undo_weak = app_weak.clone();
+    let app_redo_weak = app_weak.clone();
+    let app_size_weak = app_weak.clone();
+    let app_sculpt_pressed_weak = app_weak.clone();
+    let app_sculpt_moved_weak = app_weak.clone();
+    let app_clear_strokes_weak = app_weak.clone();
+    let app_batch_weak = app_weak.clone();
+    let app_engine_weak = app_weak.clone();
+    let app_road_clicked_weak = app_weak.clone();
+    let app_new_road_weak = app_weak.clone();
+    let app_clear_roads_weak = app_weak.clone();
+    let app_add_rule_weak = app_weak.clone();
+    let app_remove_rule_weak = app_weak.clone();
     let main_buffer: Arc<Mutex<ImageBuffer<Rgba<u8>, Vec<u8>>>> = Arc::new(Mutex::new(ImageBuffer::new(IMAGE_SIZE, IMAGE_SIZE)));
     let main_color_buffer: Arc<Mutex<ImageBuffer<Rgba<u8>, Vec<u8>>>> = Arc::new(Mutex::new(ImageBuffer::new(IMAGE_SIZE, IMAGE_SIZE)));
     let main_3d_buffer: Arc<Mutex<ImageBuffer<Rgba<u8>, Vec<u8>>>> = Arc::new(Mutex::new(ImageBuffer::new(BIG_IMAGE_SIZE, BIG_IMAGE_SIZE)));
 
+    let extra_buffers: Arc<Mutex<HashMap<String, Buffer>>> = Arc::new(Mutex::new(HashMap::new()));
+    let custom_graph: Arc<Mutex<Option<Graph>>> = Arc::new(Mutex::new(None));
+    let history: Rc<RefCell<History<SerializedTool>>> = Rc::new(RefCell::new(History::new(Duration::from_millis(500), 100)));
+
     let export_main_buffer = Arc::clone(&main_buffer);
     let export_main_color_buffer = Arc::clone(&main_color_buffer);
+    let export_extra_buffers = Arc::clone(&extra_buffers);
 
 
-    app.on_ui_changed({
+    let preview_worker: Worker<PreviewJob> = Worker::new(Duration::from_millis(50), {
         let main_buffer = Arc::clone(&main_buffer);
         let main_color_buffer = Arc::clone(&main_color_buffer);
         let main_3d_buffer = Arc::clone(&main_3d_buffer);
-        move || {
-            let clicked_handle = app_weak.upgrade().unwrap();
+        let extra_buffers = Arc::clone(&extra_buffers);
+        let handle = app_weak.clone();
+        let mut pool_cache = PoolCache::default();
+        move |job: PreviewJob, cancel: CancelToken| {
+            let pool = match pool_cache.get(job.threads) {
+                Ok(pool) => pool,
+                Err(e) => {
+                    finish_progress(&handle, &cancel, Some(e.to_string()));
+                    return;
+                }
+            };
 
-            let main_buffer = Arc::clone(&main_buffer);
-            let main_color_buffer = Arc::clone(&main_color_buffer);
-            let main_3d_buffer = Arc::clone(&main_3d_buffer);
-
-            let scale = clicked_handle.get_scale() as f64;
-            let offset_x = clicked_handle.get_offset_x() as f64;
-            let offset_y = clicked_handle.get_offset_y() as f64;
-            let seed = clicked_handle.get_seed() as u32;
-            let model_rc = clicked_handle.get_layers();
-            let layer_parms = model_rc.as_any().downcast_ref::<VecModel<LayerParams>>().unwrap();
-            let erosion_mode = clicked_handle.get_erosion_mode();
-            let erosion_iterations = clicked_handle.get_erosion_iterations() as usize;
-            let talus_angle = clicked_handle.get_talus_angle();
-            let flatten_enabled = clicked_handle.get_flatten_enabled();
-            let as_water = clicked_handle.get_as_water();
-            let ground_level = clicked_handle.get_ground_level() as u8;
-            let calculate_rivers = clicked_handle.get_calculate_rivers();
-            let river_iterations = clicked_handle.get_river_iterations() as usize;
-            let erosion_factor = clicked_handle.get_erosion_factor() as i16;
-            let river_amount = clicked_handle.get_river_amount() as usize;
-            let river_seed = clicked_handle.get_river_seed() as u64;
+            // The graph covers most of the work, the 3D preview the last part of the progress bar
+            let mut ctx = EvalContext::new(job.width, job.height);
+            ctx.cancel = cancel.clone();
+            ctx.progress = Some(Box::new({
+                // The weak handle isn't Sync, but the context is shared with the pool threads
+                let handle = Mutex::new(handle.clone());
+                let cancel = cancel.clone();
+                move |fraction| {
+                    if let Ok(handle) = handle.lock() {
+                        report_progress(&handle, &cancel, fraction * 0.9);
+                    }
+                }
+            }));
+            let mut outputs = match pool.install(|| job.graph.evaluate(&ctx)) {
+                Ok(outputs) => outputs,
+                Err(HeightmapError::Cancelled) => return,
+                Err(e) => {
+                    finish_progress(&handle, &cancel, Some(e.to_string()));
+                    return;
+                }
+            };
+            let buffer = outputs.remove("heightmap").unwrap_or_else(|| ImageBuffer::new(job.width, job.height));
+            let colored_buffer = outputs.remove("colormap").unwrap_or_else(|| buffer.clone());
+            if cancel.is_cancelled() {
+                return;
+            }
 
-            let preview_plane_res = clicked_handle.get_preview_scale() as usize;
-            let camera_vertical = clicked_handle.get_camera_vertical() as f32;
-            let camera_horizontal = clicked_handle.get_camera_horizontal() as f32;
-            let height_3d = clicked_handle.get_height_3d() as f32;
+            // The analysis, basin and topographic maps and the points are only shown in the preview, the stored colormap stays clean for exports
+            let analysis_buffer = outputs.get(job.analysis_view.name());
+            let basin_buffer = match &job.basins {
+                Some(params) if analysis_buffer.is_none() => {
+                    match watersheds(&buffer, params, &job.terrain, &cancel) {
+                        Ok(basins) => Some(render_basins(&basins, &buffer, &job.terrain)),
+                        Err(HeightmapError::Cancelled) => return,
+                        Err(e) => {
+                            report_status(&handle, format!("Couldn't find the drainage basins: {}", e), true);
+                            None
+                        }
+                    }
+                }
+                _ => None,
+            };
+            let stats = height_stats(&buffer, Some(job.sea_level));
+            let stats_text = height_summary(&stats, &job.terrain);
+            let histogram = render_histogram(&stats, Some(job.sea_level), 64);
+            let histogram_buffer = SharedPixelBuffer::<Rgba8Pixel>::clone_from_slice(histogram.as_raw().as_slice(), histogram.width(), histogram.height());
+            let landmass_text = stats_summary(&landmass_stats(&buffer, job.sea_level, job.terrain.size_m()));
+            let mut display_color_buffer = match (analysis_buffer, basin_buffer, outputs.get("topo")) {
+                (Some(analysis), _, _) => analysis.clone(),
+                (None, Some(basins), _) => basins,
+                (None, None, Some(topo)) if job.show_topo => topo.clone(),
+                _ => colored_buffer.clone(),
+            };
+            if !job.scatter_rules.is_empty() {
+                let terrain = ScatterTerrain {
+                    heightmap: &buffer,
+                    river_mask: outputs.get("rivers"),
+                    sea_level: job.terrain.sea_level(),
+                    size_m: job.terrain.size_m(),
+                    height_m: job.terrain.relief_m(),
+                    min_elevation_m: job.terrain.min_elevation_m,
+                };
+                match scatter(&terrain, &job.scatter_rules, &cancel) {
+                    Ok(sets) => draw_scatter_overlay(&mut display_color_buffer, &sets, &job.scatter_rules, job.terrain.size_m()),
+                    Err(HeightmapError::Cancelled) => return,
+                    Err(e) => report_status(&handle, format!("Couldn't scatter the points: {}", e), true),
+                }
+            }
 
-            let plane_res: usize = match preview_plane_res {
-                0 => 32,
-                1 => 64,
-                2 => 128,
-                3 => 256,
-                _ => 128,
+            let plane_res = job.plane_res;
+            let mut buffer_3d = vec![0u32; (BIG_IMAGE_SIZE * BIG_IMAGE_SIZE) as usize];
+            let mut plane = rend::reader::unit_plane(plane_res, plane_res, 0xFFFFFF);
+            let mut camera = rend::render::Camera {
+                fov: 90.0,
+                near: 0.1,
+                up: Vector4::new(0.0, 1.0, 0.0, 0.0),
+                far: 1000.0,
+                position: Vector4::new(0.0, job.camera_vertical, -25.0, 1.0),
+                look_at: Vector4::new(0.0, 0.0, 0.0, 1.0),
             };
+            camera.rotate_around_look_at(camera.up, job.camera_horizontal);
 
-            let mut layers: Vec<Layers> = Vec::new();
-            for layer in layer_parms.iter() {
-                layers.push(Layers {
-                    scale: layer.scale as f64,
-                    offset_x: layer.offset_x as f64,
-                    offset_y: layer.offset_y as f64,
-                    seed: layer.seed as u32,
-                    opacity: layer.opacity as f64,
-                    blend_mode: layer.blend_mode,
-                });
+            // we can prevent cloning if we calculate 3d after we did everything 2d
+
+            let mut hm = buffer.clone();
+            let mut cm = colored_buffer.clone();
+
+            if let Err(e) = modifiers::modifiers::scale_image(&mut hm, (plane_res as u32, plane_res as u32), FilterType::Nearest) {
+                report_status(&handle, format!("Couldn't scale the 3D preview: {}", e), true);
+            }
+            if let Err(e) = modifiers::modifiers::scale_image(&mut cm, (plane_res as u32, plane_res as u32), FilterType::Nearest) {
+                report_status(&handle, format!("Couldn't scale the 3D preview: {}", e), true);
             }
 
-            let handle = clicked_handle.as_weak();
-            thread::spawn(move || {
-                let locked_buffer_result = main_buffer.lock();
-                let locked_color_buffer_result = main_color_buffer.lock();
-                let locked_3d_buffer_result = main_3d_buffer.lock();
-                match (locked_buffer_result, locked_color_buffer_result, locked_3d_buffer_result) {
-                    (Ok(mut locked_buffer), Ok(mut locked_color_buffer), Ok(mut locked_3d_buffer)) => {
-                        let mut buffer = generate_perlin_noise_buffer(IMAGE_SIZE, IMAGE_SIZE, offset_x, offset_y, scale, 1.0, seed);
-                        for layer in layers {
-                            let layer_buffer = generate_perlin_noise_buffer(IMAGE_SIZE, IMAGE_SIZE, layer.offset_x, layer.offset_y, layer.scale, layer.opacity, layer.seed);
-                            buffer = blend_buffers(&buffer, &layer_buffer, layer.blend_mode);
-                        }
-                        let mut colored_buffer = colorize_buffer(&buffer, 2);
+            modifiers::modifiers::displace_plane(&mut plane, &hm, displacement_3d(&job.terrain, plane_res, job.height_3d));
+            modifiers::modifiers::colorize_plane(&mut plane, &cm);
+            let rotation = Vector4::new(0.0, 0.0, 0.0, 0.0);
+            let uni_size = 10.0;
+            let scale = Vector4::new(uni_size / plane_res as f32, uni_size / plane_res as f32, uni_size / plane_res as f32, 0.0);
+            let position = Vector4::new(0.0, 1.0, 0.0, 0.0);
+            rend::render::draw_object(&mut buffer_3d, &plane, (BIG_IMAGE_SIZE as usize, BIG_IMAGE_SIZE as usize), &camera, position, rotation, scale, Some(0x000000));
+            let buffer_3d_image = modifiers::modifiers::buffer_to_image_buffer_rgb(&buffer_3d, (BIG_IMAGE_SIZE, BIG_IMAGE_SIZE));
 
-                        if flatten_enabled {
-                            clamp_image_buffer(&mut buffer, &mut colored_buffer, as_water, ground_level, 255);
-                        }
+            // Jobs run one after another on this thread, so a newer result is always stored after an older one
+            if cancel.is_cancelled() {
+                return;
+            }
+            match (main_buffer.lock(), main_color_buffer.lock(), main_3d_buffer.lock(), extra_buffers.lock()) {
+                (Ok(mut locked_buffer), Ok(mut locked_color_buffer), Ok(mut locked_3d_buffer), Ok(mut locked_extra_buffers)) => {
+                    *locked_buffer = buffer.clone();
+                    *locked_color_buffer = colored_buffer.clone();
+                    *locked_3d_buffer = buffer_3d_image.clone();
+                    *locked_extra_buffers = outputs;
+                }
+                _ => {
+                    finish_progress(&handle, &cancel, Some("Couldn't store the generated images".to_string()));
+                    return;
+                }
+            }
 
-                        if erosion_mode != 0 {
-                            thermal_erosion(&mut buffer, &mut colored_buffer, erosion_iterations, talus_angle, erosion_mode);
-                        }
-                        if calculate_rivers {
-                            match simulate_river_flow(&mut buffer, &mut colored_buffer, river_iterations, erosion_factor, river_amount, river_seed) {
-                                Ok(_) => {}
-                                Err(e) => {
-                                    println!("Error: {}", e);
-                                }
-                            }
-                        }
+            let pixel_buffer = SharedPixelBuffer::<Rgba8Pixel>::clone_from_slice(buffer.as_raw().as_slice(), buffer.width(), buffer.height());
+            let colored_pixel_buffer = SharedPixelBuffer::<Rgba8Pixel>::clone_from_slice(display_color_buffer.as_raw().as_slice(), display_color_buffer.width(), display_color_buffer.height());
+            let pixel_3d_buffer = SharedPixelBuffer::<Rgba8Pixel>::clone_from_slice(buffer_3d_image.into_raw().as_slice(), BIG_IMAGE_SIZE, BIG_IMAGE_SIZE);
+            match handle.upgrade_in_event_loop(move |weak| {
+                // A newer job may have been submitted while this one was on its way to the event loop
+                if cancel.is_cancelled() {
+                    return;
+                }
+                weak.set_image(slint::Image::from_rgba8(pixel_buffer));
+                weak.set_colormap(slint::Image::from_rgba8(colored_pixel_buffer));
+                weak.set_image_perspective(slint::Image::from_rgba8(pixel_3d_buffer));
+                weak.set_landmass_stats(landmass_text.into());
+                weak.set_height_stats(stats_text.into());
+                weak.set_histogram(slint::Image::from_rgba8(histogram_buffer));
+                weak.set_progress(1.0);
+                weak.set_busy(false);
+            }) {
+                Ok(_) => {}
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                }
+            };
+        }
+    }, {
+        let handle = app_weak.clone();
+        move |message: String, cancel: CancelToken| {
+            finish_progress(&handle, &cancel, Some(format!("Preview failed: {}", message)));
+        }
+    });
 
-                        let mut buffer_3d = vec![0u32; (BIG_IMAGE_SIZE * BIG_IMAGE_SIZE) as usize];
-                        let mut plane = rend::reader::unit_plane(plane_res, plane_res, 0xFFFFFF);
-                        let mut camera = rend::render::Camera {
-                            fov: 90.0,
-                            near: 0.1,
-                            up: Vector4::new(0.0, 1.0, 0.0, 0.0),
-                            far: 1000.0,
-                            position: Vector4::new(0.0, camera_vertical, -25.0, 1.0),
-                            look_at: Vector4::new(0.0, 0.0, 0.0, 1.0),
-                        };
-                        camera.rotate_around_look_at(camera.up, camera_horizontal);
+    app.on_ui_changed({
+        let custom_graph = Arc::clone(&custom_graph);
+        let history = Rc::clone(&history);
+        move || {
+            let clicked_handle = app_weak.upgrade().unwrap();
 
-                        // we can prevent cloning if we calculate 3d after we did everything 2d
+            let settings = collect_settings(&clicked_handle);
+            {
+                let mut history = history.borrow_mut();
+                history.record(settings.clone());
+                clicked_handle.set_can_undo(history.can_undo());
+                clicked_handle.set_can_redo(history.can_redo());
+            }
+            if let Err(e) = check_size(settings.export_width, settings.export_height) {
+                set_status(&clicked_handle, &e.to_string(), true);
+                return;
+            }
+            let (width, height) = preview_size(settings.export_width, settings.export_height);
+            let graph = current_graph(&custom_graph, &settings);
 
-                        let mut hm = buffer.clone();
-                        let mut cm = colored_buffer.clone();
+            let preview_plane_res = clicked_handle.get_preview_scale() as usize;
+            let plane_res: usize = match preview_plane_res {
+                0 => 32,
+                1 => 64,
+                2 => 128,
+                3 => 256,
+                _ => 128,
+            };
 
-                        match modifiers::modifiers::scale_image(&mut hm, (plane_res as u32, plane_res as u32), FilterType::Nearest) {
-                            Ok(_) => {}
-                            Err(e) => {
-                                println!("Error: {}", e);
-                            }
-                        }
-                        match modifiers::modifiers::scale_image(&mut cm, (plane_res as u32, plane_res as u32), FilterType::Nearest) {
-                            Ok(_) => {}
-                            Err(e) => {
-                                println!("Error: {}", e);
-                            }
-                        }
-
-                        modifiers::modifiers::displace_plane(&mut plane, &hm, height_3d);
-                        modifiers::modifiers::colorize_plane(&mut plane, &cm);
-                        let rotation = Vector4::new(0.0, 0.0, 0.0, 0.0);
-                        let uni_size = 10.0;
-                        let scale = Vector4::new(uni_size / plane_res as f32, uni_size / plane_res as f32, uni_size / plane_res as f32, 0.0);
-                        let position = Vector4::new(0.0, 1.0, 0.0, 0.0);
-                        rend::render::draw_object(&mut buffer_3d, &plane, (BIG_IMAGE_SIZE as usize, BIG_IMAGE_SIZE as usize), &camera, position, rotation, scale, Some(0x000000));
-                        let buffer_3d_image = modifiers::modifiers::buffer_to_image_buffer_rgb(&buffer_3d, (BIG_IMAGE_SIZE, BIG_IMAGE_SIZE));
-
-                        *locked_buffer = buffer.clone();
-                        *locked_color_buffer = colored_buffer.clone();
-                        *locked_3d_buffer = buffer_3d_image.clone();
-                        let pixel_buffer = SharedPixelBuffer::<Rgba8Pixel>::clone_from_slice(buffer.into_raw().as_slice(), IMAGE_SIZE, IMAGE_SIZE);
-                        let colored_pixel_buffer = SharedPixelBuffer::<Rgba8Pixel>::clone_from_slice(colored_buffer.into_raw().as_slice(), IMAGE_SIZE, IMAGE_SIZE);
-                        let pixel_3d_buffer = SharedPixelBuffer::<Rgba8Pixel>::clone_from_slice(buffer_3d_image.into_raw().as_slice(), BIG_IMAGE_SIZE, BIG_IMAGE_SIZE);
-                        let weak_copy = handle.clone();
-                        match slint::invoke_from_event_loop(move || {
-                            let img = slint::Image::from_rgba8(pixel_buffer);
-                            let colormap = slint::Image::from_rgba8(colored_pixel_buffer);
-                            let map_3d = slint::Image::from_rgba8(pixel_3d_buffer);
-                            let weak = weak_copy.upgrade().unwrap();
-                            weak.set_image(img);
-                            weak.set_colormap(colormap);
-                            weak.set_image_perspective(map_3d);
-                        }) {
-                            Ok(_) => {}
-                            Err(e) => {
-                                println!("Error: {}", e);
-                            }
-                        };
-                    }
-                    (Err(e), _, _) => {
-                        println!("Error in Height Buffer: {}", e);
-                    }
-                    (_, Err(e), _) => {
-                        println!("Error in Color Buffer: {}", e);
-                    }
-                    (_, _, Err(e)) => {
-                        println!("Error in 3D Buffer: {}", e);
-                    }
-                }
+            clicked_handle.set_busy(true);
+            clicked_handle.set_progress(0.0);
+            preview_worker.submit(PreviewJob {
+                graph,
+                width,
+                height,
+                plane_res,
+                camera_vertical: clicked_handle.get_camera_vertical(),
+                camera_horizontal: clicked_handle.get_camera_horizontal(),
+                height_3d: clicked_handle.get_height_3d(),
+                threads: clicked_handle.get_threads().max(0) as usize,
+                show_topo: settings.contour_enabled && settings.contour_preview,
+                analysis_view: settings.analysis_view,
+                sea_level: settings.terrain.sea_level(),
+                basins: if settings.watershed_enabled && settings.watershed_preview { Some(settings.watershed_params.clone()) } else { None },
+                scatter_rules: if settings.scatter_overlay { settings.scatter_rules.clone() } else { Vec::new() },
+                terrain: settings.terrain,
             });
         }
     });
@@ -577,7 +1763,9 @@ fn main() {
             seed: 1.0,
             opacity: 1.0,
             blend_mode: 0,
+            import_path: slint::SharedString::new(),
         });
+        clicked_handle.invoke_ui_changed();
     });
 
     app.on_remove_layer_btn_clicked(move || {
@@ -587,181 +1775,810 @@ fn main() {
         if layers.iter().count() > 0 {
             layers.remove(layers.iter().count() - 1);
         }
+        clicked_handle.invoke_ui_changed();
     });
 
-    app.on_load_btn_clicked(move || {
-        deserialize_tool(&app_load_weak);
+    app.on_add_scatter_rule_btn_clicked(move || {
+        let handle = app_add_rule_weak.upgrade().unwrap();
+        let model_rc = handle.get_scatter_rules();
+        let rules = model_rc.as_any().downcast_ref::<VecModel<ScatterRuleParams>>().unwrap();
+        let index = rules.row_count();
+        let terrain = collect_settings(&handle).terrain;
+        rules.push(scatter_rule_params(&ScatterRule {
+            name: format!("rule{}", index + 1),
+            // Heights 0 to 200, the range new rules covered before it was in metres
+            elevation_min_m: terrain.min_elevation_m,
+            elevation_max_m: terrain.elevation_m(200.0),
+            seed: index as u32 + 1,
+            color: RULE_COLORS[index % RULE_COLORS.len()],
+            ..ScatterRule::default()
+        }));
+        handle.invoke_ui_changed();
     });
 
-    app.on_export_btn_clicked(move || {
-        let clicked_handle = app_export_weak.upgrade().unwrap();
-        let locked_buffer = export_main_buffer.lock().unwrap();
-        let locked_color_buffer = export_main_color_buffer.lock().unwrap();
-        let mut buffer = locked_buffer.clone();
-        let mut color_buffer = locked_color_buffer.clone();
-
-        let filename = clicked_handle.get_filename();
-        let export_scale = clicked_handle.get_export_scale() as u32;
-        let export_filter = clicked_handle.get_scale_type() as u32;
-
-        let image_size = match export_scale {
-            0 => { IMAGE_SIZE }
-            1 => { IMAGE_SIZE * 2 }
-            2 => { IMAGE_SIZE * 4 }
-            3 => { IMAGE_SIZE * 8 }
-            4 => { IMAGE_SIZE * 16 }
-            _ => { IMAGE_SIZE }
-        };
-
-        let image_filter = match export_filter {
-            0 => { FilterType::Nearest }
-            1 => { FilterType::Triangle }
-            2 => { FilterType::CatmullRom }
-            3 => { FilterType::Gaussian }
-            4 => { FilterType::Lanczos3 }
-            _ => { FilterType::Lanczos3 }
-        };
+    app.on_remove_scatter_rule_btn_clicked(move || {
+        let handle = app_remove_rule_weak.upgrade().unwrap();
+        let model_rc = handle.get_scatter_rules();
+        let rules = model_rc.as_any().downcast_ref::<VecModel<ScatterRuleParams>>().unwrap();
+        if rules.row_count() > 0 {
+            rules.remove(rules.row_count() - 1);
+        }
+        handle.invoke_ui_changed();
+    });
 
-        match scale_image(&mut buffer, (image_size, image_size), image_filter) {
-            Ok(_) => {}
-            Err(e) => {
-                println!("Error: {}", e);
+    app.on_undo({
+        let history = Rc::clone(&history);
+        move || {
+            let handle = app_undo_weak.upgrade().unwrap();
+            let previous = history.borrow_mut().undo();
+            if let Some(settings) = previous {
+                apply_settings(&handle, &settings);
+                handle.invoke_ui_changed();
             }
         }
-        match scale_image(&mut color_buffer, (image_size, image_size), image_filter) {
-            Ok(_) => {}
-            Err(e) => {
-                println!("Error: {}", e);
+    });
+
+    app.on_redo({
+        let history = Rc::clone(&history);
+        move || {
+            let handle = app_redo_weak.upgrade().unwrap();
+            let next = history.borrow_mut().redo();
+            if let Some(settings) = next {
+                apply_settings(&handle, &settings);
+                handle.invoke_ui_changed();
             }
         }
-        serialize_tool(&app_export_weak);
-        save_image_to_desktop(&buffer, filename.as_str(), "height");
-        save_image_to_desktop(&color_buffer, filename.as_str(), "color");
     });
-    app.run().unwrap();
-}
 
-#[derive(Serialize, Deserialize)]
-struct SerializedTool {
-    scale: f64,
-    offset_x: f64,
-    offset_y: f64,
-    seed: u32,
-    layers: String,
-    erosion_mode: i32,
-    erosion_iterations: usize,
-    talus_angle: f32,
-    flatten_enabled: bool,
-    ground_level: u8,
-    calculate_rivers: bool,
-    river_iterations: usize,
-    erosion_factor: i16,
-    river_amount: usize,
-    river_seed: u64,
-    filename: String,
-    export_scale: u32,
-    export_filter: u32,
-    as_water: bool,
-}
+    app.on_load_btn_clicked(move || {
+        let handle = app_load_weak.upgrade().unwrap();
+        match deserialize_tool(&handle) {
+            Ok(path) => set_status(&handle, &format!("Loaded {}", path.display()), false),
+            Err(e) => set_status(&handle, &e.to_string(), true),
+        }
+    });
 
-fn deserialize_tool(weak: &slint::Weak<App>) {
-    let handle = weak.upgrade().unwrap();
-    let file_name = handle.get_filename();
-    let desktop_path = dirs::desktop_dir();
-    match desktop_path {
-        Some(path) => {
-            let file_path = path.join(format!("{}_config.json", file_name));
-            let mut file = match fs::File::open(file_path) {
-                Ok(file) => file,
-                Err(e) => {
-                    println!("Couldn't open file: {}", e);
-                    return;
-                }
+    app.on_export_btn_clicked({
+        let custom_graph = Arc::clone(&custom_graph);
+        move || {
+            let clicked_handle = app_export_weak.upgrade().unwrap();
+            let mut settings = collect_settings(&clicked_handle);
+
+            // Engines only accept certain sizes, so the export snaps to the closest one
+            let (width, height) = settings.engine_preset.nearest_size(settings.export_width, settings.export_height);
+            if (width, height) != (settings.export_width, settings.export_height) {
+                set_export_size(&clicked_handle, width, height);
+                settings.export_width = width;
+                settings.export_height = height;
+                clicked_handle.invoke_ui_changed();
+            }
+            if let Err(e) = check_size(width, height) {
+                set_status(&clicked_handle, &e.to_string(), true);
+                return;
+            }
+
+            let image_filter = match settings.export_filter {
+                0 => { FilterType::Nearest }
+                1 => { FilterType::Triangle }
+                2 => { FilterType::CatmullRom }
+                3 => { FilterType::Gaussian }
+                4 => { FilterType::Lanczos3 }
+                _ => { FilterType::Lanczos3 }
             };
-            let mut contents = String::new();
-            match file.read_to_string(&mut contents) {
-                Ok(_) => {}
-                Err(e) => {
-                    println!("Couldn't read file: {}", e);
-                    return;
-                }
+
+            let mut errors: Vec<String> = Vec::new();
+            if let Err(e) = serialize_tool(&clicked_handle) {
+                errors.push(e.to_string());
             }
-            let serialized_tool: SerializedTool = match serde_json::from_str(&contents) {
-                Ok(tool) => tool,
-                Err(e) => {
-                    println!("Couldn't deserialize tool: {}", e);
-                    return;
+
+            // Fallback: upscale the finished preview instead of generating again
+            let preview_outputs = if settings.export_resample {
+                let mut outputs = match export_extra_buffers.lock() {
+                    Ok(locked_extra_buffers) => locked_extra_buffers.clone(),
+                    Err(e) => {
+                        errors.push(format!("Couldn't read the extra outputs: {}", e));
+                        HashMap::new()
+                    }
+                };
+                match (export_main_buffer.lock(), export_main_color_buffer.lock()) {
+                    (Ok(locked_buffer), Ok(locked_color_buffer)) => {
+                        outputs.insert("heightmap".to_string(), locked_buffer.clone());
+                        outputs.insert("colormap".to_string(), locked_color_buffer.clone());
+                    }
+                    (Err(e), _) | (_, Err(e)) => {
+                        set_status(&clicked_handle, &format!("Couldn't read the generated images: {}", e), true);
+                        return;
+                    }
                 }
+                Some(outputs)
+            } else {
+                None
             };
-            handle.set_scale(serialized_tool.scale as f32);
-            handle.set_offset_x(serialized_tool.offset_x as f32);
-            handle.set_offset_y(serialized_tool.offset_y as f32);
-            handle.set_seed(serialized_tool.seed as f32);
-            let layers: Vec<Layers> = match serde_json::from_str(&serialized_tool.layers) {
-                Ok(layers) => layers,
-                Err(e) => {
-                    println!("Couldn't deserialize layers: {}", e);
-                    return;
+
+            let graph = current_graph(&custom_graph, &settings);
+            let threads = clicked_handle.get_threads().max(0) as usize;
+            let dir = output_dir(&clicked_handle);
+            let filename = settings.filename.clone();
+            let handle = clicked_handle.as_weak();
+            set_status(&clicked_handle, &format!("Exporting {} at {}x{}...", filename, width, height), false);
+
+            thread::spawn(move || {
+                let mut road_paths: Option<Vec<RoadPath>> = None;
+                let outputs = match preview_outputs {
+                    Some(mut outputs) => {
+                        for buffer in outputs.values_mut() {
+                            if let Err(e) = scale_image(buffer, (width, height), image_filter) {
+                                errors.push(e.to_string());
+                            }
+                        }
+                        outputs
+                    }
+                    None => {
+                        let mut ctx = EvalContext::new(width, height);
+                        ctx.detail = detail(width, height);
+                        match build_thread_pool(threads).and_then(|pool| pool.install(|| graph.evaluate(&ctx))) {
+                            Ok(outputs) => {
+                                road_paths = ctx.road_paths.into_inner().ok();
+                                outputs
+                            }
+                            Err(e) => {
+                                errors.push(e.to_string());
+                                HashMap::new()
+                            }
+                        }
+                    }
+                };
+
+                for (name, buffer) in outputs.iter() {
+                    if let Err(e) = save_image(buffer, &output_path(&dir, &filename, export_suffix(name), "png")) {
+                        errors.push(e.to_string());
+                    }
+                }
+
+                if settings.dem_export_asc || settings.dem_export_xyz {
+                    if let Some(buffer) = outputs.get("heightmap") {
+                        // Flattened water sits exactly at sea level, so everything at or below it can be masked out
+                        let nodata_below = if settings.dem_water_nodata && settings.flatten_enabled { Some(settings.terrain.sea_level()) } else { None };
+                        let terrain = &settings.terrain;
+                        let cell_size = terrain.pixel_size_m(buffer.width(), buffer.height());
+                        let grid = grid_from_buffer(buffer, cell_size, terrain.min_elevation_m, terrain.elevation_m(255.0), nodata_below);
+                        if settings.dem_export_asc {
+                            if let Err(e) = write_ascii_grid(&grid, &output_path(&dir, &filename, "height", "asc")) {
+                                errors.push(e.to_string());
+                            }
+                        }
+                        if settings.dem_export_xyz {
+                            if let Err(e) = write_xyz(&grid, &output_path(&dir, &filename, "height", "xyz")) {
+                                errors.push(e.to_string());
+                            }
+                        }
+                    }
+                }
+
+                if settings.engine_preset != EnginePreset::None {
+                    if let Some(buffer) = outputs.get("heightmap") {
+                        if let Err(e) = export_for_engine(settings.engine_preset, buffer, &dir, &filename, &settings.terrain) {
+                            errors.push(e.to_string());
+                        }
+                    }
+                }
+
+                if settings.tile_export {
+                    if let Some(buffer) = outputs.get("heightmap") {
+                        if let Err(e) = export_world_partition_tiles(buffer, outputs.get("mask"), &dir, &filename, settings.tile_size, &settings.terrain) {
+                            errors.push(e.to_string());
+                        }
+                    }
+                }
+
+                if settings.road_export && settings.roads.iter().any(|road| road.waypoints.len() >= 2) {
+                    // Upscaled previews don't carve the roads again, so there are no centre lines to write
+                    match road_paths {
+                        Some(paths) => {
+                            if let Err(e) = export_road_paths(&paths, &dir, &filename) {
+                                errors.push(e.to_string());
+                            }
+                        }
+                        None => errors.push("Road polylines are only written when the export is generated, not upscaled".to_string()),
+                    }
+                }
+
+                if settings.contour_enabled && settings.contour_export_vector {
+                    if let Some(buffer) = outputs.get("heightmap") {
+                        if let Err(e) = export_contours(buffer, &settings.contour_params, &dir, &filename, &settings.terrain) {
+                            errors.push(e.to_string());
+                        }
+                    }
                 }
+
+                if settings.watershed_enabled {
+                    if let Some(buffer) = outputs.get("heightmap") {
+                        let result = watersheds(buffer, &settings.watershed_params, &settings.terrain, &CancelToken::new())
+                            .and_then(|basins| export_watersheds(&basins, buffer, &dir, &filename, &settings.terrain));
+                        if let Err(e) = result {
+                            errors.push(e.to_string());
+                        }
+                    }
+                }
+
+                if settings.landmass_export {
+                    if let Some(buffer) = outputs.get("heightmap") {
+                        if let Err(e) = export_landmass_stats(&landmass_stats(buffer, settings.terrain.sea_level(), settings.terrain.size_m()), &dir, &filename) {
+                            errors.push(e.to_string());
+                        }
+                    }
+                }
+
+                if settings.scatter_export && !settings.scatter_rules.is_empty() {
+                    if let Some(heightmap) = outputs.get("heightmap") {
+                        let terrain = ScatterTerrain {
+                            heightmap,
+                            river_mask: outputs.get("rivers"),
+                            sea_level: settings.terrain.sea_level(),
+                            size_m: settings.terrain.size_m(),
+                            height_m: settings.terrain.relief_m(),
+                            min_elevation_m: settings.terrain.min_elevation_m,
+                        };
+                        if let Err(e) = scatter(&terrain, &settings.scatter_rules, &CancelToken::new()).and_then(|sets| export_scatter_points(&sets, &dir, &filename)) {
+                            errors.push(e.to_string());
+                        }
+                    }
+                }
+
+                if errors.is_empty() {
+                    report_status(&handle, format!("Exported {} to {}", filename, dir.display()), false);
+                } else {
+                    report_status(&handle, errors.join("\n"), true);
+                }
+            });
+        }
+    });
+
+    app.on_size_preset_selected(move |index| {
+        let handle = app_size_weak.upgrade().unwrap();
+        if let Some(&(width, height)) = SIZE_PRESETS.get(index as usize) {
+            handle.set_export_width(slint::SharedString::from(width.to_string()));
+            handle.set_export_height(slint::SharedString::from(height.to_string()));
+            handle.invoke_ui_changed();
+        }
+    });
+
+    app.on_engine_preset_selected(move |index| {
+        let handle = app_engine_weak.upgrade().unwrap();
+        let settings = collect_settings(&handle);
+        let (width, height) = EnginePreset::from_index(index).nearest_size(settings.export_width, settings.export_height);
+        set_export_size(&handle, width, height);
+        handle.invoke_ui_changed();
+    });
+
+    // Set while the mouse button is held down on the height preview, so moves extend the last stroke
+    let stroke_active = Rc::new(Cell::new(false));
+
+    app.on_sculpt_pressed({
+        let stroke_active = Rc::clone(&stroke_active);
+        move |x, y, area_width, area_height| {
+            let handle = app_sculpt_pressed_weak.upgrade().unwrap();
+            let point = match preview_point(&handle, x, y, area_width, area_height) {
+                Some(point) if (0.0..=1.0).contains(&point[0]) && (0.0..=1.0).contains(&point[1]) => point,
+                _ => return,
             };
-            let model_rc = handle.get_layers();
-            let layer_parms = model_rc.as_any().downcast_ref::<VecModel<LayerParams>>().unwrap();
-            while layer_parms.iter().count() > 0 {
-                layer_parms.remove(0);
+            let model_rc = handle.get_strokes();
+            let strokes = model_rc.as_any().downcast_ref::<VecModel<BrushStroke>>().unwrap();
+            strokes.push(BrushStroke {
+                mode: handle.get_brush_mode(),
+                radius: handle.get_brush_radius() / 100.0,
+                strength: handle.get_brush_strength(),
+                falloff: handle.get_brush_falloff(),
+                seed: strokes.row_count() as i32 + 1,
+                points: ModelRc::new(VecModel::from(point.to_vec())),
+            });
+            stroke_active.set(true);
+            handle.invoke_ui_changed();
+        }
+    });
+
+    app.on_sculpt_moved({
+        let stroke_active = Rc::clone(&stroke_active);
+        move |x, y, area_width, area_height| {
+            if !stroke_active.get() {
+                return;
+            }
+            let handle = app_sculpt_moved_weak.upgrade().unwrap();
+            let (point, stroke) = match (preview_point(&handle, x, y, area_width, area_height), handle.get_strokes().iter().last()) {
+                (Some(point), Some(stroke)) => (point, stroke),
+                _ => return,
+            };
+            // The points model is shared with the stroke in the list, so pushing extends it in place
+            if let Some(points) = stroke.points.as_any().downcast_ref::<VecModel<f32>>() {
+                points.push(point[0]);
+                points.push(point[1]);
+            }
+            handle.invoke_ui_changed();
+        }
+    });
+
+    app.on_sculpt_released({
+        let stroke_active = Rc::clone(&stroke_active);
+        move || stroke_active.set(false)
+    });
+
+    app.on_clear_strokes_btn_clicked(move || {
+        let handle = app_clear_strokes_weak.upgrade().unwrap();
+        handle.set_strokes(ModelRc::new(VecModel::<BrushStroke>::default()));
+        handle.invoke_ui_changed();
+    });
+
+    app.on_road_clicked(move |x, y, area_width, area_height| {
+        let handle = app_road_clicked_weak.upgrade().unwrap();
+        let point = match preview_point(&handle, x, y, area_width, area_height) {
+            Some(point) if (0.0..=1.0).contains(&point[0]) && (0.0..=1.0).contains(&point[1]) => point,
+            _ => return,
+        };
+        let model_rc = handle.get_roads();
+        let roads = model_rc.as_any().downcast_ref::<VecModel<RoadWaypoints>>().unwrap();
+        if roads.row_count() == 0 {
+            roads.push(RoadWaypoints { points: ModelRc::new(VecModel::<f32>::default()) });
+        }
+        // Like stroke points, the waypoints model is shared with the road in the list
+        if let Some(road) = roads.iter().last() {
+            if let Some(points) = road.points.as_any().downcast_ref::<VecModel<f32>>() {
+                points.push(point[0]);
+                points.push(point[1]);
+            }
+        }
+        handle.invoke_ui_changed();
+    });
+
+    app.on_new_road_btn_clicked(move || {
+        let handle = app_new_road_weak.upgrade().unwrap();
+        let model_rc = handle.get_roads();
+        let roads = model_rc.as_any().downcast_ref::<VecModel<RoadWaypoints>>().unwrap();
+        roads.push(RoadWaypoints { points: ModelRc::new(VecModel::<f32>::default()) });
+        handle.set_road_placing(true);
+    });
+
+    app.on_clear_roads_btn_clicked(move || {
+        let handle = app_clear_roads_weak.upgrade().unwrap();
+        handle.set_roads(ModelRc::new(VecModel::<RoadWaypoints>::default()));
+        handle.invoke_ui_changed();
+    });
+
+    app.on_batch_btn_clicked(move || {
+        let handle = app_batch_weak.upgrade().unwrap();
+        let settings = collect_settings(&handle);
+        let options = BatchOptions {
+            count: handle.get_batch_count().round() as u32,
+            seed_start: settings.seed,
+            jitter: JitterBounds {
+                scale: handle.get_batch_jitter_scale() as f64,
+                offset: handle.get_batch_jitter_offset() as f64,
+                opacity: handle.get_batch_jitter_opacity() as f64,
+            },
+        };
+        let threads = handle.get_threads().max(0) as usize;
+        let dir = output_dir(&handle);
+        let weak = handle.as_weak();
+        set_status(&handle, &format!("Generating {} variations...", options.count), false);
+
+        thread::spawn(move || {
+            // The weak handle isn't Sync, but the progress callback runs inside the pool
+            let progress_weak = Mutex::new(weak.clone());
+            let result = build_thread_pool(threads).and_then(|pool| pool.install(|| {
+                run_batch(&settings, &options, &dir, &CancelToken::new(), &|done, total| {
+                    if let Ok(weak) = progress_weak.lock() {
+                        report_status(&weak, format!("Generated variation {} of {}", done, total), false);
+                    }
+                })
+            }));
+            match result {
+                Ok(sheet) => report_status(&weak, format!("Contact sheet written to {}", sheet.display()), false),
+                Err(e) => report_status(&weak, e.to_string(), true),
+            }
+        });
+    });
+
+    app.on_save_graph_btn_clicked({
+        let custom_graph = Arc::clone(&custom_graph);
+        move || {
+            let handle = app_save_graph_weak.upgrade().unwrap();
+            let graph = current_graph(&custom_graph, &collect_settings(&handle));
+            match save_graph(&handle, &graph) {
+                Ok(path) => set_status(&handle, &format!("Graph saved to {}", path.display()), false),
+                Err(e) => set_status(&handle, &e.to_string(), true),
             }
-            for layer in layers {
-                layer_parms.push(LayerParams {
-                    scale: layer.scale as f32,
-                    offset_x: layer.offset_x as f32,
-                    offset_y: layer.offset_y as f32,
-                    seed: layer.seed as f32,
-                    opacity: layer.opacity as f32,
-                    blend_mode: layer.blend_mode,
-                });
+        }
+    });
+
+    app.on_load_graph_btn_clicked({
+        let custom_graph = Arc::clone(&custom_graph);
+        move || {
+            let handle = app_load_graph_weak.upgrade().unwrap();
+            match load_graph(&handle) {
+                Ok(graph) => {
+                    *custom_graph.lock().unwrap() = Some(graph);
+                    handle.set_custom_graph(true);
+                    set_status(&handle, "Custom graph loaded", false);
+                    handle.invoke_ui_changed();
+                }
+                Err(e) => set_status(&handle, &e.to_string(), true),
             }
-            handle.set_erosion_mode(serialized_tool.erosion_mode as i32);
-            handle.set_erosion_iterations(serialized_tool.erosion_iterations as f32);
-            handle.set_talus_angle(serialized_tool.talus_angle as f32);
-            handle.set_flatten_enabled(serialized_tool.flatten_enabled as bool);
-            handle.set_ground_level(serialized_tool.ground_level as f32);
-            handle.set_calculate_rivers(serialized_tool.calculate_rivers as bool);
-            handle.set_river_iterations(serialized_tool.river_iterations as f32);
-            handle.set_erosion_factor(serialized_tool.erosion_factor as f32);
-            handle.set_river_amount(serialized_tool.river_amount as f32);
-            handle.set_river_seed(serialized_tool.river_seed as f32);
-            handle.set_filename(slint::SharedString::from(serialized_tool.filename));
-            handle.set_export_scale(serialized_tool.export_scale as i32);
-            handle.set_scale_type(serialized_tool.export_filter as i32);
-            handle.set_as_water(serialized_tool.as_water as bool);
         }
-        None => {
-            println!("Couldn't find desktop path");
+    });
+
+    app.on_reset_graph_btn_clicked({
+        let custom_graph = Arc::clone(&custom_graph);
+        move || {
+            let handle = app_reset_graph_weak.upgrade().unwrap();
+            *custom_graph.lock().unwrap() = None;
+            handle.set_custom_graph(false);
+            handle.invoke_ui_changed();
+        }
+    });
+    app.set_output_dir(slint::SharedString::from(default_output_dir(&args).display().to_string()));
+    app.set_threads(default_thread_count(&args) as i32);
+    let size_presets: Vec<slint::SharedString> = SIZE_PRESETS.iter().map(|&(width, height)| slint::SharedString::from(preset_label(width, height))).collect();
+    app.set_size_presets(ModelRc::new(VecModel::from(size_presets)));
+    app.set_strokes(ModelRc::new(VecModel::<BrushStroke>::default()));
+    app.set_scatter_rules(ModelRc::new(VecModel::<ScatterRuleParams>::default()));
+    app.set_roads(ModelRc::new(VecModel::<RoadWaypoints>::default()));
+
+    history.borrow_mut().record(collect_settings(&app));
+    app.run().unwrap();
+}
+
+fn run_headless_batch(args: &[String], base: &SerializedTool, options: &BatchOptions) -> Result<()> {
+    let dir = default_output_dir(args);
+    let pool = build_thread_pool(default_thread_count(args))?;
+    let sheet = pool.install(|| run_batch(base, options, &dir, &CancelToken::new(), &|done, total| {
+        println!("Generated variation {} of {}", done, total);
+    }))?;
+    println!("Contact sheet written to {}", sheet.display());
+    Ok(())
+}
+
+// Everything the background worker needs to regenerate the previews
+struct PreviewJob {
+    graph: Graph,
+    width: u32,
+    height: u32,
+    plane_res: usize,
+    camera_vertical: f32,
+    camera_horizontal: f32,
+    height_3d: f32,
+    threads: usize,
+    show_topo: bool,
+    analysis_view: AnalysisMap,
+    sea_level: u8,
+    // None when the basin map isn't shown
+    basins: Option<WatershedParams>,
+    // Empty when the point overlay is off
+    scatter_rules: Vec<ScatterRule>,
+    terrain: Terrain,
+}
+
+// displace_plane lifts height 255 by the strength and the plane is plane_res units wide, so an
+// exaggeration of 1 shows the terrain at its true proportions
+fn displacement_3d(terrain: &Terrain, plane_res: usize, exaggeration: f32) -> f32 {
+    (terrain.relief_m() / terrain.size_m().max(f64::EPSILON) * plane_res as f64) as f32 * exaggeration
+}
+
+// Progress updates of cancelled jobs are dropped so the bar only follows the latest job
+fn report_progress(weak: &slint::Weak<App>, cancel: &CancelToken, fraction: f32) {
+    let cancel = cancel.clone();
+    match weak.upgrade_in_event_loop(move |handle| {
+        if !cancel.is_cancelled() {
+            handle.set_progress(fraction);
+        }
+    }) {
+        Ok(_) => {}
+        Err(e) => {
+            eprintln!("Error: {}", e);
+        }
+    }
+}
+
+fn finish_progress(weak: &slint::Weak<App>, cancel: &CancelToken, error: Option<String>) {
+    let cancel = cancel.clone();
+    match weak.upgrade_in_event_loop(move |handle| {
+        if cancel.is_cancelled() {
+            return;
+        }
+        handle.set_busy(false);
+        if let Some(error) = error {
+            set_status(&handle, &error, true);
+        }
+    }) {
+        Ok(_) => {}
+        Err(e) => {
+            eprintln!("Error: {}", e);
         }
     }
 }
 
-fn serialize_tool(weak: &slint::Weak<App>) {
-    let handle = weak.upgrade().unwrap();
+fn output_dir(handle: &App) -> PathBuf {
+    PathBuf::from(handle.get_output_dir().as_str())
+}
+
+// Maps a position in the height preview area to 0-1 image coordinates. The image is drawn with
+// `image-fit: contain`, so it may not fill the whole area.
+fn preview_point(handle: &App, x: f32, y: f32, area_width: f32, area_height: f32) -> Option<[f32; 2]> {
+    let size = handle.get_image().size();
+    if size.width == 0 || size.height == 0 || area_width <= 0.0 || area_height <= 0.0 {
+        return None;
+    }
+    let fit = (area_width / size.width as f32).min(area_height / size.height as f32);
+    let (shown_width, shown_height) = (size.width as f32 * fit, size.height as f32 * fit);
+    Some([(x - (area_width - shown_width) / 2.0) / shown_width, (y - (area_height - shown_height) / 2.0) / shown_height])
+}
+
+fn set_export_size(handle: &App, width: u32, height: u32) {
+    handle.set_export_width(slint::SharedString::from(width.to_string()));
+    handle.set_export_height(slint::SharedString::from(height.to_string()));
+    if let Some(index) = SIZE_PRESETS.iter().position(|&size| size == (width, height)) {
+        handle.set_size_preset(index as i32);
+    }
+}
+
+fn set_status(handle: &App, message: &str, is_error: bool) {
+    handle.set_status(slint::SharedString::from(message));
+    handle.set_status_error(is_error);
+}
+
+// Status updates from worker threads have to go through the event loop
+fn report_status(weak: &slint::Weak<App>, message: String, is_error: bool) {
+    match weak.upgrade_in_event_loop(move |handle| set_status(&handle, &message, is_error)) {
+        Ok(_) => {}
+        Err(e) => {
+            eprintln!("Error: {}", e);
+        }
+    }
+}
+
+fn deserialize_tool(handle: &App) -> Result<PathBuf> {
+    let file_path = config_path(&output_dir(handle), handle.get_filename().as_str());
+    let contents = read_text(&file_path)?;
+    let serialized_tool = parse_config(&contents)?;
+    apply_settings(handle, &serialized_tool);
+    handle.invoke_ui_changed();
+    Ok(file_path)
+}
+
+fn apply_settings(handle: &App, serialized_tool: &SerializedTool) {
+    handle.set_scale(serialized_tool.scale as f32);
+    handle.set_offset_x(serialized_tool.offset_x as f32);
+    handle.set_offset_y(serialized_tool.offset_y as f32);
+    handle.set_seed(serialized_tool.seed as f32);
+    handle.set_base_import(slint::SharedString::from(serialized_tool.base_import.as_str()));
+    // Replacing the model recreates the layer panels, so their sliders pick up the restored values
+    let layer_parms: VecModel<LayerParams> = VecModel::default();
+    for layer in serialized_tool.layers.iter() {
+        layer_parms.push(LayerParams {
+            scale: layer.scale as f32,
+            offset_x: layer.offset_x as f32,
+            offset_y: layer.offset_y as f32,
+            seed: layer.seed as f32,
+            opacity: layer.opacity as f32,
+            blend_mode: layer.blend_mode,
+            import_path: slint::SharedString::from(layer.import_path.as_str()),
+        });
+    }
+    handle.set_layers(ModelRc::new(layer_parms));
+    let strokes: VecModel<BrushStroke> = VecModel::default();
+    for stroke in serialized_tool.strokes.iter() {
+        strokes.push(BrushStroke {
+            mode: stroke.mode.index(),
+            radius: stroke.radius,
+            strength: stroke.strength,
+            falloff: stroke.falloff,
+            seed: stroke.seed as i32,
+            points: ModelRc::new(VecModel::from(stroke.points.iter().flatten().copied().collect::<Vec<f32>>())),
+        });
+    }
+    handle.set_strokes(ModelRc::new(strokes));
+    let scatter_rules: VecModel<ScatterRuleParams> = VecModel::default();
+    for rule in serialized_tool.scatter_rules.iter() {
+        scatter_rules.push(scatter_rule_params(rule));
+    }
+    handle.set_scatter_rules(ModelRc::new(scatter_rules));
+    let roads: VecModel<RoadWaypoints> = VecModel::default();
+    for road in serialized_tool.roads.iter() {
+        roads.push(RoadWaypoints {
+            points: ModelRc::new(VecModel::from(road.waypoints.iter().flatten().copied().collect::<Vec<f32>>())),
+        });
+    }
+    handle.set_roads(ModelRc::new(roads));
+    handle.set_road_width(serialized_tool.road_params.width_m as f32);
+    handle.set_road_max_grade(serialized_tool.road_params.max_grade as f32);
+    handle.set_road_banking(serialized_tool.road_params.banking as f32);
+    handle.set_road_slope_cost(serialized_tool.road_params.slope_cost as f32);
+    handle.set_road_water_cost(serialized_tool.road_params.water_cost as f32);
+    handle.set_road_curve_cost(serialized_tool.road_params.curve_cost as f32);
+    handle.set_road_export(serialized_tool.road_export);
+    handle.set_contour_enabled(serialized_tool.contour_enabled);
+    handle.set_contour_interval(serialized_tool.contour_params.interval_m as f32);
+    handle.set_contour_index_every(serialized_tool.contour_params.index_every as f32);
+    handle.set_contour_base(serialized_tool.contour_params.base.index());
+    handle.set_contour_spot_heights(serialized_tool.contour_params.spot_heights);
+    handle.set_contour_preview(serialized_tool.contour_preview);
+    handle.set_contour_export_vector(serialized_tool.contour_export_vector);
+    handle.set_analysis_view(serialized_tool.analysis_view.index());
+    handle.set_analysis_export(serialized_tool.analysis_export);
+    handle.set_watershed_enabled(serialized_tool.watershed_enabled);
+    handle.set_watershed_min_area(serialized_tool.watershed_params.min_area_percent as f32);
+    handle.set_watershed_preview(serialized_tool.watershed_preview);
+    handle.set_landmass_min_island(serialized_tool.landmass_rules.min_island_px as f32);
+    handle.set_landmass_min_lake(serialized_tool.landmass_rules.min_lake_px as f32);
+    handle.set_landmass_single(serialized_tool.landmass_rules.single_landmass);
+    handle.set_landmass_export(serialized_tool.landmass_export);
+    handle.set_scatter_overlay(serialized_tool.scatter_overlay);
+    handle.set_scatter_export(serialized_tool.scatter_export);
+    // The sea level slider is bounded by the elevations, so they go first
+    handle.set_world_size_km(slint::SharedString::from(serialized_tool.terrain.world_size_km.to_string()));
+    handle.set_min_elevation(slint::SharedString::from(serialized_tool.terrain.min_elevation_m.to_string()));
+    handle.set_max_elevation(slint::SharedString::from(serialized_tool.terrain.max_elevation_m.to_string()));
+    handle.set_sea_level(serialized_tool.terrain.sea_level_m as f32);
+    handle.set_erosion_mode(serialized_tool.erosion_mode);
+    handle.set_erosion_iterations(serialized_tool.erosion_iterations as f32);
+    handle.set_talus_angle(serialized_tool.talus_angle);
+    handle.set_flatten_enabled(serialized_tool.flatten_enabled);
+    handle.set_calculate_rivers(serialized_tool.calculate_rivers);
+    handle.set_river_iterations(serialized_tool.river_iterations as f32);
+    handle.set_river_depth(serialized_tool.river_depth_m as f32);
+    handle.set_river_amount(serialized_tool.river_amount as f32);
+    handle.set_river_seed(serialized_tool.river_seed as f32);
+    handle.set_filename(slint::SharedString::from(serialized_tool.filename.as_str()));
+    set_export_size(handle, serialized_tool.export_width, serialized_tool.export_height);
+    handle.set_scale_type(serialized_tool.export_filter as i32);
+    handle.set_export_resample(serialized_tool.export_resample);
+    handle.set_dem_asc(serialized_tool.dem_export_asc);
+    handle.set_dem_xyz(serialized_tool.dem_export_xyz);
+    handle.set_dem_water_nodata(serialized_tool.dem_water_nodata);
+    handle.set_engine_preset(serialized_tool.engine_preset.index());
+    handle.set_tile_export(serialized_tool.tile_export);
+    if let Some(index) = TILE_SIZES.iter().position(|&size| size == serialized_tool.tile_size) {
+        handle.set_tile_size(index as i32);
+    }
+    handle.set_as_water(serialized_tool.as_water);
+    handle.set_normalize_mode(serialized_tool.normalize_mode.index());
+    handle.set_normalize_clip(serialized_tool.normalize_clip_percent);
+    handle.set_curve_enabled(serialized_tool.curve_enabled);
+    if let [c0, c1, c2, c3, c4] = serialized_tool.curve_points[..] {
+        handle.set_curve_0(c0);
+        handle.set_curve_1(c1);
+        handle.set_curve_2(c2);
+        handle.set_curve_3(c3);
+        handle.set_curve_4(c4);
+    }
+    handle.set_levels_enabled(serialized_tool.levels_enabled);
+    handle.set_levels_in_black(serialized_tool.levels_in_black as f32);
+    handle.set_levels_in_white(serialized_tool.levels_in_white as f32);
+    handle.set_levels_gamma(serialized_tool.levels_gamma);
+    handle.set_levels_out_black(serialized_tool.levels_out_black as f32);
+    handle.set_levels_out_white(serialized_tool.levels_out_white as f32);
+    handle.set_terrace_enabled(serialized_tool.terrace_enabled);
+    handle.set_terrace_steps(serialized_tool.terrace_steps as f32);
+    handle.set_terrace_sharpness(serialized_tool.terrace_sharpness);
+    handle.set_terrace_jitter(serialized_tool.terrace_jitter);
+    handle.set_terrace_noise_scale(serialized_tool.terrace_noise_scale as f32);
+    handle.set_terrace_seed(serialized_tool.terrace_seed as f32);
+}
+
+fn collect_settings(handle: &App) -> SerializedTool {
     let scale = handle.get_scale() as f64;
     let offset_x = handle.get_offset_x() as f64;
     let offset_y = handle.get_offset_y() as f64;
     let seed = handle.get_seed() as u32;
+    let base_import = handle.get_base_import().trim().to_string();
     let model_rc = handle.get_layers();
     let layer_parms = model_rc.as_any().downcast_ref::<VecModel<LayerParams>>().unwrap();
     let erosion_mode = handle.get_erosion_mode();
     let erosion_iterations = handle.get_erosion_iterations() as usize;
     let talus_angle = handle.get_talus_angle();
     let flatten_enabled = handle.get_flatten_enabled();
-    let ground_level = handle.get_ground_level() as u8;
     let calculate_rivers = handle.get_calculate_rivers();
     let river_iterations = handle.get_river_iterations() as usize;
-    let erosion_factor = handle.get_erosion_factor() as i16;
+    let river_depth_m = handle.get_river_depth() as f64;
     let river_amount = handle.get_river_amount() as usize;
     let river_seed = handle.get_river_seed() as u64;
     let file_name = handle.get_filename();
-    let export_scale = handle.get_export_scale() as u32;
+    // Invalid sizes become 0 and are reported when generating
+    let export_width = handle.get_export_width().trim().parse().unwrap_or(0);
+    let export_height = handle.get_export_height().trim().parse().unwrap_or(0);
     let export_filter = handle.get_scale_type() as u32;
+    let export_resample = handle.get_export_resample();
+    let dem_export_asc = handle.get_dem_asc();
+    let dem_export_xyz = handle.get_dem_xyz();
+    let dem_water_nodata = handle.get_dem_water_nodata();
+    let engine_preset = EnginePreset::from_index(handle.get_engine_preset());
+    let defaults = Terrain::default();
+    let terrain = Terrain {
+        world_size_km: handle.get_world_size_km().trim().parse().unwrap_or(defaults.world_size_km),
+        min_elevation_m: handle.get_min_elevation().trim().parse().unwrap_or(defaults.min_elevation_m),
+        max_elevation_m: handle.get_max_elevation().trim().parse().unwrap_or(defaults.max_elevation_m),
+        sea_level_m: handle.get_sea_level() as f64,
+    };
+    let tile_export = handle.get_tile_export();
+    let tile_size = TILE_SIZES.get(handle.get_tile_size().max(0) as usize).copied().unwrap_or(505);
     let as_water = handle.get_as_water();
+    let normalize_mode = NormalizeMode::from_index(handle.get_normalize_mode());
+    let normalize_clip_percent = handle.get_normalize_clip();
+    let curve_enabled = handle.get_curve_enabled();
+    let curve_points = vec![handle.get_curve_0(), handle.get_curve_1(), handle.get_curve_2(), handle.get_curve_3(), handle.get_curve_4()];
+    let levels_enabled = handle.get_levels_enabled();
+    let levels_in_black = handle.get_levels_in_black() as u8;
+    let levels_in_white = handle.get_levels_in_white() as u8;
+    let levels_gamma = handle.get_levels_gamma();
+    let levels_out_black = handle.get_levels_out_black() as u8;
+    let levels_out_white = handle.get_levels_out_white() as u8;
+    let terrace_enabled = handle.get_terrace_enabled();
+    let terrace_steps = handle.get_terrace_steps() as u32;
+    let terrace_sharpness = handle.get_terrace_sharpness();
+    let terrace_jitter = handle.get_terrace_jitter();
+    let terrace_noise_scale = handle.get_terrace_noise_scale() as f64;
+    let terrace_seed = handle.get_terrace_seed() as u32;
+    let strokes: Vec<Stroke> = handle.get_strokes().iter().map(|stroke| {
+        let points: Vec<f32> = stroke.points.iter().collect();
+        Stroke {
+            mode: BrushMode::from_index(stroke.mode),
+            radius: stroke.radius,
+            strength: stroke.strength,
+            falloff: stroke.falloff,
+            seed: stroke.seed as u32,
+            points: points.chunks_exact(2).map(|point| [point[0], point[1]]).collect(),
+        }
+    }).collect();
+    let scatter_rules: Vec<ScatterRule> = handle.get_scatter_rules().iter().map(|rule| ScatterRule {
+        name: rule.name.to_string(),
+        min_distance_m: rule.min_distance as f64,
+        density: rule.density as f64,
+        elevation_min_m: rule.elevation_min as f64,
+        elevation_max_m: rule.elevation_max as f64,
+        slope_max_deg: rule.slope_max as f64,
+        water_distance_min_m: rule.water_min as f64,
+        water_distance_max_m: rule.water_max as f64,
+        river_distance_min_m: rule.river_min as f64,
+        scale_min: rule.scale_min as f64,
+        scale_max: rule.scale_max as f64,
+        seed: rule.seed as u32,
+        color: [rule.color.red(), rule.color.green(), rule.color.blue()],
+    }).collect();
+    let roads: Vec<Road> = handle.get_roads().iter().map(|road| {
+        let points: Vec<f32> = road.points.iter().collect();
+        Road { waypoints: points.chunks_exact(2).map(|point| [point[0], point[1]]).collect() }
+    }).collect();
+    let road_params = RoadParams {
+        width_m: handle.get_road_width() as f64,
+        max_grade: handle.get_road_max_grade() as f64,
+        banking: handle.get_road_banking() as f64,
+        slope_cost: handle.get_road_slope_cost() as f64,
+        water_cost: handle.get_road_water_cost() as f64,
+        curve_cost: handle.get_road_curve_cost() as f64,
+    };
+    let road_export = handle.get_road_export();
+    let contour_enabled = handle.get_contour_enabled();
+    let contour_params = ContourParams {
+        interval_m: handle.get_contour_interval().round() as f64,
+        index_every: handle.get_contour_index_every().round() as u32,
+        base: ContourBase::from_index(handle.get_contour_base()),
+        spot_heights: handle.get_contour_spot_heights(),
+    };
+    let contour_preview = handle.get_contour_preview();
+    let contour_export_vector = handle.get_contour_export_vector();
+    let analysis_view = AnalysisMap::from_index(handle.get_analysis_view());
+    let analysis_export = handle.get_analysis_export();
+    let watershed_enabled = handle.get_watershed_enabled();
+    let watershed_params = WatershedParams {
+        min_area_percent: handle.get_watershed_min_area() as f64,
+    };
+    let watershed_preview = handle.get_watershed_preview();
+    let landmass_rules = LandmassRules {
+        min_island_px: handle.get_landmass_min_island().round() as u32,
+        min_lake_px: handle.get_landmass_min_lake().round() as u32,
+        single_landmass: handle.get_landmass_single(),
+    };
+    let landmass_export = handle.get_landmass_export();
+    let scatter_overlay = handle.get_scatter_overlay();
+    let scatter_export = handle.get_scatter_export();
     let mut layers: Vec<Layers> = Vec::new();
     for layer in layer_parms.iter() {
         layers.push(Layers {
@@ -771,47 +2588,128 @@ fn serialize_tool(weak: &slint::Weak<App>) {
             seed: layer.seed as u32,
             opacity: layer.opacity as f64,
             blend_mode: layer.blend_mode,
+            import_path: layer.import_path.to_string(),
         });
     }
-    let serialized_tool = SerializedTool {
+    SerializedTool {
+        version: CONFIG_VERSION,
         scale,
         offset_x,
         offset_y,
         seed,
-        layers: serde_json::to_string(&layers).unwrap(),
+        base_import,
+        layers,
+        terrain,
         erosion_mode,
         erosion_iterations,
         talus_angle,
         flatten_enabled,
-        ground_level,
         calculate_rivers,
         river_iterations,
-        erosion_factor,
+        river_depth_m,
         river_amount,
         river_seed,
         filename: file_name.to_string(),
-        export_scale,
+        export_width,
+        export_height,
         export_filter,
+        export_resample,
+        dem_export_asc,
+        dem_export_xyz,
+        dem_water_nodata,
+        engine_preset,
+        tile_export,
+        tile_size,
         as_water,
-    };
+        normalize_mode,
+        normalize_clip_percent,
+        curve_enabled,
+        curve_points,
+        levels_enabled,
+        levels_in_black,
+        levels_in_white,
+        levels_gamma,
+        levels_out_black,
+        levels_out_white,
+        terrace_enabled,
+        terrace_steps,
+        terrace_sharpness,
+        terrace_jitter,
+        terrace_noise_scale,
+        terrace_seed,
+        strokes,
+        scatter_rules,
+        scatter_overlay,
+        scatter_export,
+        roads,
+        road_params,
+        road_export,
+        contour_enabled,
+        contour_params,
+        contour_preview,
+        contour_export_vector,
+        analysis_view,
+        analysis_export,
+        watershed_enabled,
+        watershed_params,
+        watershed_preview,
+        landmass_rules,
+        landmass_export,
+    }
+}
 
-    let serialized_tool_json = serde_json::to_string_pretty(&serialized_tool).unwrap();
-    let desktop_path = dirs::desktop_dir();
-    match desktop_path {
-        Some(path) => {
-            let full_path = path.join(format!("{}_config.json", file_name));
-            println!("Desktop path: {}", full_path.display());
-            match fs::write(full_path, serialized_tool_json) {
-                Ok(_) => {
-                    println!("Tool saved");
-                }
-                Err(e) => {
-                    println!("Couldn't save tool: {}", e);
-                }
-            }
-        }
-        None => {
-            println!("Couldn't find desktop path");
-        }
+fn scatter_rule_params(rule: &ScatterRule) -> ScatterRuleParams {
+    ScatterRuleParams {
+        name: slint::SharedString::from(rule.name.as_str()),
+        min_distance: rule.min_distance_m as f32,
+        density: rule.density as f32,
+        elevation_min: rule.elevation_min_m as f32,
+        elevation_max: rule.elevation_max_m as f32,
+        slope_max: rule.slope_max_deg as f32,
+        water_min: rule.water_distance_min_m as f32,
+        water_max: rule.water_distance_max_m as f32,
+        river_min: rule.river_distance_min_m as f32,
+        scale_min: rule.scale_min as f32,
+        scale_max: rule.scale_max as f32,
+        seed: rule.seed as f32,
+        color: slint::Color::from_rgb_u8(rule.color[0], rule.color[1], rule.color[2]),
+    }
+}
+
+fn serialize_tool(handle: &App) -> Result<PathBuf> {
+    let serialized_tool = collect_settings(handle);
+    let serialized_tool_json = config_to_json(&serialized_tool)?;
+    let full_path = config_path(&output_dir(handle), &serialized_tool.filename);
+    write_text(&full_path, &serialized_tool_json)?;
+    Ok(full_path)
+}
+
+// The loaded custom graph if there is one, otherwise the graph built from the settings
+fn current_graph(custom_graph: &Mutex<Option<Graph>>, settings: &SerializedTool) -> Graph {
+    match custom_graph.lock() {
+        Ok(custom) => custom.clone().unwrap_or_else(|| build_pipeline_graph(settings)),
+        Err(_) => build_pipeline_graph(settings),
+    }
+}
+
+// File suffix for a graph output, keeping the names the exported textures always had
+fn export_suffix(output: &str) -> &str {
+    match output {
+        "heightmap" => "height",
+        "colormap" => "color",
+        name => name,
     }
-}
\ No newline at end of file
+}
+
+fn save_graph(handle: &App, graph: &Graph) -> Result<PathBuf> {
+    let graph_json = graph.to_json()?;
+    let full_path = output_path(&output_dir(handle), handle.get_filename().as_str(), "graph", "json");
+    write_text(&full_path, &graph_json)?;
+    Ok(full_path)
+}
+
+fn load_graph(handle: &App) -> Result<Graph> {
+    let file_path = output_path(&output_dir(handle), handle.get_filename().as_str(), "graph", "json");
+    let contents = read_text(&file_path)?;
+    Graph::from_json(&contents)
+}
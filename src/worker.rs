@@ -0,0 +1,155 @@
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+use rust_heightmap_generator::heightmap_gen::cancel::CancelToken;
+
+// Single background thread that runs the latest submitted job. Submitting replaces any job
+// that is still waiting and cancels the one that is running, so jobs never finish out of order.
+// A job only starts once no newer job was submitted for the debounce duration. A job that
+// panics is handed to `on_panic` with the panic message and the thread moves on to the next one.
+pub struct Worker<T> {
+    shared: Arc<Shared<T>>,
+}
+
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    wake: Condvar,
+}
+
+struct State<T> {
+    pending: Option<(T, CancelToken)>,
+    latest: Option<CancelToken>,
+    submitted: Instant,
+    closed: bool,
+}
+
+impl<T: Send + 'static> Worker<T> {
+    pub fn new<F, P>(debounce: Duration, mut run: F, mut on_panic: P) -> Worker<T>
+    where
+        F: FnMut(T, CancelToken) + Send + 'static,
+        P: FnMut(String, CancelToken) + Send + 'static,
+    {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State { pending: None, latest: None, submitted: Instant::now(), closed: false }),
+            wake: Condvar::new(),
+        });
+
+        let worker_shared = Arc::clone(&shared);
+        thread::spawn(move || {
+            while let Some((job, cancel)) = worker_shared.next_job(debounce) {
+                if cancel.is_cancelled() {
+                    continue;
+                }
+                // Jobs only keep caches between runs, which are rebuilt if a panic left them half updated
+                if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| run(job, cancel.clone()))) {
+                    on_panic(panic_message(payload.as_ref()), cancel);
+                }
+            }
+        });
+
+        Worker { shared }
+    }
+
+    // Queues a job and returns its token. The token is cancelled as soon as a newer job is submitted.
+    pub fn submit(&self, job: T) -> CancelToken {
+        let cancel = CancelToken::new();
+        let mut state = self.shared.lock();
+        if let Some(previous) = state.latest.replace(cancel.clone()) {
+            previous.cancel();
+        }
+        state.pending = Some((job, cancel.clone()));
+        state.submitted = Instant::now();
+        self.shared.wake.notify_one();
+        cancel
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    match payload.downcast_ref::<&str>() {
+        Some(message) => message.to_string(),
+        None => match payload.downcast_ref::<String>() {
+            Some(message) => message.clone(),
+            None => "unknown panic".to_string(),
+        },
+    }
+}
+
+impl<T> Shared<T> {
+    // The state stays consistent even if a job panicked, so a poisoned lock is still usable
+    fn lock(&self) -> MutexGuard<'_, State<T>> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn next_job(&self, debounce: Duration) -> Option<(T, CancelToken)> {
+        let mut state = self.lock();
+        loop {
+            if state.closed {
+                return None;
+            }
+            if state.pending.is_some() {
+                let waited = state.submitted.elapsed();
+                if waited >= debounce {
+                    return state.pending.take();
+                }
+                state = self.wake.wait_timeout(state, debounce - waited).unwrap_or_else(|e| e.into_inner()).0;
+            } else {
+                state = self.wake.wait(state).unwrap_or_else(|e| e.into_inner());
+            }
+        }
+    }
+}
+
+impl<T> Drop for Worker<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.closed = true;
+        if let Some(latest) = state.latest.take() {
+            latest.cancel();
+        }
+        self.shared.wake.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    const DEBOUNCE: Duration = Duration::from_millis(50);
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    #[test]
+    fn only_the_last_of_quick_submissions_runs() {
+        let (sender, receiver) = mpsc::channel();
+        let worker = Worker::new(DEBOUNCE, move |job: u32, _| sender.send(job).unwrap(), |_, _| {});
+        let tokens: Vec<CancelToken> = (1..=5).map(|job| worker.submit(job)).collect();
+
+        assert_eq!(receiver.recv_timeout(TIMEOUT), Ok(5));
+        assert!(receiver.recv_timeout(DEBOUNCE * 4).is_err());
+        assert!(tokens[..4].iter().all(|token| token.is_cancelled()));
+        assert!(!tokens[4].is_cancelled());
+    }
+
+    #[test]
+    fn a_panicking_job_is_reported_and_the_worker_keeps_running() {
+        let (sender, receiver) = mpsc::channel();
+        let (panic_sender, panic_receiver) = mpsc::channel();
+        let worker = Worker::new(
+            Duration::ZERO,
+            move |job: u32, _| {
+                if job == 1 {
+                    panic!("job {} failed", job);
+                }
+                sender.send(job).unwrap();
+            },
+            move |message, _| panic_sender.send(message).unwrap(),
+        );
+
+        worker.submit(1);
+        assert_eq!(panic_receiver.recv_timeout(TIMEOUT), Ok("job 1 failed".to_string()));
+        worker.submit(2);
+        assert_eq!(receiver.recv_timeout(TIMEOUT), Ok(2));
+    }
+}
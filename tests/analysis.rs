@@ -0,0 +1,37 @@
+// Analysis maps of a tilted plane, whose slope and aspect are known and whose curvature is zero
+
+use image::{ImageBuffer, Rgba};
+use rust_heightmap_generator::heightmap_gen::analysis::{analysis_field, AnalysisMap};
+
+#[test]
+fn plane_rising_east_has_constant_slope_and_faces_west() {
+    let size = 64;
+    // 10 m pixels and 2 m of height per pixel to the east
+    let plane: ImageBuffer<Rgba<u8>, Vec<u8>> = ImageBuffer::from_fn(size, size, |x, _| {
+        let value = (x * 2) as u8;
+        Rgba([value, value, value, 255])
+    });
+    let field = |map| analysis_field(&plane, map, 640.0, 255.0, 1.0);
+    let (slope, aspect, plan, profile, tpi, roughness) = (
+        field(AnalysisMap::Slope),
+        field(AnalysisMap::Aspect),
+        field(AnalysisMap::PlanCurvature),
+        field(AnalysisMap::ProfileCurvature),
+        field(AnalysisMap::Tpi),
+        field(AnalysisMap::Roughness),
+    );
+
+    let expected_slope = 0.2f64.atan().to_degrees();
+    // Away from the borders, where the windows are cut off
+    for y in 8..size - 8 {
+        for x in 8..size - 8 {
+            let index = (y * size + x) as usize;
+            assert!((slope[index] - expected_slope).abs() < 1e-9);
+            assert!((aspect[index] - 270.0).abs() < 1e-9);
+            assert!(plan[index].abs() < 1e-12 && profile[index].abs() < 1e-12);
+            assert!(tpi[index].abs() < 1e-9);
+            assert!((roughness[index] - roughness[(32 * size + 32) as usize]).abs() < 1e-6);
+            assert!(roughness[index] > 0.0);
+        }
+    }
+}
@@ -0,0 +1,83 @@
+// Seed sweep variants: the seeds they get, the jitter bounds and rebuilding a variant
+
+use rust_heightmap_generator::heightmap_gen::batch::{batch_variants, BatchOptions, JitterBounds, MAX_VARIANTS};
+use rust_heightmap_generator::heightmap_gen::config::{Layers, SerializedTool};
+use rust_heightmap_generator::heightmap_gen::error::HeightmapError;
+
+fn base() -> SerializedTool {
+    SerializedTool {
+        seed: 10,
+        scale: 0.05,
+        offset_x: 1.0,
+        offset_y: 2.0,
+        layers: vec![
+            Layers { seed: 20, scale: 0.1, opacity: 0.5, ..Layers::default() },
+            Layers { seed: 30, scale: 0.02, opacity: 0.9, ..Layers::default() },
+        ],
+        terrace_seed: 40,
+        river_seed: 50,
+        ..SerializedTool::default()
+    }
+}
+
+fn options(count: u32) -> BatchOptions {
+    BatchOptions { count, seed_start: 100, jitter: JitterBounds { scale: 0.25, offset: 5.0, opacity: 0.2 } }
+}
+
+#[test]
+fn variants_can_be_rebuilt() {
+    let first = batch_variants(&base(), &options(16)).unwrap();
+    assert_eq!(first, batch_variants(&base(), &options(16)).unwrap());
+    // A longer run starts with the same variants
+    assert_eq!(first[..], batch_variants(&base(), &options(32)).unwrap()[..16]);
+}
+
+#[test]
+fn variant_seeds_count_up_from_the_start() {
+    let variants = batch_variants(&base(), &options(16)).unwrap();
+    for (index, variant) in variants.iter().enumerate() {
+        let shift = 90 + index as u32;
+        assert_eq!(variant.seed, 100 + index as u32);
+        assert_eq!(variant.layers[0].seed, 20 + shift);
+        assert_eq!(variant.layers[1].seed, 30 + shift);
+        assert_eq!(variant.terrace_seed, 40 + shift);
+        assert_eq!(variant.river_seed, 50 + shift as u64);
+        assert_eq!(variant.filename, format!("noise_{:03}", index + 1));
+    }
+}
+
+#[test]
+fn jitter_stays_within_the_bounds() {
+    let base = base();
+    let bounds = options(64).jitter;
+    let variants = batch_variants(&base, &options(64)).unwrap();
+    for variant in &variants {
+        assert!((variant.scale / base.scale - 1.0).abs() <= bounds.scale + 1e-9);
+        assert!((variant.offset_x - base.offset_x).abs() <= bounds.offset);
+        assert!((variant.offset_y - base.offset_y).abs() <= bounds.offset);
+        for (layer, base_layer) in variant.layers.iter().zip(&base.layers) {
+            assert!((layer.scale / base_layer.scale - 1.0).abs() <= bounds.scale + 1e-9);
+            assert!((layer.offset_x - base_layer.offset_x).abs() <= bounds.offset);
+            assert!((layer.offset_y - base_layer.offset_y).abs() <= bounds.offset);
+            assert!((layer.opacity - base_layer.opacity).abs() <= bounds.opacity + 1e-9);
+            assert!((0.0..=1.0).contains(&layer.opacity));
+        }
+    }
+    // The jitter does vary between variants
+    assert!(variants.windows(2).any(|pair| pair[0].scale != pair[1].scale));
+
+    // Without jitter only the seeds change
+    let still = BatchOptions { jitter: JitterBounds::default(), ..options(4) };
+    for variant in batch_variants(&base, &still).unwrap() {
+        assert_eq!((variant.scale, variant.offset_x, variant.offset_y), (base.scale, base.offset_x, base.offset_y));
+        assert_eq!(variant.layers.iter().map(|layer| layer.opacity).collect::<Vec<_>>(), vec![0.5, 0.9]);
+    }
+}
+
+#[test]
+fn batch_sizes_outside_the_limit_are_an_error() {
+    assert_eq!(batch_variants(&base(), &options(MAX_VARIANTS)).unwrap().len(), MAX_VARIANTS as usize);
+    for count in [0, MAX_VARIANTS + 1, 1000] {
+        assert!(matches!(batch_variants(&base(), &options(count)), Err(HeightmapError::Config(_))), "{} variants", count);
+    }
+}
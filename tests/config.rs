@@ -0,0 +1,59 @@
+// Loading configs saved before the version key existed, through every migration
+
+use rust_heightmap_generator::heightmap_gen::config::{parse_config, Layers, CONFIG_VERSION};
+use rust_heightmap_generator::heightmap_gen::terrain::Terrain;
+
+// As written by the first release: no version, layers as a JSON string, the export size as an
+// index and the sea level and river depth in 0-255 steps
+const UNVERSIONED_CONFIG: &str = r#"{
+    "scale": 0.03,
+    "offset_x": 1.5,
+    "offset_y": -2.0,
+    "seed": 7,
+    "layers": "[{\"scale\":0.1,\"offset_x\":0.0,\"offset_y\":0.0,\"seed\":2,\"opacity\":0.5,\"blend_mode\":1},{\"scale\":0.2,\"offset_x\":3.0,\"offset_y\":4.0,\"seed\":3,\"opacity\":1.0,\"blend_mode\":0}]",
+    "erosion_mode": 1,
+    "erosion_iterations": 5,
+    "talus_angle": 0.01,
+    "flatten_enabled": true,
+    "ground_level": 51,
+    "calculate_rivers": true,
+    "river_iterations": 10,
+    "erosion_factor": 3,
+    "river_amount": 2,
+    "river_seed": 4,
+    "filename": "island",
+    "export_scale": 2,
+    "export_filter": 3,
+    "as_water": true
+}"#;
+
+#[test]
+fn unversioned_configs_load_through_every_migration() {
+    let config = parse_config(UNVERSIONED_CONFIG).unwrap();
+    assert_eq!(config.version, CONFIG_VERSION);
+
+    assert_eq!(config.layers, vec![
+        Layers { scale: 0.1, offset_x: 0.0, offset_y: 0.0, seed: 2, opacity: 0.5, blend_mode: 1, import_path: String::new() },
+        Layers { scale: 0.2, offset_x: 3.0, offset_y: 4.0, seed: 3, opacity: 1.0, blend_mode: 0, import_path: String::new() },
+    ]);
+    // Index 2 of 256, 512, 1024, 2048 and 4096
+    assert_eq!((config.export_width, config.export_height), (1024, 1024));
+
+    // Without a terrain size or height the defaults apply: 1 km wide and 500 m high
+    assert_eq!(config.terrain, Terrain { world_size_km: 1.0, min_elevation_m: 0.0, max_elevation_m: 500.0, sea_level_m: 100.0 });
+    assert_eq!(config.terrain.sea_level(), 51);
+    assert!((config.river_depth_m - 3.0 / 255.0 * 500.0).abs() < 1e-9);
+
+    // The fields that were there all along come through untouched
+    assert_eq!((config.scale, config.offset_x, config.offset_y, config.seed), (0.03, 1.5, -2.0, 7));
+    assert!(config.flatten_enabled && config.calculate_rivers && config.as_water);
+    assert_eq!((config.river_iterations, config.river_amount, config.river_seed), (10, 2, 4));
+    assert_eq!(config.filename, "island");
+}
+
+#[test]
+fn malformed_layer_strings_are_an_error() {
+    let json = UNVERSIONED_CONFIG.replace(r#""layers": "[{"#, r#""layers": "[{oops"#);
+    assert!(parse_config(&json).is_err());
+    assert!(parse_config(r#"{ "layers": "" }"#).is_err());
+}
@@ -0,0 +1,57 @@
+// Contours of a cone, whose lines are known: one closed circle per level
+
+use image::{ImageBuffer, Rgba};
+use rust_heightmap_generator::heightmap_gen::contours::{contour_lines, spot_heights, ContourParams};
+use rust_heightmap_generator::heightmap_gen::terrain::Terrain;
+
+const CENTRE: f64 = 32.0;
+
+fn cone() -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    ImageBuffer::from_fn(65, 65, |x, y| {
+        let distance = ((x as f64 - CENTRE).powi(2) + (y as f64 - CENTRE).powi(2)).sqrt();
+        let value = (255.0 - distance * 8.0).max(0.0).round() as u8;
+        Rgba([value, value, value, 255])
+    })
+}
+
+// One metre per height step
+fn terrain(min_elevation_m: f64) -> Terrain {
+    Terrain { world_size_km: 0.65, min_elevation_m, max_elevation_m: min_elevation_m + 255.0, sea_level_m: min_elevation_m }
+}
+
+#[test]
+fn cone_gives_one_circle_per_level_and_one_peak() {
+    let cone = cone();
+    let centre = CENTRE;
+    let params = ContourParams { interval_m: 50.0, index_every: 2, ..ContourParams::default() };
+    let lines = contour_lines(&cone, &params, &terrain(0.0));
+
+    assert_eq!(lines.len(), 5);
+    for (step, line) in lines.iter().enumerate() {
+        let level = (step + 1) as f64 * 50.0;
+        assert_eq!(line.elevation_m, level);
+        assert_eq!(line.index, (step + 1) % 2 == 0);
+        assert!(line.closed);
+        // Pixel centres sit at .5, the cone's top at the centre of pixel 32
+        let radius = (255.0 - level) / 8.0;
+        for point in line.points.iter() {
+            let distance = ((point[0] - centre - 0.5).powi(2) + (point[1] - centre - 0.5).powi(2)).sqrt();
+            assert!((distance - radius).abs() < 0.6, "level {}: point at {} instead of {}", level, distance, radius);
+        }
+    }
+
+    let peaks = spot_heights(&cone, &terrain(0.0));
+    assert_eq!(peaks.len(), 1);
+    assert_eq!((peaks[0].x, peaks[0].y, peaks[0].elevation_m), (32, 32, 255.0));
+}
+
+#[test]
+fn levels_are_multiples_of_the_interval_in_elevation() {
+    let params = ContourParams { interval_m: 50.0, index_every: 2, ..ContourParams::default() };
+    // The cone now reaches from -80 m to 175 m, and the lines at -50 m and 0 m are below the old base
+    let lines = contour_lines(&cone(), &params, &terrain(-80.0));
+    let levels: Vec<f64> = lines.iter().map(|line| line.elevation_m).collect();
+    assert_eq!(levels, vec![-50.0, 0.0, 50.0, 100.0, 150.0]);
+    assert_eq!(lines.iter().map(|line| line.index).collect::<Vec<_>>(), vec![false, true, false, true, false]);
+    assert_eq!(spot_heights(&cone(), &terrain(-80.0))[0].elevation_m, 175.0);
+}
@@ -0,0 +1,93 @@
+// Reading ESRI ASCII grids and XYZ point files
+
+use rust_heightmap_generator::heightmap_gen::dem::{read_ascii_grid, read_xyz, DemGrid};
+use rust_heightmap_generator::heightmap_gen::error::HeightmapError;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+fn write(name: &str, contents: &str) -> PathBuf {
+    let path = Path::new(env!("CARGO_TARGET_TMPDIR")).join("dem").join(name);
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(&path, contents).unwrap();
+    path
+}
+
+fn import_error(result: Result<DemGrid, HeightmapError>) -> String {
+    match result {
+        Err(HeightmapError::Import { message, .. }) => message,
+        Err(error) => panic!("expected an import error, got {}", error),
+        Ok(_) => panic!("expected an import error"),
+    }
+}
+
+#[test]
+fn ascii_header_in_any_order_and_case() {
+    let path = write("header.asc", "NROWS 2\nncols 3\nCellSize 5\nxllcenter 102.5\nyllcenter 52.5\n1 2 3\n4 5 6\n");
+    let grid = read_ascii_grid(&path).unwrap();
+
+    assert_eq!((grid.width, grid.height), (3, 2));
+    assert_eq!(grid.cell_size, 5.0);
+    // Centre coordinates are moved to the corner of the cell
+    assert_eq!((grid.x_corner, grid.y_corner), (100.0, 50.0));
+    assert_eq!(grid.values, vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+    assert_eq!(grid.get(2, 1), 6.0);
+}
+
+#[test]
+fn ascii_nodata_cells_are_missing() {
+    let path = write("nodata.asc", "ncols 2\nnrows 2\nxllcorner 0\nyllcorner 0\ncellsize 1\nNODATA_value -32768\n-32768 10\n20 -32768\n");
+    let grid = read_ascii_grid(&path).unwrap();
+
+    assert!(grid.get(0, 0).is_nan() && grid.get(1, 1).is_nan());
+    assert_eq!(grid.range(), Some((10.0, 20.0)));
+}
+
+#[test]
+fn ascii_cells_must_be_positive_and_square() {
+    for (name, cell) in [("zero.asc", "cellsize 0"), ("negative.asc", "cellsize -2"), ("nan.asc", "cellsize NaN"), ("infinite.asc", "cellsize inf")] {
+        let path = write(name, &format!("ncols 1\nnrows 1\n{}\n7\n", cell));
+        let message = import_error(read_ascii_grid(&path));
+        assert!(message.contains("cellsize"), "{}: {}", name, message);
+    }
+
+    let path = write("rectangular.asc", "ncols 1\nnrows 1\ndx 2\ndy 3\n7\n");
+    assert!(import_error(read_ascii_grid(&path)).contains("square"));
+
+    let path = write("square.asc", "ncols 1\nnrows 1\ndx 2\ndy 2\n7\n");
+    assert_eq!(read_ascii_grid(&path).unwrap().cell_size, 2.0);
+}
+
+#[test]
+fn xyz_cell_size_comes_from_the_point_spacing() {
+    // A 3x2 grid with 25 m cells and one missing point, listed out of order
+    let path = write("points.xyz", "x y z\n62.5 37.5 6\n12.5 12.5 1\n37.5 12.5 2\n62.5 12.5 3\n12.5 37.5 4\n");
+    let grid = read_xyz(&path).unwrap();
+
+    assert_eq!(grid.cell_size, 25.0);
+    assert_eq!((grid.width, grid.height), (3, 2));
+    assert_eq!((grid.x_corner, grid.y_corner), (0.0, 0.0));
+    // The first row is the north edge
+    assert_eq!(grid.get(0, 0), 4.0);
+    assert!(grid.get(1, 0).is_nan());
+    assert_eq!(grid.get(2, 1), 3.0);
+}
+
+#[test]
+fn xyz_points_must_lie_on_one_square_grid() {
+    // 10 m apart in x but 20 m apart in y
+    let path = write("uneven.xyz", "0 0 1\n10 0 2\n0 20 3\n10 20 4\n");
+    assert!(import_error(read_xyz(&path)).contains("square"));
+
+    // The last point falls halfway between two 10 m cells
+    let path = write("off_grid.xyz", "0 0 1\n10 0 2\n0 10 3\n10 10 4\n25 10 5\n");
+    assert!(import_error(read_xyz(&path)).contains("not on the"));
+
+    let path = write("duplicate.xyz", "0 0 1\n10 0 2\n0 10 3\n0 10 5\n");
+    assert!(import_error(read_xyz(&path)).contains("more than one point"));
+}
+
+#[test]
+fn xyz_grids_too_large_to_hold_are_an_error() {
+    let path = write("huge.xyz", "0 0 1\n1 0 2\n1e30 0 3\n");
+    assert!(matches!(read_xyz(&path), Err(HeightmapError::InvalidSize { .. })));
+}
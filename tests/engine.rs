@@ -0,0 +1,53 @@
+// Engine size snapping and the import settings written next to the heightmap
+
+use rust_heightmap_generator::heightmap_gen::engine::{engine_sidecar, EnginePreset, UNITY_SIZES, UNREAL_SIZES};
+use rust_heightmap_generator::heightmap_gen::terrain::Terrain;
+
+#[test]
+fn sizes_snap_to_the_nearest_engine_size() {
+    for (preset, sizes) in [(EnginePreset::Unity, &UNITY_SIZES[..]), (EnginePreset::Unreal, &UNREAL_SIZES[..])] {
+        for &size in sizes {
+            assert_eq!(preset.nearest_size(size, size), (size, size), "{:?}", preset);
+            assert_eq!(preset.nearest_size(size + 1, size / 2), (size, size), "{:?}", preset);
+            assert_eq!(preset.nearest_size(size / 3, size - 1), (size, size), "{:?}", preset);
+        }
+    }
+    assert_eq!(EnginePreset::Unity.nearest_size(512, 512), (513, 513));
+    assert_eq!(EnginePreset::Unity.nearest_size(8000, 100), (4097, 4097));
+    assert_eq!(EnginePreset::Unreal.nearest_size(512, 512), (505, 505));
+    assert_eq!(EnginePreset::Unreal.nearest_size(16, 16), (127, 127));
+    // Without an engine any size is kept
+    assert_eq!(EnginePreset::None.nearest_size(300, 200), (300, 200));
+}
+
+#[test]
+fn unity_sidecar() {
+    let terrain = Terrain { world_size_km: 2.048, min_elevation_m: -24.0, max_elevation_m: 1000.0, sea_level_m: 0.0 };
+    let sidecar = engine_sidecar(EnginePreset::Unity, "map_unity.raw", 1025, &terrain);
+
+    assert_eq!(sidecar.engine, "Unity");
+    assert_eq!(sidecar.heightmap, "map_unity.raw");
+    assert_eq!(sidecar.terrain_size_m, 2048.0);
+    assert_eq!(sidecar.terrain_height_m, 1024.0);
+    assert_eq!(sidecar.min_elevation_m, -24.0);
+    assert_eq!(sidecar.pixel_spacing_m, 2.0);
+    assert_eq!(sidecar.unity_terrain_size, Some([2048.0, 2048.0, 1024.0]));
+    assert_eq!(sidecar.unity_position_y, Some(-24.0));
+    assert_eq!(sidecar.unreal_scale, None);
+    assert_eq!(sidecar.unreal_location_z_cm, None);
+}
+
+#[test]
+fn unreal_sidecar() {
+    let terrain = Terrain { world_size_km: 2.016, min_elevation_m: -24.0, max_elevation_m: 1000.0, sea_level_m: 0.0 };
+    let sidecar = engine_sidecar(EnginePreset::Unreal, "map_unreal.png", 1009, &terrain);
+
+    assert_eq!(sidecar.engine, "Unreal");
+    assert_eq!(sidecar.pixel_spacing_m, 2.0);
+    // 1024 m is twice the 512 m a Z scale of 100 covers
+    assert_eq!(sidecar.unreal_scale, Some([200.0, 200.0, 200.0]));
+    // The middle of the range, 488 m, in centimetres
+    assert_eq!(sidecar.unreal_location_z_cm, Some(48800.0));
+    assert_eq!(sidecar.unity_terrain_size, None);
+    assert_eq!(sidecar.unity_position_y, None);
+}
@@ -0,0 +1,92 @@
+// Validation and evaluation order of node graphs
+
+use rust_heightmap_generator::heightmap_gen::error::HeightmapError;
+use rust_heightmap_generator::heightmap_gen::graph::{EvalContext, Graph, Link, NodeKind, PortType, GRAPH_VERSION};
+
+fn perlin() -> NodeKind {
+    NodeKind::Perlin { scale: 0.05, offset_x: 0.0, offset_y: 0.0, opacity: 1.0, seed: 7 }
+}
+
+fn graph_error(result: Result<Graph, HeightmapError>) -> String {
+    match result {
+        Err(HeightmapError::Graph(message)) => message,
+        Err(error) => panic!("expected a graph error, got {}", error),
+        Ok(_) => panic!("expected a graph error"),
+    }
+}
+
+#[test]
+fn cycles_are_rejected() {
+    let mut graph = Graph::new();
+    let source = graph.add_node(perlin(), vec![]);
+    let blend = graph.add_node(NodeKind::Blend { blend_mode: 0 }, vec![Link { node: source, output: 0 }, Link { node: 2, output: 0 }]);
+    graph.add_node(NodeKind::Curve { points: vec![0.0, 0.5, 1.0] }, vec![Link { node: blend, output: 0 }]);
+
+    let message = graph_error(Graph::from_json(&graph.to_json().unwrap()));
+    assert!(message.contains("cycle"), "{}", message);
+    assert!(graph.evaluate(&EvalContext::new(8, 8)).is_err());
+}
+
+#[test]
+fn mismatched_ports_are_rejected() {
+    // A normal map is not a colour image, so it can't be drawn on as a topographic base
+    let mut graph = Graph::new();
+    let source = graph.add_node(perlin(), vec![]);
+    let normal = graph.add_node(NodeKind::NormalMap { strength: 1.0 }, vec![Link { node: source, output: 0 }]);
+    graph.add_node(NodeKind::Topographic { params: Default::default(), terrain: Default::default() }, vec![Link { node: source, output: 0 }, Link { node: normal, output: 0 }]);
+
+    let message = graph_error(Graph::from_json(&graph.to_json().unwrap()));
+    assert!(message.contains("expects Color") && message.contains("Normal"), "{}", message);
+
+    // It only goes to an output of its own type
+    let mut graph = Graph::new();
+    let source = graph.add_node(perlin(), vec![]);
+    let normal = graph.add_node(NodeKind::NormalMap { strength: 1.0 }, vec![Link { node: source, output: 0 }]);
+    graph.add_node(NodeKind::Output { name: "normal".to_string(), port: PortType::Color }, vec![Link { node: normal, output: 0 }]);
+    assert!(Graph::from_json(&graph.to_json().unwrap()).is_err());
+    graph.nodes[2].kind = NodeKind::Output { name: "normal".to_string(), port: PortType::Normal };
+    assert!(Graph::from_json(&graph.to_json().unwrap()).is_ok());
+}
+
+#[test]
+fn nodes_are_evaluated_after_their_inputs() {
+    // Declared back to front: the output first, the generator last
+    let json = r#"{ "nodes": [
+        { "id": 0, "kind": { "type": "Output", "name": "height", "port": "Height" }, "inputs": [{ "node": 1, "output": 0 }] },
+        { "id": 1, "kind": { "type": "Curve", "points": [1.0, 0.0] }, "inputs": [{ "node": 2, "output": 0 }] },
+        { "id": 2, "kind": { "type": "Perlin", "scale": 0.05, "offset_x": 0.0, "offset_y": 0.0, "opacity": 1.0, "seed": 7 }, "inputs": [] }
+    ] }"#;
+    let reversed = Graph::from_json(json).unwrap().evaluate(&EvalContext::new(16, 16)).unwrap();
+
+    let mut graph = Graph::new();
+    let source = graph.add_node(perlin(), vec![]);
+    let curve = graph.add_node(NodeKind::Curve { points: vec![1.0, 0.0] }, vec![Link { node: source, output: 0 }]);
+    graph.add_node(NodeKind::Output { name: "height".to_string(), port: PortType::Height }, vec![Link { node: curve, output: 0 }]);
+    let ordered = graph.evaluate(&EvalContext::new(16, 16)).unwrap();
+
+    assert_eq!(reversed["height"], ordered["height"]);
+}
+
+#[test]
+fn version_2_roads_get_a_sea_mask() {
+    let json = r#"{ "version": 2, "nodes": [
+        { "id": 0, "kind": { "type": "Perlin", "scale": 0.05, "offset_x": 0.0, "offset_y": 0.0, "opacity": 1.0, "seed": 7 }, "inputs": [] },
+        { "id": 1, "kind": { "type": "Colorize", "start_index": 2 }, "inputs": [{ "node": 0, "output": 0 }] },
+        { "id": 2, "kind": { "type": "Roads", "roads": [{ "waypoints": [[0.1, 0.1], [0.9, 0.9]] }], "params": {},
+            "terrain": { "world_size_km": 1.0, "min_elevation_m": 0.0, "max_elevation_m": 255.0, "sea_level_m": 60.0 } },
+            "inputs": [{ "node": 0, "output": 0 }, { "node": 1, "output": 0 }] },
+        { "id": 3, "kind": { "type": "Output", "name": "heightmap", "port": "Height" }, "inputs": [{ "node": 2, "output": 0 }] }
+    ] }"#;
+    let graph = Graph::from_json(json).unwrap();
+    assert_eq!(graph.version, GRAPH_VERSION);
+    assert_eq!(graph.nodes[2].inputs[2], Link { node: 4, output: 0 });
+    match &graph.nodes[4].kind {
+        NodeKind::HeightMask { min, max } => assert_eq!((*min, *max), (0, 60)),
+        kind => panic!("expected a height mask, got {:?}", kind),
+    }
+    assert_eq!(graph.nodes[4].inputs, vec![Link { node: 0, output: 0 }]);
+
+    let ctx = EvalContext::new(32, 32);
+    graph.evaluate(&ctx).unwrap();
+    assert_eq!(ctx.road_paths.lock().unwrap().len(), 1);
+}
@@ -0,0 +1,202 @@
+// Golden image tests for the heightmap stages. Each stage runs on fixed inputs and seeds and is
+// compared pixel by pixel with its reference PNG in tests/golden, whose hash is recorded in
+// tests/golden/hashes.txt. A changed stage, or an upgrade of noise, image or rand that changes
+// the output, fails here and writes the actual image to target/tmp/golden for comparison.
+//
+// After an intentional change, regenerate the references with
+//     UPDATE_GOLDEN=1 cargo test --test heightmap_golden
+// and check the new images in the diff before committing them.
+
+use image::{imageops, ImageBuffer, Rgba};
+use rust_heightmap_generator::heightmap_gen::cancel::CancelToken;
+use rust_heightmap_generator::heightmap_gen::heightmap::{blend_buffers, clamp_image_buffer, colorize_buffer, generate_normal_map, generate_perlin_noise_buffer, mask_buffer, simulate_river_flow, thermal_erosion};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+type Buffer = ImageBuffer<Rgba<u8>, Vec<u8>>;
+
+// Not square, so swapped width and height show up
+const WIDTH: u32 = 64;
+const HEIGHT: u32 = 48;
+
+// Tests run in parallel but share the hashes file
+static HASHES: Mutex<()> = Mutex::new(());
+
+fn golden_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("golden")
+}
+
+fn updating() -> bool {
+    std::env::var_os("UPDATE_GOLDEN").is_some()
+}
+
+// FNV-1a over the size and the raw RGBA bytes
+fn hash(buffer: &Buffer) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    let (width, height) = buffer.dimensions();
+    for byte in width.to_le_bytes().iter().chain(height.to_le_bytes().iter()).chain(buffer.as_raw().iter()) {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+fn read_hashes(path: &Path) -> BTreeMap<String, String> {
+    fs::read_to_string(path)
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| line.split_once(' '))
+        .map(|(name, hash)| (name.to_string(), hash.trim().to_string()))
+        .collect()
+}
+
+fn assert_golden(name: &str, actual: &Buffer) {
+    let dir = golden_dir();
+    let image_path = dir.join(format!("{}.png", name));
+    let hashes_path = dir.join("hashes.txt");
+    let actual_hash = format!("{:016x}", hash(actual));
+    let _lock = HASHES.lock().unwrap_or_else(|e| e.into_inner());
+
+    if updating() {
+        fs::create_dir_all(&dir).unwrap();
+        actual.save(&image_path).unwrap();
+        let mut hashes = read_hashes(&hashes_path);
+        hashes.insert(name.to_string(), actual_hash);
+        let text: String = hashes.iter().map(|(name, hash)| format!("{} {}\n", name, hash)).collect();
+        fs::write(&hashes_path, text).unwrap();
+        return;
+    }
+
+    let expected = image::open(&image_path)
+        .unwrap_or_else(|e| panic!("{}: missing reference {} ({}), run with UPDATE_GOLDEN=1", name, image_path.display(), e))
+        .into_rgba8();
+    let expected_hash = read_hashes(&hashes_path).get(name).cloned()
+        .unwrap_or_else(|| panic!("{}: no hash in {}, run with UPDATE_GOLDEN=1", name, hashes_path.display()));
+    assert_eq!(format!("{:016x}", hash(&expected)), expected_hash, "{}: the reference image doesn't match its recorded hash", name);
+
+    if actual_hash != expected_hash {
+        let out_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("golden");
+        fs::create_dir_all(&out_dir).unwrap();
+        let out_path = out_dir.join(format!("{}.png", name));
+        actual.save(&out_path).unwrap();
+
+        let (differing, largest) = if expected.dimensions() == actual.dimensions() {
+            expected.as_raw().chunks(4).zip(actual.as_raw().chunks(4))
+                .filter(|(a, b)| a != b)
+                .fold((0, 0), |(count, largest), (a, b)| {
+                    let difference = a.iter().zip(b.iter()).map(|(a, b)| a.abs_diff(*b)).max().unwrap_or(0);
+                    (count + 1, largest.max(difference))
+                })
+        } else {
+            (actual.len() / 4, 255)
+        };
+        panic!(
+            "{}: output changed ({} of {} pixels differ, by up to {}). Expected {}, got {}. Actual image written to {}",
+            name, differing, actual.width() * actual.height(), largest, expected_hash, actual_hash, out_path.display()
+        );
+    }
+}
+
+// Height and color next to each other, for the stages that change both
+fn side_by_side(left: &Buffer, right: &Buffer) -> Buffer {
+    let (width, height) = left.dimensions();
+    let mut combined = ImageBuffer::new(width * 2, height);
+    imageops::replace(&mut combined, left, 0, 0);
+    imageops::replace(&mut combined, right, width as i64, 0);
+    combined
+}
+
+fn base() -> Buffer {
+    generate_perlin_noise_buffer(WIDTH, HEIGHT, 0.0, 0.0, 0.05, 1.0, 42)
+}
+
+fn layer() -> Buffer {
+    generate_perlin_noise_buffer(WIDTH, HEIGHT, 10.0, 5.0, 0.1, 0.5, 7)
+}
+
+fn terrain() -> (Buffer, Buffer) {
+    let height = base();
+    let color = colorize_buffer(&height, 2).unwrap();
+    (height, color)
+}
+
+#[test]
+fn perlin_noise() {
+    assert_golden("perlin_noise", &base());
+    assert_golden("perlin_noise_layer", &layer());
+}
+
+#[test]
+fn blend_modes() {
+    for (mode, name) in [(0, "blend_normal"), (1, "blend_multiply"), (2, "blend_add")] {
+        assert_golden(name, &blend_buffers(&base(), &layer(), mode).unwrap());
+    }
+}
+
+#[test]
+fn colorize() {
+    assert_golden("colorize", &colorize_buffer(&base(), 2).unwrap());
+    assert_golden("colorize_with_water", &colorize_buffer(&base(), 0).unwrap());
+}
+
+#[test]
+fn clamp() {
+    for (as_water, name) in [(false, "clamp_land"), (true, "clamp_water")] {
+        let (mut height, mut color) = terrain();
+        clamp_image_buffer(&mut height, &mut color, as_water, 110, 255).unwrap();
+        assert_golden(name, &side_by_side(&height, &color));
+    }
+}
+
+#[test]
+fn thermal() {
+    for (mode, name) in [(1, "thermal_erosion_lower"), (2, "thermal_erosion_talus")] {
+        let (mut height, mut color) = terrain();
+        thermal_erosion(&mut height, &mut color, 5, 0.01, mode, &CancelToken::new()).unwrap();
+        assert_golden(name, &side_by_side(&height, &color));
+    }
+}
+
+#[test]
+fn rivers() {
+    let (mut height, mut color) = terrain();
+    simulate_river_flow(&mut height, &mut color, 2, 1, 3, 1, 1.0, &CancelToken::new()).unwrap();
+    assert_golden("rivers", &side_by_side(&height, &color));
+
+    // Detail above 1 takes the wider river path used for exports
+    let (mut height, mut color) = terrain();
+    simulate_river_flow(&mut height, &mut color, 2, 1, 3, 1, 3.0, &CancelToken::new()).unwrap();
+    assert_golden("rivers_detail", &side_by_side(&height, &color));
+}
+
+// Every parallel stage once, for comparing runs on different thread pools
+fn parallel_stages() -> Vec<Buffer> {
+    let mut buffers = vec![base(), layer()];
+    for mode in 0..=2 {
+        buffers.push(blend_buffers(&base(), &layer(), mode).unwrap());
+    }
+    buffers.push(colorize_buffer(&base(), 0).unwrap());
+    buffers.push(generate_normal_map(&base(), 2.0));
+    buffers.push(mask_buffer(&base(), 100, 180));
+    for mode in 0..=2 {
+        let (mut height, mut color) = terrain();
+        thermal_erosion(&mut height, &mut color, 5, 0.01, mode, &CancelToken::new()).unwrap();
+        buffers.push(height);
+        buffers.push(color);
+    }
+    buffers
+}
+
+#[test]
+fn parallel_stages_match_a_single_thread() {
+    let single = rayon::ThreadPoolBuilder::new().num_threads(1).build().unwrap();
+    let serial = single.install(parallel_stages);
+    let parallel = parallel_stages();
+
+    assert_eq!(serial.len(), parallel.len());
+    for (index, (serial, parallel)) in serial.iter().zip(parallel.iter()).enumerate() {
+        assert!(serial == parallel, "stage {} differs between one thread and the default pool", index);
+    }
+}
@@ -0,0 +1,257 @@
+// Property tests for the heightmap stages: the same invariants are checked on many random sizes,
+// seeds and parameters. The cases come from a fixed seed, so a failure always reproduces.
+
+use image::{ImageBuffer, Rgba};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rust_heightmap_generator::heightmap_gen::cancel::CancelToken;
+use rust_heightmap_generator::heightmap_gen::error::HeightmapError;
+use rust_heightmap_generator::heightmap_gen::heightmap::{blend_buffers, clamp_image_buffer, colorize_buffer, generate_perlin_noise_buffer, simulate_river_flow, thermal_erosion};
+
+type Buffer = ImageBuffer<Rgba<u8>, Vec<u8>>;
+
+const CASES: usize = 32;
+
+fn rng() -> StdRng {
+    StdRng::seed_from_u64(0x5eed)
+}
+
+// Random heights with a random but uniform alpha, like a layer with an opacity
+fn random_buffer(rng: &mut StdRng, width: u32, height: u32) -> Buffer {
+    let alpha = rng.gen();
+    ImageBuffer::from_fn(width, height, |_, _| {
+        let value = rng.gen();
+        Rgba([value, value, value, alpha])
+    })
+}
+
+fn random_size(rng: &mut StdRng, min: u32) -> (u32, u32) {
+    (rng.gen_range(min..40), rng.gen_range(min..40))
+}
+
+#[test]
+fn perlin_noise_has_the_requested_size_and_opacity() {
+    let mut rng = rng();
+    for _ in 0..CASES {
+        let (width, height) = random_size(&mut rng, 1);
+        let opacity: f64 = rng.gen_range(0.0..=1.0);
+        let buffer = generate_perlin_noise_buffer(width, height, rng.gen_range(-100.0..100.0), rng.gen_range(-100.0..100.0), rng.gen_range(0.001..0.5), opacity, rng.gen());
+
+        assert_eq!(buffer.dimensions(), (width, height));
+        let alpha = (opacity * 255.0) as u8;
+        for pixel in buffer.pixels() {
+            assert_eq!(pixel[3], alpha);
+            assert!(pixel[0] == pixel[1] && pixel[1] == pixel[2], "noise must be gray, got {:?}", pixel);
+        }
+    }
+}
+
+#[test]
+fn perlin_noise_is_deterministic() {
+    let a = generate_perlin_noise_buffer(33, 17, 3.0, 4.0, 0.07, 1.0, 9);
+    let b = generate_perlin_noise_buffer(33, 17, 3.0, 4.0, 0.07, 1.0, 9);
+    assert_eq!(a, b);
+}
+
+#[test]
+fn blend_keeps_the_size_and_combines_alpha() {
+    let mut rng = rng();
+    for _ in 0..CASES {
+        let (width, height) = random_size(&mut rng, 1);
+        let a = random_buffer(&mut rng, width, height);
+        let b = random_buffer(&mut rng, width, height);
+        for mode in 0..=2 {
+            let blended = blend_buffers(&a, &b, mode).unwrap();
+            assert_eq!(blended.dimensions(), (width, height));
+            for ((pixel, pixel_a), pixel_b) in blended.pixels().zip(a.pixels()).zip(b.pixels()) {
+                // Alpha is "b over a", so it never drops below either input
+                assert!(pixel[3] >= pixel_a[3].max(pixel_b[3]).saturating_sub(1), "mode {}: alpha {} from {} and {}", mode, pixel[3], pixel_a[3], pixel_b[3]);
+            }
+        }
+    }
+}
+
+#[test]
+fn blend_with_an_opaque_layer_replaces_the_base() {
+    let mut rng = rng();
+    let a = random_buffer(&mut rng, 20, 10);
+    let mut b = random_buffer(&mut rng, 20, 10);
+    for pixel in b.pixels_mut() {
+        pixel[3] = 255;
+    }
+    assert_eq!(blend_buffers(&a, &b, 0).unwrap(), b);
+}
+
+#[test]
+fn blend_with_a_transparent_layer_keeps_the_base() {
+    let mut rng = rng();
+    let mut a = random_buffer(&mut rng, 20, 10);
+    let mut b = random_buffer(&mut rng, 20, 10);
+    for pixel in a.pixels_mut() {
+        pixel[3] = 255;
+    }
+    for pixel in b.pixels_mut() {
+        pixel[3] = 0;
+    }
+    for mode in 0..=1 {
+        assert_eq!(blend_buffers(&a, &b, mode).unwrap(), a, "mode {}", mode);
+    }
+}
+
+#[test]
+fn blend_rejects_bad_input() {
+    let a = generate_perlin_noise_buffer(8, 8, 0.0, 0.0, 0.1, 1.0, 1);
+    let b = generate_perlin_noise_buffer(8, 9, 0.0, 0.0, 0.1, 1.0, 1);
+    assert!(matches!(blend_buffers(&a, &b, 0), Err(HeightmapError::SizeMismatch { .. })));
+    assert!(matches!(blend_buffers(&a, &a, 3), Err(HeightmapError::InvalidBlendMode(3))));
+}
+
+#[test]
+fn colorize_is_opaque_and_keeps_the_size() {
+    let mut rng = rng();
+    for _ in 0..CASES {
+        let (width, height) = random_size(&mut rng, 1);
+        let buffer = random_buffer(&mut rng, width, height);
+        let colored = colorize_buffer(&buffer, rng.gen_range(0..10)).unwrap();
+        assert_eq!(colored.dimensions(), (width, height));
+        assert!(colored.pixels().all(|pixel| pixel[3] == 255));
+    }
+    assert!(matches!(colorize_buffer(&ImageBuffer::new(4, 4), 10), Err(HeightmapError::InvalidColorIndex(10))));
+}
+
+#[test]
+fn clamp_only_raises_heights_at_or_below_the_minimum() {
+    let mut rng = rng();
+    for _ in 0..CASES {
+        let (width, height) = random_size(&mut rng, 1);
+        let original = random_buffer(&mut rng, width, height);
+        let min: u8 = rng.gen();
+        let mut clamped = original.clone();
+        let mut color = colorize_buffer(&original, 2).unwrap();
+        clamp_image_buffer(&mut clamped, &mut color, rng.gen(), min, 255).unwrap();
+
+        assert_eq!(clamped.dimensions(), (width, height));
+        for (before, after) in original.pixels().zip(clamped.pixels()) {
+            assert_eq!(after[3], before[3], "alpha must not change");
+            if before[0] <= min {
+                assert_eq!(after[0], min);
+            } else {
+                assert_eq!(after, before);
+            }
+        }
+    }
+}
+
+#[test]
+fn clamp_is_monotonic() {
+    // Every possible height in one row: clamping must keep their order, and a higher minimum
+    // must never give a lower result
+    let ramp: Buffer = ImageBuffer::from_fn(256, 1, |x, _| Rgba([x as u8, x as u8, x as u8, 255]));
+    let clamp = |min: u8| {
+        let mut height = ramp.clone();
+        let mut color = ramp.clone();
+        clamp_image_buffer(&mut height, &mut color, true, min, 255).unwrap();
+        height
+    };
+
+    let mut previous = clamp(0);
+    for min in 1..=255u8 {
+        let clamped = clamp(min);
+        for x in 1..256 {
+            assert!(clamped.get_pixel(x, 0)[0] >= clamped.get_pixel(x - 1, 0)[0], "min {}: not increasing at {}", min, x);
+        }
+        for (lower, higher) in previous.pixels().zip(clamped.pixels()) {
+            assert!(higher[0] >= lower[0], "min {}: a higher minimum lowered a height", min);
+        }
+        previous = clamped;
+    }
+}
+
+#[test]
+fn thermal_erosion_keeps_the_size_and_only_copies_neighbours() {
+    let mut rng = rng();
+    for _ in 0..CASES {
+        let (width, height) = random_size(&mut rng, 3);
+        let original = random_buffer(&mut rng, width, height);
+        let mut eroded = original.clone();
+        let mut color = colorize_buffer(&original, 2).unwrap();
+        let mode = rng.gen_range(0..=2);
+        thermal_erosion(&mut eroded, &mut color, rng.gen_range(0..4), rng.gen_range(0.0..0.2), mode, &CancelToken::new()).unwrap();
+
+        assert_eq!(eroded.dimensions(), (width, height));
+        assert_eq!(color.dimensions(), (width, height));
+        let alpha = original.get_pixel(0, 0)[3];
+        assert!(eroded.pixels().all(|pixel| pixel[3] == alpha), "uniform alpha must stay uniform");
+        // Pixels are moved around, never created, so the range can only shrink
+        let range = |buffer: &Buffer| (buffer.pixels().map(|p| p[0]).min(), buffer.pixels().map(|p| p[0]).max());
+        let (min, max) = range(&original);
+        let (eroded_min, eroded_max) = range(&eroded);
+        assert!(eroded_min >= min && eroded_max <= max);
+        // The border is never touched
+        for x in 0..width {
+            assert_eq!(eroded.get_pixel(x, 0), original.get_pixel(x, 0));
+            assert_eq!(eroded.get_pixel(x, height - 1), original.get_pixel(x, height - 1));
+        }
+    }
+}
+
+#[test]
+fn thermal_erosion_without_iterations_or_mode_is_a_no_op() {
+    let mut rng = rng();
+    let original = random_buffer(&mut rng, 16, 12);
+    let original_color = colorize_buffer(&original, 2).unwrap();
+    for (iterations, mode) in [(0, 1), (5, 0)] {
+        let mut height = original.clone();
+        let mut color = original_color.clone();
+        thermal_erosion(&mut height, &mut color, iterations, 0.01, mode, &CancelToken::new()).unwrap();
+        assert_eq!(height, original);
+        assert_eq!(color, original_color);
+    }
+}
+
+#[test]
+fn erosion_stages_reject_tiny_and_mismatched_images() {
+    let cancel = CancelToken::new();
+    let mut tiny: Buffer = ImageBuffer::new(2, 5);
+    let mut tiny_color: Buffer = ImageBuffer::new(2, 5);
+    assert!(matches!(thermal_erosion(&mut tiny, &mut tiny_color, 1, 0.01, 1, &cancel), Err(HeightmapError::ImageTooSmall { .. })));
+    assert!(matches!(simulate_river_flow(&mut tiny, &mut tiny_color, 1, 1, 1, 1, 1.0, &cancel), Err(HeightmapError::ImageTooSmall { .. })));
+
+    let mut height: Buffer = ImageBuffer::new(8, 8);
+    let mut color: Buffer = ImageBuffer::new(8, 9);
+    assert!(matches!(thermal_erosion(&mut height, &mut color, 1, 0.01, 1, &cancel), Err(HeightmapError::SizeMismatch { .. })));
+    assert!(matches!(simulate_river_flow(&mut height, &mut color, 1, 1, 1, 1, 1.0, &cancel), Err(HeightmapError::SizeMismatch { .. })));
+}
+
+#[test]
+fn cancelled_erosion_stops() {
+    let cancel = CancelToken::new();
+    cancel.cancel();
+    let mut height = generate_perlin_noise_buffer(16, 16, 0.0, 0.0, 0.1, 1.0, 1);
+    let mut color = colorize_buffer(&height, 2).unwrap();
+    assert!(matches!(thermal_erosion(&mut height, &mut color, 3, 0.01, 1, &cancel), Err(HeightmapError::Cancelled)));
+}
+
+#[test]
+fn rivers_keep_the_size_and_are_deterministic() {
+    let mut rng = rng();
+    for _ in 0..CASES {
+        let (width, height) = random_size(&mut rng, 3);
+        let original = generate_perlin_noise_buffer(width, height, 0.0, 0.0, 0.1, 1.0, rng.gen());
+        let original_color = colorize_buffer(&original, 2).unwrap();
+        let seed: u64 = rng.gen();
+        let detail = rng.gen_range(1.0..3.0);
+        let run = || {
+            let mut height_buffer = original.clone();
+            let mut color = original_color.clone();
+            simulate_river_flow(&mut height_buffer, &mut color, 2, 1, 3, seed, detail, &CancelToken::new()).unwrap();
+            (height_buffer, color)
+        };
+
+        let (first_height, first_color) = run();
+        assert_eq!(first_height.dimensions(), (width, height));
+        assert_eq!(first_color.dimensions(), (width, height));
+        assert!(first_height.pixels().all(|pixel| pixel[3] == 255), "alpha must stay opaque");
+        assert_eq!(run(), (first_height, first_color));
+    }
+}
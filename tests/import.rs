@@ -0,0 +1,93 @@
+// Exported heightmaps imported again, and the cache of decoded files
+
+use image::{imageops, ImageBuffer, Luma, Rgba};
+use rust_heightmap_generator::heightmap_gen::dem::{grid_from_buffer, write_ascii_grid};
+use rust_heightmap_generator::heightmap_gen::engine::{to_height16, write_raw16};
+use rust_heightmap_generator::heightmap_gen::import::{import_heightmap, load_heightfield};
+use rust_heightmap_generator::heightmap_gen::output::{save_height16, save_image, write_bytes};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+// Every height once, in a square that isn't symmetric, so flips and transposes show up
+fn heights() -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    ImageBuffer::from_fn(16, 16, |x, y| {
+        let value = (y * 16 + x) as u8;
+        Rgba([value, value, value, 255])
+    })
+}
+
+fn temp_path(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_TARGET_TMPDIR")).join("import").join(name)
+}
+
+fn assert_heights(path: &Path) {
+    let imported = import_heightmap(path, 16, 16, 0.5).unwrap();
+    for (expected, actual) in heights().pixels().zip(imported.pixels()) {
+        assert_eq!(actual.0, [expected[0], expected[0], expected[0], 127], "{}", path.display());
+    }
+}
+
+#[test]
+fn exported_png_and_16_bit_png_import_unchanged() {
+    let path = temp_path("heights.png");
+    save_image(&heights(), &path).unwrap();
+    assert_heights(&path);
+
+    let path = temp_path("heights16.png");
+    save_height16(&to_height16(&heights()), &path).unwrap();
+    assert_eq!(*load_heightfield(&path).unwrap(), to_height16(&heights()));
+    assert_heights(&path);
+}
+
+#[test]
+fn exported_raw_files_import_unchanged() {
+    let path = temp_path("heights_unity.raw");
+    write_raw16(&to_height16(&heights()), &path).unwrap();
+    assert_heights(&path);
+
+    // 8-bit raw files are written top down here, so the rows are flipped before writing
+    let path = temp_path("heights.r8");
+    let flipped = imageops::flip_vertical(&heights());
+    write_bytes(&path, &flipped.pixels().map(|pixel| pixel[0]).collect::<Vec<u8>>()).unwrap();
+    assert_heights(&path);
+}
+
+#[test]
+fn exported_ascii_grids_import_unchanged() {
+    // The grid is normalised to its own range, which here covers every height
+    let path = temp_path("heights.asc");
+    write_ascii_grid(&grid_from_buffer(&heights(), 10.0, 100.0, 355.0, None), &path).unwrap();
+    assert_heights(&path);
+}
+
+#[test]
+fn sixteen_bit_values_round_to_the_nearest_height() {
+    let path = temp_path("rounding.png");
+    let exact = ImageBuffer::from_fn(16, 16, |x, y| Luma([((y * 16 + x) * 257) as u16]));
+    save_height16(&exact, &path).unwrap();
+    assert_heights(&path);
+
+    let path = temp_path("rounding_edges.png");
+    let edges = ImageBuffer::from_fn(4, 1, |x, _| Luma([[128, 129, 65535 - 129, 65535 - 128][x as usize]]));
+    save_height16(&edges, &path).unwrap();
+    let imported = import_heightmap(&path, 4, 1, 1.0).unwrap();
+    assert_eq!(imported.pixels().map(|pixel| pixel[0]).collect::<Vec<u8>>(), vec![0, 1, 254, 255]);
+}
+
+#[test]
+fn files_are_decoded_again_when_they_change() {
+    let path = temp_path("cached.png");
+    save_image(&heights(), &path).unwrap();
+    let first = load_heightfield(&path).unwrap();
+    assert!(Arc::ptr_eq(&first, &load_heightfield(&path).unwrap()));
+
+    // A new modification time, as file systems may not tell two quick writes apart
+    save_image(&imageops::flip_horizontal(&heights()), &path).unwrap();
+    let modified = fs::metadata(&path).unwrap().modified().unwrap() + Duration::from_secs(10);
+    File::options().write(true).open(&path).unwrap().set_modified(modified).unwrap();
+    let changed = load_heightfield(&path).unwrap();
+    assert!(!Arc::ptr_eq(&first, &changed));
+    assert_eq!(*changed, to_height16(&imageops::flip_horizontal(&heights())));
+}
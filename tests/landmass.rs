@@ -0,0 +1,68 @@
+// Islands and lakes of a map drawn from rectangles, whose areas and coasts are known
+
+use image::{ImageBuffer, Rgba};
+use rust_heightmap_generator::heightmap_gen::landmass::{apply_landmass_rules, landmass_stats, LandmassRules};
+
+const SEA: u8 = 50;
+
+type Buffer = ImageBuffer<Rgba<u8>, Vec<u8>>;
+
+// A 20x20 island with a 4x4 lake in it and a 3x3 island, at 10 m per pixel
+fn map() -> (Buffer, Buffer) {
+    let heightmap = ImageBuffer::from_fn(64, 64, |x, y| {
+        let value = if (18..22).contains(&x) && (18..22).contains(&y) {
+            SEA
+        } else if (10..30).contains(&x) && (10..30).contains(&y) {
+            120
+        } else if (40..43).contains(&x) && (40..43).contains(&y) {
+            90
+        } else {
+            SEA
+        };
+        Rgba([value, value, value, 255])
+    });
+    let colormap = ImageBuffer::from_fn(64, 64, |x, y| {
+        if heightmap.get_pixel(x, y)[0] > SEA { Rgba([0, 128, 0, 255]) } else { Rgba([0, 0, 200, 255]) }
+    });
+    (heightmap, colormap)
+}
+
+#[test]
+fn stats_count_islands_lakes_and_coasts() {
+    let (heightmap, _) = map();
+    let stats = landmass_stats(&heightmap, SEA, 640.0);
+
+    assert_eq!(stats.islands.len(), 2);
+    assert_eq!(stats.islands[0].area_px, 400 - 16);
+    assert_eq!(stats.islands[1].area_px, 9);
+    // The outer coast and the lake shore
+    assert!((stats.islands[0].perimeter_km - (80.0 + 16.0) * 0.01).abs() < 1e-9);
+    assert!((stats.islands[1].centroid_m[0] - 415.0).abs() < 1e-9);
+    assert_eq!(stats.lakes.len(), 1);
+    assert_eq!(stats.lakes[0].area_px, 16);
+    assert!((stats.land_percent - 393.0 / 4096.0 * 100.0).abs() < 1e-9);
+}
+
+#[test]
+fn rules_sink_small_islands_and_fill_small_lakes() {
+    let (mut heightmap, mut colormap) = map();
+    let rules = LandmassRules { min_island_px: 10, min_lake_px: 20, single_landmass: false };
+    apply_landmass_rules(&mut heightmap, &mut colormap, SEA, &rules, 1.0).unwrap();
+
+    assert_eq!(heightmap.get_pixel(41, 41)[0], SEA);
+    assert_eq!(colormap.get_pixel(41, 41).0, [0, 0, 200, 255]);
+    assert_eq!(heightmap.get_pixel(20, 20)[0], 120);
+    assert_eq!(colormap.get_pixel(20, 20).0, [0, 128, 0, 255]);
+    let stats = landmass_stats(&heightmap, SEA, 640.0);
+    assert_eq!((stats.islands.len(), stats.lakes.len()), (1, 0));
+
+    // At half the detail the limits cover a quarter of the pixels, so nothing is small enough
+    let (mut heightmap, mut colormap) = map();
+    apply_landmass_rules(&mut heightmap, &mut colormap, SEA, &rules, 0.5).unwrap();
+    assert_eq!(heightmap, map().0);
+
+    let (mut heightmap, mut colormap) = map();
+    apply_landmass_rules(&mut heightmap, &mut colormap, SEA, &LandmassRules { single_landmass: true, ..LandmassRules::default() }, 1.0).unwrap();
+    let stats = landmass_stats(&heightmap, SEA, 640.0);
+    assert_eq!((stats.islands.len(), stats.lakes.len()), (1, 1));
+}
@@ -0,0 +1,55 @@
+// Normalisation and height statistics of a ramp whose heights are known
+
+use image::{ImageBuffer, Rgba};
+use rust_heightmap_generator::heightmap_gen::remap::{apply_normalize, NormalizeMode};
+use rust_heightmap_generator::heightmap_gen::stats::height_stats;
+
+type Buffer = ImageBuffer<Rgba<u8>, Vec<u8>>;
+
+// Heights 40 to 180, one column each, with a single spike at 250
+fn ramp() -> Buffer {
+    let mut buffer = ImageBuffer::from_fn(141, 100, |x, _| {
+        let value = 40 + x as u8;
+        Rgba([value, value, value, 255])
+    });
+    buffer.put_pixel(0, 0, Rgba([250, 250, 250, 255]));
+    buffer
+}
+
+#[test]
+fn stats_of_the_ramp() {
+    let stats = height_stats(&ramp(), Some(109));
+    assert_eq!((stats.min, stats.max), (40, 250));
+    assert_eq!(stats.histogram.iter().sum::<u64>(), 14100);
+    assert_eq!(stats.histogram[40], 99);
+    assert!((stats.mean - (110.0 * 14100.0 + 210.0) / 14100.0).abs() < 1e-9);
+    assert!((stats.std_dev - 40.7).abs() < 0.1);
+    assert!((stats.below_sea_percent.unwrap() - 6999.0 / 14100.0 * 100.0).abs() < 1e-9);
+    assert!(height_stats(&ramp(), None).below_sea_percent.is_none());
+}
+
+#[test]
+fn normalize_modes_fill_the_range() {
+    let mut stretched = ramp();
+    apply_normalize(&mut stretched, NormalizeMode::Stretch, 0.0);
+    let stats = height_stats(&stretched, None);
+    assert_eq!((stats.min, stats.max), (0, 255));
+    // The spike keeps the ramp from reaching the top
+    assert_eq!(stretched.get_pixel(140, 50)[0], 170);
+
+    let mut clipped = ramp();
+    apply_normalize(&mut clipped, NormalizeMode::PercentileClip, 1.0);
+    assert_eq!(clipped.get_pixel(1, 50)[0], 0);
+    assert_eq!(clipped.get_pixel(139, 50)[0], 255);
+    assert!(clipped.get_pixel(70, 50)[0].abs_diff(128) <= 3);
+
+    let mut equalized = ramp();
+    apply_normalize(&mut equalized, NormalizeMode::Equalize, 0.0);
+    let stats = height_stats(&equalized, None);
+    assert_eq!((stats.min, stats.max), (0, 255));
+    assert!((stats.mean - 127.5).abs() < 2.0);
+
+    let mut untouched = ramp();
+    apply_normalize(&mut untouched, NormalizeMode::Off, 0.0);
+    assert_eq!(untouched, ramp());
+}
@@ -0,0 +1,60 @@
+// Curve, levels and terrace remapping of a ramp that holds every height once
+
+use std::collections::BTreeSet;
+use image::{ImageBuffer, Rgba};
+use rust_heightmap_generator::heightmap_gen::config::SerializedTool;
+use rust_heightmap_generator::heightmap_gen::remap::{apply_curve, apply_levels, apply_terrace};
+
+type Buffer = ImageBuffer<Rgba<u8>, Vec<u8>>;
+
+// Heights 0 to 255, one column each
+fn ramp() -> Buffer {
+    ImageBuffer::from_fn(256, 4, |x, _| {
+        let value = x as u8;
+        Rgba([value, value, value, 255])
+    })
+}
+
+fn heights(buffer: &Buffer) -> BTreeSet<u8> {
+    buffer.pixels().map(|pixel| pixel[0]).collect()
+}
+
+#[test]
+fn default_curve_is_the_identity() {
+    let mut buffer = ramp();
+    apply_curve(&mut buffer, &SerializedTool::default().curve_points);
+    assert_eq!(buffer, ramp());
+}
+
+#[test]
+fn levels_map_the_input_range_to_the_output_range() {
+    let mut buffer = ramp();
+    apply_levels(&mut buffer, 50, 200, 1.0, 20, 220);
+    assert_eq!(buffer.get_pixel(0, 0)[0], 20);
+    assert_eq!(buffer.get_pixel(50, 0)[0], 20);
+    assert_eq!(buffer.get_pixel(200, 0)[0], 220);
+    assert_eq!(buffer.get_pixel(255, 0)[0], 220);
+    // Gamma 1 is a straight line in between
+    for x in 50..=200u32 {
+        let expected = 20.0 + (x as f32 - 50.0) / 150.0 * 200.0;
+        assert!((buffer.get_pixel(x, 0)[0] as f32 - expected).abs() <= 0.5, "height {}", x);
+    }
+    // The colour channels are remapped, alpha is kept
+    assert_eq!(*buffer.get_pixel(125, 2), Rgba([120, 120, 120, 255]));
+}
+
+#[test]
+fn sharp_terraces_have_one_height_per_step() {
+    for steps in [2, 5, 8, 16] {
+        let mut buffer = ramp();
+        apply_terrace(&mut buffer, steps, 1.0, 0.0, 0.05, 1);
+        assert_eq!(heights(&buffer).len(), steps as usize, "{} steps", steps);
+    }
+}
+
+#[test]
+fn zero_steps_leave_the_buffer_unchanged() {
+    let mut buffer = ramp();
+    apply_terrace(&mut buffer, 0, 1.0, 0.5, 0.05, 1);
+    assert_eq!(buffer, ramp());
+}
@@ -0,0 +1,97 @@
+// Checks the carved roads on seeded random terrains, like heightmap_properties.rs
+
+use image::{ImageBuffer, Rgba};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rust_heightmap_generator::heightmap_gen::cancel::CancelToken;
+use rust_heightmap_generator::heightmap_gen::heightmap::{colorize_buffer, generate_perlin_noise_buffer};
+use rust_heightmap_generator::heightmap_gen::roads::{carve_roads, Road, RoadParams, ROAD_COLOR};
+use rust_heightmap_generator::heightmap_gen::terrain::Terrain;
+
+const TERRAIN: Terrain = Terrain { world_size_km: 1.0, min_elevation_m: 0.0, max_elevation_m: 200.0, sea_level_m: 0.0 };
+
+#[test]
+fn roads_reach_their_waypoints_within_the_grade() {
+    let mut rng = StdRng::seed_from_u64(0x5eed);
+    for _ in 0..4 {
+        let (width, height) = (rng.gen_range(32..96), rng.gen_range(32..96));
+        let mut heightmap = generate_perlin_noise_buffer(width, height, 0.0, 0.0, 0.05, 1.0, rng.gen());
+        let mut colormap = colorize_buffer(&heightmap, 2).unwrap();
+        let params = RoadParams { max_grade: rng.gen_range(5.0..20.0), ..RoadParams::default() };
+        let start = [rng.gen_range(0.0..0.3), rng.gen_range(0.0..1.0)];
+        let end = [rng.gen_range(0.7..1.0), rng.gen_range(0.0..1.0)];
+        let roads = vec![Road { waypoints: vec![start, end] }, Road { waypoints: vec![start] }];
+        let paths = carve_roads(&mut heightmap, &mut colormap, None, &roads, &params, &TERRAIN, &CancelToken::new()).unwrap();
+
+        // A single waypoint makes no road
+        assert_eq!(paths.len(), 1);
+        let points = &paths[0].points;
+        let long_side = width.max(height) as f64;
+        let cell = 1000.0 / long_side;
+        let first = points[0];
+        let last = points[points.len() - 1];
+        assert!((first[0] - start[0] as f64 * width as f64 * cell).abs() <= cell && (first[1] - start[1] as f64 * height as f64 * cell).abs() <= cell);
+        assert!((last[0] - end[0] as f64 * width as f64 * cell).abs() <= cell && (last[1] - end[1] as f64 * height as f64 * cell).abs() <= cell);
+        for pair in points.windows(2) {
+            let distance = ((pair[1][0] - pair[0][0]).powi(2) + (pair[1][1] - pair[0][1]).powi(2)).sqrt();
+            assert!((pair[1][2] - pair[0][2]).abs() <= distance * params.max_grade / 100.0 + 1e-9);
+        }
+        assert!(colormap.pixels().any(|pixel| pixel.0[0..3] == ROAD_COLOR.0[0..3]));
+    }
+}
+
+#[test]
+fn road_heights_are_elevations() {
+    // The same relief starting lower gives the same road, lowered by the same amount
+    let heightmap = generate_perlin_noise_buffer(64, 64, 0.0, 0.0, 0.05, 1.0, 3);
+    let colormap = colorize_buffer(&heightmap, 2).unwrap();
+    let roads = vec![Road { waypoints: vec![[0.1, 0.2], [0.9, 0.7]] }];
+    let lowered = Terrain { min_elevation_m: -50.0, max_elevation_m: 150.0, ..TERRAIN };
+
+    let carve = |terrain: &Terrain| {
+        let (mut heightmap, mut colormap) = (heightmap.clone(), colormap.clone());
+        carve_roads(&mut heightmap, &mut colormap, None, &roads, &RoadParams::default(), terrain, &CancelToken::new()).unwrap().remove(0).points
+    };
+    let points = carve(&TERRAIN);
+    let lowered_points = carve(&lowered);
+
+    assert_eq!(points.len(), lowered_points.len());
+    for (point, lowered_point) in points.iter().zip(lowered_points.iter()) {
+        assert_eq!(point[0..2], lowered_point[0..2]);
+        assert!((lowered_point[2] - (point[2] - 50.0)).abs() < 1e-9);
+        assert!(lowered_point[2] >= -50.0 && lowered_point[2] <= 150.0);
+    }
+}
+
+#[test]
+fn roads_go_around_the_sea_and_rivers() {
+    // Level ground at 100 with a wall of water from the top to 80% down the middle. The colormap
+    // isn't painted blue, so only the heights and the river mask show the water.
+    let in_wall = |x: f64, y: f64| (0.45..0.55).contains(&x) && y < 0.8;
+    let size = 64;
+    let ground = |wall_height: u8| ImageBuffer::from_fn(size, size, |x, y| {
+        let value = if in_wall((x as f64 + 0.5) / size as f64, (y as f64 + 0.5) / size as f64) { wall_height } else { 100 };
+        Rgba([value, value, value, 255])
+    });
+    let roads = vec![Road { waypoints: vec![[0.1, 0.3], [0.9, 0.3]] }];
+    // Sea level at height 51
+    let terrain = Terrain { sea_level_m: 40.0, ..TERRAIN };
+
+    let sea = ground(20);
+    let river_mask = ImageBuffer::from_fn(size, size, |x, y| {
+        let value = if in_wall((x as f64 + 0.5) / size as f64, (y as f64 + 0.5) / size as f64) { 255 } else { 0 };
+        Rgba([value, value, value, 255])
+    });
+    for (heightmap, river_mask) in [(sea, None), (ground(100), Some(&river_mask))] {
+        let mut heightmap = heightmap;
+        let mut colormap = colorize_buffer(&heightmap, 2).unwrap();
+        let paths = carve_roads(&mut heightmap, &mut colormap, river_mask, &roads, &RoadParams::default(), &terrain, &CancelToken::new()).unwrap();
+        let points = &paths[0].points;
+        assert!(points.iter().any(|point| point[1] / 1000.0 > 0.8), "the road didn't go around");
+        for point in points {
+            // The smoothed line may cut a corner by a little
+            let (x, y) = (point[0] / 1000.0, point[1] / 1000.0);
+            assert!(!((0.47..0.53).contains(&x) && y < 0.78), "road over water at {:?}", point);
+        }
+    }
+}
@@ -0,0 +1,88 @@
+// Property tests for the point scattering, on seeded random terrains like heightmap_properties.rs
+
+use image::{ImageBuffer, Rgba};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rust_heightmap_generator::heightmap_gen::cancel::CancelToken;
+use rust_heightmap_generator::heightmap_gen::heightmap::generate_perlin_noise_buffer;
+use rust_heightmap_generator::heightmap_gen::scatter::{scatter, ScatterRule, ScatterTerrain};
+
+const CASES: usize = 8;
+
+#[test]
+fn points_keep_their_distance_and_limits() {
+    let mut rng = StdRng::seed_from_u64(0x5eed);
+    for _ in 0..CASES {
+        let (width, height) = (rng.gen_range(16..80), rng.gen_range(16..80));
+        let heightmap = generate_perlin_noise_buffer(width, height, 0.0, 0.0, rng.gen_range(0.01..0.1), 1.0, rng.gen());
+        let terrain = ScatterTerrain { heightmap: &heightmap, river_mask: None, sea_level: 0, size_m: rng.gen_range(100.0..2000.0), height_m: 300.0, min_elevation_m: -100.0 };
+        let rule = ScatterRule {
+            min_distance_m: rng.gen_range(5.0..50.0),
+            elevation_min_m: rng.gen_range(-100.0..20.0),
+            elevation_max_m: rng.gen_range(80.0..=200.0),
+            scale_min: 0.5,
+            scale_max: 2.0,
+            seed: rng.gen(),
+            ..ScatterRule::default()
+        };
+        let sets = scatter(&terrain, std::slice::from_ref(&rule), &CancelToken::new()).unwrap();
+        let points = &sets[0].points;
+        assert_eq!(sets, scatter(&terrain, std::slice::from_ref(&rule), &CancelToken::new()).unwrap());
+
+        let long_side = width.max(height) as f64;
+        let pixel_size = terrain.size_m / long_side;
+        for (i, point) in points.iter().enumerate() {
+            assert!(point.x >= 0.0 && point.x < terrain.size_m * width as f64 / long_side);
+            assert!(point.y >= 0.0 && point.y < terrain.size_m * height as f64 / long_side);
+            assert!(point.scale >= 0.5 && point.scale <= 2.0);
+            assert!(point.normal[2] > 0.0);
+            let value = heightmap.get_pixel(((point.x / pixel_size) as u32).min(width - 1), ((point.y / pixel_size) as u32).min(height - 1))[0];
+            let elevation = -100.0 + value as f64 / 255.0 * 300.0;
+            assert!(elevation >= rule.elevation_min_m && elevation <= rule.elevation_max_m);
+            for other in points[i + 1..].iter() {
+                let distance = ((point.x - other.x).powi(2) + (point.y - other.y).powi(2)).sqrt();
+                assert!(distance >= rule.min_distance_m, "points {} m apart, minimum {}", distance, rule.min_distance_m);
+            }
+        }
+    }
+}
+
+#[test]
+fn cancelled_scatter_stops() {
+    let heightmap = generate_perlin_noise_buffer(16, 16, 0.0, 0.0, 0.1, 1.0, 1);
+    let terrain = ScatterTerrain { heightmap: &heightmap, river_mask: None, sea_level: 0, size_m: 100.0, height_m: 100.0, min_elevation_m: 0.0 };
+    let cancel = CancelToken::new();
+    cancel.cancel();
+    assert!(scatter(&terrain, &[ScatterRule::default()], &cancel).is_err());
+}
+
+#[test]
+fn points_keep_away_from_rivers_in_the_mask() {
+    // Flat land with a river down the middle, one metre per pixel
+    let heightmap = ImageBuffer::from_pixel(64, 64, Rgba([128, 128, 128, 255]));
+    let river_mask = ImageBuffer::from_fn(64, 64, |x, _| if x == 32 { Rgba([255, 255, 255, 255]) } else { Rgba([0, 0, 0, 255]) });
+    let terrain = ScatterTerrain { heightmap: &heightmap, river_mask: Some(&river_mask), sea_level: 0, size_m: 64.0, height_m: 100.0, min_elevation_m: 0.0 };
+    let rule = ScatterRule { min_distance_m: 2.0, density: 1.0, river_distance_min_m: 6.0, ..ScatterRule::default() };
+
+    let points = &scatter(&terrain, std::slice::from_ref(&rule), &CancelToken::new()).unwrap()[0].points;
+    assert!(!points.is_empty());
+    assert!(points.iter().all(|point| (point.x.floor() - 32.0).abs() >= 6.0));
+
+    // Without a river mask there are no rivers to keep away from
+    let terrain = ScatterTerrain { river_mask: None, ..terrain };
+    let points = &scatter(&terrain, std::slice::from_ref(&rule), &CancelToken::new()).unwrap()[0].points;
+    assert!(points.iter().any(|point| point.x.floor() == 32.0));
+}
+
+#[test]
+fn points_keep_away_from_the_sea_without_a_mask() {
+    // Sea on the left half at height 20, land on the right, one metre per pixel
+    let heightmap = ImageBuffer::from_fn(64, 64, |x, _| if x < 32 { Rgba([20, 20, 20, 255]) } else { Rgba([128, 128, 128, 255]) });
+    let terrain = ScatterTerrain { heightmap: &heightmap, river_mask: None, sea_level: 51, size_m: 64.0, height_m: 100.0, min_elevation_m: 0.0 };
+    let rule = ScatterRule { min_distance_m: 2.0, density: 1.0, water_distance_min_m: 1.0, water_distance_max_m: 8.0, ..ScatterRule::default() };
+
+    // Only the strip of land along the shore is close enough to the water
+    let points = &scatter(&terrain, std::slice::from_ref(&rule), &CancelToken::new()).unwrap()[0].points;
+    assert!(!points.is_empty());
+    assert!(points.iter().all(|point| point.x >= 32.0 && point.x < 41.0));
+}
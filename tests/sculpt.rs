@@ -0,0 +1,112 @@
+// Brush strokes replayed on flat, sloped and noisy heightmaps
+
+use image::{ImageBuffer, Rgba};
+use rust_heightmap_generator::heightmap_gen::cancel::CancelToken;
+use rust_heightmap_generator::heightmap_gen::sculpt::{apply_strokes, BrushMode, Stroke};
+
+type Buffer = ImageBuffer<Rgba<u8>, Vec<u8>>;
+
+fn filled(width: u32, height: u32, value: impl Fn(u32, u32) -> u8) -> Buffer {
+    ImageBuffer::from_fn(width, height, |x, y| {
+        let value = value(x, y);
+        Rgba([value, value, value, 255])
+    })
+}
+
+// A dab in the middle of the image, a quarter of the long side in radius
+fn dab(mode: BrushMode, falloff: f32) -> Stroke {
+    Stroke { mode, radius: 0.25, strength: 1.0, falloff, seed: 1, points: vec![[0.5, 0.5]] }
+}
+
+fn sculpt(mut buffer: Buffer, strokes: &[Stroke]) -> Buffer {
+    apply_strokes(&mut buffer, strokes, &CancelToken::new()).unwrap();
+    buffer
+}
+
+// Distance of the pixel centre from the middle, relative to the brush radius
+fn distance(buffer: &Buffer, x: u32, y: u32) -> f32 {
+    let (width, height) = buffer.dimensions();
+    let radius = width.max(height) as f32 * 0.25;
+    ((x as f32 + 0.5 - width as f32 / 2.0).powi(2) + (y as f32 + 0.5 - height as f32 / 2.0).powi(2)).sqrt() / radius
+}
+
+fn variance(buffer: &Buffer) -> f64 {
+    let count = buffer.pixels().len() as f64;
+    let mean = buffer.pixels().map(|pixel| pixel[0] as f64).sum::<f64>() / count;
+    buffer.pixels().map(|pixel| (pixel[0] as f64 - mean).powi(2)).sum::<f64>() / count
+}
+
+#[test]
+fn raise_and_lower_stay_inside_the_radius_and_fade_outward() {
+    for (mode, sign) in [(BrushMode::Raise, 1), (BrushMode::Lower, -1)] {
+        let sculpted = sculpt(filled(64, 64, |_, _| 128), &vec![dab(mode, 1.0); 10]);
+        for (x, y, pixel) in sculpted.enumerate_pixels() {
+            let change = pixel[0] as i32 - 128;
+            if distance(&sculpted, x, y) >= 1.0 {
+                assert_eq!(change, 0, "{:?} outside the radius at {}, {}", mode, x, y);
+            } else {
+                assert!(change * sign >= 0, "{:?} went the wrong way at {}, {}", mode, x, y);
+            }
+        }
+
+        // Along a row from the centre the change never grows
+        let changes: Vec<i32> = (32..64).map(|x| (sculpted.get_pixel(x, 32)[0] as i32 - 128) * sign).collect();
+        assert!(changes[0] > 30, "{:?} changed the centre by {}", mode, changes[0]);
+        assert!(changes.windows(2).all(|pair| pair[0] >= pair[1]), "{:?}: {:?}", mode, changes);
+        assert_eq!(*changes.last().unwrap(), 0);
+    }
+}
+
+#[test]
+fn flatten_pulls_heights_toward_the_first_dab() {
+    let slope = filled(64, 64, |x, _| (x * 4) as u8);
+    let target = slope.get_pixel(32, 32)[0] as i32;
+    let mut stroke = dab(BrushMode::Flatten, 0.5);
+    stroke.points.push([0.6, 0.5]);
+    let sculpted = sculpt(slope.clone(), &[stroke]);
+
+    let mut moved = 0;
+    for (before, after) in slope.pixels().zip(sculpted.pixels()) {
+        let (before, after) = (before[0] as i32, after[0] as i32);
+        assert!((after - target).abs() <= (before - target).abs());
+        if after != before {
+            moved += 1;
+        }
+    }
+    assert!(moved > 100);
+    // Next to the first dab the ground is level
+    assert!((sculpted.get_pixel(36, 32)[0] as i32 - target).abs() <= 1);
+}
+
+#[test]
+fn smooth_lowers_the_variance() {
+    let noisy = filled(64, 64, |x, y| if (x + y) % 2 == 0 { 100 } else { 150 });
+    let mut stroke = dab(BrushMode::Smooth, 0.0);
+    stroke.radius = 0.75;
+    let sculpted = sculpt(noisy.clone(), &[stroke]);
+    assert!(variance(&sculpted) < variance(&noisy) * 0.5, "{} against {}", variance(&sculpted), variance(&noisy));
+}
+
+#[test]
+fn strokes_cover_the_same_area_at_every_size() {
+    let mut stroke = dab(BrushMode::Raise, 0.0);
+    stroke.radius = 0.1;
+    stroke.points = vec![[0.25, 0.5], [0.75, 0.5]];
+
+    // Share of the pixels the stroke changed and their centre, in 0-1 coordinates
+    let footprint = |width: u32, height: u32| {
+        let sculpted = sculpt(filled(width, height, |_, _| 128), std::slice::from_ref(&stroke));
+        let changed: Vec<(u32, u32)> = sculpted.enumerate_pixels().filter(|(_, _, pixel)| pixel[0] != 128).map(|(x, y, _)| (x, y)).collect();
+        let count = changed.len() as f32;
+        let centre_x = changed.iter().map(|&(x, _)| x as f32 + 0.5).sum::<f32>() / count / width as f32;
+        let centre_y = changed.iter().map(|&(_, y)| y as f32 + 0.5).sum::<f32>() / count / height as f32;
+        (count / (width * height) as f32, centre_x, centre_y)
+    };
+
+    let (small_share, small_x, small_y) = footprint(64, 64);
+    let (large_share, large_x, large_y) = footprint(256, 256);
+    assert!((small_share - large_share).abs() < 0.02, "{} against {}", small_share, large_share);
+    assert!((small_x - large_x).abs() < 0.01 && (small_y - large_y).abs() < 0.01);
+    // The last dab may fall up to one spacing short of the end of the stroke
+    assert!((large_x - 0.5).abs() < 0.02 && (large_y - 0.5).abs() < 0.01);
+}
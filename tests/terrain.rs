@@ -0,0 +1,121 @@
+// Real-world units and the upgrade of version 3 configs and graphs to them
+
+use rust_heightmap_generator::heightmap_gen::config::{parse_config, CONFIG_VERSION};
+use rust_heightmap_generator::heightmap_gen::graph::{EvalContext, Graph, NodeKind, PortType, GRAPH_VERSION};
+use rust_heightmap_generator::heightmap_gen::terrain::Terrain;
+
+#[test]
+fn heights_map_to_the_elevation_range() {
+    let terrain = Terrain { world_size_km: 2.56, min_elevation_m: -100.0, max_elevation_m: 410.0, sea_level_m: 0.0 };
+    assert_eq!(terrain.relief_m(), 510.0);
+    assert_eq!(terrain.elevation_m(0.0), -100.0);
+    assert_eq!(terrain.elevation_m(255.0), 410.0);
+    assert_eq!(terrain.sea_level(), 50);
+    assert_eq!(terrain.height_steps(4.0), 2.0);
+    assert_eq!(terrain.pixel_size_m(256, 128), 10.0);
+    // A 45 degree slope rises one pixel, 10 m, per pixel, which is a 51st of the range
+    assert!((terrain.slope_threshold(45.0, 256, 256) - 10.0 / 510.0).abs() < 1e-12);
+}
+
+#[test]
+fn version_3_configs_keep_their_terrain_and_erosion() {
+    let config = parse_config(r#"{
+        "version": 3,
+        "scale": 0.02,
+        "terrain_size_m": 2560.0,
+        "terrain_height_m": 1000.0,
+        "ground_level": 51,
+        "talus_angle": 0.01,
+        "erosion_factor": 5,
+        "dem_min_elevation": 100.0,
+        "dem_max_elevation": 200.0,
+        "scatter_rules": [{ "name": "trees", "height_min": 51, "height_max": 204 }, { "name": "rocks" }]
+    }"#).unwrap();
+
+    assert_eq!(config.version, CONFIG_VERSION);
+    assert_eq!(config.terrain, Terrain { world_size_km: 2.56, min_elevation_m: 0.0, max_elevation_m: 1000.0, sea_level_m: 200.0 });
+    // A step of 0.01 of the range per preview pixel: 10 m over 10 m
+    assert!((config.talus_angle - 45.0).abs() < 1e-4);
+    assert!((config.river_depth_m - 5.0 / 255.0 * 1000.0).abs() < 1e-9);
+    // The terrace jitter keeps following the old base scale
+    assert_eq!(config.terrace_noise_scale, 0.02);
+    // Scatter height limits become elevations, missing ones the old defaults of 0 and 200
+    let limits: Vec<(f64, f64)> = config.scatter_rules.iter().map(|rule| (rule.elevation_min_m, rule.elevation_max_m)).collect();
+    assert_eq!(limits, vec![(200.0, 800.0), (0.0, 200.0 / 255.0 * 1000.0)]);
+    assert_eq!(config.scatter_rules[0].name, "trees");
+}
+
+#[test]
+fn version_3_dem_exports_keep_the_dem_range() {
+    let config = parse_config(r#"{
+        "version": 3,
+        "dem_export_asc": true,
+        "dem_min_elevation": 100.0,
+        "dem_max_elevation": 355.0,
+        "ground_level": 10
+    }"#).unwrap();
+
+    assert_eq!(config.terrain, Terrain { world_size_km: 1.0, min_elevation_m: 100.0, max_elevation_m: 355.0, sea_level_m: 110.0 });
+    assert_eq!(config.terrain.sea_level(), 10);
+}
+
+// Saved from the node editor before graphs had a version
+const VERSION_3_GRAPH: &str = r#"{ "nodes": [
+    { "id": 0, "kind": { "type": "Perlin", "scale": 0.05, "offset_x": 0.0, "offset_y": 0.0, "opacity": 1.0, "seed": 1 }, "inputs": [] },
+    { "id": 1, "kind": { "type": "Colorize", "start_index": 2 }, "inputs": [{ "node": 0, "output": 0 }] },
+    { "id": 2, "kind": { "type": "ThermalErosion", "iterations": 5, "talus_angle": 0.01, "erosion_mode": 2 }, "inputs": [{ "node": 0, "output": 0 }, { "node": 1, "output": 0 }] },
+    { "id": 3, "kind": { "type": "Rivers", "rain_iterations": 20, "erosion_factor": 5, "num_rivers": 2, "seed": 1 }, "inputs": [{ "node": 2, "output": 0 }, { "node": 2, "output": 1 }] },
+    { "id": 4, "kind": { "type": "Topographic", "params": { "interval_m": 50.0 }, "size_m": 2560.0, "height_m": 1000.0 }, "inputs": [{ "node": 3, "output": 0 }, { "node": 3, "output": 1 }] },
+    { "id": 5, "kind": { "type": "NormalMap", "strength": 4.0 }, "inputs": [{ "node": 3, "output": 0 }] },
+    { "id": 6, "kind": { "type": "Output", "name": "heightmap", "port": "Height" }, "inputs": [{ "node": 3, "output": 0 }] },
+    { "id": 7, "kind": { "type": "Output", "name": "topo", "port": "Color" }, "inputs": [{ "node": 4, "output": 0 }] },
+    { "id": 8, "kind": { "type": "Output", "name": "normal", "port": "Color" }, "inputs": [{ "node": 5, "output": 0 }] }
+] }"#;
+
+#[test]
+fn version_3_graphs_keep_their_erosion_and_maps() {
+    let graph = Graph::from_json(VERSION_3_GRAPH).unwrap();
+    assert_eq!(graph.version, GRAPH_VERSION);
+    let terrain = Terrain::default();
+
+    match &graph.nodes[2].kind {
+        NodeKind::ThermalErosion { talus_angle, terrain: node_terrain, .. } => {
+            assert_eq!(*node_terrain, terrain);
+            // The same slope per preview pixel as before
+            assert!((terrain.slope_threshold(*talus_angle as f64, 256, 256) - 0.01).abs() < 1e-6);
+        }
+        kind => panic!("expected thermal erosion, got {:?}", kind),
+    }
+    match &graph.nodes[3].kind {
+        NodeKind::Rivers { depth_m, terrain: node_terrain, .. } => {
+            assert_eq!(*node_terrain, terrain);
+            assert!((node_terrain.height_steps(*depth_m) - 5.0).abs() < 1e-9);
+        }
+        kind => panic!("expected rivers, got {:?}", kind),
+    }
+    match &graph.nodes[4].kind {
+        NodeKind::Topographic { params, terrain } => {
+            assert_eq!(params.interval_m, 50.0);
+            assert_eq!(*terrain, Terrain { world_size_km: 2.56, min_elevation_m: 0.0, max_elevation_m: 1000.0, sea_level_m: 0.0 });
+        }
+        kind => panic!("expected a topographic map, got {:?}", kind),
+    }
+    match &graph.nodes[8].kind {
+        NodeKind::Output { port, .. } => assert_eq!(*port, PortType::Normal),
+        kind => panic!("expected an output, got {:?}", kind),
+    }
+
+    let outputs = graph.evaluate(&EvalContext::new(32, 32)).unwrap();
+    assert_eq!(outputs.len(), 3);
+
+    // Saved again it is a current graph
+    let saved = graph.to_json().unwrap();
+    assert!(saved.contains(&format!("\"version\": {}", GRAPH_VERSION)));
+    assert_eq!(Graph::from_json(&saved).unwrap().to_json().unwrap(), saved);
+}
+
+#[test]
+fn newer_graphs_are_rejected() {
+    let json = format!(r#"{{ "version": {}, "nodes": [] }}"#, GRAPH_VERSION + 1);
+    assert!(Graph::from_json(&json).is_err());
+}
@@ -0,0 +1,73 @@
+// Tiling for World Partition and the paint layer weights
+
+use image::{ImageBuffer, Rgba};
+use rust_heightmap_generator::heightmap_gen::tiles::{extract_tile, tile_grid, weight_maps, WEIGHT_LAYERS};
+
+type Buffer = ImageBuffer<Rgba<u8>, Vec<u8>>;
+
+// Every pixel is different, so a tile cut from the wrong place can't match by chance
+fn positions(width: u32, height: u32) -> ImageBuffer<Rgba<u16>, Vec<u16>> {
+    ImageBuffer::from_fn(width, height, |x, y| Rgba([x as u16, y as u16, 0, 0]))
+}
+
+#[test]
+fn tiles_cover_the_image_with_shared_edges() {
+    assert_eq!(tile_grid(253, 253, 253), (1, 1));
+    assert_eq!(tile_grid(505, 253, 253), (2, 1));
+    assert_eq!(tile_grid(506, 1009, 253), (3, 4));
+
+    let (width, height, tile_size) = (40, 30, 9);
+    let image = positions(width, height);
+    let (columns, rows) = tile_grid(width, height, tile_size);
+    assert_eq!((columns, rows), (5, 4));
+
+    for row in 0..rows {
+        for column in 0..columns {
+            let tile = extract_tile(&image, column, row, tile_size);
+            assert_eq!(tile.dimensions(), (tile_size, tile_size));
+            if column + 1 < columns {
+                let right = extract_tile(&image, column + 1, row, tile_size);
+                for y in 0..tile_size {
+                    assert_eq!(tile.get_pixel(tile_size - 1, y), right.get_pixel(0, y), "tile {} {} row {}", column, row, y);
+                }
+            }
+            if row + 1 < rows {
+                let below = extract_tile(&image, column, row + 1, tile_size);
+                for x in 0..tile_size {
+                    assert_eq!(tile.get_pixel(x, tile_size - 1), below.get_pixel(x, 0), "tile {} {} column {}", column, row, x);
+                }
+            }
+        }
+    }
+
+    // The last tiles reach the far edges and repeat them past the end
+    let corner = extract_tile(&image, columns - 1, rows - 1, tile_size);
+    assert_eq!(corner.get_pixel(tile_size - 1, tile_size - 1).0, [width as u16 - 1, height as u16 - 1, 0, 0]);
+}
+
+#[test]
+fn weights_add_up_to_255() {
+    let heightmap: Buffer = ImageBuffer::from_fn(256, 4, |x, _| Rgba([x as u8, x as u8, x as u8, 255]));
+    // No water, full water and partial water in the rows
+    let mask: Buffer = ImageBuffer::from_fn(256, 4, |x, y| {
+        let water = match y {
+            0 => 0,
+            1 => 255,
+            _ => (x * 7 % 256) as u8,
+        };
+        Rgba([water, water, water, 255])
+    });
+
+    for water_mask in [None, Some(&mask)] {
+        let maps = weight_maps(&heightmap, water_mask);
+        assert_eq!(maps.len(), WEIGHT_LAYERS.len());
+        for (x, y, _) in heightmap.enumerate_pixels() {
+            let sum: u32 = maps.iter().map(|map| map.get_pixel(x, y)[0] as u32).sum();
+            assert_eq!(sum, 255, "pixel {} {}", x, y);
+        }
+    }
+
+    let maps = weight_maps(&heightmap, Some(&mask));
+    assert!((0..256).all(|x| maps[0].get_pixel(x, 1)[0] == 255));
+    assert_eq!(maps[4].get_pixel(255, 0)[0], 255);
+}
@@ -0,0 +1,62 @@
+// Drainage basins of shapes whose divides are known
+
+use image::{ImageBuffer, Rgba};
+use rust_heightmap_generator::heightmap_gen::cancel::CancelToken;
+use rust_heightmap_generator::heightmap_gen::terrain::Terrain;
+use rust_heightmap_generator::heightmap_gen::watershed::{render_basins, watersheds, Outlet, WatershedParams};
+
+const TERRAIN: Terrain = Terrain { world_size_km: 0.64, min_elevation_m: 0.0, max_elevation_m: 100.0, sea_level_m: 0.0 };
+
+fn gray(size: u32, value: impl Fn(f64, f64) -> f64) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    ImageBuffer::from_fn(size, size, |x, y| {
+        let value = value(x as f64, y as f64).clamp(0.0, 255.0).round() as u8;
+        Rgba([value, value, value, 255])
+    })
+}
+
+#[test]
+fn ridge_splits_the_map_into_two_basins() {
+    let size = 64;
+    let ridge = gray(size, |x, _| 200.0 - (x - 31.5).abs() * 5.0);
+    let params = WatershedParams { min_area_percent: 40.0 };
+    let basins = watersheds(&ridge, &params, &TERRAIN, &CancelToken::new()).unwrap();
+
+    assert_eq!(basins.basins.len(), 2);
+    assert_eq!(basins.basins.iter().map(|basin| basin.area_px).sum::<usize>(), (size * size) as usize);
+    let label = |x: u32, y: u32| basins.labels[(y * size + x) as usize];
+    let (left, right) = (label(0, 32), label(63, 32));
+    assert_ne!(left, right);
+    for y in 0..size {
+        for x in 0..30 {
+            assert_eq!(label(x, y), left);
+            assert_eq!(label(size - 1 - x, y), right);
+        }
+    }
+    for basin in basins.basins.iter() {
+        assert_eq!(basin.drains_to, Outlet::Edge);
+        assert_eq!(basin.outlet_px[0] < 32, basin.id == left);
+        assert!((basin.area_km2 - basin.area_px as f64 * 0.0001).abs() < 1e-9);
+    }
+}
+
+#[test]
+fn island_drains_into_the_surrounding_sea() {
+    let size = 64;
+    let island = gray(size, |x, y| 200.0 - ((x - 32.0).powi(2) + (y - 32.0).powi(2)).sqrt() * 8.0);
+    // No flattened ground or water mask, only a sea level at height 41
+    let terrain = Terrain { sea_level_m: 16.0, ..TERRAIN };
+    let basins = watersheds(&island, &WatershedParams::default(), &terrain, &CancelToken::new()).unwrap();
+
+    assert!(!basins.basins.is_empty());
+    for (index, pixel) in island.pixels().enumerate() {
+        assert_eq!(basins.labels[index] == 0, pixel[0] <= terrain.sea_level());
+    }
+    // The open sea, away from the outlet dots on the shore, has the water colour
+    let colors = render_basins(&basins, &island, &terrain);
+    assert_eq!(*colors.get_pixel(0, 0), Rgba([40, 70, 130, 255]));
+    for basin in basins.basins.iter() {
+        assert_eq!(basin.drains_to, Outlet::Water);
+        let [x, y] = basin.outlet_px;
+        assert_eq!(basins.labels[(y * size + x) as usize], basin.id);
+    }
+}